@@ -3,11 +3,33 @@ extends PluginLoaderBase
 
 # Config for execute command action
 var _exec_cmd_config: Config = Config.new()
+# Config for power action
+var _power_config: Config = Config.new()
 
 
 func _init():
 	_exec_cmd_config.add_dict("SSH Client", "ssh_client", null, {})
 	_exec_cmd_config.add_string("Command", "command", "")
+	_exec_cmd_config.add_string_array(
+		"Shell",
+		"shell",
+		"default",
+		["default", "sh", "bash", "powershell", "cmd"],
+		"Shell the command is written for. It will be wrapped to work with the server's default shell."
+	)
+	_exec_cmd_config.add_bool(
+		"Run as root",
+		"elevated",
+		false,
+		"Run the command with sudo. The sudo password is sent to sudo directly, never as part of the command."
+	)
+	_power_config.add_dict("SSH Client", "ssh_client", null, {})
+	_power_config.add_string_array(
+		"Action", "action", "shutdown", ["shutdown", "reboot", "suspend"]
+	)
+	_power_config.add_bool(
+		"Run as root", "elevated", false, "Run the power command with sudo."
+	)
 	actions = [
 		PluginCoordinator.PluginActionDefinition.new(
 			"Execute SSH command",
@@ -16,6 +38,14 @@ func _init():
 			_exec_cmd_config,
 			"SSH",
 			"SSHController"
+		),
+		PluginCoordinator.PluginActionDefinition.new(
+			"Power SSH host",
+			"power_client",
+			"Shut down, reboot or suspend the server of a SSH client",
+			_power_config,
+			"SSH",
+			"SSHController"
 		)
 	]
 
@@ -25,10 +55,11 @@ func _init():
 
 
 func set_client_config(clients: Dictionary) -> void:
-	var ssh_client_object: Config.DictObject = _exec_cmd_config.get_object("ssh_client")
-	ssh_client_object.set_dict(clients)
-	if clients.size() > 0:
-		ssh_client_object.set_value(clients.values()[0])
+	for config in [_exec_cmd_config, _power_config]:
+		var ssh_client_object: Config.DictObject = config.get_object("ssh_client")
+		ssh_client_object.set_dict(clients)
+		if clients.size() > 0:
+			ssh_client_object.set_value(clients.values()[0])
 
 
 func _on_settings_button_pressed() -> void:
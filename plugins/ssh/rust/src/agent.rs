@@ -0,0 +1,333 @@
+use crate::logger::{LogEvent, Logger};
+use async_std::task::block_on;
+use client::Msg;
+use russh::keys::ssh_key::HashAlg;
+use russh::keys::{PrivateKey, PublicKey};
+use russh::*;
+use signature::Signer;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Condvar, LazyLock, Mutex};
+use std::time::Duration;
+
+/// Agent messages longer than this are rejected, like OpenSSH does.
+const MAX_MESSAGE_LENGTH: usize = 256 * 1024;
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+/// Sign request flag asking for an rsa-sha2-512 signature.
+const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+/// Where forwarded agent requests are answered.
+#[derive(Clone)]
+pub enum AgentBackend {
+    /// The local agent at `$SSH_AUTH_SOCK`. Only listing keys and signing are passed on,
+    /// so the server can't add, remove or lock keys.
+    Local,
+    /// Keys held by the client itself.
+    Keys(Arc<Vec<PrivateKey>>),
+}
+
+/// A request of the server to sign with a forwarded key.
+pub struct AgentSignRequest {
+    pub fingerprint: String,
+    /// Comment of the key, empty if unknown.
+    pub comment: String,
+}
+
+/// Decides whether a sign request is allowed. Called on the agent's thread, so it may block.
+pub type ConfirmHook = Arc<dyn Fn(&AgentSignRequest) -> bool + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct AgentForwarding {
+    /// None disables forwarding.
+    pub backend: Option<AgentBackend>,
+    /// Request forwarding on every channel instead of only for single commands.
+    pub always: bool,
+    /// Asked before every signature, all are allowed if None.
+    pub confirm: Option<ConfirmHook>,
+}
+
+/// Sign requests of all clients waiting for a confirmation. Global, so requests can be answered
+/// without binding the client, which stays bound while it runs the command the request comes from.
+pub static CONFIRMATIONS: LazyLock<Confirmations> = LazyLock::new(Confirmations::default);
+
+/// Sign requests waiting for a confirmation, see [ConfirmHook].
+#[derive(Default)]
+pub struct Confirmations {
+    /// Next request id and the answers of the pending requests.
+    pending: Mutex<(u64, HashMap<u64, Option<bool>>)>,
+    answered: Condvar,
+}
+
+impl Confirmations {
+    /// Registers a request, passes its id to `ask` and waits for its [Confirmations::answer].
+    /// Requests not answered within `timeout` are denied.
+    pub fn confirm(&self, ask: impl FnOnce(u64), timeout: Duration) -> bool {
+        let id = match self.pending.lock() {
+            Ok(mut pending) => {
+                let id = pending.0;
+                pending.0 += 1;
+                pending.1.insert(id, None);
+                id
+            }
+            Err(_) => return false,
+        };
+        ask(id);
+        let Ok(pending) = self.pending.lock() else {
+            return false;
+        };
+        let result = self
+            .answered
+            .wait_timeout_while(pending, timeout, |pending| {
+                matches!(pending.1.get(&id), Some(None))
+            });
+        match result {
+            Ok((mut pending, _)) => pending.1.remove(&id).flatten().unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    /// Answers the pending request `id`. Returns false if there is none, e.g. because it timed out.
+    pub fn answer(&self, id: u64, allowed: bool) -> bool {
+        let Ok(mut pending) = self.pending.lock() else {
+            return false;
+        };
+        match pending.1.get_mut(&id) {
+            Some(answer @ None) => {
+                *answer = Some(allowed);
+                self.answered.notify_all();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Answers the agent requests of a forwarded agent channel until it closes.
+/// Blocks, so call it on a separate thread.
+pub fn serve_agent_channel(
+    mut channel: Channel<Msg>,
+    agent: AgentForwarding,
+    log: Logger,
+    ip: String,
+    port: u16,
+) {
+    let Some(backend) = agent.backend.clone() else {
+        let _ = block_on(channel.close());
+        return;
+    };
+    let mut local = None;
+    if matches!(backend, AgentBackend::Local) {
+        match connect_local_agent() {
+            Ok(stream) => local = Some(stream),
+            Err(e) => {
+                log.warn(LogEvent::Channel, &ip, port, || {
+                    format!("Couldn't forward agent: {}", e)
+                });
+                let _ = block_on(channel.close());
+                return;
+            }
+        }
+    }
+
+    let mut buffer: Vec<u8> = Vec::new();
+    loop {
+        match block_on(channel.wait()) {
+            Some(ChannelMsg::Data { data }) => buffer.extend_from_slice(&data),
+            Some(ChannelMsg::Eof) | Some(ChannelMsg::Close) | None => break,
+            Some(_) => continue,
+        }
+        if buffer.len() > MAX_MESSAGE_LENGTH + 4 {
+            log.warn(LogEvent::Channel, &ip, port, || {
+                "Forwarded agent message too long, closing".to_string()
+            });
+            break;
+        }
+        while let Some(request) = take_message(&mut buffer) {
+            let response = match handle_request(&request, &backend, local.as_mut(), &agent) {
+                Ok(response) => response,
+                Err(e) => {
+                    log.warn(LogEvent::Channel, &ip, port, || {
+                        format!("Forwarded agent request failed: {}", e)
+                    });
+                    vec![SSH_AGENT_FAILURE]
+                }
+            };
+            if block_on(channel.data(&frame(&response)[..])).is_err() {
+                return;
+            }
+        }
+    }
+    let _ = block_on(channel.close());
+}
+
+fn connect_local_agent() -> anyhow::Result<UnixStream> {
+    let Some(path) = std::env::var_os("SSH_AUTH_SOCK") else {
+        anyhow::bail!("SSH_AUTH_SOCK isn't set, is an agent running?");
+    };
+    match UnixStream::connect(&path) {
+        Ok(stream) => Ok(stream),
+        Err(e) => anyhow::bail!("Failed to connect to agent at {:?}: {}", path, e),
+    }
+}
+
+fn handle_request(
+    request: &[u8],
+    backend: &AgentBackend,
+    local: Option<&mut UnixStream>,
+    agent: &AgentForwarding,
+) -> anyhow::Result<Vec<u8>> {
+    let Some((&message_type, body)) = request.split_first() else {
+        anyhow::bail!("Empty agent request");
+    };
+    match (message_type, backend) {
+        (SSH_AGENTC_REQUEST_IDENTITIES, AgentBackend::Keys(keys)) => identities_answer(keys),
+        (SSH_AGENTC_SIGN_REQUEST, AgentBackend::Keys(keys)) => {
+            let sign_request = SignRequest::parse(body)?;
+            let Some(key) = keys.iter().find(|key| {
+                key.public_key()
+                    .to_bytes()
+                    .is_ok_and(|blob| blob == sign_request.key_blob)
+            }) else {
+                anyhow::bail!("Sign request for an unknown key");
+            };
+            if !confirm(agent, key.public_key(), key.comment()) {
+                anyhow::bail!("Sign request was denied");
+            }
+            sign_response(key, &sign_request)
+        }
+        (SSH_AGENTC_REQUEST_IDENTITIES, AgentBackend::Local) => forward(local, request),
+        (SSH_AGENTC_SIGN_REQUEST, AgentBackend::Local) => {
+            let sign_request = SignRequest::parse(body)?;
+            let key = match PublicKey::from_bytes(&sign_request.key_blob) {
+                Ok(key) => key,
+                Err(e) => anyhow::bail!("Invalid key in sign request: {}", e),
+            };
+            if !confirm(agent, &key, "") {
+                anyhow::bail!("Sign request was denied");
+            }
+            forward(local, request)
+        }
+        // Adding, removing and locking keys and extensions are never forwarded,
+        // an extension could do any of that without a confirmation
+        _ => Ok(vec![SSH_AGENT_FAILURE]),
+    }
+}
+
+fn confirm(agent: &AgentForwarding, key: &PublicKey, comment: &str) -> bool {
+    match &agent.confirm {
+        Some(confirm) => confirm(&AgentSignRequest {
+            fingerprint: key.fingerprint(HashAlg::Sha256).to_string(),
+            comment: comment.to_string(),
+        }),
+        None => true,
+    }
+}
+
+/// Passes `request` to the local agent and returns its response.
+fn forward(local: Option<&mut UnixStream>, request: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let Some(stream) = local else {
+        anyhow::bail!("Not connected to the local agent");
+    };
+    stream.write_all(&frame(request))?;
+    let mut length = [0u8; 4];
+    stream.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_MESSAGE_LENGTH {
+        anyhow::bail!("Local agent response too long");
+    }
+    let mut response = vec![0u8; length];
+    stream.read_exact(&mut response)?;
+    Ok(response)
+}
+
+fn identities_answer(keys: &[PrivateKey]) -> anyhow::Result<Vec<u8>> {
+    let mut response = vec![SSH_AGENT_IDENTITIES_ANSWER];
+    response.extend_from_slice(&(keys.len() as u32).to_be_bytes());
+    for key in keys {
+        put_string(&mut response, &key.public_key().to_bytes()?);
+        put_string(&mut response, key.comment().as_bytes());
+    }
+    Ok(response)
+}
+
+fn sign_response(key: &PrivateKey, request: &SignRequest) -> anyhow::Result<Vec<u8>> {
+    // RSA keys sign with SHA-512, the other hashes aren't offered
+    if key.algorithm().is_rsa() && request.flags & SSH_AGENT_RSA_SHA2_512 == 0 {
+        anyhow::bail!("Only rsa-sha2-512 signatures are supported");
+    }
+    let signature = match key.try_sign(&request.data) {
+        Ok(signature) => signature,
+        Err(e) => anyhow::bail!("Failed to sign: {}", e),
+    };
+    let mut blob = Vec::new();
+    put_string(&mut blob, signature.algorithm().as_str().as_bytes());
+    put_string(&mut blob, signature.as_bytes());
+    let mut response = vec![SSH_AGENT_SIGN_RESPONSE];
+    put_string(&mut response, &blob);
+    Ok(response)
+}
+
+struct SignRequest {
+    key_blob: Vec<u8>,
+    data: Vec<u8>,
+    flags: u32,
+}
+
+impl SignRequest {
+    fn parse(mut body: &[u8]) -> anyhow::Result<Self> {
+        let key_blob = take_string(&mut body)?;
+        let data = take_string(&mut body)?;
+        let flags = take_u32(&mut body)?;
+        Ok(Self {
+            key_blob,
+            data,
+            flags,
+        })
+    }
+}
+
+/// Removes the first complete length prefixed message from `buffer`.
+fn take_message(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let length = u32::from_be_bytes(buffer.get(..4)?.try_into().ok()?) as usize;
+    if buffer.len() < 4 + length {
+        return None;
+    }
+    let message = buffer[4..4 + length].to_vec();
+    buffer.drain(..4 + length);
+    Some(message)
+}
+
+fn frame(message: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(message.len() + 4);
+    put_string(&mut framed, message);
+    framed
+}
+
+pub(crate) fn put_string(buffer: &mut Vec<u8>, string: &[u8]) {
+    buffer.extend_from_slice(&(string.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(string);
+}
+
+pub(crate) fn take_u32(buffer: &mut &[u8]) -> anyhow::Result<u32> {
+    let Some((value, rest)) = buffer.split_first_chunk::<4>() else {
+        anyhow::bail!("Truncated message");
+    };
+    *buffer = rest;
+    Ok(u32::from_be_bytes(*value))
+}
+
+pub(crate) fn take_string(buffer: &mut &[u8]) -> anyhow::Result<Vec<u8>> {
+    let length = take_u32(buffer)? as usize;
+    if buffer.len() < length {
+        anyhow::bail!("Truncated message");
+    }
+    let (string, rest) = buffer.split_at(length);
+    *buffer = rest;
+    Ok(string.to_vec())
+}
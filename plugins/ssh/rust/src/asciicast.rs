@@ -0,0 +1,296 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Header line of an asciicast v2 file.
+#[derive(Serialize, Deserialize, Debug)]
+struct Header {
+    version: u32,
+    width: u32,
+    height: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    idle_time_limit: Option<f64>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    env: HashMap<String, String>,
+}
+
+/// Decodes a byte stream as UTF-8, keeping sequences split across chunks until they are complete.
+#[derive(Default)]
+struct Utf8Stream {
+    partial: Vec<u8>,
+}
+
+impl Utf8Stream {
+    fn push(&mut self, data: &[u8]) -> String {
+        self.partial.extend_from_slice(data);
+        // Only an incomplete sequence at the end is kept, invalid bytes are replaced
+        let keep = incomplete_tail(&self.partial);
+        let tail = self.partial.split_off(self.partial.len() - keep);
+        let text = String::from_utf8_lossy(&self.partial).into_owned();
+        self.partial = tail;
+        text
+    }
+
+    fn finish(&mut self) -> String {
+        String::from_utf8_lossy(&std::mem::take(&mut self.partial)).into_owned()
+    }
+}
+
+/// Length of the incomplete UTF-8 sequence at the end of `bytes`, 0 if there is none.
+/// Only the end is checked, so invalid bytes before it don't matter.
+fn incomplete_tail(bytes: &[u8]) -> usize {
+    // A sequence has at most 4 bytes, so an incomplete one starts in the last 3
+    for start in (bytes.len().saturating_sub(3)..bytes.len()).rev() {
+        // Continuation bytes are 0b10xxxxxx
+        if bytes[start] & 0xC0 != 0x80 {
+            return match std::str::from_utf8(&bytes[start..]) {
+                Err(e) if e.error_len().is_none() => bytes.len() - start,
+                _ => 0,
+            };
+        }
+    }
+    0
+}
+
+/// Writes a terminal session as an [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/) file.
+/// Event times are relative to when the writer was created.
+pub struct AsciicastWriter {
+    file: BufWriter<fs::File>,
+    started: Instant,
+    output: Utf8Stream,
+    input: Utf8Stream,
+}
+
+impl AsciicastWriter {
+    pub fn create(
+        path: &Path,
+        cols: u32,
+        rows: u32,
+        term: &str,
+        title: Option<String>,
+    ) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                anyhow::bail!("Failed to create {}: {}", parent.display(), e);
+            }
+        }
+        let file = match fs::File::create(path) {
+            Ok(file) => file,
+            Err(e) => anyhow::bail!("Failed to create recording {}: {}", path.display(), e),
+        };
+        let header = Header {
+            version: 2,
+            width: cols,
+            height: rows,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|time| time.as_secs()),
+            title,
+            idle_time_limit: None,
+            env: HashMap::from([("TERM".to_string(), term.to_string())]),
+        };
+        let mut writer = Self {
+            file: BufWriter::new(file),
+            started: Instant::now(),
+            output: Utf8Stream::default(),
+            input: Utf8Stream::default(),
+        };
+        writer.write_line(&header)?;
+        Ok(writer)
+    }
+
+    /// Records output of the terminal which arrived `at`.
+    pub fn output(&mut self, at: Instant, data: &[u8]) -> anyhow::Result<()> {
+        let text = self.output.push(data);
+        self.event(at, "o", text)
+    }
+
+    /// Records input sent to the terminal `at`.
+    pub fn input(&mut self, at: Instant, data: &[u8]) -> anyhow::Result<()> {
+        let text = self.input.push(data);
+        self.event(at, "i", text)
+    }
+
+    pub fn resize(&mut self, at: Instant, cols: u32, rows: u32) -> anyhow::Result<()> {
+        self.event(at, "r", format!("{}x{}", cols, rows))
+    }
+
+    /// Adds a marker players can jump to.
+    pub fn marker(&mut self, at: Instant, label: &str) -> anyhow::Result<()> {
+        self.event(at, "m", label.to_string())
+    }
+
+    /// Writes what is left of split UTF-8 sequences and flushes the file.
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        let at = Instant::now();
+        let output = self.output.finish();
+        self.event(at, "o", output)?;
+        let input = self.input.finish();
+        self.event(at, "i", input)?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    fn event(&mut self, at: Instant, kind: &str, data: String) -> anyhow::Result<()> {
+        // Markers may be empty, other events without data are dropped
+        if data.is_empty() && kind != "m" {
+            return Ok(());
+        }
+        let time = at.saturating_duration_since(self.started).as_secs_f64();
+        // Microseconds are plenty and keep the file readable
+        let time = (time * 1_000_000.0).round() / 1_000_000.0;
+        self.write_line(&(time, kind, data))
+    }
+
+    fn write_line(&mut self, value: &impl Serialize) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(value)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum EventKind {
+    Output(String),
+    Input(String),
+    Resize(u32, u32),
+    Marker(String),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Event {
+    /// Seconds since the start of the recording.
+    pub time: f64,
+    pub kind: EventKind,
+}
+
+/// A loaded asciicast v2 recording.
+pub struct Recording {
+    pub width: u32,
+    pub height: u32,
+    pub title: Option<String>,
+    /// Events ordered by time.
+    pub events: Vec<Event>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => anyhow::bail!("Failed to read recording {}: {}", path.display(), e),
+        };
+        let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+        let header = match lines.next().map(serde_json::from_str::<Header>) {
+            Some(Ok(header)) => header,
+            Some(Err(e)) => anyhow::bail!("Invalid recording header in {}: {}", path.display(), e),
+            None => anyhow::bail!("Recording {} is empty", path.display()),
+        };
+        if header.version != 2 {
+            anyhow::bail!(
+                "Unsupported asciicast version {} in {}",
+                header.version,
+                path.display()
+            );
+        }
+
+        let mut events = Vec::new();
+        for (index, line) in lines.enumerate() {
+            let (time, kind, data) = match serde_json::from_str::<(f64, String, String)>(line) {
+                Ok(event) => event,
+                Err(e) => anyhow::bail!(
+                    "Invalid event {} in recording {}: {}",
+                    index + 1,
+                    path.display(),
+                    e
+                ),
+            };
+            // Unknown event types are skipped, as players should
+            let kind = match kind.as_str() {
+                "o" => EventKind::Output(data),
+                "i" => EventKind::Input(data),
+                "m" => EventKind::Marker(data),
+                "r" => match data.split_once('x').and_then(|(cols, rows)| {
+                    Some((cols.parse::<u32>().ok()?, rows.parse::<u32>().ok()?))
+                }) {
+                    Some((cols, rows)) => EventKind::Resize(cols, rows),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            events.push(Event { time, kind });
+        }
+        events.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let mut recording = Self {
+            width: header.width,
+            height: header.height,
+            title: header.title,
+            events,
+        };
+        if let Some(limit) = header.idle_time_limit {
+            recording.limit_idle(limit);
+        }
+        Ok(recording)
+    }
+
+    /// Shortens pauses between events to at most `limit` seconds.
+    pub fn limit_idle(&mut self, limit: f64) {
+        if limit <= 0.0 {
+            return;
+        }
+        let mut previous = 0.0;
+        let mut shift = 0.0;
+        for event in &mut self.events {
+            let gap = event.time - previous;
+            previous = event.time;
+            if gap > limit {
+                shift += gap - limit;
+            }
+            event.time -= shift;
+        }
+    }
+
+    /// Seconds until the last event.
+    pub fn duration(&self) -> f64 {
+        self.events.last().map_or(0.0, |event| event.time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_characters_are_joined() {
+        let mut stream = Utf8Stream::default();
+        let euro = "€".as_bytes();
+        assert_eq!(stream.push(&[b'a', euro[0]]), "a");
+        assert_eq!(stream.push(&euro[1..2]), "");
+        assert_eq!(stream.push(&[euro[2], b'b']), "€b");
+        assert_eq!(stream.finish(), "");
+    }
+
+    #[test]
+    fn split_character_after_invalid_byte() {
+        let mut stream = Utf8Stream::default();
+        let euro = "€".as_bytes();
+        assert_eq!(stream.push(&[0xFF, b'a', euro[0], euro[1]]), "\u{FFFD}a");
+        assert_eq!(stream.push(&euro[2..]), "€");
+    }
+
+    #[test]
+    fn incomplete_sequence_is_flushed_on_finish() {
+        let mut stream = Utf8Stream::default();
+        assert_eq!(stream.push(&"€".as_bytes()[..2]), "");
+        assert_eq!(stream.finish(), "\u{FFFD}");
+    }
+}
@@ -0,0 +1,312 @@
+use chrono::{DateTime, Local};
+use godot::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// An audit log that can be shared between multiple clients.
+pub type SharedAuditLog = Arc<Mutex<AuditLog>>;
+
+/// One executed command, stored as a single JSON line.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    /// RFC 3339 timestamp of when the command was started.
+    pub timestamp: String,
+    /// Identity of the client, e.g. the uuid of the DreamDeck client.
+    pub client: String,
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub command: String,
+    /// None if the command couldn't be started or the server didn't send an exit status.
+    pub exit_status: Option<i64>,
+    pub duration_ms: u64,
+    pub stdout_bytes: u64,
+    pub stderr_bytes: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Captured output, only set if output capture is enabled. Truncated to the capture limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        dict! {
+            "timestamp" => self.timestamp.clone(),
+            "client" => self.client.clone(),
+            "host" => self.host.clone(),
+            "port" => self.port,
+            "user" => self.user.clone(),
+            "command" => self.command.clone(),
+            "exit_status" => match self.exit_status {
+                Some(exit_status) => Variant::from(exit_status),
+                None => Variant::nil(),
+            },
+            "duration_ms" => self.duration_ms as i64,
+            "stdout_bytes" => self.stdout_bytes as i64,
+            "stderr_bytes" => self.stderr_bytes as i64,
+            "error" => match &self.error {
+                Some(error) => Variant::from(error.clone()),
+                None => Variant::nil(),
+            },
+            "stdout" => match &self.stdout {
+                Some(stdout) => Variant::from(stdout.clone()),
+                None => Variant::nil(),
+            },
+            "stderr" => match &self.stderr {
+                Some(stderr) => Variant::from(stderr.clone()),
+                None => Variant::nil(),
+            },
+        }
+    }
+}
+
+/// Criteria for [AuditLog::query]. Unset fields match everything.
+#[derive(Default)]
+pub struct AuditFilter {
+    pub client: Option<String>,
+    pub host: Option<String>,
+    pub user: Option<String>,
+    /// Matches if the command contains this string.
+    pub command: Option<String>,
+    /// Unix timestamp in seconds, only entries started at or after it match.
+    pub since: Option<i64>,
+    /// Only match commands which failed or didn't exit with status 0.
+    pub failed_only: bool,
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        if self.client.as_ref().is_some_and(|c| *c != entry.client)
+            || self.host.as_ref().is_some_and(|h| *h != entry.host)
+            || self.user.as_ref().is_some_and(|u| *u != entry.user)
+            || self
+                .command
+                .as_ref()
+                .is_some_and(|c| !entry.command.contains(c.as_str()))
+        {
+            return false;
+        }
+        if self.failed_only && entry.exit_status == Some(0) {
+            return false;
+        }
+        if let Some(since) = self.since {
+            return DateTime::parse_from_rfc3339(&entry.timestamp)
+                .is_ok_and(|timestamp| timestamp.timestamp() >= since);
+        }
+        true
+    }
+}
+
+/// Append only JSON-lines log of executed commands.
+///
+/// When the log file would grow beyond `max_file_size` it is rotated to `<path>.1`,
+/// older files are shifted up to `<path>.<max_files>` and the oldest one is deleted.
+pub struct AuditLog {
+    path: PathBuf,
+    max_file_size: u64,
+    max_files: u32,
+    /// Maximum bytes of stdout and stderr each stored per entry, 0 disables output capture.
+    capture_limit: usize,
+}
+
+impl AuditLog {
+    pub fn new(path: &Path, max_file_size: u64, max_files: u32) -> anyhow::Result<Self> {
+        if max_file_size == 0 {
+            anyhow::bail!("Max audit log size must be greater than 0");
+        }
+        if let Some(parent) = path.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                anyhow::bail!("Failed to create {}: {}", parent.display(), e);
+            }
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_file_size,
+            max_files,
+            capture_limit: 0,
+        })
+    }
+
+    pub fn set_capture_limit(&mut self, capture_limit: usize) {
+        self.capture_limit = capture_limit;
+    }
+
+    pub fn capture_limit(&self) -> usize {
+        self.capture_limit
+    }
+
+    pub fn append(&mut self, entry: &AuditEntry) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+
+        let size = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > self.max_file_size {
+            self.rotate()?;
+        }
+
+        let mut file = match fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(&self.path)
+        {
+            Ok(file) => file,
+            Err(e) => anyhow::bail!("Failed to open audit log {}: {}", self.path.display(), e),
+        };
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            anyhow::bail!("Failed to write audit log {}: {}", self.path.display(), e);
+        }
+        Ok(())
+    }
+
+    /// Returns up to `limit` entries matching `filter`, newest first.
+    pub fn query(&self, filter: &AuditFilter, limit: usize) -> anyhow::Result<Vec<AuditEntry>> {
+        let mut entries: Vec<AuditEntry> = Vec::new();
+        if limit == 0 {
+            return Ok(entries);
+        }
+        for i in 0..=self.max_files {
+            let path = self.file_path(i);
+            let file = match fs::File::open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+                Err(e) => anyhow::bail!("Failed to read audit log {}: {}", path.display(), e),
+            };
+            let lines: Vec<String> = BufReader::new(file).lines().collect::<Result<_, _>>()?;
+            // Skip lines that can't be parsed, e.g. a partially written last line
+            for entry in lines
+                .iter()
+                .rev()
+                .filter_map(|line| serde_json::from_str::<AuditEntry>(line).ok())
+            {
+                if !filter.matches(&entry) {
+                    continue;
+                }
+                entries.push(entry);
+                if entries.len() >= limit {
+                    return Ok(entries);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn rotate(&self) -> anyhow::Result<()> {
+        if self.max_files == 0 {
+            return Ok(fs::remove_file(&self.path)?);
+        }
+        let oldest = self.file_path(self.max_files);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for i in (0..self.max_files).rev() {
+            let from = self.file_path(i);
+            if from.exists() {
+                if let Err(e) = fs::rename(&from, self.file_path(i + 1)) {
+                    anyhow::bail!("Failed to rotate audit log {}: {}", from.display(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Path of the log file, `0` being the current one.
+    fn file_path(&self, index: u32) -> PathBuf {
+        if index == 0 {
+            return self.path.clone();
+        }
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+}
+
+/// Which audit log a client writes to and under which identity.
+#[derive(Clone)]
+pub struct AuditTarget {
+    pub log: SharedAuditLog,
+    pub client_id: String,
+}
+
+/// Collects the data of a single exec and writes it to the audit log once finished.
+pub struct ExecRecord {
+    log: SharedAuditLog,
+    entry: AuditEntry,
+    started: Instant,
+    capture_limit: usize,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl ExecRecord {
+    pub fn start(target: &AuditTarget, host: &str, port: u16, user: &str, command: &str) -> Self {
+        let capture_limit = match target.log.lock() {
+            Ok(log) => log.capture_limit(),
+            Err(_) => 0,
+        };
+        Self {
+            log: target.log.clone(),
+            entry: AuditEntry {
+                timestamp: Local::now().to_rfc3339(),
+                client: target.client_id.clone(),
+                host: host.to_string(),
+                port,
+                user: user.to_string(),
+                command: command.to_string(),
+                exit_status: None,
+                duration_ms: 0,
+                stdout_bytes: 0,
+                stderr_bytes: 0,
+                error: None,
+                stdout: None,
+                stderr: None,
+            },
+            started: Instant::now(),
+            capture_limit,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        }
+    }
+
+    pub fn stdout(&mut self, data: &[u8]) {
+        self.entry.stdout_bytes += data.len() as u64;
+        capture(&mut self.stdout, data, self.capture_limit);
+    }
+
+    pub fn stderr(&mut self, data: &[u8]) {
+        self.entry.stderr_bytes += data.len() as u64;
+        capture(&mut self.stderr, data, self.capture_limit);
+    }
+
+    /// Writes the entry. Failing to write the audit log doesn't fail the command itself,
+    /// so errors are only reported.
+    pub fn finish(mut self, exit_status: Option<i64>, error: Option<String>) {
+        self.entry.exit_status = exit_status;
+        self.entry.error = error;
+        self.entry.duration_ms = self.started.elapsed().as_millis() as u64;
+        if self.capture_limit > 0 {
+            self.entry.stdout = Some(String::from_utf8_lossy(&self.stdout).into_owned());
+            self.entry.stderr = Some(String::from_utf8_lossy(&self.stderr).into_owned());
+        }
+        let result = match self.log.lock() {
+            Ok(mut log) => log.append(&self.entry),
+            Err(_) => Err(anyhow::anyhow!("Audit log is poisoned")),
+        };
+        if let Err(e) = result {
+            godot_error!("Failed to write audit log entry: {}", e);
+        }
+    }
+}
+
+fn capture(buffer: &mut Vec<u8>, data: &[u8], limit: usize) {
+    let remaining = limit.saturating_sub(buffer.len());
+    buffer.extend_from_slice(&data[..remaining.min(data.len())]);
+}
@@ -0,0 +1,131 @@
+use godot::prelude::*;
+use russh::Sig;
+use std::collections::VecDeque;
+
+/// Maximum bytes kept of stdout and stderr. None keeps everything, 0 discards the output entirely.
+#[derive(Clone, Copy, Debug)]
+pub struct CaptureLimits {
+    pub max_stdout: Option<usize>,
+    pub max_stderr: Option<usize>,
+}
+
+impl CaptureLimits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_stdout: None,
+            max_stderr: None,
+        }
+    }
+}
+
+/// Collects a stream while keeping at most `limit` bytes.
+///
+/// If the stream is longer, its first and last `limit / 2` bytes are kept
+/// and a marker with the amount of dropped bytes is put in between.
+pub struct CaptureBuffer {
+    limit: Option<usize>,
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    total: u64,
+}
+
+impl CaptureBuffer {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            total: 0,
+        }
+    }
+
+    pub fn push(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+        let Some(limit) = self.limit else {
+            self.head.extend_from_slice(data);
+            return;
+        };
+
+        let head_limit = limit / 2;
+        if self.head.len() < head_limit {
+            let n = (head_limit - self.head.len()).min(data.len());
+            self.head.extend_from_slice(&data[..n]);
+            data = &data[n..];
+        }
+        let tail_limit = limit - head_limit;
+        if data.len() >= tail_limit {
+            self.tail.clear();
+            self.tail.extend(&data[data.len() - tail_limit..]);
+        } else {
+            self.tail.extend(data);
+            let excess = self.tail.len().saturating_sub(tail_limit);
+            self.tail.drain(..excess);
+        }
+    }
+
+    /// Total amount of bytes received.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.total > (self.head.len() + self.tail.len()) as u64
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        let dropped = self.total - (self.head.len() + self.tail.len()) as u64;
+        let mut bytes = self.head;
+        if dropped > 0 && self.limit != Some(0) {
+            bytes
+                .extend_from_slice(format!("\n[... {} bytes truncated ...]\n", dropped).as_bytes());
+        }
+        bytes.extend(self.tail);
+        bytes
+    }
+}
+
+/// Output of a finished command as raw bytes.
+pub struct BinaryExecOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Total bytes received, including truncated or discarded ones.
+    pub stdout_size: u64,
+    pub stderr_size: u64,
+    pub stdout_truncated: bool,
+    pub stderr_truncated: bool,
+    /// -1 if the server didn't send an exit status.
+    pub exit_status: i64,
+    /// Name of the signal which terminated the command, e.g. "TERM".
+    pub exit_signal: Option<String>,
+    pub core_dumped: bool,
+    /// Error message sent along with the exit signal.
+    pub signal_message: String,
+}
+
+impl BinaryExecOutput {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        dict! {
+            "stdout" => PackedByteArray::from(self.stdout.as_slice()),
+            "stderr" => PackedByteArray::from(self.stderr.as_slice()),
+            "stdout_size" => self.stdout_size as i64,
+            "stderr_size" => self.stderr_size as i64,
+            "stdout_truncated" => self.stdout_truncated,
+            "stderr_truncated" => self.stderr_truncated,
+            "exit_status" => self.exit_status,
+            "exit_signal" => match &self.exit_signal {
+                Some(exit_signal) => Variant::from(exit_signal.clone()),
+                None => Variant::nil(),
+            },
+            "core_dumped" => self.core_dumped,
+            "signal_message" => self.signal_message.clone(),
+        }
+    }
+}
+
+/// Name of the signal without the `SIG` prefix, as sent by the server.
+pub fn signal_name(signal: &Sig) -> String {
+    match signal {
+        Sig::Custom(name) => name.clone(),
+        signal => format!("{:?}", signal),
+    }
+}
@@ -0,0 +1,668 @@
+use crate::agent::{put_string, take_string, take_u32};
+use crate::internal_ssh_client::{InternalSSHClient, ServerCheckMethod};
+use crate::logger::LogEvent;
+use crate::ssh_config::{home_dir, wildcard_match};
+use async_std::future;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{Local, TimeZone};
+use russh::keys::ssh_key::certificate::CertType;
+use russh::keys::ssh_key::public::KeyData;
+use russh::keys::ssh_key::{Certificate, HashAlg};
+use russh::keys::{parse_public_key_base64, PublicKey};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// How long fetching a host certificate may take, see [fetch_host_certificate].
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest packet accepted while fetching a host certificate.
+const MAX_PACKET_LENGTH: usize = 256 * 1024;
+
+/// Algorithms offered when fetching a host certificate. Only the key exchange is started,
+/// so only the host key algorithms matter, the others just need to be supported by the server.
+const FETCH_KEX: &str = "curve25519-sha256,curve25519-sha256@libssh.org";
+const FETCH_CIPHERS: &str =
+    "chacha20-poly1305@openssh.com,aes128-gcm@openssh.com,aes256-gcm@openssh.com,aes128-ctr,aes256-ctr";
+const FETCH_MACS: &str =
+    "hmac-sha2-256-etm@openssh.com,hmac-sha2-512-etm@openssh.com,hmac-sha2-256,hmac-sha2-512";
+
+const SSH_MSG_DISCONNECT: u8 = 1;
+const SSH_MSG_IGNORE: u8 = 2;
+const SSH_MSG_DEBUG: u8 = 4;
+const SSH_MSG_KEXINIT: u8 = 20;
+const SSH_MSG_KEX_ECDH_INIT: u8 = 30;
+const SSH_MSG_KEX_ECDH_REPLY: u8 = 31;
+
+#[derive(thiserror::Error, Debug)]
+pub enum CertificateError {
+    #[error("Certificate \"{key_id}\" expired at {at}")]
+    Expired { key_id: String, at: String },
+    #[error("Certificate \"{key_id}\" is only valid from {at}")]
+    NotYetValid { key_id: String, at: String },
+    #[error("Certificate \"{key_id}\" isn't valid for \"{principal}\", only for: {principals}")]
+    WrongPrincipal {
+        key_id: String,
+        principal: String,
+        principals: String,
+    },
+    #[error("Certificate \"{key_id}\" is a {actual} certificate, not a {expected} certificate")]
+    WrongType {
+        key_id: String,
+        actual: &'static str,
+        expected: &'static str,
+    },
+    #[error("Certificate \"{key_id}\" doesn't belong to the {for_key}")]
+    KeyMismatch {
+        key_id: String,
+        for_key: &'static str,
+    },
+    #[error(
+        "Certificate \"{key_id}\" is signed by CA {fingerprint}, which isn't trusted for this host"
+    )]
+    UntrustedCa { key_id: String, fingerprint: String },
+    #[error("Certificate \"{key_id}\" has an invalid signature: {reason}")]
+    InvalidSignature { key_id: String, reason: String },
+}
+
+fn type_name(cert_type: CertType) -> &'static str {
+    if cert_type == CertType::Host {
+        "host"
+    } else {
+        "user"
+    }
+}
+
+fn format_time(unix_time: u64) -> String {
+    match Local.timestamp_opt(unix_time as i64, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S %Z").to_string(),
+        None => unix_time.to_string(),
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+/// Checks the type, validity window and principals of `cert`, but not its signature.
+/// A certificate without principals is valid for any principal.
+pub fn check_certificate(
+    cert: &Certificate,
+    cert_type: CertType,
+    principal: &str,
+    now: u64,
+) -> Result<(), CertificateError> {
+    let key_id = cert.key_id().to_string();
+    if cert.cert_type() != cert_type {
+        return Err(CertificateError::WrongType {
+            key_id,
+            actual: type_name(cert.cert_type()),
+            expected: type_name(cert_type),
+        });
+    }
+    if now < cert.valid_after() {
+        return Err(CertificateError::NotYetValid {
+            key_id,
+            at: format_time(cert.valid_after()),
+        });
+    }
+    if now >= cert.valid_before() {
+        return Err(CertificateError::Expired {
+            key_id,
+            at: format_time(cert.valid_before()),
+        });
+    }
+    let principals = cert.valid_principals();
+    if !principals.is_empty() && !principals.iter().any(|valid| valid == principal) {
+        return Err(CertificateError::WrongPrincipal {
+            key_id,
+            principal: principal.to_string(),
+            principals: principals.join(", "),
+        });
+    }
+    Ok(())
+}
+
+/// A CA trusted to sign host certificates for the hosts matching `hosts`.
+#[derive(Clone, Debug)]
+pub struct HostAuthority {
+    /// known_hosts style patterns, e.g. "*.example.com" or "!bastion.example.com".
+    pub hosts: Vec<String>,
+    pub key: PublicKey,
+}
+
+impl HostAuthority {
+    /// Parses a CA public key line like "ssh-ed25519 AAAA... comment".
+    pub fn parse(public_key: &str, hosts: &str) -> anyhow::Result<Self> {
+        let mut parts = public_key.split_whitespace();
+        let key = match (parts.next(), parts.next()) {
+            (Some(_), Some(key)) => key,
+            (Some(key), None) => key,
+            _ => anyhow::bail!("Empty CA public key"),
+        };
+        let key = match parse_public_key_base64(key) {
+            Ok(key) => key,
+            Err(e) => anyhow::bail!("Invalid CA public key: {}", e),
+        };
+        let hosts = match hosts.trim() {
+            "" => vec!["*".to_string()],
+            hosts => hosts.split(',').map(str::to_string).collect(),
+        };
+        Ok(Self { hosts, key })
+    }
+
+    fn matches(&self, host: &str, port: u16) -> bool {
+        // Like ssh, hosts on other ports than 22 are matched as "[host]:port"
+        let host = if port == 22 {
+            host.to_lowercase()
+        } else {
+            format!("[{}]:{}", host.to_lowercase(), port)
+        };
+        let mut matched = false;
+        for pattern in &self.hosts {
+            let pattern = pattern.to_lowercase();
+            match pattern.strip_prefix('!') {
+                Some(pattern) if wildcard_match(pattern, &host) => return false,
+                Some(_) => {}
+                None => matched |= wildcard_match(&pattern, &host),
+            }
+        }
+        matched
+    }
+}
+
+/// Reads the `@cert-authority` lines of a known_hosts file. A missing file has none.
+pub fn load_known_hosts_authorities(path: &Path) -> anyhow::Result<Vec<HostAuthority>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => anyhow::bail!("Failed to read {}: {}", path.display(), e),
+    };
+    let mut authorities = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let Some(line) = line.trim().strip_prefix("@cert-authority") else {
+            continue;
+        };
+        let Some((hosts, key)) = line.trim().split_once(char::is_whitespace) else {
+            anyhow::bail!(
+                "Invalid @cert-authority line {} in {}",
+                index + 1,
+                path.display()
+            );
+        };
+        match HostAuthority::parse(key, hosts) {
+            Ok(authority) => authorities.push(authority),
+            Err(e) => anyhow::bail!("Line {} in {}: {}", index + 1, path.display(), e),
+        }
+    }
+    Ok(authorities)
+}
+
+/// The known_hosts file ssh uses by default.
+pub fn default_known_hosts() -> PathBuf {
+    home_dir().join(".ssh").join("known_hosts")
+}
+
+/// Verifies that `cert` certifies the `host_key` the server presented: it has to be a host certificate
+/// for `host`, be currently valid and be signed by one of the `authorities` trusted for `host`.
+pub fn verify_host_certificate(
+    cert: &Certificate,
+    host_key: &PublicKey,
+    authorities: &[HostAuthority],
+    host: &str,
+    port: u16,
+) -> Result<(), CertificateError> {
+    let key_id = cert.key_id().to_string();
+    if cert.public_key() != host_key.key_data() {
+        return Err(CertificateError::KeyMismatch {
+            key_id,
+            for_key: "host key the server presented",
+        });
+    }
+    let now = unix_now();
+    check_certificate(cert, CertType::Host, host, now)?;
+
+    let ca_fingerprint = cert.signature_key().fingerprint(HashAlg::Sha256);
+    if !authorities.iter().any(|authority| {
+        authority.key.key_data() == cert.signature_key() && authority.matches(host, port)
+    }) {
+        return Err(CertificateError::UntrustedCa {
+            key_id,
+            fingerprint: ca_fingerprint.to_string(),
+        });
+    }
+    // Checks the signature, the window was already checked with clearer errors
+    if let Err(e) = cert.validate_at(now, [&ca_fingerprint]) {
+        return Err(CertificateError::InvalidSignature {
+            key_id,
+            reason: e.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Checks that the user certificate `cert` belongs to `key`, is currently valid and lists `user`.
+pub fn check_user_certificate(
+    cert: &Certificate,
+    key: &KeyData,
+    user: &str,
+) -> Result<(), CertificateError> {
+    if cert.public_key() != key {
+        return Err(CertificateError::KeyMismatch {
+            key_id: cert.key_id().to_string(),
+            for_key: "private key",
+        });
+    }
+    check_certificate(cert, CertType::User, user, unix_now())
+}
+
+/// Path ssh looks for the certificate of a private key at, e.g. `id_ed25519-cert.pub`.
+pub fn certificate_path(key_file_path: &Path) -> PathBuf {
+    let mut path = key_file_path.as_os_str().to_owned();
+    path.push("-cert.pub");
+    PathBuf::from(path)
+}
+
+pub fn load_certificate(path: &Path) -> anyhow::Result<Certificate> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => anyhow::bail!("Failed to read certificate {}: {}", path.display(), e),
+    };
+    parse_certificate(&content)
+}
+
+pub fn parse_certificate(certificate: &str) -> anyhow::Result<Certificate> {
+    match Certificate::from_openssh(certificate.trim()) {
+        Ok(certificate) => Ok(certificate),
+        Err(e) => anyhow::bail!("Invalid certificate: {}", e),
+    }
+}
+
+/// Certificate host key algorithms for the type of `host_key`.
+fn certificate_algorithms(host_key: &PublicKey) -> anyhow::Result<&'static str> {
+    match host_key.algorithm().as_str() {
+        "ssh-ed25519" => Ok("ssh-ed25519-cert-v01@openssh.com"),
+        "ecdsa-sha2-nistp256" => Ok("ecdsa-sha2-nistp256-cert-v01@openssh.com"),
+        "ecdsa-sha2-nistp384" => Ok("ecdsa-sha2-nistp384-cert-v01@openssh.com"),
+        "ecdsa-sha2-nistp521" => Ok("ecdsa-sha2-nistp521-cert-v01@openssh.com"),
+        "ssh-rsa" => Ok("rsa-sha2-512-cert-v01@openssh.com,rsa-sha2-256-cert-v01@openssh.com"),
+        algorithm => anyhow::bail!("No host certificates for {} keys", algorithm),
+    }
+}
+
+/// Fetches the server's host certificate for the type of `host_key`, like `ssh-keyscan -c` does:
+/// a key exchange offering only certificate host key algorithms is started and aborted once the server
+/// presented its certificate. The certificate is public, so nothing is verified here.
+pub async fn fetch_host_certificate(
+    ip: &str,
+    port: u16,
+    host_key: &PublicKey,
+) -> anyhow::Result<Certificate> {
+    let algorithms = certificate_algorithms(host_key)?;
+    match future::timeout(
+        FETCH_TIMEOUT,
+        request_host_certificate(ip, port, algorithms),
+    )
+    .await
+    {
+        Ok(result) => result,
+        Err(_) => anyhow::bail!("Timed out fetching the host certificate"),
+    }
+}
+
+async fn request_host_certificate(
+    ip: &str,
+    port: u16,
+    algorithms: &str,
+) -> anyhow::Result<Certificate> {
+    let mut stream = TcpStream::connect((ip, port)).await?;
+    stream.write_all(b"SSH-2.0-DreamdeckSSH\r\n").await?;
+    read_identification(&mut stream).await?;
+
+    let mut kexinit = vec![SSH_MSG_KEXINIT];
+    // The cookie only needs to be random for a real key exchange
+    kexinit.extend_from_slice(&[0u8; 16]);
+    for name_list in [
+        FETCH_KEX,
+        algorithms,
+        FETCH_CIPHERS,
+        FETCH_CIPHERS,
+        FETCH_MACS,
+        FETCH_MACS,
+        "none",
+        "none",
+        "",
+        "",
+    ] {
+        put_string(&mut kexinit, name_list.as_bytes());
+    }
+    // No guessed kex packet follows and the reserved field
+    kexinit.push(0);
+    kexinit.extend_from_slice(&0u32.to_be_bytes());
+    write_packet(&mut stream, &kexinit).await?;
+
+    loop {
+        let payload = read_packet(&mut stream).await?;
+        let Some((&message_type, mut body)) = payload.split_first() else {
+            anyhow::bail!("Empty packet from server");
+        };
+        match message_type {
+            SSH_MSG_IGNORE | SSH_MSG_DEBUG => continue,
+            SSH_MSG_KEXINIT => {
+                // Any point works, as the key exchange is never finished. 9 is the curve25519 base point
+                let mut ecdh_init = vec![SSH_MSG_KEX_ECDH_INIT];
+                let mut public_key = [0u8; 32];
+                public_key[0] = 9;
+                put_string(&mut ecdh_init, &public_key);
+                write_packet(&mut stream, &ecdh_init).await?;
+            }
+            SSH_MSG_KEX_ECDH_REPLY => {
+                let blob = take_string(&mut body)?;
+                let mut blob_reader = &blob[..];
+                let algorithm =
+                    String::from_utf8_lossy(&take_string(&mut blob_reader)?).into_owned();
+                let _ = stream.shutdown().await;
+                return parse_certificate(&format!("{} {}", algorithm, STANDARD.encode(&blob)));
+            }
+            SSH_MSG_DISCONNECT => {
+                // Usually because there is no certificate for the offered algorithms
+                let description = take_u32(&mut body)
+                    .and_then(|_reason_code| take_string(&mut body))
+                    .unwrap_or_default();
+                anyhow::bail!(
+                    "Server didn't present a host certificate: {}",
+                    String::from_utf8_lossy(&description)
+                );
+            }
+            other => anyhow::bail!(
+                "Unexpected message {} while fetching the host certificate",
+                other
+            ),
+        }
+    }
+}
+
+/// Reads the server's identification string, skipping the lines servers may send before it.
+async fn read_identification(stream: &mut TcpStream) -> anyhow::Result<()> {
+    for _ in 0..32 {
+        let mut line = Vec::new();
+        loop {
+            let byte = stream.read_u8().await?;
+            if byte == b'\n' {
+                break;
+            }
+            if line.len() >= 255 {
+                anyhow::bail!("Server identification too long");
+            }
+            line.push(byte);
+        }
+        if line.starts_with(b"SSH-") {
+            return Ok(());
+        }
+    }
+    anyhow::bail!("Server didn't identify itself as SSH server")
+}
+
+/// Writes an unencrypted packet, as used before the first key exchange finished.
+async fn write_packet(stream: &mut TcpStream, payload: &[u8]) -> anyhow::Result<()> {
+    // Packets are padded to a multiple of 8 with at least 4 bytes
+    let mut padding = 8 - (payload.len() + 5) % 8;
+    if padding < 4 {
+        padding += 8;
+    }
+    let mut packet = Vec::with_capacity(payload.len() + padding + 5);
+    packet.extend_from_slice(&((payload.len() + padding + 1) as u32).to_be_bytes());
+    packet.push(padding as u8);
+    packet.extend_from_slice(payload);
+    packet.resize(packet.len() + padding, 0);
+    stream.write_all(&packet).await?;
+    Ok(())
+}
+
+/// Reads an unencrypted packet and returns its payload.
+async fn read_packet(stream: &mut TcpStream) -> anyhow::Result<Vec<u8>> {
+    let length = stream.read_u32().await? as usize;
+    if length == 0 || length > MAX_PACKET_LENGTH {
+        anyhow::bail!("Invalid packet length {}", length);
+    }
+    let mut packet = vec![0u8; length];
+    stream.read_exact(&mut packet).await?;
+    let padding = packet[0] as usize;
+    if padding + 1 > length {
+        anyhow::bail!("Invalid packet padding");
+    }
+    Ok(packet[1..length - padding].to_vec())
+}
+
+/// Fetches the host certificate of the server at `ip` and verifies that it certifies the `host_key` it
+/// presented, see [verify_host_certificate]. Returns the certificate's key id.
+pub async fn check_host_certificate(
+    host_key: &PublicKey,
+    authorities: &[HostAuthority],
+    ip: &str,
+    port: u16,
+) -> anyhow::Result<String> {
+    let cert = fetch_host_certificate(ip, port, host_key).await?;
+    verify_host_certificate(&cert, host_key, authorities, ip, port)?;
+    Ok(cert.key_id().to_string())
+}
+
+impl InternalSSHClient {
+    /// The known_hosts file whose `@cert-authority` lines apply to the current server check.
+    fn known_hosts_path(&self) -> Option<PathBuf> {
+        match &self.server_check {
+            ServerCheckMethod::DefaultKnownHostsFile => Some(default_known_hosts()),
+            ServerCheckMethod::KnownHostsFile(path) => Some(PathBuf::from(path)),
+            _ => None,
+        }
+    }
+
+    /// CAs trusted to sign host certificates: the configured `host_authorities`
+    /// and the `@cert-authority` lines of the known hosts.
+    pub fn trusted_host_authorities(&self) -> anyhow::Result<Vec<HostAuthority>> {
+        let mut authorities = self.host_authorities.clone();
+        if let Some(path) = self.known_hosts_path() {
+            authorities.extend(load_known_hosts_authorities(&path)?);
+        }
+        Ok(authorities)
+    }
+
+    /// Accepts the host key the server presented at the last connection attempt from now on,
+    /// if `certificate` certifies it and is signed by a CA trusted for `ip`, see [verify_host_certificate].
+    /// Trusted are the configured `host_authorities` and the `@cert-authority` lines of the known hosts.
+    pub fn attest_host_key(
+        &mut self,
+        certificate: &str,
+        ip: &String,
+        port: u16,
+    ) -> anyhow::Result<()> {
+        let cert = parse_certificate(certificate)?;
+        let host_key = match self.host_key.lock() {
+            Ok(host_key) => host_key.as_ref().map(|host_key| host_key.key.clone()),
+            Err(_) => None,
+        };
+        let Some(host_key) = host_key else {
+            anyhow::bail!("The server didn't present a host key yet, try to connect first");
+        };
+
+        let authorities = self.trusted_host_authorities()?;
+        if authorities.is_empty() {
+            anyhow::bail!("No host certificate authorities configured");
+        }
+        verify_host_certificate(&cert, &host_key, &authorities, ip, port)?;
+
+        if !self.attested_host_keys.contains(&host_key) {
+            self.attested_host_keys.push(host_key.clone());
+        }
+        self.log.info(LogEvent::Connect, ip, port, || {
+            format!(
+                "Host key {} is certified by \"{}\"",
+                host_key.fingerprint(HashAlg::Sha256),
+                cert.key_id()
+            )
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Generated with ssh-keygen, all signed by CA_KEY
+    const CA_KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIGflUbM0wpOhgy3fZgUy8J5FqUkAilyB9IWto9Y8xh5v";
+    const HOST_KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIIrgZOA4B2PQSuHZqiMJzdOjAaFCOnkB56BKaQt+M4W8";
+    const USER_KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIGKsU6Vi/nWhGmM7/B0F/j8wTQ1kpeJ7b8XchoOKt0Nv";
+    /// Host certificate of HOST_KEY for "web.example.com" and "web", valid during 2024 (UTC).
+    const HOST_CERT_2024: &str = "ssh-ed25519-cert-v01@openssh.com AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIDX7po3Dv60oUoOAn5kaZ7ajF0LzT6dMpZPrxvzZTl0oAAAAIIrgZOA4B2PQSuHZqiMJzdOjAaFCOnkB56BKaQt+M4W8AAAAAAAAAAAAAAACAAAAA3dlYgAAABoAAAAPd2ViLmV4YW1wbGUuY29tAAAAA3dlYgAAAABlkgCAAAAAAGd0hYAAAAAAAAAAAAAAAAAAAAAzAAAAC3NzaC1lZDI1NTE5AAAAIGflUbM0wpOhgy3fZgUy8J5FqUkAilyB9IWto9Y8xh5vAAAAUwAAAAtzc2gtZWQyNTUxOQAAAECt/gVIeiVGYlvoPc8dWSa+Go8lcNwfo9Ac3bDL7yxtQw4wfC2/pwwrcScUaYUNe8mE7uZrlh3s//ZnTSAFQmUN";
+    /// Host certificate of HOST_KEY for "web.example.com", valid until 2100.
+    const HOST_CERT: &str = "ssh-ed25519-cert-v01@openssh.com AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIMw9M697d1w/XHqgORcYdlzb/jyGbhBdoOtVfZWYyDVjAAAAIIrgZOA4B2PQSuHZqiMJzdOjAaFCOnkB56BKaQt+M4W8AAAAAAAAAAAAAAACAAAACHdlYi0yMTAwAAAAEwAAAA93ZWIuZXhhbXBsZS5jb20AAAAAXgvhAAAAAAD0hlcAAAAAAAAAAAAAAAAAAAAAMwAAAAtzc2gtZWQyNTUxOQAAACBn5VGzNMKToYMt32YFMvCeRalJAIpcgfSFraPWPMYebwAAAFMAAAALc3NoLWVkMjU1MTkAAABALAQI/RO8uGip0H5xoMkNjKA2LzoWDRBWXFB950pnfqWe+foBy3FphqXsRMxiP5Jou7L138nwMLQqV2OAqJVDAQ==";
+    /// User certificate of USER_KEY for "alice" and "deploy", valid until 2100.
+    const USER_CERT: &str = "ssh-ed25519-cert-v01@openssh.com AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIKwnKN9K6LbC0/LYK+n5+Ia2FbsdvBBHElPT3M7HymRdAAAAIGKsU6Vi/nWhGmM7/B0F/j8wTQ1kpeJ7b8XchoOKt0NvAAAAAAAAAAAAAAABAAAABWFsaWNlAAAAEwAAAAVhbGljZQAAAAZkZXBsb3kAAAAAXgvhAAAAAAD0hlcAAAAAAAAAAIIAAAAVcGVybWl0LVgxMS1mb3J3YXJkaW5nAAAAAAAAABdwZXJtaXQtYWdlbnQtZm9yd2FyZGluZwAAAAAAAAAWcGVybWl0LXBvcnQtZm9yd2FyZGluZwAAAAAAAAAKcGVybWl0LXB0eQAAAAAAAAAOcGVybWl0LXVzZXItcmMAAAAAAAAAAAAAADMAAAALc3NoLWVkMjU1MTkAAAAgZ+VRszTCk6GDLd9mBTLwnkWpSQCKXIH0ha2j1jzGHm8AAABTAAAAC3NzaC1lZDI1NTE5AAAAQKKWyy6UbCYvmQzAL+yPmlEWGgzKYCQ/5w+xen/uvQ+4x9OhIlODqpeyTpSq10iL0zFjm1MhS6j7Zw65P/W7tgo=";
+    /// User certificate of USER_KEY without principals, valid until 2100.
+    const USER_CERT_ANY: &str = "ssh-ed25519-cert-v01@openssh.com AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIN/rj8iq+3ELVkc25VhT21eQMcsccyNyHTPsxy2RJGdIAAAAIGKsU6Vi/nWhGmM7/B0F/j8wTQ1kpeJ7b8XchoOKt0NvAAAAAAAAAAAAAAABAAAABmFueW9uZQAAAAAAAAAAXgvhAAAAAAD0hlcAAAAAAAAAAIIAAAAVcGVybWl0LVgxMS1mb3J3YXJkaW5nAAAAAAAAABdwZXJtaXQtYWdlbnQtZm9yd2FyZGluZwAAAAAAAAAWcGVybWl0LXBvcnQtZm9yd2FyZGluZwAAAAAAAAAKcGVybWl0LXB0eQAAAAAAAAAOcGVybWl0LXVzZXItcmMAAAAAAAAAAAAAADMAAAALc3NoLWVkMjU1MTkAAAAgZ+VRszTCk6GDLd9mBTLwnkWpSQCKXIH0ha2j1jzGHm8AAABTAAAAC3NzaC1lZDI1NTE5AAAAQKez9f8ucKG7qZC92Q8FvPKZQ0qgd9fZylungnvkmMoZvCK6h/wM9etTBHWLlUK8tt/zIasoLcaJfg1RHYHzfAg=";
+
+    const VALID_AFTER: u64 = 1704067200;
+    const VALID_BEFORE: u64 = 1735689600;
+
+    fn cert(certificate: &str) -> Certificate {
+        parse_certificate(certificate).unwrap()
+    }
+
+    fn key(public_key: &str) -> PublicKey {
+        PublicKey::from_openssh(public_key).unwrap()
+    }
+
+    fn authority(hosts: &str) -> HostAuthority {
+        HostAuthority::parse(CA_KEY, hosts).unwrap()
+    }
+
+    #[test]
+    fn valid_within_window() {
+        let cert = cert(HOST_CERT_2024);
+        assert!(check_certificate(&cert, CertType::Host, "web", VALID_AFTER).is_ok());
+        assert!(
+            check_certificate(&cert, CertType::Host, "web.example.com", VALID_BEFORE - 1).is_ok()
+        );
+    }
+
+    #[test]
+    fn not_yet_valid_and_expired() {
+        let cert = cert(HOST_CERT_2024);
+        assert!(matches!(
+            check_certificate(&cert, CertType::Host, "web", VALID_AFTER - 1),
+            Err(CertificateError::NotYetValid { .. })
+        ));
+        assert!(matches!(
+            check_certificate(&cert, CertType::Host, "web", VALID_BEFORE),
+            Err(CertificateError::Expired { .. })
+        ));
+    }
+
+    #[test]
+    fn wrong_type() {
+        let error = check_certificate(
+            &cert(HOST_CERT),
+            CertType::User,
+            "web.example.com",
+            unix_now(),
+        );
+        assert!(matches!(
+            error,
+            Err(CertificateError::WrongType {
+                actual: "host",
+                expected: "user",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn wrong_principal() {
+        let error = check_certificate(&cert(HOST_CERT_2024), CertType::Host, "db", VALID_AFTER);
+        match error {
+            Err(CertificateError::WrongPrincipal {
+                principal,
+                principals,
+                ..
+            }) => {
+                assert_eq!(principal, "db");
+                assert_eq!(principals, "web.example.com, web");
+            }
+            other => panic!("Expected a wrong principal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn no_principals_are_valid_for_anyone() {
+        let cert = cert(USER_CERT_ANY);
+        assert!(check_certificate(&cert, CertType::User, "root", unix_now()).is_ok());
+    }
+
+    #[test]
+    fn user_certificate_of_other_key() {
+        let cert = cert(USER_CERT);
+        assert!(check_user_certificate(&cert, key(USER_KEY).key_data(), "deploy").is_ok());
+        assert!(matches!(
+            check_user_certificate(&cert, key(USER_KEY).key_data(), "bob"),
+            Err(CertificateError::WrongPrincipal { .. })
+        ));
+        assert!(matches!(
+            check_user_certificate(&cert, key(HOST_KEY).key_data(), "alice"),
+            Err(CertificateError::KeyMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn authorities_match_like_known_hosts() {
+        assert!(authority("").matches("anything", 22));
+        assert!(authority("*.example.com").matches("Web.Example.com", 22));
+        assert!(!authority("*.example.com").matches("web.example.com", 2222));
+        assert!(authority("[*.example.com]:2222").matches("web.example.com", 2222));
+        assert!(!authority("*.example.com,!bastion.example.com").matches("bastion.example.com", 22));
+        assert!(!authority("!bastion.example.com").matches("web.example.com", 22));
+    }
+
+    #[test]
+    fn host_certificate_is_verified() {
+        let cert = cert(HOST_CERT);
+        let host_key = key(HOST_KEY);
+        let trusted = [authority("*.example.com")];
+        assert!(verify_host_certificate(&cert, &host_key, &trusted, "web.example.com", 22).is_ok());
+        assert!(matches!(
+            verify_host_certificate(&cert, &host_key, &trusted, "web.example.com", 2222),
+            Err(CertificateError::UntrustedCa { .. })
+        ));
+        assert!(matches!(
+            verify_host_certificate(&cert, &key(USER_KEY), &trusted, "web.example.com", 22),
+            Err(CertificateError::KeyMismatch { .. })
+        ));
+        let other_ca = [HostAuthority::parse(USER_KEY, "*").unwrap()];
+        assert!(matches!(
+            verify_host_certificate(&cert, &host_key, &other_ca, "web.example.com", 22),
+            Err(CertificateError::UntrustedCa { .. })
+        ));
+    }
+
+    #[test]
+    fn tampered_certificate_is_rejected() {
+        // Another key id, but the signature over the original one
+        let (algorithm, blob) = HOST_CERT.split_once(' ').unwrap();
+        let mut blob = STANDARD.decode(blob).unwrap();
+        let key_id = blob.windows(8).position(|id| id == b"web-2100").unwrap();
+        blob[key_id] = b'W';
+        let cert = cert(&format!("{} {}", algorithm, STANDARD.encode(&blob)));
+        let trusted = [authority("*")];
+        assert!(matches!(
+            verify_host_certificate(&cert, &key(HOST_KEY), &trusted, "web.example.com", 22),
+            Err(CertificateError::InvalidSignature { .. })
+        ));
+    }
+}
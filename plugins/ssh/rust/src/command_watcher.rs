@@ -0,0 +1,203 @@
+use crate::capture::BinaryExecOutput;
+use godot::prelude::*;
+use regex::Regex;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+/// Longest delay until the next poll. Longer ones, e.g. 1e19 seconds, would overflow [Instant].
+pub const MAX_DELAY: Duration = Duration::from_secs(100 * 365 * 24 * 3600);
+
+/// What part of a command's result is watched.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatchMode {
+    ExitStatus,
+    Output,
+    /// Exit status and output, the value is an object with the keys `exit_status` and `output`.
+    Both,
+}
+
+impl WatchMode {
+    pub fn parse(mode: &str) -> anyhow::Result<Self> {
+        match mode {
+            "exit_status" => Ok(WatchMode::ExitStatus),
+            "output" => Ok(WatchMode::Output),
+            "both" => Ok(WatchMode::Both),
+            _ => anyhow::bail!("Unknown watch mode: {}", mode),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Clone)]
+enum Extraction {
+    Trimmed,
+    Regex(Regex),
+    JsonPath(Vec<PathSegment>),
+}
+
+/// Turns the result of a command into the watched value.
+#[derive(Clone)]
+pub struct Extractor {
+    mode: WatchMode,
+    extraction: Extraction,
+}
+
+impl Extractor {
+    /// Creates an extractor, `regex` takes precedence over `json_path` if both are set.
+    pub fn new(mode: WatchMode, regex: &str, json_path: &str) -> anyhow::Result<Self> {
+        let extraction = if !regex.is_empty() {
+            match Regex::new(regex) {
+                Ok(regex) => Extraction::Regex(regex),
+                Err(e) => anyhow::bail!("Invalid regex \"{}\": {}", regex, e),
+            }
+        } else if !json_path.is_empty() {
+            Extraction::JsonPath(parse_json_path(json_path)?)
+        } else {
+            Extraction::Trimmed
+        };
+        Ok(Self { mode, extraction })
+    }
+
+    pub fn extract(&self, output: &BinaryExecOutput) -> Value {
+        match self.mode {
+            WatchMode::ExitStatus => Value::from(output.exit_status),
+            WatchMode::Output => self.extract_output(&output.stdout),
+            WatchMode::Both => serde_json::json!({
+                "exit_status": output.exit_status,
+                "output": self.extract_output(&output.stdout),
+            }),
+        }
+    }
+
+    /// Output which doesn't match the regex or json path results in null.
+    fn extract_output(&self, stdout: &[u8]) -> Value {
+        let stdout = String::from_utf8_lossy(stdout);
+        match &self.extraction {
+            Extraction::Trimmed => Value::from(stdout.trim()),
+            Extraction::Regex(regex) => match regex.captures(&stdout) {
+                // The first group if the regex has one, otherwise the whole match
+                Some(captures) => match captures.get(1).or_else(|| captures.get(0)) {
+                    Some(found) => Value::from(found.as_str()),
+                    None => Value::Null,
+                },
+                None => Value::Null,
+            },
+            Extraction::JsonPath(path) => match serde_json::from_str::<Value>(&stdout) {
+                Ok(json) => lookup(&json, path).cloned().unwrap_or(Value::Null),
+                Err(_) => Value::Null,
+            },
+        }
+    }
+}
+
+/// Parses a path like `$.services[0].state`. The leading `$` is optional.
+fn parse_json_path(path: &str) -> anyhow::Result<Vec<PathSegment>> {
+    let mut segments = Vec::new();
+    let rest = path.strip_prefix('$').unwrap_or(path);
+    for part in rest.split('.') {
+        let (key, mut indices) = match part.find('[') {
+            Some(start) => (&part[..start], &part[start..]),
+            None => (part, ""),
+        };
+        if !key.is_empty() {
+            segments.push(PathSegment::Key(key.to_string()));
+        }
+        while !indices.is_empty() {
+            let Some(end) = indices.find(']') else {
+                anyhow::bail!("Invalid json path \"{}\": missing ]", path);
+            };
+            match indices[1..end].parse::<usize>() {
+                Ok(index) => segments.push(PathSegment::Index(index)),
+                Err(_) => anyhow::bail!(
+                    "Invalid json path \"{}\": {} is no index",
+                    path,
+                    &indices[1..end]
+                ),
+            }
+            indices = &indices[end + 1..];
+            if !indices.is_empty() && !indices.starts_with('[') {
+                anyhow::bail!("Invalid json path \"{}\"", path);
+            }
+        }
+    }
+    Ok(segments)
+}
+
+fn lookup<'a>(json: &'a Value, path: &[PathSegment]) -> Option<&'a Value> {
+    path.iter().try_fold(json, |value, segment| match segment {
+        PathSegment::Key(key) => value.get(key),
+        PathSegment::Index(index) => value.get(index),
+    })
+}
+
+/// Delay until the next poll after `failures` consecutive failures.
+/// Doubles with every failure, but is never longer than `max_backoff`.
+pub fn backoff_delay(interval: Duration, max_backoff: Duration, failures: u32) -> Duration {
+    if failures == 0 {
+        return interval;
+    }
+    interval
+        .saturating_mul(2u32.saturating_pow(failures.min(16)))
+        .min(max_backoff.max(interval))
+}
+
+/// When a poll `delay` from now is due. Delays are capped at [MAX_DELAY].
+pub fn due_after(delay: Duration) -> Instant {
+    Instant::now() + delay.min(MAX_DELAY)
+}
+
+pub fn json_to_variant(value: &Value) -> Variant {
+    match value {
+        Value::Null => Variant::nil(),
+        Value::Bool(value) => Variant::from(*value),
+        Value::Number(number) => match number.as_i64() {
+            Some(number) => Variant::from(number),
+            None => Variant::from(number.as_f64().unwrap_or_default()),
+        },
+        Value::String(value) => Variant::from(value.clone()),
+        Value::Array(values) => {
+            let mut array: Array<Variant> = Array::new();
+            for value in values {
+                array.push(&json_to_variant(value));
+            }
+            Variant::from(array)
+        }
+        Value::Object(values) => {
+            let mut dict: Dictionary<GString, Variant> = Dictionary::new();
+            for (key, value) in values {
+                dict.set(key.as_str(), &json_to_variant(value));
+            }
+            Variant::from(dict)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let interval = Duration::from_secs(5);
+        let max_backoff = Duration::from_secs(60);
+        assert_eq!(backoff_delay(interval, max_backoff, 0), interval);
+        assert_eq!(
+            backoff_delay(interval, max_backoff, 2),
+            Duration::from_secs(20)
+        );
+        assert_eq!(backoff_delay(interval, max_backoff, 10), max_backoff);
+        assert_eq!(backoff_delay(interval, Duration::ZERO, 3), interval);
+    }
+
+    #[test]
+    fn huge_delays_are_capped() {
+        let delay = Duration::try_from_secs_f64(1e19).unwrap();
+        assert!(due_after(delay) <= Instant::now() + MAX_DELAY);
+        assert!(due_after(backoff_delay(delay, delay, 40)) > Instant::now());
+    }
+}
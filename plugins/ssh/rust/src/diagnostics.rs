@@ -0,0 +1,467 @@
+use crate::internal_ssh_client::{client_config, InternalSSHClient, ServerCheckMethod};
+use async_std::future;
+use async_std::task::block_on;
+use godot::prelude::*;
+use russh::client::AuthResult;
+use russh::keys::HashAlg;
+use russh::MethodKind;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// Timeout of every network operation while diagnosing.
+const DIAGNOSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Banner sent to the server when inspecting its key exchange offer.
+const DIAGNOSE_BANNER: &str = "SSH-2.0-DreamDeck_diagnose\r\n";
+const SSH_MSG_KEXINIT: u8 = 20;
+/// Output of the test command.
+const EXEC_MARKER: &str = "dreamdeck_diagnose";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StepStatus {
+    Ok,
+    Failed,
+    /// Not run, because a previous step failed.
+    Skipped,
+}
+
+impl StepStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StepStatus::Ok => "ok",
+            StepStatus::Failed => "failed",
+            StepStatus::Skipped => "skipped",
+        }
+    }
+}
+
+type Details = Vec<(&'static str, String)>;
+
+/// Result of a single stage of [InternalSSHClient::diagnose].
+pub struct DiagnosticStep {
+    pub name: &'static str,
+    pub status: StepStatus,
+    pub duration_ms: u64,
+    pub message: String,
+    pub details: Details,
+}
+
+impl DiagnosticStep {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        let mut details: Dictionary<GString, Variant> = Dictionary::new();
+        for (key, value) in &self.details {
+            details.set(*key, &Variant::from(value.clone()));
+        }
+        dict! {
+            "step" => self.name.to_string(),
+            "status" => self.status.as_str().to_string(),
+            "duration_ms" => self.duration_ms as i64,
+            "message" => self.message.clone(),
+            "details" => details,
+        }
+    }
+}
+
+/// Runs the steps of a diagnosis, skipping all steps after the first failure.
+#[derive(Default)]
+struct Diagnosis {
+    steps: Vec<DiagnosticStep>,
+    failed: bool,
+}
+
+impl Diagnosis {
+    fn run<T>(
+        &mut self,
+        name: &'static str,
+        step: impl FnOnce() -> anyhow::Result<(T, String, Details)>,
+    ) -> Option<T> {
+        if self.failed {
+            self.steps.push(DiagnosticStep {
+                name,
+                status: StepStatus::Skipped,
+                duration_ms: 0,
+                message: String::new(),
+                details: Vec::new(),
+            });
+            return None;
+        }
+        let started = Instant::now();
+        let result = step();
+        let duration_ms = started.elapsed().as_millis() as u64;
+        match result {
+            Ok((value, message, details)) => {
+                self.steps.push(DiagnosticStep {
+                    name,
+                    status: StepStatus::Ok,
+                    duration_ms,
+                    message,
+                    details,
+                });
+                Some(value)
+            }
+            Err(e) => {
+                self.failed = true;
+                self.steps.push(DiagnosticStep {
+                    name,
+                    status: StepStatus::Failed,
+                    duration_ms,
+                    message: e.to_string(),
+                    details: Vec::new(),
+                });
+                None
+            }
+        }
+    }
+}
+
+impl InternalSSHClient {
+    /// Runs every stage of connecting to the server separately and reports where it breaks:
+    /// DNS resolution, TCP connect, SSH banner, algorithm negotiation, host key check,
+    /// offered auth methods, authentication and channel open with a test command.
+    ///
+    /// A separate session is used, so an already open session is kept as is.
+    pub fn diagnose(&mut self, ip: &String, user: &String, port: u16) -> Vec<DiagnosticStep> {
+        let mut diagnosis = Diagnosis::default();
+
+        let addrs = diagnosis.run("dns", || resolve(ip, port));
+        let stream = diagnosis.run("tcp", || connect_tcp(&addrs.unwrap_or_default()));
+        let reader = diagnosis.run("banner", || read_banner(stream));
+        diagnosis.run("algorithms", || negotiate_algorithms(reader));
+
+        // Use a separate session for the remaining steps and restore the current one afterwards
+        let previous_session = self.session.take();
+        let previous_remote_info = self.remote_info.take();
+
+        let connected = diagnosis.run("host_key", || self.diagnose_host_key(ip, port));
+        diagnosis.run("auth_methods", || {
+            self.diagnose_auth_methods(user, connected.unwrap_or_default())
+        });
+        diagnosis.run("auth", || {
+            block_on(self.authenticate(user))?;
+            Ok(((), format!("Authenticated as {}", user), Vec::new()))
+        });
+        diagnosis.run("exec", || self.diagnose_exec(ip, user, port));
+
+        // Not disconnect_session, which would reset the uptime of the restored session
+        if let Err(e) = block_on(self.close_session()) {
+            godot_warn!("Failed to close diagnostic session: {}", e);
+        }
+        self.session = previous_session;
+        self.remote_info = previous_remote_info;
+
+        diagnosis.steps
+    }
+
+    /// Connects to the server and checks its host key, leaving the unauthenticated session open.
+    fn diagnose_host_key(
+        &mut self,
+        ip: &String,
+        port: u16,
+    ) -> anyhow::Result<(bool, String, Details)> {
+        if let Ok(mut host_key) = self.host_key.lock() {
+            *host_key = None;
+        }
+        let connect = russh::client::connect(
+            client_config(),
+            (ip.clone(), port),
+            self.new_handler(ip, port),
+        );
+        let result = block_on(future::timeout(DIAGNOSE_TIMEOUT, connect));
+
+        let mut details: Details = vec![("check_method", check_method_name(&self.server_check))];
+        let accepted = match self.host_key.lock() {
+            Ok(host_key) => match host_key.as_ref() {
+                Some(host_key) => {
+                    details.push(("key_type", host_key.key.algorithm().as_str().to_string()));
+                    details.push((
+                        "fingerprint",
+                        host_key.key.fingerprint(HashAlg::Sha256).to_string(),
+                    ));
+                    Some(host_key.accepted)
+                }
+                None => None,
+            },
+            Err(_) => None,
+        };
+        match (result, accepted) {
+            (Ok(Ok(session)), _) => {
+                self.session = Some(session);
+                Ok((
+                    true,
+                    format!("Host key accepted ({})", details[0].1),
+                    details,
+                ))
+            }
+            (_, Some(false)) => anyhow::bail!(
+                "Host key {} was rejected ({})",
+                details.last().map(|d| d.1.as_str()).unwrap_or_default(),
+                details[0].1
+            ),
+            (Ok(Err(e)), _) => anyhow::bail!("SSH handshake failed: {}", e),
+            (Err(_), _) => anyhow::bail!("Timed out during SSH handshake"),
+        }
+    }
+
+    fn diagnose_auth_methods(
+        &mut self,
+        user: &String,
+        connected: bool,
+    ) -> anyhow::Result<((), String, Details)> {
+        let Some(session) = self.session.as_mut().filter(|_| connected) else {
+            anyhow::bail!("No session active");
+        };
+        match block_on(session.authenticate_none(user))? {
+            AuthResult::Success => Ok((
+                (),
+                "Server accepted login without any credentials".to_string(),
+                Vec::new(),
+            )),
+            AuthResult::Failure {
+                remaining_methods, ..
+            } => {
+                let methods: Vec<&str> = remaining_methods.iter().map(method_name).collect();
+                Ok((
+                    (),
+                    format!("Server offers: {}", methods.join(", ")),
+                    vec![("methods", methods.join(","))],
+                ))
+            }
+        }
+    }
+
+    fn diagnose_exec(
+        &mut self,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<((), String, Details)> {
+        let started = Instant::now();
+        let output = self.exec_ssh_output(format!("echo {}", EXEC_MARKER), ip, user, port)?;
+        let round_trip = started.elapsed().as_millis();
+        let details = vec![
+            ("exit_status", output.exit_status.to_string()),
+            ("round_trip_ms", round_trip.to_string()),
+        ];
+        if !output.stdout.contains(EXEC_MARKER) {
+            anyhow::bail!(
+                "Test command returned unexpected output (exit status {}): {}",
+                output.exit_status,
+                output.stderr.trim()
+            );
+        }
+        Ok((
+            (),
+            format!("Test command ran in {} ms", round_trip),
+            details,
+        ))
+    }
+}
+
+fn resolve(ip: &str, port: u16) -> anyhow::Result<(Vec<SocketAddr>, String, Details)> {
+    let addrs: Vec<SocketAddr> = match (ip, port).to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(e) => anyhow::bail!("Failed to resolve {}: {}", ip, e),
+    };
+    if addrs.is_empty() {
+        anyhow::bail!("{} didn't resolve to any address", ip);
+    }
+    let list = addrs
+        .iter()
+        .map(|addr| addr.ip().to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    Ok((
+        addrs,
+        format!("Resolved to {}", list),
+        vec![("addresses", list)],
+    ))
+}
+
+fn connect_tcp(addrs: &[SocketAddr]) -> anyhow::Result<(TcpStream, String, Details)> {
+    let mut errors: Vec<String> = Vec::new();
+    for addr in addrs {
+        let started = Instant::now();
+        match TcpStream::connect_timeout(addr, DIAGNOSE_TIMEOUT) {
+            Ok(stream) => {
+                let latency = started.elapsed().as_millis();
+                return Ok((
+                    stream,
+                    format!("Connected to {} in {} ms", addr, latency),
+                    vec![
+                        ("address", addr.to_string()),
+                        ("latency_ms", latency.to_string()),
+                    ],
+                ));
+            }
+            Err(e) => errors.push(format!("{}: {}", addr, e)),
+        }
+    }
+    anyhow::bail!("Failed to connect: {}", errors.join(", "))
+}
+
+/// Reads the server's identification string. Servers may send other lines before it.
+fn read_banner(
+    stream: Option<TcpStream>,
+) -> anyhow::Result<(BufReader<TcpStream>, String, Details)> {
+    let Some(stream) = stream else {
+        anyhow::bail!("No connection");
+    };
+    stream.set_read_timeout(Some(DIAGNOSE_TIMEOUT))?;
+    stream.set_write_timeout(Some(DIAGNOSE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    for _ in 0..20 {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => anyhow::bail!("Server closed the connection without sending a banner"),
+            Ok(_) => (),
+            Err(e) => anyhow::bail!("Failed to read SSH banner: {}", e),
+        }
+        let line = line.trim_end();
+        let Some(version) = line.strip_prefix("SSH-") else {
+            continue;
+        };
+        let (protocol, software) = version.split_once('-').unwrap_or((version, ""));
+        if protocol != "2.0" && protocol != "1.99" {
+            anyhow::bail!("Server only supports SSH protocol {}", protocol);
+        }
+        let software = software.to_string();
+        return Ok((
+            reader,
+            format!("Server runs {}", software),
+            vec![
+                ("banner", line.to_string()),
+                ("protocol", protocol.to_string()),
+                ("software", software),
+            ],
+        ));
+    }
+    anyhow::bail!("No SSH banner received, is this an SSH server?")
+}
+
+/// Reads the server's key exchange offer and compares it with the algorithms this client supports.
+fn negotiate_algorithms(
+    reader: Option<BufReader<TcpStream>>,
+) -> anyhow::Result<((), String, Details)> {
+    let Some(mut reader) = reader else {
+        anyhow::bail!("No connection");
+    };
+    reader.get_mut().write_all(DIAGNOSE_BANNER.as_bytes())?;
+    let offer = read_kexinit(&mut reader)?;
+
+    let preferred = russh::Preferred::default();
+    let kex = negotiate(preferred.kex.iter().map(|name| name.as_ref()), &offer[0]);
+    let host_key = negotiate(preferred.key.iter().map(|algo| algo.as_str()), &offer[1]);
+    let cipher = negotiate(preferred.cipher.iter().map(|name| name.as_ref()), &offer[2]);
+    let mac = match &cipher {
+        // AEAD ciphers don't use a separate MAC
+        Some(cipher) if cipher.contains("poly1305") || cipher.contains("gcm") => {
+            Some("implicit".to_string())
+        }
+        _ => negotiate(preferred.mac.iter().map(|name| name.as_ref()), &offer[4]),
+    };
+
+    let mut details: Details = vec![
+        ("server_kex", offer[0].join(",")),
+        ("server_host_key", offer[1].join(",")),
+        ("server_cipher", offer[2].join(",")),
+        ("server_mac", offer[4].join(",")),
+    ];
+    let mut missing: Vec<&str> = Vec::new();
+    for (name, negotiated) in [
+        ("kex", kex),
+        ("host_key", host_key),
+        ("cipher", cipher),
+        ("mac", mac),
+    ] {
+        match negotiated {
+            Some(negotiated) => details.push((name, negotiated)),
+            None => missing.push(name),
+        }
+    }
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "No common algorithm for {}, server offers: {}",
+            missing.join(", "),
+            details
+                .iter()
+                .filter(|(key, _)| missing.iter().any(|m| key.ends_with(m)))
+                .map(|(key, value)| format!("{}: {}", key, value))
+                .collect::<Vec<String>>()
+                .join("; ")
+        );
+    }
+    let summary = details[4..]
+        .iter()
+        .map(|(_, value)| value.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ");
+    Ok(((), format!("Negotiated {}", summary), details))
+}
+
+/// Reads the unencrypted KEXINIT packet and returns its first ten name-lists.
+fn read_kexinit(reader: &mut BufReader<TcpStream>) -> anyhow::Result<Vec<Vec<String>>> {
+    let mut length = [0u8; 4];
+    reader.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if !(5..=256 * 1024).contains(&length) {
+        anyhow::bail!("Invalid packet length {} in key exchange", length);
+    }
+    let mut packet = vec![0u8; length];
+    reader.read_exact(&mut packet)?;
+    let padding = packet[0] as usize;
+    if padding + 1 >= length {
+        anyhow::bail!("Invalid padding in key exchange packet");
+    }
+    let payload = &packet[1..length - padding];
+    if payload[0] != SSH_MSG_KEXINIT {
+        anyhow::bail!("Expected key exchange init, got message {}", payload[0]);
+    }
+
+    // Skip message id and 16 byte cookie
+    let mut rest = payload.get(17..).unwrap_or_default();
+    let mut lists: Vec<Vec<String>> = Vec::new();
+    for _ in 0..10 {
+        if rest.len() < 4 {
+            anyhow::bail!("Truncated key exchange packet");
+        }
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let Some(list) = rest.get(4..4 + len) else {
+            anyhow::bail!("Truncated key exchange packet");
+        };
+        lists.push(
+            String::from_utf8_lossy(list)
+                .split(',')
+                .filter(|name| !name.is_empty())
+                .map(|name| name.to_string())
+                .collect(),
+        );
+        rest = &rest[4 + len..];
+    }
+    Ok(lists)
+}
+
+/// Picks the first algorithm of the client which the server supports, like SSH does.
+fn negotiate<'a>(mut ours: impl Iterator<Item = &'a str>, theirs: &[String]) -> Option<String> {
+    ours.find(|ours| theirs.iter().any(|theirs| theirs == ours))
+        .map(|name| name.to_string())
+}
+
+fn method_name(method: &MethodKind) -> &'static str {
+    match method {
+        MethodKind::None => "none",
+        MethodKind::Password => "password",
+        MethodKind::PublicKey => "publickey",
+        MethodKind::HostBased => "hostbased",
+        MethodKind::KeyboardInteractive => "keyboard-interactive",
+    }
+}
+
+fn check_method_name(method: &ServerCheckMethod) -> String {
+    match method {
+        ServerCheckMethod::NoCheck => "no check".to_string(),
+        ServerCheckMethod::DefaultKnownHostsFile => "default known hosts file".to_string(),
+        ServerCheckMethod::PublicKey(_) => "public key".to_string(),
+        ServerCheckMethod::PublicKeyFile(path) => format!("public key file {}", path),
+        ServerCheckMethod::KnownHostsFile(path) => format!("known hosts file {}", path),
+    }
+}
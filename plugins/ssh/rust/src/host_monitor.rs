@@ -0,0 +1,320 @@
+use async_std::future;
+use async_std::io::prelude::BufReadExt;
+use async_std::io::BufReader;
+use async_std::net::TcpStream;
+use async_std::task;
+use chrono::Local;
+use godot::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy)]
+pub struct MonitorConfig {
+    pub interval: Duration,
+    /// Timeout of a single probe.
+    pub timeout: Duration,
+    /// Consecutive failed probes after which a host is considered down.
+    pub failure_threshold: u32,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(3),
+            failure_threshold: 2,
+        }
+    }
+}
+
+/// Current health of a monitored host.
+#[derive(Clone, Default)]
+pub struct HostStatus {
+    /// None until the host was probed successfully or failed `failure_threshold` times.
+    pub up: Option<bool>,
+    /// Time until the TCP connection was established.
+    pub connect_ms: Option<f64>,
+    /// Time until the SSH banner was received.
+    pub rtt_ms: Option<f64>,
+    pub consecutive_failures: u32,
+    pub banner: String,
+    pub last_error: String,
+    /// RFC 3339 timestamp of the last probe.
+    pub last_probe: String,
+}
+
+impl HostStatus {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        dict! {
+            "up" => match self.up {
+                Some(up) => Variant::from(up),
+                None => Variant::nil(),
+            },
+            "connect_ms" => match self.connect_ms {
+                Some(connect_ms) => Variant::from(connect_ms),
+                None => Variant::nil(),
+            },
+            "rtt_ms" => match self.rtt_ms {
+                Some(rtt_ms) => Variant::from(rtt_ms),
+                None => Variant::nil(),
+            },
+            "consecutive_failures" => self.consecutive_failures as i64,
+            "banner" => self.banner.clone(),
+            "last_error" => self.last_error.clone(),
+            "last_probe" => self.last_probe.clone(),
+        }
+    }
+}
+
+/// A host went up or down.
+pub struct StateChange {
+    pub host_id: String,
+    pub status: HostStatus,
+}
+
+#[derive(Clone)]
+struct Target {
+    ip: String,
+    port: u16,
+}
+
+#[derive(Default)]
+struct MonitorState {
+    config: MonitorConfig,
+    targets: HashMap<String, Target>,
+    statuses: HashMap<String, HostStatus>,
+    changes: Vec<StateChange>,
+    running: bool,
+    /// Incremented on every start, so a loop of a previous start stops even if restarted in between.
+    generation: u64,
+}
+
+/// Probes hosts on an interval by connecting and waiting for the SSH banner.
+///
+/// Probing runs on background tasks, state changes are collected until [HostMonitor::take_changes] is called.
+pub struct HostMonitor {
+    state: Arc<Mutex<MonitorState>>,
+}
+
+impl Default for HostMonitor {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MonitorState::default())),
+        }
+    }
+}
+
+impl HostMonitor {
+    /// Adds or updates a host. Its status is reset if the address changed.
+    pub fn set_host(&self, host_id: &str, ip: &str, port: u16) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let target = Target {
+            ip: ip.to_string(),
+            port,
+        };
+        let changed = state
+            .targets
+            .get(host_id)
+            .is_none_or(|old| old.ip != target.ip || old.port != target.port);
+        if changed {
+            state
+                .statuses
+                .insert(host_id.to_string(), HostStatus::default());
+        }
+        state.targets.insert(host_id.to_string(), target);
+    }
+
+    pub fn remove_host(&self, host_id: &str) -> bool {
+        let Ok(mut state) = self.state.lock() else {
+            return false;
+        };
+        state.statuses.remove(host_id);
+        state.targets.remove(host_id).is_some()
+    }
+
+    pub fn status(&self, host_id: &str) -> Option<HostStatus> {
+        self.state.lock().ok()?.statuses.get(host_id).cloned()
+    }
+
+    pub fn statuses(&self) -> Vec<(String, HostStatus)> {
+        match self.state.lock() {
+            Ok(state) => state
+                .statuses
+                .iter()
+                .map(|(host_id, status)| (host_id.clone(), status.clone()))
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn take_changes(&self) -> Vec<StateChange> {
+        match self.state.lock() {
+            Ok(mut state) => std::mem::take(&mut state.changes),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.lock().is_ok_and(|state| state.running)
+    }
+
+    /// Starts probing with `config`. If already running, the new config applies from the next round.
+    pub fn start(&self, config: MonitorConfig) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        state.config = config;
+        if state.running {
+            return;
+        }
+        state.running = true;
+        state.generation += 1;
+        let generation = state.generation;
+        drop(state);
+
+        task::spawn(run(self.state.clone(), generation));
+    }
+
+    pub fn stop(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.running = false;
+        }
+    }
+}
+
+impl Drop for HostMonitor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+async fn run(state: Arc<Mutex<MonitorState>>, generation: u64) {
+    loop {
+        let (targets, config) = match state.lock() {
+            Ok(state) if state.running && state.generation == generation => {
+                (state.targets.clone(), state.config)
+            }
+            _ => return,
+        };
+
+        // Probe all hosts concurrently
+        let probes: Vec<_> = targets
+            .into_iter()
+            .map(|(host_id, target)| {
+                task::spawn(async move {
+                    let result = probe(&target, config.timeout).await;
+                    (host_id, target, result)
+                })
+            })
+            .collect();
+        let mut results = Vec::new();
+        for probe in probes {
+            results.push(probe.await);
+        }
+
+        match state.lock() {
+            Ok(mut state) if state.running && state.generation == generation => {
+                for (host_id, target, result) in results {
+                    update_status(&mut state, host_id, &target, result, config);
+                }
+            }
+            _ => return,
+        }
+
+        task::sleep(config.interval).await;
+    }
+}
+
+struct ProbeResult {
+    connect_ms: f64,
+    rtt_ms: f64,
+    banner: String,
+}
+
+fn update_status(
+    state: &mut MonitorState,
+    host_id: String,
+    target: &Target,
+    result: Result<ProbeResult, String>,
+    config: MonitorConfig,
+) {
+    // Skip results of hosts which were removed or changed while probing
+    let current = state.targets.get(&host_id);
+    if current.is_none_or(|current| current.ip != target.ip || current.port != target.port) {
+        return;
+    }
+    let status = state.statuses.entry(host_id.clone()).or_default();
+    status.last_probe = Local::now().to_rfc3339();
+    let was_up = status.up;
+    match result {
+        Ok(result) => {
+            status.connect_ms = Some(result.connect_ms);
+            status.rtt_ms = Some(result.rtt_ms);
+            status.banner = result.banner;
+            status.consecutive_failures = 0;
+            status.last_error.clear();
+            status.up = Some(true);
+        }
+        Err(error) => {
+            status.connect_ms = None;
+            status.rtt_ms = None;
+            status.consecutive_failures += 1;
+            status.last_error = error;
+            if status.consecutive_failures >= config.failure_threshold.max(1) {
+                status.up = Some(false);
+            }
+        }
+    }
+    if status.up != was_up && status.up.is_some() {
+        let status = status.clone();
+        state.changes.push(StateChange { host_id, status });
+    }
+}
+
+/// Connects to the host and waits for its SSH identification string.
+async fn probe(target: &Target, timeout: Duration) -> Result<ProbeResult, String> {
+    let started = Instant::now();
+    let stream = match future::timeout(
+        timeout,
+        TcpStream::connect((target.ip.as_str(), target.port)),
+    )
+    .await
+    {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return Err(format!("Failed to connect: {}", e)),
+        Err(_) => return Err("Timed out connecting".to_string()),
+    };
+    let connect_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let remaining = timeout.saturating_sub(started.elapsed());
+    let banner = match future::timeout(remaining, read_banner(stream)).await {
+        Ok(Ok(banner)) => banner,
+        Ok(Err(e)) => return Err(e),
+        Err(_) => return Err("Timed out waiting for SSH banner".to_string()),
+    };
+    Ok(ProbeResult {
+        connect_ms,
+        rtt_ms: started.elapsed().as_secs_f64() * 1000.0,
+        banner,
+    })
+}
+
+/// Reads lines until the SSH identification string. Servers may send other lines before it.
+async fn read_banner(stream: TcpStream) -> Result<String, String> {
+    let mut reader = BufReader::new(stream);
+    for _ in 0..20 {
+        let mut line = String::new();
+        match reader.read_line(&mut line).await {
+            Ok(0) => return Err("Connection closed before SSH banner".to_string()),
+            Ok(_) => (),
+            Err(e) => return Err(format!("Failed to read SSH banner: {}", e)),
+        }
+        if line.starts_with("SSH-") {
+            return Ok(line.trim_end().to_string());
+        }
+    }
+    Err("No SSH banner received".to_string())
+}
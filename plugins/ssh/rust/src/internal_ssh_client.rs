@@ -1,18 +1,32 @@
+use crate::agent::{serve_agent_channel, AgentForwarding};
+use crate::audit_log::{AuditTarget, ExecRecord};
+use crate::capture::{signal_name, BinaryExecOutput, CaptureBuffer, CaptureLimits};
+use crate::certificate::{
+    check_host_certificate, check_user_certificate, load_certificate, HostAuthority,
+};
+use crate::key_utils::decode_private_key;
+use crate::logger::{LogEvent, LogLevel, Logger};
+use crate::power::PreConnect;
+use crate::remote_info::RemoteInfo;
+use crate::session_stats::{CountingStream, SessionStats};
+use crate::ssh_client::emit_deferred;
+use crate::ssh_vault::SSHVault;
 use anyhow::anyhow;
 use async_std::future;
+use async_std::task;
 use async_std::task::block_on;
-use chrono::Local;
 use client::Msg;
 use godot::prelude::*;
-use keys::ssh_key::private::{Ed25519Keypair, KeypairData, RsaKeypair};
-use keys::{PrivateKey, PrivateKeyWithHashAlg};
+use keys::PrivateKeyWithHashAlg;
 use russh::client::Handle;
-use russh::keys::key::safe_rng;
 use russh::*;
+use std::fmt;
 use std::io;
 use std::path::PathBuf;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
 
 #[derive(thiserror::Error, Debug)]
 #[non_exhaustive]
@@ -40,39 +54,179 @@ pub enum ServerCheckMethod {
     KnownHostsFile(String),
 }
 
-#[derive(Clone, PartialEq)]
+/// How long a keepalive waits for the server's reply.
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A password, passphrase or key that is wiped from memory when dropped.
+pub type Secret = Zeroizing<String>;
+
+/// Where a secret of an [AuthMethod] comes from.
+pub enum SecretSource {
+    /// Set directly, it's wiped after the first successful authentication.
+    Value(Secret),
+    /// Read from the vault whenever the client authenticates, so it's only held while
+    /// authenticating.
+    Vault { vault: Gd<SSHVault>, id: String },
+    /// A value that was wiped after authentication. It has to be set again to authenticate again.
+    Wiped,
+}
+
+impl SecretSource {
+    pub fn get(&self) -> anyhow::Result<Secret> {
+        match self {
+            SecretSource::Value(secret) => Ok(secret.clone()),
+            SecretSource::Vault { vault, id } => vault.bind().vault()?.get_secret_string(id),
+            SecretSource::Wiped => {
+                anyhow::bail!("The credentials were wiped after authentication, set them again")
+            }
+        }
+    }
+}
+
+/// How the client authenticates.
+///
+/// Secrets set directly are wiped after authentication, secrets in a vault are read again for every
+/// authentication. Decrypted keys only exist while authenticating.
 #[allow(dead_code)]
 pub enum AuthMethod {
     None,
-    Password(String),
+    Password(SecretSource),
     PrivateKey {
         /// entire contents of private key file
-        key_data: String,
-        key_pass: Option<String>,
+        key_data: SecretSource,
+        key_pass: Option<SecretSource>,
     },
     PrivateKeyFile {
         key_file_path: PathBuf,
-        key_pass: Option<String>,
+        key_pass: Option<SecretSource>,
     },
     PublicKeyFile {
         key_file_path: PathBuf,
     },
+    /// A private key file with an OpenSSH user certificate signed by a CA.
+    Certificate {
+        key_file_path: PathBuf,
+        cert_file_path: PathBuf,
+        key_pass: Option<SecretSource>,
+    },
+}
+
+impl AuthMethod {
+    fn secrets_mut(&mut self) -> Vec<&mut SecretSource> {
+        match self {
+            AuthMethod::Password(password) => vec![password],
+            AuthMethod::PrivateKey { key_data, key_pass } => {
+                std::iter::once(key_data).chain(key_pass).collect()
+            }
+            AuthMethod::PrivateKeyFile { key_pass, .. }
+            | AuthMethod::Certificate { key_pass, .. } => key_pass.iter_mut().collect(),
+            AuthMethod::None | AuthMethod::PublicKeyFile { .. } => Vec::new(),
+        }
+    }
+
+    /// Wipes the secrets that were set directly, the ones in a vault are read again when needed.
+    pub fn wipe_secrets(&mut self) {
+        for secret in self.secrets_mut() {
+            if let SecretSource::Value(_) = secret {
+                *secret = SecretSource::Wiped;
+            }
+        }
+    }
+
+    /// Whether a secret was wiped after authentication, so it can't authenticate again.
+    pub fn is_wiped(&self) -> bool {
+        let wiped = |secret: &Option<SecretSource>| matches!(secret, Some(SecretSource::Wiped));
+        match self {
+            AuthMethod::Password(password) => matches!(password, SecretSource::Wiped),
+            AuthMethod::PrivateKey { key_data, key_pass } => {
+                matches!(key_data, SecretSource::Wiped) || wiped(key_pass)
+            }
+            AuthMethod::PrivateKeyFile { key_pass, .. }
+            | AuthMethod::Certificate { key_pass, .. } => wiped(key_pass),
+            AuthMethod::None | AuthMethod::PublicKeyFile { .. } => false,
+        }
+    }
 }
 
+/// Never print any secrets.
+impl fmt::Debug for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthMethod::None => write!(f, "None"),
+            AuthMethod::Password(_) => write!(f, "Password(<redacted>)"),
+            AuthMethod::PrivateKey { key_pass, .. } => write!(
+                f,
+                "PrivateKey {{ key_data: <redacted>, key_pass: {} }}",
+                if key_pass.is_some() {
+                    "<redacted>"
+                } else {
+                    "None"
+                }
+            ),
+            AuthMethod::PrivateKeyFile {
+                key_file_path,
+                key_pass,
+            } => write!(
+                f,
+                "PrivateKeyFile {{ key_file_path: {:?}, key_pass: {} }}",
+                key_file_path,
+                if key_pass.is_some() {
+                    "<redacted>"
+                } else {
+                    "None"
+                }
+            ),
+            AuthMethod::PublicKeyFile { key_file_path } => {
+                write!(f, "PublicKeyFile {{ key_file_path: {:?} }}", key_file_path)
+            }
+            AuthMethod::Certificate {
+                key_file_path,
+                cert_file_path,
+                key_pass,
+            } => write!(
+                f,
+                "Certificate {{ key_file_path: {:?}, cert_file_path: {:?}, key_pass: {} }}",
+                key_file_path,
+                cert_file_path,
+                if key_pass.is_some() {
+                    "<redacted>"
+                } else {
+                    "None"
+                }
+            ),
+        }
+    }
+}
+
+/// Host key presented by the server of the last connection attempt and whether it was accepted.
+pub struct HostKeyCheck {
+    pub key: russh::keys::PublicKey,
+    pub accepted: bool,
+}
+
+pub type HostKeySlot = Arc<Mutex<Option<HostKeyCheck>>>;
+
 pub struct Client {
-    debug: bool,
+    log: Logger,
     ip: String,
     port: u16,
     server_check: ServerCheckMethod,
+    /// Host keys certified by a trusted CA, accepted regardless of `server_check`.
+    attested_host_keys: Vec<russh::keys::PublicKey>,
+    /// CAs whose host certificates are checked for keys `server_check` doesn't accept.
+    host_authorities: Vec<HostAuthority>,
+    host_key: HostKeySlot,
+    agent: AgentForwarding,
 }
 
-impl client::Handler for Client {
-    type Error = SSHError;
-
-    async fn check_server_key(
-        &mut self,
+impl Client {
+    fn verify_server_key(
+        &self,
         server_public_key: &russh::keys::PublicKey,
-    ) -> Result<bool, Self::Error> {
+    ) -> Result<bool, SSHError> {
+        if self.attested_host_keys.contains(server_public_key) {
+            return Ok(true);
+        }
         match &self.server_check {
             ServerCheckMethod::NoCheck => Ok(true),
             ServerCheckMethod::PublicKey(key) => {
@@ -106,6 +260,44 @@ impl client::Handler for Client {
             }
         }
     }
+}
+
+impl client::Handler for Client {
+    type Error = SSHError;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &russh::keys::PublicKey,
+    ) -> Result<bool, Self::Error> {
+        let mut result = self.verify_server_key(server_public_key);
+        if !matches!(result, Ok(true)) && !self.host_authorities.is_empty() {
+            match check_host_certificate(
+                server_public_key,
+                &self.host_authorities,
+                &self.ip,
+                self.port,
+            )
+            .await
+            {
+                Ok(key_id) => {
+                    self.log.info(LogEvent::Connect, &self.ip, self.port, || {
+                        format!("Host key is certified by \"{}\"", key_id)
+                    });
+                    result = Ok(true);
+                }
+                Err(e) => self.log.warn(LogEvent::Connect, &self.ip, self.port, || {
+                    format!("Host key isn't certified: {}", e)
+                }),
+            }
+        }
+        if let Ok(mut host_key) = self.host_key.lock() {
+            *host_key = Some(HostKeyCheck {
+                key: server_public_key.clone(),
+                accepted: matches!(result, Ok(true)),
+            });
+        }
+        result
+    }
 
     async fn data(
         &mut self,
@@ -113,16 +305,14 @@ impl client::Handler for Client {
         data: &[u8],
         _session: &mut client::Session,
     ) -> Result<(), Self::Error> {
-        if self.debug {
-            godot_print!(
-                "{}: SSH STDOUT on {}:{}:{:?}: {}",
-                Local::now().format("%Y-%m-%dT%H:%M:%S"),
-                self.ip,
-                self.port,
-                channel,
-                String::from_utf8_lossy(data)
-            );
-        }
+        self.log.log(
+            LogLevel::Trace,
+            LogEvent::Stdout,
+            &self.ip,
+            self.port,
+            Some(channel),
+            || String::from_utf8_lossy(data).into_owned(),
+        );
         Ok(())
     }
 
@@ -133,174 +323,423 @@ impl client::Handler for Client {
         data: &[u8],
         _session: &mut client::Session,
     ) -> Result<(), Self::Error> {
-        if ext == 1 && self.debug {
-            godot_print_rich!(
-                "[color=yellow]{}: SSH STDERR on {}:{}:{:?}: {}[color=white][/color]",
-                Local::now().format("%Y-%m-%dT%H:%M:%S"),
-                self.ip,
+        if ext == 1 {
+            self.log.log(
+                LogLevel::Trace,
+                LogEvent::Stderr,
+                &self.ip,
                 self.port,
-                channel,
-                String::from_utf8_lossy(data)
+                Some(channel),
+                || String::from_utf8_lossy(data).into_owned(),
             );
         }
         Ok(())
     }
 
+    async fn server_channel_open_agent_forward(
+        &mut self,
+        channel: Channel<Msg>,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        if self.agent.backend.is_none() {
+            self.log.warn(LogEvent::Channel, &self.ip, self.port, || {
+                "Server opened an agent channel without forwarding being enabled".to_string()
+            });
+            let _ = channel.close().await;
+            return Ok(());
+        }
+        self.log.debug(LogEvent::Channel, &self.ip, self.port, || {
+            "Forwarding agent".to_string()
+        });
+        // Answering may wait for a confirmation, which must not block the session
+        let (agent, log, ip, port) = (
+            self.agent.clone(),
+            self.log.clone(),
+            self.ip.clone(),
+            self.port,
+        );
+        thread::spawn(move || serve_agent_channel(channel, agent, log, ip, port));
+        Ok(())
+    }
+
     async fn exit_status(
         &mut self,
         channel: ChannelId,
         exit_status: u32,
         _session: &mut client::Session,
     ) -> Result<(), Self::Error> {
-        if self.debug {
-            let color = if exit_status != 0 { "red" } else { "white" };
-            godot_print_rich!(
-                "[color={}]{}: SSH on {}:{}:{:?}: exited with code {}[color=white][/color]",
-                color,
-                Local::now().format("%Y-%m-%dT%H:%M:%S"),
-                self.ip,
-                self.port,
-                channel,
-                exit_status
-            );
-        }
+        let level = if exit_status != 0 {
+            LogLevel::Warn
+        } else {
+            LogLevel::Debug
+        };
+        self.log.log(
+            level,
+            LogEvent::ExitStatus,
+            &self.ip,
+            self.port,
+            Some(channel),
+            || format!("Exited with code {}", exit_status),
+        );
+        Ok(())
+    }
+
+    async fn exit_signal(
+        &mut self,
+        channel: ChannelId,
+        signal: Sig,
+        core_dumped: bool,
+        error_message: &str,
+        _lang_tag: &str,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        self.log.log(
+            LogLevel::Warn,
+            LogEvent::ExitStatus,
+            &self.ip,
+            self.port,
+            Some(channel),
+            || {
+                format!(
+                    "Terminated by signal {}{}: {}",
+                    signal_name(&signal),
+                    if core_dumped { " (core dumped)" } else { "" },
+                    error_message
+                )
+            },
+        );
         Ok(())
     }
 }
 
+/// Collected output of a finished command.
+pub struct ExecOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// -1 if the server didn't send an exit status.
+    pub exit_status: i64,
+}
+
+impl ExecOutput {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        dict! {
+            "stdout" => self.stdout.clone(),
+            "stderr" => self.stderr.clone(),
+            "exit_status" => self.exit_status,
+        }
+    }
+}
+
+/// A command started by [InternalSSHClient::start_capture] whose output is yet to be collected.
+pub struct PendingCapture {
+    channel: Channel<Msg>,
+    record: Option<ExecRecord>,
+    stats: Arc<SessionStats>,
+    limits: CaptureLimits,
+    timeout: Option<Duration>,
+    started: Instant,
+}
+
+impl PendingCapture {
+    /// Waits for the command to finish, collecting its output.
+    pub fn wait(mut self) -> anyhow::Result<BinaryExecOutput> {
+        let result = self.collect_output();
+        if let Some(record) = self.record {
+            match &result {
+                Ok(output) => record.finish(
+                    (output.exit_status != -1).then_some(output.exit_status),
+                    output
+                        .exit_signal
+                        .as_ref()
+                        .map(|signal| format!("Terminated by signal {}", signal)),
+                ),
+                Err(e) => record.finish(None, Some(e.to_string())),
+            }
+        }
+        result
+    }
+
+    fn collect_output(&mut self) -> anyhow::Result<BinaryExecOutput> {
+        let channel = &mut self.channel;
+        let record = &mut self.record;
+        let mut stdout = CaptureBuffer::new(self.limits.max_stdout);
+        let mut stderr = CaptureBuffer::new(self.limits.max_stderr);
+        let mut exit_status = -1;
+        let mut exit_signal = None;
+        let mut core_dumped = false;
+        let mut signal_message = String::new();
+        // A timeout too long for an Instant, e.g. 1e19 seconds, never expires
+        let deadline = self
+            .timeout
+            .and_then(|timeout| self.started.checked_add(timeout));
+        loop {
+            let msg = match deadline {
+                Some(deadline) => match block_on(future::timeout(
+                    deadline.saturating_duration_since(Instant::now()),
+                    channel.wait(),
+                )) {
+                    Ok(msg) => msg,
+                    Err(_) => {
+                        let _ = block_on(channel.signal(Sig::TERM));
+                        let _ = block_on(channel.close());
+                        anyhow::bail!(
+                            "Command timed out after {}s",
+                            self.timeout.unwrap_or_default().as_secs_f64()
+                        );
+                    }
+                },
+                None => block_on(channel.wait()),
+            };
+            match msg {
+                Some(msg) => match msg {
+                    ChannelMsg::Data { data } => {
+                        if let Some(record) = record.as_mut() {
+                            record.stdout(&data);
+                        }
+                        stdout.push(&data);
+                    }
+                    ChannelMsg::ExtendedData { ext, data } => {
+                        if ext == 1 {
+                            if let Some(record) = record.as_mut() {
+                                record.stderr(&data);
+                            }
+                            stderr.push(&data);
+                        }
+                    }
+                    ChannelMsg::ExitStatus {
+                        exit_status: new_exit_status,
+                    } => exit_status = new_exit_status as i64,
+                    ChannelMsg::ExitSignal {
+                        signal_name: signal,
+                        core_dumped: new_core_dumped,
+                        error_message,
+                        ..
+                    } => {
+                        exit_signal = Some(signal_name(&signal));
+                        core_dumped = new_core_dumped;
+                        signal_message = error_message;
+                    }
+                    _ => (),
+                },
+                None => break,
+            }
+        }
+        self.stats.command_finished(self.started.elapsed());
+
+        Ok(BinaryExecOutput {
+            stdout_size: stdout.total(),
+            stderr_size: stderr.total(),
+            stdout_truncated: stdout.truncated(),
+            stderr_truncated: stderr.truncated(),
+            stdout: stdout.into_bytes(),
+            stderr: stderr.into_bytes(),
+            exit_status,
+            exit_signal,
+            core_dumped,
+            signal_message,
+        })
+    }
+}
+
+/// Access to a client for work made of several commands, e.g. a script.
+///
+/// Implemented by [InternalSSHClient] and by `ClientLease`, which only binds its `SSHClient` while a command
+/// is started, so the client stays usable by others while the work waits for output.
+pub trait ClientAccess {
+    /// Calls `f` with the client.
+    fn with_client<R>(
+        &mut self,
+        f: impl FnOnce(&mut InternalSSHClient) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R>;
+
+    /// Like [InternalSSHClient::exec_ssh_timed], but the client is only needed to start the command.
+    fn exec_timed(
+        &mut self,
+        cmd: String,
+        limits: CaptureLimits,
+        timeout: Option<Duration>,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<BinaryExecOutput> {
+        self.with_client(|client| client.start_capture(cmd, limits, timeout, ip, user, port))?
+            .wait()
+    }
+
+    /// Like [InternalSSHClient::exec_ssh_output], but the client is only needed to start the command.
+    fn exec_output(
+        &mut self,
+        cmd: String,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<ExecOutput> {
+        let output = self.exec_timed(cmd, CaptureLimits::unlimited(), None, ip, user, port)?;
+        Ok(ExecOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            exit_status: output.exit_status,
+        })
+    }
+}
+
+impl ClientAccess for InternalSSHClient {
+    fn with_client<R>(
+        &mut self,
+        f: impl FnOnce(&mut InternalSSHClient) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        f(self)
+    }
+}
+
 pub struct InternalSSHClient {
-    pub debug: bool,
+    pub log: Logger,
     pub session: Option<Handle<Client>>,
     pub auth_method: AuthMethod,
     pub server_check: ServerCheckMethod,
+    /// Cached info about the server of the current session.
+    pub remote_info: Option<RemoteInfo>,
+    /// Audit log every executed command is recorded in.
+    pub audit: Option<AuditTarget>,
+    /// Host key check of the last connection attempt.
+    pub host_key: HostKeySlot,
+    /// CAs trusted to sign host certificates, in addition to the `@cert-authority` lines of the known hosts.
+    pub host_authorities: Vec<HostAuthority>,
+    /// Host keys certified by a trusted CA, see [InternalSSHClient::attest_host_key].
+    pub attested_host_keys: Vec<russh::keys::PublicKey>,
+    /// Agent forwarding, see [crate::agent].
+    pub agent: AgentForwarding,
+    /// Password sudo is answered with when running elevated commands.
+    /// Kept for every elevated command until it's replaced or cleared, e.g. because sudo rejected it.
+    pub sudo_password: Option<Secret>,
+    /// Steps run before connecting, e.g. to wake the server.
+    pub pre_connect: PreConnect,
+    /// Traffic and channel statistics, see [crate::session_stats].
+    pub stats: Arc<SessionStats>,
 }
 
 impl InternalSSHClient {
-    pub fn add_key_to_server(
+    pub fn exec_ssh_blocking(
         &mut self,
-        password: String,
+        cmd: String,
         ip: &String,
         user: &String,
         port: u16,
-    ) -> anyhow::Result<()> {
-        let passphrase: Option<String>;
-
-        let mut private_key = match self.auth_method.clone() {
-            AuthMethod::PrivateKeyFile {
-                key_file_path,
-                key_pass,
-            } => {
-                passphrase = key_pass;
-                match PrivateKey::read_openssh_file(&key_file_path) {
-                    Ok(key_data) => key_data,
-                    Err(e) => {
-                        anyhow::bail!("Failed to read key at {}: {}", key_file_path.display(), e)
-                    }
-                }
-            }
-            AuthMethod::PrivateKey { key_data, key_pass } => {
-                passphrase = key_pass;
-                match PrivateKey::from_openssh(key_data) {
-                    Ok(private_key) => private_key,
-                    Err(e) => anyhow::bail!("Failed to parse private key: {}", e),
-                }
-            }
-            // Is only possible if self.auth_method is of type private key
-            _ => anyhow::bail!("Wrong auth method set"),
-        };
-        if private_key.is_encrypted() {
-            private_key = if let Some(passphrase) = passphrase {
-                match private_key.decrypt(passphrase) {
-                    Ok(private_key) => private_key,
-                    Err(e) => anyhow::bail!("Failed to decrypt private key: {}", e),
-                }
-            } else {
-                anyhow::bail!("Key is encrypted but no password provided.");
-            };
-        }
+    ) -> anyhow::Result<Dictionary<GString, Variant>> {
+        Ok(self.exec_ssh_output(cmd, ip, user, port)?.to_dict())
+    }
 
-        let pub_key = match private_key.public_key().to_openssh() {
-            Ok(pub_key) => pub_key,
-            Err(e) => anyhow::bail!("Failed to serialize public key: {}", e),
-        };
+    /// Execute `cmd` and wait for it to finish, collecting its output.
+    /// Output that isn't valid UTF-8 is converted lossily.
+    pub fn exec_ssh_output(
+        &mut self,
+        cmd: String,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<ExecOutput> {
+        let output = self.exec_ssh_bytes(cmd, CaptureLimits::unlimited(), ip, user, port)?;
+        Ok(ExecOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            exit_status: output.exit_status,
+        })
+    }
 
-        // Temporarily set auth method to password to allow login
-        let auth_method_store = self.auth_method.clone();
-        self.auth_method = AuthMethod::Password(password);
+    /// Execute `cmd` and wait for it to finish, collecting its output as raw bytes within `limits`.
+    /// The command is recorded in the audit log if one is set.
+    pub fn exec_ssh_bytes(
+        &mut self,
+        cmd: String,
+        limits: CaptureLimits,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<BinaryExecOutput> {
+        self.exec_ssh_timed(cmd, limits, None, ip, user, port)
+    }
 
-        if self.debug {
-            godot_print!("Copying public key to SSH server");
-        }
+    /// Like [InternalSSHClient::exec_ssh_bytes], but fails if the command doesn't finish within `timeout`.
+    /// A command which timed out is sent SIGTERM and its channel is closed.
+    pub fn exec_ssh_timed(
+        &mut self,
+        cmd: String,
+        limits: CaptureLimits,
+        timeout: Option<Duration>,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<BinaryExecOutput> {
+        self.start_capture(cmd, limits, timeout, ip, user, port)?
+            .wait()
+    }
 
-        block_on(self.disconnect_session())?;
-        block_on(self.open_session(ip, user, port))?;
-        // Adding the key via a ssh command
-        // This is kind of ugly but it is the best way I found that should work
-        // most reliably even if the server is windows.
-        // The mkdir will most likely fail because on most setups .ssh should
-        // already exist, but this only prints a error and still works.
-        self.exec_ssh_blocking("mkdir .ssh".to_string(), ip, user, port)?;
-        self.exec_ssh_blocking(
-            format!("echo {} >> .ssh/authorized_keys", pub_key),
+    /// Starts `cmd` for [InternalSSHClient::exec_ssh_timed]. Waiting for its output doesn't need the client,
+    /// so the client can be released while the command runs, see [ClientAccess].
+    pub fn start_capture(
+        &mut self,
+        cmd: String,
+        limits: CaptureLimits,
+        timeout: Option<Duration>,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<PendingCapture> {
+        let record = self
+            .audit
+            .as_ref()
+            .map(|target| ExecRecord::start(target, ip, port, user, &cmd));
+        let channel = match self.start_command(&cmd, ip, user, port) {
+            Ok(channel) => channel,
+            Err(e) => {
+                if let Some(record) = record {
+                    record.finish(None, Some(e.to_string()));
+                }
+                return Err(e);
+            }
+        };
+        self.stats.command_run();
+        self.log.log(
+            LogLevel::Debug,
+            LogEvent::Exec,
             ip,
-            user,
             port,
-        )?;
-
-        // Change back auth_method
-        self.auth_method = auth_method_store;
-
-        // A session may have been opened by the _exec_ssh call
-        block_on(self.disconnect_session())?;
-
-        Ok(())
+            Some(channel.id()),
+            || format!("Executing command: \"{}\"", cmd),
+        );
+        Ok(PendingCapture {
+            channel,
+            record,
+            stats: self.stats.clone(),
+            limits,
+            timeout,
+            started: Instant::now(),
+        })
     }
 
-    pub fn exec_ssh_blocking(
+    fn start_command(
         &mut self,
-        cmd: String,
+        cmd: &str,
         ip: &String,
         user: &String,
         port: u16,
-    ) -> anyhow::Result<Dictionary<GString, Variant>> {
-        let mut channel = block_on(self.open_channel(ip, user, port))?;
+    ) -> anyhow::Result<Channel<Msg>> {
+        let channel = block_on(self.open_channel(ip, user, port))?;
 
         // run cmd
-        if let Err(error) = block_on(channel.exec(false, cmd.clone())) {
+        if let Err(error) = block_on(channel.exec(false, cmd)) {
             anyhow::bail!(
                 "Couldn't execute command: \"{}\" on {:?}: {}",
                 cmd,
                 channel.id(),
                 error
             );
-        } else if self.debug {
-            godot_print!("Executing command: \"{}\" on {:?}", cmd, channel.id());
         }
-        let mut stdout = String::new();
-        let mut stderr = String::new();
-        let mut exit_status: i64 = -1;
-        loop {
-            let msg = block_on(channel.wait());
-            match msg {
-                Some(msg) => match msg {
-                    ChannelMsg::Data { data } => stdout.push_str(&String::from_utf8_lossy(&data)),
-                    ChannelMsg::ExtendedData { ext, data } => {
-                        if ext == 1 {
-                            stderr.push_str(&String::from_utf8_lossy(&data))
-                        }
-                    }
-                    ChannelMsg::ExitStatus {
-                        exit_status: new_exit_status,
-                    } => exit_status = new_exit_status as i64,
-                    _ => (),
-                },
-                None => break,
-            }
-        }
-
-        Ok(dict! {"stdout" => stdout, "stderr" => stderr, "exit_status" => exit_status})
+        Ok(channel)
     }
 
+    /// Execute `cmd` without waiting for it to finish. The command is waited for in the background to
+    /// record its latency. If an audit log is set, its output is recorded once it exits.
     pub async fn exec_ssh(
         &mut self,
         cmd: String,
@@ -308,33 +747,183 @@ impl InternalSSHClient {
         user: &String,
         port: u16,
     ) -> anyhow::Result<()> {
-        let channel = self.open_channel(ip, user, port).await?;
+        let record = self
+            .audit
+            .as_ref()
+            .map(|target| ExecRecord::start(target, ip, port, user, &cmd));
+        let mut channel = match self.open_channel(ip, user, port).await {
+            Ok(channel) => channel,
+            Err(e) => {
+                if let Some(record) = record {
+                    record.finish(None, Some(e.to_string()));
+                }
+                return Err(e);
+            }
+        };
 
         // run cmd
         if let Err(error) = channel.exec(false, cmd.clone()).await {
-            anyhow::bail!(
+            let error = anyhow!(
                 "Couldn't execute command: \"{}\" on {:?}: {}",
                 cmd,
                 channel.id(),
                 error
             );
-        } else if self.debug {
-            godot_print!("Executing command: \"{}\" on {:?}", cmd, channel.id());
+            if let Some(record) = record {
+                record.finish(None, Some(error.to_string()));
+            }
+            return Err(error);
         }
+        self.stats.command_run();
+        self.log.log(
+            LogLevel::Debug,
+            LogEvent::Exec,
+            ip,
+            port,
+            Some(channel.id()),
+            || format!("Executing command: \"{}\"", cmd),
+        );
+
+        // Waited for in the background to record the latency and the audit record
+        let stats = self.stats.clone();
+        let started = Instant::now();
+        task::spawn(async move {
+            let mut exit_status = None;
+            let mut error = None;
+            let mut record = record;
+            while let Some(msg) = channel.wait().await {
+                let Some(record) = record.as_mut() else {
+                    continue;
+                };
+                match msg {
+                    ChannelMsg::Data { data } => record.stdout(&data),
+                    ChannelMsg::ExtendedData { ext: 1, data } => record.stderr(&data),
+                    ChannelMsg::ExitStatus {
+                        exit_status: new_exit_status,
+                    } => exit_status = Some(new_exit_status as i64),
+                    ChannelMsg::ExitSignal {
+                        signal_name: signal,
+                        ..
+                    } => error = Some(format!("Terminated by signal {}", signal_name(&signal))),
+                    _ => (),
+                }
+            }
+            stats.command_finished(started.elapsed());
+            if let Some(record) = record {
+                record.finish(exit_status, error);
+            }
+        });
 
         Ok(())
     }
 
-    async fn open_channel(
+    /// Execute `cmd` and return its channel without reading from it, e.g. to stream the output of
+    /// a long running command. If an audit log is set, the returned record should be finished once
+    /// the channel closes.
+    pub fn exec_ssh_channel(
+        &mut self,
+        cmd: String,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<(Channel<Msg>, Option<ExecRecord>)> {
+        let record = self
+            .audit
+            .as_ref()
+            .map(|target| ExecRecord::start(target, ip, port, user, &cmd));
+        let result = block_on(async {
+            let channel = self.open_channel(ip, user, port).await?;
+            if let Err(error) = channel.exec(false, cmd.clone()).await {
+                anyhow::bail!(
+                    "Couldn't execute command: \"{}\" on {:?}: {}",
+                    cmd,
+                    channel.id(),
+                    error
+                );
+            }
+            Ok(channel)
+        });
+        match result {
+            Ok(channel) => {
+                self.stats.command_run();
+                self.log.log(
+                    LogLevel::Debug,
+                    LogEvent::Exec,
+                    ip,
+                    port,
+                    Some(channel.id()),
+                    || format!("Streaming command: \"{}\"", cmd),
+                );
+                Ok((channel, record))
+            }
+            Err(e) => {
+                if let Some(record) = record {
+                    record.finish(None, Some(e.to_string()));
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Opens an interactive login shell with a PTY of `cols` x `rows` and returns its channel.
+    /// The shell is audited as a single entry, see [crate::terminal::run_terminal].
+    pub fn exec_ssh_shell(
+        &mut self,
+        term: &str,
+        cols: u32,
+        rows: u32,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<(Channel<Msg>, Option<ExecRecord>)> {
+        let record = self
+            .audit
+            .as_ref()
+            .map(|target| ExecRecord::start(target, ip, port, user, "<interactive shell>"));
+        let result = block_on(async {
+            let channel = self.open_channel(ip, user, port).await?;
+            if let Err(error) = channel
+                .request_pty(false, term, cols, rows, 0, 0, &[])
+                .await
+            {
+                anyhow::bail!("Couldn't request PTY on {:?}: {}", channel.id(), error);
+            }
+            if let Err(error) = channel.request_shell(false).await {
+                anyhow::bail!("Couldn't start shell on {:?}: {}", channel.id(), error);
+            }
+            Ok(channel)
+        });
+        match result {
+            Ok(channel) => {
+                self.log.log(
+                    LogLevel::Debug,
+                    LogEvent::Exec,
+                    ip,
+                    port,
+                    Some(channel.id()),
+                    || format!("Opened interactive shell ({}x{})", cols, rows),
+                );
+                Ok((channel, record))
+            }
+            Err(e) => {
+                if let Some(record) = record {
+                    record.finish(None, Some(e.to_string()));
+                }
+                Err(e)
+            }
+        }
+    }
+
+    pub(crate) async fn open_channel(
         &mut self,
         ip: &String,
         user: &String,
         port: u16,
     ) -> anyhow::Result<Channel<Msg>> {
         if self.session.is_none() {
-            if self.debug {
-                godot_print!("No session open at exec call, trying to open one")
-            }
+            self.log.debug(LogEvent::Connect, ip, port, || {
+                "No session open at exec call, trying to open one".to_string()
+            });
             if let Err(e) = block_on(self.open_session(ip, user, port)) {
                 anyhow::bail!("Failed to open ssh session: {}", e);
             }
@@ -356,13 +945,45 @@ impl InternalSSHClient {
                 Ok(channel) => channel,
                 Err(_) => {
                     self.session = None;
+                    self.stats.channel_failed();
+                    self.log.warn(LogEvent::Channel, ip, port, || {
+                        "Timed out when trying to open channel, dropping session".to_string()
+                    });
                     anyhow::bail!("Timed out when trying to open channel");
                 }
             };
-        match channel {
-            Ok(channel) => Ok(channel),
-            Err(error) => anyhow::bail!("Couldn't open channel: {}", error),
+        let channel = match channel {
+            Ok(channel) => channel,
+            Err(error) => {
+                self.stats.channel_failed();
+                anyhow::bail!("Couldn't open channel: {}", error);
+            }
+        };
+        self.stats.channel_opened();
+        if self.agent.always && self.agent.backend.is_some() {
+            if let Err(error) = channel.agent_forward(false).await {
+                anyhow::bail!("Couldn't request agent forwarding: {}", error);
+            }
+        }
+        Ok(channel)
+    }
+
+    /// Executes `cmd` with agent forwarding requested, even if it isn't for every command.
+    pub fn exec_ssh_forwarding_agent(
+        &mut self,
+        cmd: String,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<ExecOutput> {
+        if self.agent.backend.is_none() {
+            anyhow::bail!("Agent forwarding isn't enabled");
         }
+        let always = std::mem::replace(&mut self.agent.always, true);
+        let result = self.exec_ssh_output(cmd, ip, user, port);
+        // Change back the forwarding mode
+        self.agent.always = always;
+        result
     }
 
     /// Open a new session
@@ -372,38 +993,51 @@ impl InternalSSHClient {
         user: &String,
         port: u16,
     ) -> anyhow::Result<()> {
+        let result = self.connect(ip, user, port).await;
+        match &result {
+            Ok(_) => self.log.info(LogEvent::Connect, ip, port, || {
+                format!("Successfully connected as {}", user)
+            }),
+            Err(e) => self.log.error(LogEvent::Connect, ip, port, || {
+                format!("Failed to connect as {}: {}", user, e)
+            }),
+        }
+        result
+    }
+
+    async fn connect(&mut self, ip: &String, user: &String, port: u16) -> anyhow::Result<()> {
         // If a session is currently active this will disconnect it
         self.disconnect_session().await?;
 
-        if self.auth_method == AuthMethod::None {
+        if matches!(self.auth_method, AuthMethod::None) {
             anyhow::bail!("No authentication method set");
         }
+        if self.auth_method.is_wiped() {
+            if let Some(emitter) = self.log.emitter() {
+                emit_deferred(emitter, "credentials_required", &[]);
+            }
+            anyhow::bail!(
+                "The credentials were wiped after authentication, set the auth method again"
+            );
+        }
+        self.run_pre_connect(ip, port)?;
 
-        let config = russh::client::Config {
-            // TODO make this configurable
-            keepalive_interval: Some(Duration::new(300, 0)),
-            ..Default::default()
-        };
-        let config = Arc::new(config);
-        let sh = Client {
-            ip: ip.to_string(),
-            port,
-            server_check: self.server_check.clone(),
-            debug: self.debug,
-        };
+        let config = client_config();
+        let sh = self.new_handler(ip, port);
+        let stats = self.stats.clone();
 
-        if self.debug {
-            godot_print!("Trying to connect to {}:{}", ip, port);
-        }
+        self.log.debug(LogEvent::Connect, ip, port, || {
+            "Trying to connect".to_string()
+        });
 
         // TODO maybe make this configurable
         let dur = Duration::new(1, 0);
-        self.session = Some(match future::timeout(
-            dur,
-            russh::client::connect(config, (ip.clone(), port), sh),
-        )
-        .await
-        {
+        // The connection is wrapped to count its traffic
+        let connect = async {
+            let socket = tokio::net::TcpStream::connect((ip.as_str(), port)).await?;
+            russh::client::connect_stream(config, CountingStream::new(socket, stats), sh).await
+        };
+        self.session = Some(match future::timeout(dur, connect).await {
             Ok(channel) => channel,
             Err(_) => {
                 anyhow::bail!("Timed out when trying to open channel");
@@ -411,16 +1045,45 @@ impl InternalSSHClient {
         }?);
 
         self.authenticate(user).await?;
+        self.log.debug(LogEvent::Auth, ip, port, || {
+            format!("Authenticated as {}", user)
+        });
+        self.stats.session_opened();
+        Ok(())
+    }
 
-        if self.debug {
-            godot_print!("Successfully connected to {}:{}", ip, port);
+    /// Creates the handler for a new session to `ip`.
+    pub fn new_handler(&self, ip: &str, port: u16) -> Client {
+        let host_authorities = match self.trusted_host_authorities() {
+            Ok(host_authorities) => host_authorities,
+            Err(e) => {
+                self.log.warn(LogEvent::Connect, ip, port, || {
+                    format!("Ignoring host certificate authorities: {}", e)
+                });
+                Vec::new()
+            }
+        };
+        Client {
+            ip: ip.to_string(),
+            port,
+            server_check: self.server_check.clone(),
+            attested_host_keys: self.attested_host_keys.clone(),
+            host_authorities,
+            agent: self.agent.clone(),
+            log: self.log.clone(),
+            host_key: self.host_key.clone(),
         }
-
-        Ok(())
     }
 
     /// Disconnects current session
     pub async fn disconnect_session(&mut self) -> Result<(), russh::Error> {
+        self.remote_info = None;
+        self.stats.session_closed();
+        self.close_session().await
+    }
+
+    /// Disconnects current session without updating the stats, e.g. for a temporary session.
+    pub async fn close_session(&mut self) -> Result<(), russh::Error> {
         if let Some(session) = &self.session {
             if !session.is_closed() {
                 session
@@ -432,34 +1095,66 @@ impl InternalSSHClient {
         Ok(())
     }
 
+    /// Whether a session is open, it may have been closed by the server without us noticing yet.
+    pub fn is_session_active(&self) -> bool {
+        self.session
+            .as_ref()
+            .is_some_and(|session| !session.is_closed())
+    }
+
+    pub fn session_stats(&self) -> Dictionary<GString, Variant> {
+        self.stats.to_dict(self.is_session_active())
+    }
+
+    /// Sends a keepalive over the open session and waits for the reply, the round-trip is kept
+    /// in the statistics. Doesn't open a session, as that would measure the connection setup.
+    pub async fn measure_keepalive(&mut self) -> anyhow::Result<Duration> {
+        let session = match &self.session {
+            Some(session) if !session.is_closed() => session,
+            _ => anyhow::bail!("No session active"),
+        };
+        let start = Instant::now();
+        match future::timeout(KEEPALIVE_TIMEOUT, session.send_ping()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => anyhow::bail!("Keepalive failed: {}", e),
+            Err(_) => anyhow::bail!("No keepalive reply within {}s", KEEPALIVE_TIMEOUT.as_secs()),
+        }
+        let rtt = start.elapsed();
+        self.stats.keepalive_measured(rtt);
+        Ok(rtt)
+    }
+
     /// This takes a handle and performs authentication with the given method.
-    async fn authenticate(&mut self, user: &String) -> Result<(), anyhow::Error> {
+    /// Secrets set directly are wiped once authenticated, see [AuthMethod::wipe_secrets].
+    pub async fn authenticate(&mut self, user: &String) -> Result<(), anyhow::Error> {
+        self.authenticate_with_method(user).await?;
+        self.auth_method.wipe_secrets();
+        Ok(())
+    }
+
+    async fn authenticate_with_method(&mut self, user: &String) -> Result<(), anyhow::Error> {
         let session = match &mut self.session {
             Some(session) => session,
             None => anyhow::bail!("No session active"),
         };
         match &self.auth_method {
             AuthMethod::Password(password) => {
-                if session.authenticate_password(user, password).await.is_ok() {
+                let password = password.get()?;
+                if session
+                    .authenticate_password(user, password.as_str())
+                    .await
+                    .is_ok()
+                {
                     return Ok(());
                 };
                 Err(anyhow!("Wrong Password"))
             }
             AuthMethod::PrivateKey { key_data, key_pass } => {
-                let mut private_key = match PrivateKey::from_openssh(key_data) {
-                    Ok(kp) => kp,
-                    Err(e) => return Err(anyhow!(e)),
-                };
-                if private_key.is_encrypted() {
-                    private_key = if let Some(key_pass) = key_pass {
-                        match private_key.decrypt(key_pass) {
-                            Ok(private_key) => private_key,
-                            Err(e) => anyhow::bail!("Failed to decrypt private key: {}", e),
-                        }
-                    } else {
-                        anyhow::bail!("Key is encrypted but no password provided.");
-                    };
-                }
+                let key_data = key_data.get()?;
+                let key_pass = key_pass.as_ref().map(SecretSource::get).transpose()?;
+                // The decrypted key is wiped when dropped after authentication
+                let private_key =
+                    decode_private_key(&key_data, key_pass.as_deref().map(String::as_str))?;
 
                 let result = session
                     .authenticate_publickey(
@@ -479,8 +1174,11 @@ impl InternalSSHClient {
                 key_file_path,
                 key_pass,
             } => {
-                let cprivk = match russh::keys::load_secret_key(key_file_path, key_pass.as_deref())
-                {
+                let key_pass = key_pass.as_ref().map(SecretSource::get).transpose()?;
+                let cprivk = match russh::keys::load_secret_key(
+                    key_file_path,
+                    key_pass.as_deref().map(String::as_str),
+                ) {
                     Ok(kp) => kp,
                     Err(e) => return Err(anyhow!(e)),
                 };
@@ -499,6 +1197,33 @@ impl InternalSSHClient {
                     _ => Err(anyhow!("Private key auth failed")),
                 }
             }
+            AuthMethod::Certificate {
+                key_file_path,
+                cert_file_path,
+                key_pass,
+            } => {
+                let key_pass = key_pass.as_ref().map(SecretSource::get).transpose()?;
+                let private_key = match russh::keys::load_secret_key(
+                    key_file_path,
+                    key_pass.as_deref().map(String::as_str),
+                ) {
+                    Ok(private_key) => private_key,
+                    Err(e) => return Err(anyhow!(e)),
+                };
+                let cert = load_certificate(cert_file_path)?;
+                // Checked locally, as the server only reports that auth failed
+                check_user_certificate(&cert, private_key.public_key().key_data(), user)?;
+
+                let result = session
+                    .authenticate_openssh_cert(user, Arc::new(private_key), cert)
+                    .await?;
+                match result {
+                    client::AuthResult::Success => Ok(()),
+                    _ => Err(anyhow!(
+                        "Certificate auth failed, is the CA trusted by the server?"
+                    )),
+                }
+            }
             _ => Err(anyhow!("Private key auth failed")),
         }
     }
@@ -507,35 +1232,27 @@ impl InternalSSHClient {
 impl Default for InternalSSHClient {
     fn default() -> Self {
         Self {
-            debug: false,
+            log: Logger::default(),
             session: None,
             auth_method: AuthMethod::None,
             server_check: ServerCheckMethod::NoCheck,
+            remote_info: None,
+            audit: None,
+            host_key: Arc::new(Mutex::new(None)),
+            host_authorities: Vec::new(),
+            attested_host_keys: Vec::new(),
+            agent: AgentForwarding::default(),
+            sudo_password: None,
+            pre_connect: PreConnect::default(),
+            stats: Arc::default(),
         }
     }
 }
 
-/// Helper function that generates a base64 encoded key.
-pub fn generate_private_key(
-    key_type: String,
-    key_size: i64,
-    comment: String,
-) -> anyhow::Result<String> {
-    let mut rng = safe_rng();
-    let key_data = match key_type.as_str() {
-        "ED25519" => KeypairData::from(Ed25519Keypair::random(&mut rng)),
-        "RSA" => KeypairData::from(match RsaKeypair::random(&mut rng, key_size as usize) {
-            Ok(key_data) => key_data,
-            Err(e) => anyhow::bail!("Failed to generate rsa key data: {}", e),
-        }),
-        _ => anyhow::bail!("Unknown key type: {}", key_type),
-    };
-    let private_key = match PrivateKey::new(key_data, comment) {
-        Ok(private_key) => private_key,
-        Err(e) => anyhow::bail!("Failed to generate private key: {}", e),
-    };
-    Ok(private_key
-        .to_openssh(keys::ssh_key::LineEnding::default())
-        .unwrap()
-        .to_string())
+pub fn client_config() -> Arc<russh::client::Config> {
+    Arc::new(russh::client::Config {
+        // TODO make this configurable
+        keepalive_interval: Some(Duration::new(300, 0)),
+        ..Default::default()
+    })
 }
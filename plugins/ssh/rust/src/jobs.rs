@@ -0,0 +1,382 @@
+use crate::capture::CaptureLimits;
+use crate::internal_ssh_client::InternalSSHClient;
+use crate::logger::LogEvent;
+use crate::shell::posix_quote;
+use chrono::Local;
+use godot::prelude::*;
+use std::ops::Range;
+
+/// Directory on the server all job directories are created in.
+const JOBS_DIR: &str = "$HOME/.dreamdeck/jobs";
+
+/// Runs the job's command and records its exit status once it finished.
+/// `$1` is the job directory.
+const RUN_SCRIPT: &str = r#"d="$1"
+echo $$ > "$d/pid"
+cd "$HOME" || exit 1
+sh "$d/cmd" < /dev/null > "$d/stdout" 2> "$d/stderr"
+echo $? > "$d/exit.tmp" && mv "$d/exit.tmp" "$d/exit"
+"#;
+
+/// Prints the state of a job as `key=value` lines. `$1` is the job directory.
+const STATUS_SCRIPT: &str = r#"d="$1"
+[ -d "$d" ] || { echo state=missing; exit 0; }
+pid=$(cat "$d/pid" 2>/dev/null)
+if [ -f "$d/exit" ]; then echo state=exited; echo "exit_status=$(cat "$d/exit")"
+elif [ -f "$d/killed" ]; then echo state=killed
+elif [ -n "$pid" ] && kill -0 "$pid" 2>/dev/null; then echo state=running
+else echo state=lost; fi
+echo "pid=$pid"
+echo "mode=$(cat "$d/mode" 2>/dev/null)"
+echo "started=$(cat "$d/started" 2>/dev/null)"
+echo "stdout_size=$(wc -c < "$d/stdout" 2>/dev/null)"
+echo "stderr_size=$(wc -c < "$d/stderr" 2>/dev/null)"
+"#;
+
+/// State of a detached job.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum JobState {
+    Running,
+    Exited,
+    Killed,
+    /// The job isn't running anymore, but didn't record an exit status, e.g. after the server rebooted.
+    Lost,
+    Missing,
+}
+
+impl JobState {
+    fn parse(state: &str) -> anyhow::Result<Self> {
+        match state {
+            "running" => Ok(JobState::Running),
+            "exited" => Ok(JobState::Exited),
+            "killed" => Ok(JobState::Killed),
+            "lost" => Ok(JobState::Lost),
+            "missing" => Ok(JobState::Missing),
+            _ => anyhow::bail!("Unknown job state: {}", state),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Running => "running",
+            JobState::Exited => "exited",
+            JobState::Killed => "killed",
+            JobState::Lost => "lost",
+            JobState::Missing => "missing",
+        }
+    }
+}
+
+pub struct JobStatus {
+    pub state: JobState,
+    pub exit_status: Option<i64>,
+    pub pid: Option<i64>,
+    /// "systemd" if the job runs as transient systemd unit, otherwise "nohup".
+    pub mode: String,
+    /// Unix timestamp the job was started at.
+    pub started: Option<i64>,
+    pub stdout_size: u64,
+    pub stderr_size: u64,
+}
+
+impl JobStatus {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        dict! {
+            "state" => self.state.as_str().to_string(),
+            "exit_status" => match self.exit_status {
+                Some(exit_status) => Variant::from(exit_status),
+                None => Variant::nil(),
+            },
+            "pid" => match self.pid {
+                Some(pid) => Variant::from(pid),
+                None => Variant::nil(),
+            },
+            "mode" => self.mode.clone(),
+            "started" => match self.started {
+                Some(started) => Variant::from(started),
+                None => Variant::nil(),
+            },
+            "stdout_size" => self.stdout_size as i64,
+            "stderr_size" => self.stderr_size as i64,
+        }
+    }
+
+    fn parse(output: &str) -> anyhow::Result<Self> {
+        let mut status = JobStatus {
+            state: JobState::Missing,
+            exit_status: None,
+            pid: None,
+            mode: String::new(),
+            started: None,
+            stdout_size: 0,
+            stderr_size: 0,
+        };
+        for line in output.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match key {
+                "state" => status.state = JobState::parse(value)?,
+                "exit_status" => status.exit_status = value.parse().ok(),
+                "pid" => status.pid = value.parse().ok(),
+                "mode" => status.mode = value.to_string(),
+                "started" => status.started = value.parse().ok(),
+                "stdout_size" => status.stdout_size = value.parse().unwrap_or_default(),
+                "stderr_size" => status.stderr_size = value.parse().unwrap_or_default(),
+                _ => (),
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// A chunk of a job's output.
+pub struct JobOutput {
+    pub data: Vec<u8>,
+    /// Offset to read the next chunk from.
+    pub next_offset: u64,
+}
+
+impl JobOutput {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        dict! {
+            "data" => PackedByteArray::from(self.data.as_slice()),
+            "next_offset" => self.next_offset as i64,
+        }
+    }
+}
+
+impl InternalSSHClient {
+    /// Starts `cmd` detached from the session, so it keeps running after disconnecting.
+    /// Runs as transient systemd user unit if the user has lingering enabled, as other user units
+    /// are stopped on logout, otherwise with `nohup` and `setsid`. Returns the job id.
+    ///
+    /// The command is run by `sh` in the home directory, its output is written to files
+    /// in the job directory on the server, so it can be fetched later.
+    pub fn start_job(
+        &mut self,
+        cmd: &str,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<String> {
+        self.check_unix(ip, user, port)?;
+        let job_id = Local::now().format("%Y%m%d-%H%M%S-%3f").to_string();
+        let script = format!(
+            r#"mkdir -p "{jobs_dir}" && d="{jobs_dir}/{job_id}" && mkdir "$d" || exit 1
+printf '%s\n' {cmd} > "$d/cmd"
+printf '%s' {run} > "$d/run"
+date +%s > "$d/started"
+if [ -e "/var/lib/systemd/linger/$(id -un)" ] && command -v systemd-run > /dev/null 2>&1 \
+  && systemd-run --user --quiet --collect --unit="dreamdeck-job-{job_id}" sh "$d/run" "$d" > /dev/null 2>&1; then
+  echo systemd > "$d/mode"
+else
+  echo nohup > "$d/mode"
+  if command -v setsid > /dev/null 2>&1; then
+    nohup setsid sh "$d/run" "$d" > /dev/null 2>&1 &
+  else
+    nohup sh "$d/run" "$d" > /dev/null 2>&1 &
+  fi
+fi
+# Wait for the pid, so the job is never reported as lost right after starting
+i=0
+while [ ! -f "$d/pid" ] && [ $i -lt 50 ]; do sleep 0.1; i=$((i + 1)); done
+echo started"#,
+            jobs_dir = JOBS_DIR,
+            job_id = job_id,
+            cmd = posix_quote(cmd),
+            run = posix_quote(RUN_SCRIPT),
+        );
+        let output = self.exec_ssh_output(sh_command(&script), ip, user, port)?;
+        if output.exit_status != 0 || output.stdout.trim() != "started" {
+            anyhow::bail!(
+                "Failed to start job (exit status {}): {}",
+                output.exit_status,
+                output.stderr.trim()
+            );
+        }
+        self.log.info(LogEvent::Exec, ip, port, || {
+            format!("Started job {}: \"{}\"", job_id, cmd)
+        });
+        Ok(job_id)
+    }
+
+    pub fn job_status(
+        &mut self,
+        job_id: &str,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<JobStatus> {
+        let cmd = job_script(STATUS_SCRIPT, job_id)?;
+        let output = self.exec_ssh_output(cmd, ip, user, port)?;
+        if output.exit_status != 0 {
+            anyhow::bail!(
+                "Failed to get status of job {} (exit status {}): {}",
+                job_id,
+                output.exit_status,
+                output.stderr.trim()
+            );
+        }
+        JobStatus::parse(&output.stdout)
+    }
+
+    /// Reads the bytes in `range` of the job's stdout, or stderr. Returns less if there is no more output yet.
+    pub fn read_job_output(
+        &mut self,
+        job_id: &str,
+        stderr: bool,
+        range: Range<u64>,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<JobOutput> {
+        let max_bytes = range.end.saturating_sub(range.start) as usize;
+        let script = format!(
+            r#"f="$1/{file}"
+[ -f "$f" ] || {{ echo "Job output not found" >&2; exit 1; }}
+tail -c +{start} "$f" | head -c {max_bytes}"#,
+            file = if stderr { "stderr" } else { "stdout" },
+            start = range.start + 1,
+            max_bytes = max_bytes,
+        );
+        let limits = CaptureLimits {
+            max_stdout: Some(max_bytes),
+            max_stderr: Some(4096),
+        };
+        let output = self.exec_ssh_bytes(job_script(&script, job_id)?, limits, ip, user, port)?;
+        if output.exit_status != 0 {
+            anyhow::bail!(
+                "Failed to read output of job {}: {}",
+                job_id,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(JobOutput {
+            next_offset: range.start + output.stdout.len() as u64,
+            data: output.stdout,
+        })
+    }
+
+    /// Terminates the job and all processes it started. Returns false if it wasn't running.
+    pub fn kill_job(
+        &mut self,
+        job_id: &str,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<bool> {
+        let script = format!(
+            r#"d="$1"
+pid=$(cat "$d/pid" 2>/dev/null)
+if [ -f "$d/exit" ] || [ -z "$pid" ] || ! kill -0 "$pid" 2>/dev/null; then echo absent; exit 0; fi
+touch "$d/killed"
+if [ "$(cat "$d/mode")" = systemd ]; then
+  systemctl --user stop "dreamdeck-job-{job_id}"
+else
+  kill -TERM -- "-$pid" 2>/dev/null || kill -TERM "$pid"
+fi
+echo killed"#,
+            job_id = job_id,
+        );
+        let output = self.exec_ssh_output(job_script(&script, job_id)?, ip, user, port)?;
+        if output.exit_status != 0 {
+            anyhow::bail!(
+                "Failed to kill job {} (exit status {}): {}",
+                job_id,
+                output.exit_status,
+                output.stderr.trim()
+            );
+        }
+        let killed = output.stdout.trim() == "killed";
+        if killed {
+            self.log.info(LogEvent::Exec, ip, port, || {
+                format!("Killed job {}", job_id)
+            });
+        }
+        Ok(killed)
+    }
+
+    /// Returns the ids of all jobs on the server, oldest first.
+    pub fn list_jobs(
+        &mut self,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<Vec<String>> {
+        self.check_unix(ip, user, port)?;
+        let script = format!(
+            r#"[ -d "{jobs_dir}" ] || exit 0
+for d in "{jobs_dir}"/*/; do [ -d "$d" ] && basename "$d"; done"#,
+            jobs_dir = JOBS_DIR,
+        );
+        let output = self.exec_ssh_output(sh_command(&script), ip, user, port)?;
+        if output.exit_status != 0 {
+            anyhow::bail!(
+                "Failed to list jobs (exit status {}): {}",
+                output.exit_status,
+                output.stderr.trim()
+            );
+        }
+        let mut job_ids: Vec<String> = output
+            .stdout
+            .lines()
+            .filter(|job_id| is_valid_job_id(job_id))
+            .map(str::to_string)
+            .collect();
+        job_ids.sort();
+        Ok(job_ids)
+    }
+
+    /// Deletes the job directory including its output. Running jobs can't be removed.
+    pub fn remove_job(
+        &mut self,
+        job_id: &str,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<()> {
+        let script = r#"d="$1"
+[ -d "$d" ] || exit 0
+pid=$(cat "$d/pid" 2>/dev/null)
+if [ ! -f "$d/exit" ] && [ -n "$pid" ] && kill -0 "$pid" 2>/dev/null; then echo "Job is still running" >&2; exit 1; fi
+rm -rf "$d""#;
+        let output = self.exec_ssh_output(job_script(script, job_id)?, ip, user, port)?;
+        if output.exit_status != 0 {
+            anyhow::bail!("Failed to remove job {}: {}", job_id, output.stderr.trim());
+        }
+        Ok(())
+    }
+
+    fn check_unix(&mut self, ip: &String, user: &String, port: u16) -> anyhow::Result<()> {
+        if !self.remote_info(ip, user, port)?.os.is_unix() {
+            anyhow::bail!("Detached jobs are only supported on unix servers");
+        }
+        Ok(())
+    }
+}
+
+/// Job ids are generated by [InternalSSHClient::start_job], but are passed back in from outside,
+/// so they are validated before being used in paths.
+fn is_valid_job_id(job_id: &str) -> bool {
+    !job_id.is_empty() && job_id.chars().all(|c| c.is_ascii_digit() || c == '-')
+}
+
+/// Wraps `script` so it runs in `sh` with the job directory as `$1`.
+fn job_script(script: &str, job_id: &str) -> anyhow::Result<String> {
+    if !is_valid_job_id(job_id) {
+        anyhow::bail!("Invalid job id: {}", job_id);
+    }
+    Ok(format!(
+        "sh -c {} sh \"{}/{}\"",
+        posix_quote(script),
+        JOBS_DIR,
+        job_id
+    ))
+}
+
+/// Runs `script` in `sh`, regardless of the login shell.
+fn sh_command(script: &str) -> String {
+    format!("sh -c {}", posix_quote(script))
+}
@@ -0,0 +1,268 @@
+use crate::internal_ssh_client::{AuthMethod, InternalSSHClient, Secret, SecretSource};
+use crate::key_utils::public_key_line;
+use crate::logger::LogEvent;
+use crate::remote_info::RemoteOs;
+use crate::shell::{posix_quote, powershell_encoded_command, powershell_quote};
+use async_std::task::block_on;
+use russh::keys::PublicKey;
+use std::fs;
+use zeroize::Zeroizing;
+
+/// Result of checking the authorized keys on the server for a key.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum KeyChange {
+    /// The key was added/removed.
+    Changed,
+    /// The key was already present/absent.
+    Unchanged,
+}
+
+impl InternalSSHClient {
+    /// Returns the public key line of the currently set auth method.
+    pub fn auth_public_key(&self) -> anyhow::Result<String> {
+        match &self.auth_method {
+            AuthMethod::PrivateKeyFile {
+                key_file_path,
+                key_pass,
+            }
+            | AuthMethod::Certificate {
+                key_file_path,
+                key_pass,
+                ..
+            } => match fs::read_to_string(key_file_path).map(Zeroizing::new) {
+                Ok(key_data) => {
+                    let key_pass = key_pass.as_ref().map(SecretSource::get).transpose()?;
+                    public_key_line(&key_data, key_pass.as_deref().map(String::as_str))
+                }
+                Err(e) => {
+                    anyhow::bail!("Failed to read key at {}: {}", key_file_path.display(), e)
+                }
+            },
+            AuthMethod::PrivateKey { key_data, key_pass } => {
+                let key_pass = key_pass.as_ref().map(SecretSource::get).transpose()?;
+                public_key_line(&key_data.get()?, key_pass.as_deref().map(String::as_str))
+            }
+            AuthMethod::PublicKeyFile { key_file_path } => {
+                match fs::read_to_string(key_file_path) {
+                    Ok(public_key) => Ok(public_key.trim().to_string()),
+                    Err(e) => {
+                        anyhow::bail!("Failed to read key at {}: {}", key_file_path.display(), e)
+                    }
+                }
+            }
+            // Is only possible if self.auth_method is of type key
+            _ => anyhow::bail!("Wrong auth method set"),
+        }
+    }
+
+    /// Adds `public_key` to the authorized keys of the server, like `ssh-copy-id` does.
+    /// Keys that are already authorized are skipped, regardless of their options or comment.
+    ///
+    /// If `password` is set, a new session authenticated by password is used for this and closed afterwards,
+    /// otherwise the current auth method is used.
+    ///
+    /// * `options` - Options prepended to the key, e.g. `from="10.0.0.0/8",command="uptime"`.
+    pub fn deploy_public_key(
+        &mut self,
+        password: Option<Secret>,
+        public_key: &str,
+        options: &str,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<KeyChange> {
+        let key_id = key_identity(public_key)?;
+        if options.contains(['\n', '\r']) {
+            anyhow::bail!("Key options must not contain line breaks");
+        }
+        let line = if options.is_empty() {
+            public_key.trim().to_string()
+        } else {
+            format!("{} {}", options, public_key.trim())
+        };
+
+        self.log.info(LogEvent::Key, ip, port, || {
+            "Copying public key to SSH server".to_string()
+        });
+
+        self.with_password_session(password, ip, user, port, |client| {
+            let cmd = match client.remote_info(ip, user, port)?.os {
+                RemoteOs::Windows => windows_add_key_cmd(&key_id, &line),
+                _ => unix_add_key_cmd(&key_id, &line),
+            };
+            client.run_key_cmd(cmd, "added", ip, user, port)
+        })
+    }
+
+    /// Removes all entries of `public_key` from the authorized keys of the server.
+    ///
+    /// If `password` is set, a new session authenticated by password is used for this and closed afterwards,
+    /// otherwise the current auth method is used.
+    pub fn revoke_public_key(
+        &mut self,
+        password: Option<Secret>,
+        public_key: &str,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<KeyChange> {
+        let key_id = key_identity(public_key)?;
+
+        self.log.info(LogEvent::Key, ip, port, || {
+            "Removing public key from SSH server".to_string()
+        });
+
+        self.with_password_session(password, ip, user, port, |client| {
+            let cmd = match client.remote_info(ip, user, port)?.os {
+                RemoteOs::Windows => windows_remove_key_cmd(&key_id),
+                _ => unix_remove_key_cmd(&key_id),
+            };
+            client.run_key_cmd(cmd, "removed", ip, user, port)
+        })
+    }
+
+    /// Runs `f` on a fresh session authenticated with `password` if it is set.
+    /// The auth method is always restored and the temporary session closed afterwards,
+    /// even if `f` fails.
+    fn with_password_session<T>(
+        &mut self,
+        password: Option<Secret>,
+        ip: &String,
+        user: &String,
+        port: u16,
+        f: impl FnOnce(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let Some(password) = password else {
+            return f(self);
+        };
+
+        // Temporarily set auth method to password to allow login
+        let auth_method_store = std::mem::replace(
+            &mut self.auth_method,
+            AuthMethod::Password(SecretSource::Value(password)),
+        );
+        let result = block_on(self.disconnect_session())
+            .map_err(anyhow::Error::from)
+            .and_then(|_| block_on(self.open_session(ip, user, port)))
+            .and_then(|_| f(self));
+
+        // Change back auth_method
+        self.auth_method = auth_method_store;
+        block_on(self.disconnect_session())?;
+
+        result
+    }
+
+    /// Runs a key management command, which prints `changed_marker` if it changed something.
+    fn run_key_cmd(
+        &mut self,
+        cmd: String,
+        changed_marker: &str,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<KeyChange> {
+        let output = self.exec_ssh_output(cmd, ip, user, port)?;
+        if output.exit_status != 0 {
+            anyhow::bail!(
+                "Failed to update authorized keys (exit status {}): {}",
+                output.exit_status,
+                output.stderr.trim()
+            );
+        }
+        if output.stdout.trim() == changed_marker {
+            Ok(KeyChange::Changed)
+        } else {
+            Ok(KeyChange::Unchanged)
+        }
+    }
+}
+
+/// Returns the key type and base64 blob of a public key line, which identifies the key
+/// independently of its comment.
+fn key_identity(public_key: &str) -> anyhow::Result<String> {
+    let parsed = match PublicKey::from_openssh(public_key.trim()) {
+        Ok(parsed) => parsed,
+        Err(e) => anyhow::bail!("Invalid public key: {}", e),
+    };
+    let serialized = match parsed.to_openssh() {
+        Ok(serialized) => serialized,
+        Err(e) => anyhow::bail!("Failed to serialize public key: {}", e),
+    };
+    Ok(serialized
+        .split_whitespace()
+        .take(2)
+        .collect::<Vec<&str>>()
+        .join(" "))
+}
+
+fn unix_add_key_cmd(key_id: &str, line: &str) -> String {
+    format!(
+        "umask 077 && mkdir -p ~/.ssh && chmod 700 ~/.ssh && touch ~/.ssh/authorized_keys \
+         && chmod 600 ~/.ssh/authorized_keys && \
+         if grep -qF -- {key_id} ~/.ssh/authorized_keys; then echo present; else \
+         if [ -s ~/.ssh/authorized_keys ] && [ -n \"$(tail -c 1 ~/.ssh/authorized_keys)\" ]; \
+         then echo >> ~/.ssh/authorized_keys; fi && \
+         printf '%s\\n' {line} >> ~/.ssh/authorized_keys && echo added; fi",
+        key_id = posix_quote(key_id),
+        line = posix_quote(line),
+    )
+}
+
+fn unix_remove_key_cmd(key_id: &str) -> String {
+    format!(
+        "if [ -f ~/.ssh/authorized_keys ] && grep -qF -- {key_id} ~/.ssh/authorized_keys; then \
+         tmp=$(mktemp ~/.ssh/authorized_keys.XXXXXX) && \
+         {{ grep -vF -- {key_id} ~/.ssh/authorized_keys > \"$tmp\" || true; }} && \
+         chmod 600 \"$tmp\" && mv \"$tmp\" ~/.ssh/authorized_keys && echo removed; \
+         else echo absent; fi",
+        key_id = posix_quote(key_id),
+    )
+}
+
+/// Windows OpenSSH uses `administrators_authorized_keys` for members of the administrators group,
+/// which also needs to be only accessible by administrators and SYSTEM.
+const WINDOWS_KEY_FILE_SCRIPT: &str = r#"$ErrorActionPreference = 'Stop'
+$isAdmin = [bool](whoami /groups | Select-String 'S-1-5-32-544')
+if ($isAdmin) {
+    $file = Join-Path $env:ProgramData 'ssh\administrators_authorized_keys'
+    $grants = @('*S-1-5-32-544:F', '*S-1-5-18:F')
+} else {
+    $dir = Join-Path $env:USERPROFILE '.ssh'
+    New-Item -ItemType Directory -Force -Path $dir | Out-Null
+    $file = Join-Path $dir 'authorized_keys'
+    $grants = @("${env:USERNAME}:F", '*S-1-5-18:F')
+}
+if (!(Test-Path $file)) { New-Item -ItemType File -Path $file | Out-Null }
+$lines = @(Get-Content -Path $file)
+"#;
+
+fn windows_add_key_cmd(key_id: &str, line: &str) -> String {
+    powershell_encoded_command(&format!(
+        "{}$keyId = {}
+if ($lines | Where-Object {{ $_.Contains($keyId) }}) {{ 'present' }} else {{
+    Add-Content -Path $file -Value {} -Encoding ascii
+    'added'
+}}
+$icaclsArgs = @($file, '/inheritance:r') + ($grants | ForEach-Object {{ '/grant', $_ }})
+icacls.exe @icaclsArgs | Out-Null
+",
+        WINDOWS_KEY_FILE_SCRIPT,
+        powershell_quote(key_id),
+        powershell_quote(line),
+    ))
+}
+
+fn windows_remove_key_cmd(key_id: &str) -> String {
+    powershell_encoded_command(&format!(
+        "{}$keyId = {}
+$kept = @($lines | Where-Object {{ -not $_.Contains($keyId) }})
+if ($kept.Count -eq $lines.Count) {{ 'absent' }} else {{
+    Set-Content -Path $file -Value $kept -Encoding ascii
+    'removed'
+}}
+",
+        WINDOWS_KEY_FILE_SCRIPT,
+        powershell_quote(key_id),
+    ))
+}
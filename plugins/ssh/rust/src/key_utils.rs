@@ -0,0 +1,129 @@
+use keys::ssh_key::private::{EcdsaKeypair, Ed25519Keypair, KeypairData, RsaKeypair};
+use keys::ssh_key::{EcdsaCurve, HashAlg, LineEnding, PublicKey};
+use keys::PrivateKey;
+use russh::keys;
+use russh::keys::key::safe_rng;
+
+/// Helper function that generates a key in the openssh format.
+/// If `passphrase` is not empty the key will be encrypted with it.
+///
+/// For ECDSA keys `key_size` selects the curve and has to be 256, 384 or 521.
+pub fn generate_private_key(
+    key_type: &str,
+    key_size: i64,
+    comment: String,
+    passphrase: &str,
+) -> anyhow::Result<String> {
+    let mut rng = safe_rng();
+    let key_data = match key_type {
+        "ED25519" => KeypairData::from(Ed25519Keypair::random(&mut rng)),
+        "RSA" => KeypairData::from(match RsaKeypair::random(&mut rng, key_size as usize) {
+            Ok(key_data) => key_data,
+            Err(e) => anyhow::bail!("Failed to generate rsa key data: {}", e),
+        }),
+        "ECDSA" => {
+            let curve = match key_size {
+                256 => EcdsaCurve::NistP256,
+                384 => EcdsaCurve::NistP384,
+                521 => EcdsaCurve::NistP521,
+                _ => anyhow::bail!("Unsupported ecdsa key size: {}", key_size),
+            };
+            KeypairData::from(match EcdsaKeypair::random(&mut rng, curve) {
+                Ok(key_data) => key_data,
+                Err(e) => anyhow::bail!("Failed to generate ecdsa key data: {}", e),
+            })
+        }
+        _ => anyhow::bail!("Unknown key type: {}", key_type),
+    };
+    let mut private_key = match PrivateKey::new(key_data, comment) {
+        Ok(private_key) => private_key,
+        Err(e) => anyhow::bail!("Failed to generate private key: {}", e),
+    };
+    if !passphrase.is_empty() {
+        private_key = match private_key.encrypt(&mut rng, passphrase) {
+            Ok(private_key) => private_key,
+            Err(e) => anyhow::bail!("Failed to encrypt private key: {}", e),
+        };
+    }
+    match private_key.to_openssh(LineEnding::default()) {
+        Ok(key) => Ok(key.to_string()),
+        Err(e) => anyhow::bail!("Failed to serialize private key: {}", e),
+    }
+}
+
+/// Decodes and if needed decrypts a private key.
+/// Supports the openssh format as well as PEM encoded PKCS#1, PKCS#8 and SEC1 keys.
+pub fn decode_private_key(key_data: &str, passphrase: Option<&str>) -> anyhow::Result<PrivateKey> {
+    match keys::decode_secret_key(key_data, passphrase) {
+        Ok(private_key) => Ok(private_key),
+        Err(e) => anyhow::bail!("Failed to decode private key: {}", e),
+    }
+}
+
+/// Returns the public key belonging to `key_data`.
+/// Keys in the openssh format store the public key unencrypted,
+/// so for those `passphrase` is only needed for the other formats.
+fn public_key(key_data: &str, passphrase: Option<&str>) -> anyhow::Result<PublicKey> {
+    if let Ok(private_key) = PrivateKey::from_openssh(key_data) {
+        return Ok(private_key.public_key().clone());
+    }
+    Ok(decode_private_key(key_data, passphrase)?
+        .public_key()
+        .clone())
+}
+
+/// Returns the public key line as it would appear in `authorized_keys`, including the comment.
+pub fn public_key_line(key_data: &str, passphrase: Option<&str>) -> anyhow::Result<String> {
+    match public_key(key_data, passphrase)?.to_openssh() {
+        Ok(pub_key) => Ok(pub_key),
+        Err(e) => anyhow::bail!("Failed to serialize public key: {}", e),
+    }
+}
+
+/// Returns the fingerprint of the key in the same format `ssh-keygen -l` uses.
+///
+/// * `hash_alg` - Either "SHA256" or "MD5".
+pub fn fingerprint(
+    key_data: &str,
+    passphrase: Option<&str>,
+    hash_alg: &str,
+) -> anyhow::Result<String> {
+    let public_key = public_key(key_data, passphrase)?;
+    match hash_alg {
+        "SHA256" => Ok(public_key.fingerprint(HashAlg::Sha256).to_string()),
+        "MD5" => {
+            let blob = match public_key.to_bytes() {
+                Ok(blob) => blob,
+                Err(e) => anyhow::bail!("Failed to encode public key: {}", e),
+            };
+            let digest = md5::compute(blob);
+            let hex: Vec<String> = digest
+                .0
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            Ok(format!("MD5:{}", hex.join(":")))
+        }
+        _ => anyhow::bail!("Unknown hash algorithm: {}", hash_alg),
+    }
+}
+
+/// Converts a private key of any supported format (see [decode_private_key]) to the openssh format.
+/// If `new_passphrase` is not empty the converted key will be encrypted with it.
+pub fn convert_to_openssh(
+    key_data: &str,
+    passphrase: Option<&str>,
+    new_passphrase: &str,
+) -> anyhow::Result<String> {
+    let mut private_key = decode_private_key(key_data, passphrase)?;
+    if !new_passphrase.is_empty() {
+        private_key = match private_key.encrypt(&mut safe_rng(), new_passphrase) {
+            Ok(private_key) => private_key,
+            Err(e) => anyhow::bail!("Failed to encrypt private key: {}", e),
+        };
+    }
+    match private_key.to_openssh(LineEnding::default()) {
+        Ok(key) => Ok(key.to_string()),
+        Err(e) => anyhow::bail!("Failed to serialize private key: {}", e),
+    }
+}
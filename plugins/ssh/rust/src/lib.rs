@@ -1,7 +1,42 @@
 use godot::prelude::*;
 
+mod agent;
+mod asciicast;
+mod audit_log;
+mod capture;
+mod certificate;
+mod command_watcher;
+mod diagnostics;
+mod host_monitor;
 mod internal_ssh_client;
+mod jobs;
+mod key_deployment;
+mod key_utils;
+mod log_tail;
+mod logger;
+mod power;
+mod remote_info;
+mod scheduler;
+mod script;
+mod services;
+mod session_stats;
+mod shell;
+mod ssh_audit_log;
 mod ssh_client;
+mod ssh_command_watcher;
+mod ssh_config;
+mod ssh_host_monitor;
+mod ssh_log_tail;
+mod ssh_scheduler;
+mod ssh_stats_collector;
+mod ssh_terminal;
+mod ssh_terminal_player;
+mod ssh_vault;
+mod stats;
+mod sudo;
+mod sync;
+mod terminal;
+mod vault;
 
 struct DreamDeckSSH;
 
@@ -0,0 +1,234 @@
+use crate::ssh_client::SSHClient;
+use async_std::future;
+use async_std::task::block_on;
+use godot::prelude::*;
+use russh::ChannelMsg;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Lines longer than this are split, so a stream without newlines can't grow without bound.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Splits a byte stream into lines without their line ending.
+#[derive(Default)]
+pub struct LineSplitter {
+    partial: Vec<u8>,
+}
+
+impl LineSplitter {
+    /// Returns all lines completed by `data`.
+    pub fn push(&mut self, data: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &byte in data {
+            if byte == b'\n' {
+                lines.push(self.take());
+            } else {
+                self.partial.push(byte);
+                if self.partial.len() >= MAX_LINE_LENGTH {
+                    lines.push(self.take());
+                }
+            }
+        }
+        lines
+    }
+
+    /// Returns the last line if the stream didn't end with a line ending.
+    pub fn finish(&mut self) -> Option<String> {
+        (!self.partial.is_empty()).then(|| self.take())
+    }
+
+    fn take(&mut self) -> String {
+        let mut line = std::mem::take(&mut self.partial);
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        String::from_utf8_lossy(&line).into_owned()
+    }
+}
+
+/// Keeps the most recent `capacity` lines.
+pub struct LineBuffer {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+impl LineBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        let excess = self.lines.len().saturating_sub(capacity);
+        self.lines.drain(..excess);
+    }
+
+    pub fn push(&mut self, line: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.lines.len() >= self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    /// Returns up to `count` of the most recent lines, oldest first.
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &String> {
+        self.lines
+            .iter()
+            .skip(self.lines.len().saturating_sub(count))
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+}
+
+/// State shared between a stream's thread and its owner.
+#[derive(Default)]
+pub struct StreamState {
+    /// Lines received since they were last taken.
+    pub lines: Vec<String>,
+    /// Set once the command runs.
+    pub started: bool,
+    /// Reason the stream ended, set once it did.
+    pub ended: Option<String>,
+    /// Requests the stream to end.
+    pub stop: bool,
+}
+
+pub type SharedStream = Arc<Mutex<StreamState>>;
+
+/// Runs `cmd` on the client until it exits, the session closes or a stop is requested,
+/// collecting the lines of stdout and stderr in `shared`. Blocks, so call it on a separate thread.
+pub fn run_stream(client_id: InstanceId, cmd: String, shared: SharedStream) {
+    let reason = match stream(client_id, cmd, &shared) {
+        Ok(reason) => reason,
+        Err(e) => e.to_string(),
+    };
+    if let Ok(mut shared) = shared.lock() {
+        shared.ended = Some(reason);
+    }
+}
+
+fn stream(client_id: InstanceId, cmd: String, shared: &SharedStream) -> anyhow::Result<String> {
+    // The client is only bound while starting the command, so it can be used while streaming
+    let (mut channel, mut record) = {
+        let Ok(mut client) = Gd::<SSHClient>::try_from_instance_id(client_id) else {
+            anyhow::bail!("Client was freed");
+        };
+        let result = client.bind_mut().exec_channel(cmd);
+        result?
+    };
+    match shared.lock() {
+        Ok(mut shared) => shared.started = true,
+        Err(_) => anyhow::bail!("Stream state is poisoned"),
+    }
+
+    let mut stdout = LineSplitter::default();
+    let mut stderr = LineSplitter::default();
+    let mut exit_status = None;
+    let reason = loop {
+        if shared.lock().is_ok_and(|shared| shared.stop) {
+            let _ = block_on(channel.close());
+            break "Stopped".to_string();
+        }
+        // Wait in short slices, so a stop request is noticed even if the command is silent
+        let lines = match block_on(future::timeout(Duration::from_millis(500), channel.wait())) {
+            Err(_) => continue,
+            Ok(None) => match exit_status {
+                Some(exit_status) => break format!("Command exited with status {}", exit_status),
+                None => break "Channel closed".to_string(),
+            },
+            Ok(Some(ChannelMsg::Data { data })) => {
+                if let Some(record) = record.as_mut() {
+                    record.stdout(&data);
+                }
+                stdout.push(&data)
+            }
+            Ok(Some(ChannelMsg::ExtendedData { ext: 1, data })) => {
+                if let Some(record) = record.as_mut() {
+                    record.stderr(&data);
+                }
+                stderr.push(&data)
+            }
+            Ok(Some(ChannelMsg::ExitStatus {
+                exit_status: new_exit_status,
+            })) => {
+                exit_status = Some(new_exit_status as i64);
+                continue;
+            }
+            Ok(Some(_)) => continue,
+        };
+        if let Ok(mut shared) = shared.lock() {
+            shared.lines.extend(lines);
+        }
+    };
+
+    if let Ok(mut shared) = shared.lock() {
+        shared.lines.extend(stdout.finish());
+        shared.lines.extend(stderr.finish());
+    }
+    if let Some(record) = record {
+        record.finish(exit_status, None);
+    }
+    Ok(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_continue_across_chunks() {
+        let mut splitter = LineSplitter::default();
+        assert_eq!(splitter.push(b"first\nsec"), ["first"]);
+        assert!(splitter.push(b"ond").is_empty());
+        assert_eq!(splitter.push(b"\n\nthird"), ["second", ""]);
+        assert_eq!(splitter.finish().as_deref(), Some("third"));
+        assert_eq!(splitter.finish(), None);
+    }
+
+    #[test]
+    fn line_endings_are_removed() {
+        let mut splitter = LineSplitter::default();
+        assert_eq!(splitter.push(b"dos\r\nunix\n"), ["dos", "unix"]);
+        // A carriage return split from its newline is still removed
+        assert!(splitter.push(b"split\r").is_empty());
+        assert_eq!(splitter.push(b"\n"), ["split"]);
+    }
+
+    #[test]
+    fn long_lines_are_split() {
+        let mut splitter = LineSplitter::default();
+        let lines = splitter.push(&vec![b'a'; MAX_LINE_LENGTH + 10]);
+        assert_eq!(lines, ["a".repeat(MAX_LINE_LENGTH)]);
+        assert_eq!(splitter.finish(), Some("a".repeat(10)));
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let mut splitter = LineSplitter::default();
+        assert_eq!(splitter.push(b"caf\xc3\xa9 \xff\n"), ["café \u{fffd}"]);
+    }
+
+    #[test]
+    fn buffer_keeps_the_most_recent_lines() {
+        let mut buffer = LineBuffer::new(3);
+        for line in ["1", "2", "3", "4"] {
+            buffer.push(line.to_string());
+        }
+        assert!(buffer.recent(10).eq(["2", "3", "4"].iter()));
+        assert!(buffer.recent(2).eq(["3", "4"].iter()));
+        buffer.set_capacity(1);
+        assert!(buffer.recent(10).eq(["4"].iter()));
+        buffer.set_capacity(0);
+        buffer.push("5".to_string());
+        assert_eq!(buffer.recent(10).count(), 0);
+    }
+}
@@ -0,0 +1,253 @@
+use chrono::{Local, SecondsFormat};
+use godot::prelude::*;
+use russh::ChannelId;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    /// Disables logging, never used for records.
+    Off,
+}
+
+impl LogLevel {
+    pub fn parse(level: &str) -> anyhow::Result<Self> {
+        match level {
+            "trace" => Ok(LogLevel::Trace),
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warn" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            "off" => Ok(LogLevel::Off),
+            _ => anyhow::bail!("Unknown log level: {}", level),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Trace => "trace",
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+            LogLevel::Off => "off",
+        }
+    }
+}
+
+/// What a log record is about.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LogEvent {
+    Connect,
+    Auth,
+    Channel,
+    Exec,
+    Stdout,
+    Stderr,
+    ExitStatus,
+    Key,
+    RemoteInfo,
+}
+
+impl LogEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogEvent::Connect => "connect",
+            LogEvent::Auth => "auth",
+            LogEvent::Channel => "channel",
+            LogEvent::Exec => "exec",
+            LogEvent::Stdout => "stdout",
+            LogEvent::Stderr => "stderr",
+            LogEvent::ExitStatus => "exit_status",
+            LogEvent::Key => "key",
+            LogEvent::RemoteInfo => "remote_info",
+        }
+    }
+}
+
+pub struct LogRecord {
+    /// RFC 3339 timestamp with milliseconds.
+    pub timestamp: String,
+    pub level: LogLevel,
+    pub event: LogEvent,
+    pub host: String,
+    pub port: u16,
+    pub channel: Option<u32>,
+    pub message: String,
+}
+
+impl LogRecord {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        dict! {
+            "timestamp" => self.timestamp.clone(),
+            "level" => self.level.as_str().to_string(),
+            "event" => self.event.as_str().to_string(),
+            "host" => self.host.clone(),
+            "port" => self.port,
+            "channel" => match self.channel {
+                Some(channel) => Variant::from(channel as i64),
+                None => Variant::nil(),
+            },
+            "message" => self.message.clone(),
+        }
+    }
+
+    fn to_json_line(&self) -> String {
+        let mut line = serde_json::json!({
+            "timestamp": self.timestamp,
+            "level": self.level.as_str(),
+            "event": self.event.as_str(),
+            "host": self.host,
+            "port": self.port,
+            "channel": self.channel,
+            "message": self.message,
+        })
+        .to_string();
+        line.push('\n');
+        line
+    }
+}
+
+struct LoggerState {
+    level: LogLevel,
+    file: Option<fs::File>,
+    /// Object the `log_record` signal is emitted on.
+    emitter: Option<InstanceId>,
+}
+
+/// Leveled logger shared between a client and its session handler.
+///
+/// Records are emitted as `log_record` signal on the emitter object and optionally appended
+/// to a JSON-lines file. As records are created on whatever thread the session runs on,
+/// the signal is emitted deferred, so it is always received on the main thread.
+#[derive(Clone)]
+pub struct Logger {
+    state: Arc<Mutex<LoggerState>>,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(LoggerState {
+                level: LogLevel::Warn,
+                file: None,
+                emitter: None,
+            })),
+        }
+    }
+}
+
+impl Logger {
+    pub fn set_emitter(&self, emitter: InstanceId) {
+        if let Ok(mut state) = self.state.lock() {
+            state.emitter = Some(emitter);
+        }
+    }
+
+    /// The object signals of the client are emitted on.
+    pub fn emitter(&self) -> Option<InstanceId> {
+        self.state.lock().ok().and_then(|state| state.emitter)
+    }
+
+    pub fn set_level(&self, level: LogLevel) {
+        if let Ok(mut state) = self.state.lock() {
+            state.level = level;
+        }
+    }
+
+    pub fn level(&self) -> LogLevel {
+        match self.state.lock() {
+            Ok(state) => state.level,
+            Err(_) => LogLevel::Off,
+        }
+    }
+
+    /// Appends all records to the file at `path`. None stops writing to a file.
+    pub fn set_file(&self, path: Option<&Path>) -> anyhow::Result<()> {
+        let file = match path {
+            Some(path) => match fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .mode(0o600)
+                .open(path)
+            {
+                Ok(file) => Some(file),
+                Err(e) => anyhow::bail!("Failed to open log file {}: {}", path.display(), e),
+            },
+            None => None,
+        };
+        match self.state.lock() {
+            Ok(mut state) => {
+                state.file = file;
+                Ok(())
+            }
+            Err(_) => anyhow::bail!("Logger is poisoned"),
+        }
+    }
+
+    /// Logs a record if `level` is enabled. `message` is only evaluated in that case.
+    pub fn log(
+        &self,
+        level: LogLevel,
+        event: LogEvent,
+        host: &str,
+        port: u16,
+        channel: Option<ChannelId>,
+        message: impl FnOnce() -> String,
+    ) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if level < state.level || level == LogLevel::Off {
+            return;
+        }
+        let record = LogRecord {
+            timestamp: Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            level,
+            event,
+            host: host.to_string(),
+            port,
+            channel: channel.map(u32::from),
+            message: message(),
+        };
+
+        if let Some(file) = &mut state.file {
+            if let Err(e) = file.write_all(record.to_json_line().as_bytes()) {
+                godot_error!("Failed to write SSH log file: {}", e);
+                state.file = None;
+            }
+        }
+        if let Some(emitter) = state.emitter {
+            if let Ok(mut object) = Gd::<Object>::try_from_instance_id(emitter) {
+                object.call_deferred(
+                    "emit_signal",
+                    &["log_record".to_variant(), record.to_dict().to_variant()],
+                );
+            }
+        }
+    }
+
+    pub fn debug(&self, event: LogEvent, host: &str, port: u16, message: impl FnOnce() -> String) {
+        self.log(LogLevel::Debug, event, host, port, None, message);
+    }
+
+    pub fn info(&self, event: LogEvent, host: &str, port: u16, message: impl FnOnce() -> String) {
+        self.log(LogLevel::Info, event, host, port, None, message);
+    }
+
+    pub fn warn(&self, event: LogEvent, host: &str, port: u16, message: impl FnOnce() -> String) {
+        self.log(LogLevel::Warn, event, host, port, None, message);
+    }
+
+    pub fn error(&self, event: LogEvent, host: &str, port: u16, message: impl FnOnce() -> String) {
+        self.log(LogLevel::Error, event, host, port, None, message);
+    }
+}
@@ -0,0 +1,276 @@
+use crate::internal_ssh_client::InternalSSHClient;
+use crate::logger::LogEvent;
+use crate::remote_info::RemoteOs;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Port magic packets are sent to if the broadcast address has none.
+const WAKE_PORT: u16 = 9;
+/// Timeout of a single connection attempt while knocking or waiting for the port.
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+/// Pause between two knocks, so they arrive in order.
+const KNOCK_DELAY: Duration = Duration::from_millis(100);
+
+/// Steps run before a session is opened, e.g. to wake a sleeping server.
+#[derive(Clone, Debug)]
+pub struct PreConnect {
+    /// MAC address and broadcast address to send a Wake-on-LAN magic packet to.
+    pub wake: Option<([u8; 6], SocketAddr)>,
+    /// Ports knocked in order.
+    pub knock: Vec<Knock>,
+    /// How long to wait for the SSH port to answer after waking or knocking.
+    pub wait_timeout: Duration,
+}
+
+impl Default for PreConnect {
+    fn default() -> Self {
+        Self {
+            wake: None,
+            knock: Vec::new(),
+            wait_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Knock {
+    pub port: u16,
+    pub udp: bool,
+}
+
+impl Knock {
+    /// Parses a knock like "7000", "7000/tcp" or "7000/udp".
+    pub fn parse(knock: &str) -> anyhow::Result<Self> {
+        let (port, protocol) = knock
+            .trim()
+            .split_once('/')
+            .unwrap_or((knock.trim(), "tcp"));
+        let port = match port.parse::<u16>() {
+            Ok(port) => port,
+            Err(e) => anyhow::bail!("Invalid knock port \"{}\": {}", port, e),
+        };
+        match protocol.to_ascii_lowercase().as_str() {
+            "tcp" => Ok(Knock { port, udp: false }),
+            "udp" => Ok(Knock { port, udp: true }),
+            _ => anyhow::bail!("Invalid knock protocol: {}", protocol),
+        }
+    }
+}
+
+/// Parses a MAC address like "00:11:22:33:44:55" or "00-11-22-33-44-55".
+pub fn parse_mac(mac: &str) -> anyhow::Result<[u8; 6]> {
+    let octets: Vec<&str> = mac.trim().split([':', '-']).collect();
+    if octets.len() != 6 {
+        anyhow::bail!("Invalid MAC address: {}", mac);
+    }
+    let mut parsed = [0; 6];
+    for (octet, parsed) in octets.iter().zip(parsed.iter_mut()) {
+        *parsed = match u8::from_str_radix(octet, 16) {
+            Ok(parsed) if octet.len() == 2 => parsed,
+            _ => anyhow::bail!("Invalid MAC address: {}", mac),
+        };
+    }
+    Ok(parsed)
+}
+
+/// Parses a broadcast address like "192.168.1.255" or "192.168.1.255:7".
+pub fn parse_broadcast(broadcast: &str) -> anyhow::Result<SocketAddr> {
+    let broadcast = broadcast.trim();
+    if let Ok(address) = broadcast.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+    match (broadcast, WAKE_PORT)
+        .to_socket_addrs()
+        .map(|mut addrs| addrs.next())
+    {
+        Ok(Some(address)) => Ok(address),
+        Ok(None) => anyhow::bail!("Invalid broadcast address: {}", broadcast),
+        Err(e) => anyhow::bail!("Invalid broadcast address {}: {}", broadcast, e),
+    }
+}
+
+/// A magic packet is 6 bytes of 0xFF followed by the MAC address repeated 16 times.
+fn magic_packet(mac: &[u8; 6]) -> Vec<u8> {
+    let mut packet = vec![0xFF; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(mac);
+    }
+    packet
+}
+
+pub fn send_magic_packet(mac: &[u8; 6], broadcast: SocketAddr) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind(unspecified_for(&broadcast))?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&magic_packet(mac), broadcast)?;
+    Ok(())
+}
+
+/// Any local address of the same family as `addr`, to send UDP packets from.
+fn unspecified_for(addr: &SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    }
+}
+
+/// Actions to change the power state of the server.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PowerAction {
+    Shutdown,
+    Reboot,
+    Suspend,
+}
+
+impl PowerAction {
+    pub fn parse(action: &str) -> anyhow::Result<Self> {
+        match action {
+            "shutdown" => Ok(PowerAction::Shutdown),
+            "reboot" => Ok(PowerAction::Reboot),
+            "suspend" => Ok(PowerAction::Suspend),
+            _ => anyhow::bail!("Unknown power action: {}", action),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PowerAction::Shutdown => "shutdown",
+            PowerAction::Reboot => "reboot",
+            PowerAction::Suspend => "suspend",
+        }
+    }
+
+    /// Command performing the action on `os`, None if it's not supported.
+    fn command(&self, os: RemoteOs) -> Option<&'static str> {
+        match (os, self) {
+            (RemoteOs::Linux, PowerAction::Shutdown) => Some("systemctl poweroff"),
+            (RemoteOs::Linux, PowerAction::Reboot) => Some("systemctl reboot"),
+            (RemoteOs::Linux, PowerAction::Suspend) => Some("systemctl suspend"),
+            (RemoteOs::MacOs, PowerAction::Suspend) => Some("pmset sleepnow"),
+            (RemoteOs::Bsd, PowerAction::Suspend) => Some("zzz"),
+            (RemoteOs::Windows, PowerAction::Shutdown) => Some("shutdown /s /t 0"),
+            (RemoteOs::Windows, PowerAction::Reboot) => Some("shutdown /r /t 0"),
+            (RemoteOs::Windows, PowerAction::Suspend) => {
+                Some("rundll32.exe powrprof.dll,SetSuspendState 0,1,0")
+            }
+            (RemoteOs::Other, PowerAction::Suspend) => None,
+            (_, PowerAction::Shutdown) => Some("shutdown -h now"),
+            (_, PowerAction::Reboot) => Some("shutdown -r now"),
+        }
+    }
+}
+
+impl InternalSSHClient {
+    /// Runs the configured pre-connect steps: sends the Wake-on-LAN packet, knocks the ports
+    /// and then waits until `port` answers. Does nothing if neither waking nor knocking is configured.
+    pub fn run_pre_connect(&self, ip: &String, port: u16) -> anyhow::Result<()> {
+        if self.pre_connect.wake.is_none() && self.pre_connect.knock.is_empty() {
+            return Ok(());
+        }
+
+        if let Some((mac, broadcast)) = &self.pre_connect.wake {
+            if let Err(e) = send_magic_packet(mac, *broadcast) {
+                anyhow::bail!("Failed to send Wake-on-LAN packet to {}: {}", broadcast, e);
+            }
+            self.log.debug(LogEvent::Connect, ip, port, || {
+                format!("Sent Wake-on-LAN packet to {}", broadcast)
+            });
+        }
+
+        let addrs: Vec<SocketAddr> = match (ip.as_str(), port).to_socket_addrs() {
+            Ok(addrs) => addrs.collect(),
+            Err(e) => anyhow::bail!("Failed to resolve {}: {}", ip, e),
+        };
+        let Some(addr) = addrs.first() else {
+            anyhow::bail!("Failed to resolve {}", ip);
+        };
+
+        for knock in &self.pre_connect.knock {
+            let target = SocketAddr::new(addr.ip(), knock.port);
+            // Knocks are only seen by the firewall, so failing to connect is expected
+            if knock.udp {
+                if let Err(e) = UdpSocket::bind(unspecified_for(&target))
+                    .and_then(|socket| socket.send_to(&[], target))
+                {
+                    anyhow::bail!("Failed to knock on UDP port {}: {}", knock.port, e);
+                }
+            } else {
+                let _ = TcpStream::connect_timeout(&target, CONNECT_TIMEOUT);
+            }
+            thread::sleep(KNOCK_DELAY);
+        }
+        if !self.pre_connect.knock.is_empty() {
+            self.log.debug(LogEvent::Connect, ip, port, || {
+                format!("Knocked {} ports", self.pre_connect.knock.len())
+            });
+        }
+
+        let start = Instant::now();
+        loop {
+            if addrs
+                .iter()
+                .any(|addr| TcpStream::connect_timeout(addr, CONNECT_TIMEOUT).is_ok())
+            {
+                self.log.debug(LogEvent::Connect, ip, port, || {
+                    format!("Port answered after {:.1}s", start.elapsed().as_secs_f64())
+                });
+                return Ok(());
+            }
+            if start.elapsed() >= self.pre_connect.wait_timeout {
+                anyhow::bail!(
+                    "Port {} didn't answer within {}s",
+                    port,
+                    self.pre_connect.wait_timeout.as_secs()
+                );
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    /// Shuts down, reboots or suspends the server with the command of its OS.
+    /// As the server may drop the connection before reporting an exit status, that counts as success.
+    ///
+    /// * `elevated` - Run the command with sudo, only supported on unix servers.
+    pub fn power_action(
+        &mut self,
+        action: PowerAction,
+        elevated: bool,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<()> {
+        let remote_info = self.remote_info(ip, user, port)?;
+        let Some(cmd) = action.command(remote_info.os) else {
+            anyhow::bail!(
+                "{} isn't supported on {}",
+                action.as_str(),
+                remote_info.os_name
+            );
+        };
+
+        let output = if elevated {
+            if !remote_info.os.is_unix() {
+                anyhow::bail!("Elevated commands are only supported on unix servers");
+            }
+            let output = self.exec_ssh_elevated(cmd, "sh", ip, user, port)?;
+            if let Some(sudo_error) = output.sudo_error {
+                anyhow::bail!("Couldn't {}: {}", action.as_str(), sudo_error.message());
+            }
+            output.output
+        } else {
+            self.exec_ssh_output(cmd.to_string(), ip, user, port)?
+        };
+        if output.exit_status > 0 {
+            anyhow::bail!(
+                "Couldn't {} (exit status {}): {}",
+                action.as_str(),
+                output.exit_status,
+                output.stderr.trim()
+            );
+        }
+        self.log.info(LogEvent::Exec, ip, port, || {
+            format!("Requested {}", action.as_str())
+        });
+        Ok(())
+    }
+}
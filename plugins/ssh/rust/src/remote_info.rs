@@ -0,0 +1,225 @@
+use crate::internal_ssh_client::InternalSSHClient;
+use crate::logger::LogEvent;
+use godot::prelude::*;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RemoteOs {
+    Linux,
+    MacOs,
+    Bsd,
+    Windows,
+    Other,
+}
+
+impl RemoteOs {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RemoteOs::Linux => "linux",
+            RemoteOs::MacOs => "macos",
+            RemoteOs::Bsd => "bsd",
+            RemoteOs::Windows => "windows",
+            RemoteOs::Other => "other",
+        }
+    }
+
+    pub fn is_unix(&self) -> bool {
+        !matches!(self, RemoteOs::Windows)
+    }
+}
+
+/// The shell the server runs exec requests in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RemoteShell {
+    Sh,
+    Bash,
+    Zsh,
+    Fish,
+    Cmd,
+    PowerShell,
+    Other,
+}
+
+impl RemoteShell {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RemoteShell::Sh => "sh",
+            RemoteShell::Bash => "bash",
+            RemoteShell::Zsh => "zsh",
+            RemoteShell::Fish => "fish",
+            RemoteShell::Cmd => "cmd",
+            RemoteShell::PowerShell => "powershell",
+            RemoteShell::Other => "other",
+        }
+    }
+
+    fn from_path(path: &str) -> Self {
+        let name = path
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match name.trim_end_matches(".exe") {
+            "sh" | "dash" | "ash" | "ksh" | "mksh" => RemoteShell::Sh,
+            "bash" => RemoteShell::Bash,
+            "zsh" => RemoteShell::Zsh,
+            "fish" => RemoteShell::Fish,
+            "cmd" => RemoteShell::Cmd,
+            "powershell" | "pwsh" => RemoteShell::PowerShell,
+            _ => RemoteShell::Other,
+        }
+    }
+}
+
+/// Information about the server, detected by [InternalSSHClient::remote_info].
+#[derive(Clone, Debug)]
+pub struct RemoteInfo {
+    pub os: RemoteOs,
+    /// Raw OS name, e.g. the output of `uname -s`.
+    pub os_name: String,
+    pub shell: RemoteShell,
+    /// Path of the shell if it could be detected.
+    pub shell_path: String,
+    /// Normalized architecture, e.g. "x86_64" or "aarch64".
+    pub arch: String,
+    pub hostname: String,
+}
+
+impl RemoteInfo {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        dict! {
+            "os" => self.os.as_str().to_string(),
+            "os_name" => self.os_name.clone(),
+            "shell" => self.shell.as_str().to_string(),
+            "shell_path" => self.shell_path.clone(),
+            "arch" => self.arch.clone(),
+            "hostname" => self.hostname.clone(),
+        }
+    }
+}
+
+impl InternalSSHClient {
+    /// Returns the remote info of the current session, probing the server if it wasn't probed yet.
+    /// The result is cached until the session is closed.
+    pub fn remote_info(
+        &mut self,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<RemoteInfo> {
+        // Make sure the cache belongs to an open session
+        if self
+            .session
+            .as_ref()
+            .is_none_or(|session| session.is_closed())
+        {
+            self.remote_info = None;
+        }
+        if let Some(remote_info) = &self.remote_info {
+            return Ok(remote_info.clone());
+        }
+
+        let remote_info = self.probe_remote(ip, user, port)?;
+        self.log.debug(LogEvent::RemoteInfo, ip, port, || {
+            format!(
+                "Detected remote {} ({}) with shell {}",
+                remote_info.os_name,
+                remote_info.arch,
+                remote_info.shell.as_str()
+            )
+        });
+        self.remote_info = Some(remote_info.clone());
+        Ok(remote_info)
+    }
+
+    /// Detects OS, shell, architecture and hostname of the server.
+    ///
+    /// Unix servers are detected by `uname`, which also works with every common login shell.
+    /// On Windows cmd expands `%VAR%` while PowerShell prints it as is, which is used to tell them apart.
+    fn probe_remote(
+        &mut self,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<RemoteInfo> {
+        let output =
+            self.exec_ssh_output("uname -snm; echo \"$SHELL\"".to_string(), ip, user, port)?;
+        if output.exit_status == 0 {
+            let mut lines = output.stdout.lines();
+            let uname: Vec<&str> = lines
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect();
+            let shell_path = lines.next().unwrap_or_default().trim().to_string();
+            let os_name = uname.first().copied().unwrap_or_default().to_string();
+            let os = match os_name.as_str() {
+                "Linux" => RemoteOs::Linux,
+                "Darwin" => RemoteOs::MacOs,
+                name if name.ends_with("BSD") => RemoteOs::Bsd,
+                // Cygwin/MSYS sshd on Windows
+                name if name.starts_with("CYGWIN") || name.starts_with("MINGW") => {
+                    RemoteOs::Windows
+                }
+                _ => RemoteOs::Other,
+            };
+            return Ok(RemoteInfo {
+                os,
+                os_name,
+                shell: RemoteShell::from_path(&shell_path),
+                shell_path,
+                arch: normalize_arch(uname.last().copied().unwrap_or_default()),
+                hostname: uname.get(1).copied().unwrap_or_default().to_string(),
+            });
+        }
+
+        let output = self.exec_ssh_output(
+            "echo %COMSPEC% %PROCESSOR_ARCHITECTURE% %COMPUTERNAME%".to_string(),
+            ip,
+            user,
+            port,
+        )?;
+        if !output.stdout.trim_start().starts_with('%') {
+            let values: Vec<&str> = output.stdout.split_whitespace().collect();
+            if values.len() < 3 {
+                anyhow::bail!("Failed to detect remote platform: {}", output.stderr.trim());
+            }
+            return Ok(RemoteInfo {
+                os: RemoteOs::Windows,
+                os_name: "Windows".to_string(),
+                shell: RemoteShell::Cmd,
+                shell_path: values[0].to_string(),
+                arch: normalize_arch(values[1]),
+                hostname: values[2].to_string(),
+            });
+        }
+
+        let output = self.exec_ssh_output(
+            "(Get-Process -Id $PID).Path; $env:PROCESSOR_ARCHITECTURE; $env:COMPUTERNAME"
+                .to_string(),
+            ip,
+            user,
+            port,
+        )?;
+        let values: Vec<&str> = output.stdout.lines().map(|line| line.trim()).collect();
+        if output.exit_status != 0 || values.len() < 3 {
+            anyhow::bail!("Failed to detect remote platform: {}", output.stderr.trim());
+        }
+        Ok(RemoteInfo {
+            os: RemoteOs::Windows,
+            os_name: "Windows".to_string(),
+            shell: RemoteShell::PowerShell,
+            shell_path: values[0].to_string(),
+            arch: normalize_arch(values[1]),
+            hostname: values[2].to_string(),
+        })
+    }
+}
+
+fn normalize_arch(arch: &str) -> String {
+    match arch.to_ascii_lowercase().as_str() {
+        "x86_64" | "amd64" | "x64" => "x86_64".to_string(),
+        "aarch64" | "arm64" => "aarch64".to_string(),
+        "i386" | "i686" | "x86" => "x86".to_string(),
+        other => other.to_string(),
+    }
+}
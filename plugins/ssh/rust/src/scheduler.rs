@@ -0,0 +1,512 @@
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Timelike};
+use godot::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// How far ahead the next run of a cron expression is searched, so expressions like "0 0 30 2 *" end.
+const SEARCH_YEARS: i64 = 5;
+/// How late a run may start before it counts as missed.
+const MISSED_GRACE: TimeDelta = TimeDelta::seconds(60);
+
+/// When a job runs.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Schedule {
+    Cron(CronExpr),
+    Interval(Duration),
+}
+
+impl Schedule {
+    /// Parses a 5 field cron expression like "30 2 * * 1-5", a macro like "@daily"
+    /// or an interval like "@every 1h30m".
+    pub fn parse(schedule: &str) -> anyhow::Result<Self> {
+        let schedule = schedule.trim();
+        let expr = match schedule {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            schedule => match schedule.strip_prefix("@every") {
+                Some(interval) => return Ok(Schedule::Interval(parse_interval(interval.trim())?)),
+                None => schedule,
+            },
+        };
+        Ok(Schedule::Cron(CronExpr::parse(expr)?))
+    }
+
+    /// Returns the first run after `time`, None if there is none.
+    pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Schedule::Cron(expr) => expr.next_after(time),
+            Schedule::Interval(interval) => {
+                time.checked_add_signed(TimeDelta::from_std(*interval).ok()?)
+            }
+        }
+    }
+}
+
+/// Parses a duration like "90s", "30m" or "1h30m". Units are "s", "m", "h" and "d".
+fn parse_interval(interval: &str) -> anyhow::Result<Duration> {
+    let mut seconds = 0u64;
+    let mut number = String::new();
+    for c in interval.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => anyhow::bail!("Invalid interval unit '{}' in \"{}\"", c, interval),
+        };
+        let Ok(value) = number.parse::<u64>() else {
+            anyhow::bail!("Invalid interval: {}", interval);
+        };
+        seconds = seconds.saturating_add(value.saturating_mul(unit));
+        number.clear();
+    }
+    if !number.is_empty() || seconds == 0 {
+        anyhow::bail!("Invalid interval: {}", interval);
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// A parsed cron expression. Every field is a bitmask of the allowed values.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month or day of week field is "*". If both are restricted,
+    /// a day matching either is run, like cron does.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronExpr {
+    pub fn parse(expr: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            anyhow::bail!("Cron expression needs 5 fields: {}", expr);
+        };
+        let mut weekdays = parse_field(weekday, 0, 7, &WEEKDAY_NAMES)?;
+        // Both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59, &[])?,
+            hours: parse_field(hour, 0, 23, &[])?,
+            days: parse_field(day, 1, 31, &[])?,
+            months: parse_field(month, 1, 12, &MONTH_NAMES)?,
+            weekdays,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let time = time.naive_local();
+        let mut next =
+            time.date().and_hms_opt(time.hour(), time.minute(), 0)? + TimeDelta::minutes(1);
+        let limit = next + TimeDelta::days(366 * SEARCH_YEARS);
+        while next < limit {
+            if self.months & (1 << next.month()) == 0 {
+                next = start_of_next_month(next)?;
+            } else if !self.matches_day(next.date()) {
+                next = next.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << next.hour()) == 0 {
+                next = next.date().and_hms_opt(next.hour(), 0, 0)? + TimeDelta::hours(1);
+            } else if self.minutes & (1 << next.minute()) == 0 {
+                next += TimeDelta::minutes(1);
+            } else {
+                // Times skipped by a DST change don't exist, repeated ones run once
+                match Local.from_local_datetime(&next).earliest() {
+                    Some(next) => return Some(next),
+                    None => next += TimeDelta::minutes(1),
+                }
+            }
+        }
+        None
+    }
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Parses a cron field like "*", "*/15", "1,15", "mon-fri" or "8-18/2" into a bitmask.
+/// `names` are alternatives for the values starting at `min`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> anyhow::Result<u64> {
+    let value = |value: &str| -> anyhow::Result<u32> {
+        let lower = value.to_ascii_lowercase();
+        if let Some(index) = names.iter().position(|name| *name == lower) {
+            return Ok(index as u32 + min);
+        }
+        match value.parse::<u32>() {
+            Ok(value) if (min..=max).contains(&value) => Ok(value),
+            _ => anyhow::bail!("Invalid value \"{}\" in cron field \"{}\"", value, field),
+        }
+    };
+
+    let mut mask = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => anyhow::bail!("Invalid step in cron field \"{}\"", field),
+            },
+            None => (item, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            // "5/10" means every 10th value from 5 on
+            None if item.contains('/') => (value(range)?, max),
+            None => {
+                let value = value(range)?;
+                (value, value)
+            }
+        };
+        if start > end {
+            anyhow::bail!("Invalid range in cron field \"{}\"", field);
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+fn start_of_next_month(time: NaiveDateTime) -> Option<NaiveDateTime> {
+    let (year, month) = match time.month() {
+        12 => (time.year() + 1, 1),
+        month => (time.year(), month + 1),
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)
+}
+
+/// First due run of a job, continuing from its last due run if there is one.
+/// Interval jobs which never ran are due right away.
+pub fn first_due(
+    schedule: &Schedule,
+    last_due: Option<i64>,
+    now: DateTime<Local>,
+) -> Option<DateTime<Local>> {
+    match last_due.and_then(|last_due| DateTime::from_timestamp(last_due, 0)) {
+        Some(last_due) => schedule.next_after(last_due.with_timezone(&Local)),
+        None => match schedule {
+            Schedule::Interval(_) => Some(now),
+            Schedule::Cron(_) => schedule.next_after(now),
+        },
+    }
+}
+
+/// Returns whether the run due at `due` was missed and when the next run is due.
+/// All runs missed until `now` are caught up by one run, so the next run is the first one after `now`.
+pub fn catch_up(
+    schedule: &Schedule,
+    due: DateTime<Local>,
+    now: DateTime<Local>,
+) -> (bool, Option<DateTime<Local>>) {
+    let missed = now - due > MISSED_GRACE;
+    (missed, schedule.next_after(if missed { now } else { due }))
+}
+
+/// What to do with a run which was missed, e.g. because the application wasn't running.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MissedPolicy {
+    /// Skip all missed runs and wait for the next one.
+    Skip,
+    /// Run once for all missed runs.
+    RunOnce,
+}
+
+impl MissedPolicy {
+    pub fn parse(policy: &str) -> anyhow::Result<Self> {
+        match policy {
+            "skip" => Ok(MissedPolicy::Skip),
+            "run_once" => Ok(MissedPolicy::RunOnce),
+            _ => anyhow::bail!("Unknown missed run policy: {}", policy),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MissedPolicy::Skip => "skip",
+            MissedPolicy::RunOnce => "run_once",
+        }
+    }
+}
+
+/// Persisted state of a job.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct JobRecord {
+    /// Unix time of the last run which was due, whether it ran or was skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_due: Option<i64>,
+    /// Unix time the last run started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_status: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Seconds the last run took.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+}
+
+impl JobRecord {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        let optional = |value: Option<Variant>| value.unwrap_or_default();
+        dict! {
+            "last_run" => optional(self.last_run.map(Variant::from)),
+            "last_exit_status" => optional(self.exit_status.map(Variant::from)),
+            "last_error" => optional(self.error.clone().map(Variant::from)),
+            "last_duration" => optional(self.duration.map(Variant::from)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct StateFile {
+    jobs: HashMap<String, JobRecord>,
+}
+
+/// Loads the job records from `path`, an empty state if the file doesn't exist yet.
+pub fn load_records(path: &Path) -> anyhow::Result<HashMap<String, JobRecord>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => anyhow::bail!("Failed to read scheduler state {}: {}", path.display(), e),
+    };
+    match serde_json::from_str::<StateFile>(&content) {
+        Ok(state) => Ok(state.jobs),
+        Err(e) => anyhow::bail!("Failed to parse scheduler state {}: {}", path.display(), e),
+    }
+}
+
+/// Writes the job records to `path` atomically.
+pub fn save_records(path: &Path, records: &HashMap<String, JobRecord>) -> anyhow::Result<()> {
+    let content = serde_json::to_string_pretty(&StateFile {
+        jobs: records.clone(),
+    })?;
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            anyhow::bail!("Failed to create {}: {}", parent.display(), e);
+        }
+    }
+    let tmp_path = path.with_extension("tmp");
+    let mut tmp_file = match fs::File::create(&tmp_path) {
+        Ok(tmp_file) => tmp_file,
+        Err(e) => anyhow::bail!(
+            "Failed to write scheduler state {}: {}",
+            tmp_path.display(),
+            e
+        ),
+    };
+    tmp_file.write_all(content.as_bytes())?;
+    tmp_file.sync_all()?;
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        anyhow::bail!("Failed to write scheduler state {}: {}", path.display(), e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn next(schedule: &str, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        Schedule::parse(schedule).unwrap().next_after(after)
+    }
+
+    #[test]
+    fn parses_fields() {
+        assert_eq!(
+            parse_field("*/15", 0, 59, &[]).unwrap(),
+            1 | 1 << 15 | 1 << 30 | 1 << 45
+        );
+        assert_eq!(parse_field("1,3-5", 0, 59, &[]).unwrap(), 0b111010);
+        assert_eq!(
+            parse_field("8-18/5", 0, 23, &[]).unwrap(),
+            1 << 8 | 1 << 13 | 1 << 18
+        );
+        assert_eq!(parse_field("50/5", 0, 59, &[]).unwrap(), 1 << 50 | 1 << 55);
+        assert_eq!(
+            parse_field("Mon-fri", 0, 7, &WEEKDAY_NAMES).unwrap(),
+            0b111110
+        );
+        assert_eq!(parse_field("dec", 1, 12, &MONTH_NAMES).unwrap(), 1 << 12);
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for expr in [
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "* * * * sun-",
+            "@every",
+            "@every 0s",
+            "@every 90",
+            "@every 1w",
+        ] {
+            assert!(Schedule::parse(expr).is_err(), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn sunday_is_0_and_7() {
+        // 2025-01-03 is a Friday
+        let friday = local(2025, 1, 3, 12, 0);
+        assert_eq!(next("0 0 * * 7", friday), Some(local(2025, 1, 5, 0, 0)));
+        assert_eq!(next("@weekly", friday), Some(local(2025, 1, 5, 0, 0)));
+    }
+
+    #[test]
+    fn parses_intervals() {
+        assert_eq!(
+            Schedule::parse("@every 1h30m").unwrap(),
+            Schedule::Interval(Duration::from_secs(5400))
+        );
+        assert_eq!(
+            Schedule::parse("@every 1d10s").unwrap(),
+            Schedule::Interval(Duration::from_secs(86410))
+        );
+    }
+
+    #[test]
+    fn huge_intervals_never_run() {
+        let now = local(2025, 1, 3, 12, 0);
+        assert_eq!(next("@every 9999999999d", now), None);
+        assert_eq!(next("@every 18446744073709551615s", now), None);
+    }
+
+    #[test]
+    fn next_run_of_cron_expression() {
+        // 2025-01-03 is a Friday
+        let friday = local(2025, 1, 3, 12, 0);
+        assert_eq!(next("30 2 * * 1-5", friday), Some(local(2025, 1, 6, 2, 30)));
+        assert_eq!(
+            next("*/15 * * * *", friday),
+            Some(local(2025, 1, 3, 12, 15))
+        );
+        assert_eq!(
+            next("@hourly", local(2025, 1, 3, 12, 59)),
+            Some(local(2025, 1, 3, 13, 0))
+        );
+        assert_eq!(next("@monthly", friday), Some(local(2025, 2, 1, 0, 0)));
+        assert_eq!(next("0 12 29 2 *", friday), Some(local(2028, 2, 29, 12, 0)));
+        assert_eq!(next("0 0 30 2 *", friday), None);
+    }
+
+    #[test]
+    fn restricted_day_and_weekday_match_either() {
+        let new_year = local(2025, 1, 1, 12, 0);
+        // The first Friday comes before the 13th
+        assert_eq!(
+            next("0 0 13 * fri", new_year),
+            Some(local(2025, 1, 3, 0, 0))
+        );
+        assert_eq!(next("0 0 13 * *", new_year), Some(local(2025, 1, 13, 0, 0)));
+    }
+
+    #[test]
+    fn first_due_continues_from_last_due() {
+        let now = local(2025, 1, 10, 12, 0);
+        let daily = Schedule::parse("@daily").unwrap();
+        let every_hour = Schedule::parse("@every 1h").unwrap();
+        let last_due = local(2025, 1, 7, 0, 0).timestamp();
+        assert_eq!(
+            first_due(&daily, Some(last_due), now),
+            Some(local(2025, 1, 8, 0, 0))
+        );
+        assert_eq!(first_due(&daily, None, now), Some(local(2025, 1, 11, 0, 0)));
+        assert_eq!(first_due(&every_hour, None, now), Some(now));
+        assert_eq!(
+            first_due(&every_hour, Some(last_due), now),
+            Some(local(2025, 1, 7, 1, 0))
+        );
+    }
+
+    #[test]
+    fn missed_runs_are_caught_up_once() {
+        let now = local(2025, 1, 10, 12, 0);
+        let daily = Schedule::parse("@daily").unwrap();
+        let due = first_due(&daily, Some(local(2025, 1, 7, 0, 0).timestamp()), now).unwrap();
+        assert_eq!(
+            catch_up(&daily, due, now),
+            (true, Some(local(2025, 1, 11, 0, 0)))
+        );
+    }
+
+    #[test]
+    fn late_runs_within_grace_are_not_missed() {
+        let daily = Schedule::parse("@daily").unwrap();
+        let due = local(2025, 1, 10, 0, 0);
+        assert_eq!(
+            catch_up(&daily, due, due + TimeDelta::seconds(30)),
+            (false, Some(local(2025, 1, 11, 0, 0)))
+        );
+        let every_hour = Schedule::parse("@every 1h").unwrap();
+        assert_eq!(
+            catch_up(&every_hour, due, due + TimeDelta::hours(2)),
+            (true, Some(due + TimeDelta::hours(3)))
+        );
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let dir = std::env::temp_dir().join(format!("dreamdeck-scheduler-{}", std::process::id()));
+        let path = dir.join("state.json");
+        assert!(load_records(&path).unwrap().is_empty());
+        let mut records = HashMap::new();
+        records.insert(
+            "backup".to_string(),
+            JobRecord {
+                last_due: Some(1_700_000_000),
+                exit_status: Some(0),
+                ..Default::default()
+            },
+        );
+        save_records(&path, &records).unwrap();
+        let loaded = load_records(&path).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(loaded["backup"].last_due, Some(1_700_000_000));
+        assert_eq!(loaded["backup"].exit_status, Some(0));
+        assert_eq!(loaded["backup"].last_run, None);
+    }
+}
@@ -0,0 +1,339 @@
+use crate::capture::CaptureLimits;
+use crate::internal_ssh_client::ClientAccess;
+use crate::logger::LogEvent;
+use godot::prelude::*;
+use std::time::{Duration, Instant};
+
+/// Maximum bytes of stdout and stderr each kept per command.
+const OUTPUT_LIMIT: usize = 64 * 1024;
+
+/// A step of a script.
+pub struct ScriptStep {
+    pub name: String,
+    pub command: String,
+    pub timeout: Option<Duration>,
+    /// Exit codes which count as success.
+    pub expected_exit_codes: Vec<i64>,
+    /// Command undoing the step, run if the script fails.
+    pub rollback: Option<String>,
+}
+
+impl ScriptStep {
+    /// Reads a step from a [Dictionary] as described in `SSHClient::run_script`.
+    pub fn from_dict(index: usize, dict: &Dictionary<GString, Variant>) -> anyhow::Result<Self> {
+        let string = |key: &str| {
+            dict.get(key)
+                .and_then(|value| value.try_to::<String>().ok())
+                .filter(|value| !value.is_empty())
+        };
+        let Some(command) = string("command") else {
+            anyhow::bail!("Step {} has no command", index);
+        };
+        // Timeouts may be passed as int or float
+        let timeout = dict.get("timeout").map(|timeout| {
+            timeout
+                .try_to::<f64>()
+                .or_else(|_| timeout.try_to::<i64>().map(|timeout| timeout as f64))
+        });
+        let timeout = match timeout {
+            None => None,
+            Some(Ok(timeout)) if timeout > 0.0 => match Duration::try_from_secs_f64(timeout) {
+                Ok(timeout) => Some(timeout),
+                Err(e) => anyhow::bail!("Step {} has an invalid timeout: {}", index, e),
+            },
+            Some(Ok(_)) => None,
+            Some(Err(_)) => anyhow::bail!("Step {} has an invalid timeout", index),
+        };
+        let expected_exit_codes = match dict.get("expected_exit_codes") {
+            None => vec![0],
+            Some(codes) => match exit_codes(&codes) {
+                Some(codes) => codes,
+                None => anyhow::bail!("Step {} has invalid expected exit codes", index),
+            },
+        };
+        Ok(Self {
+            name: string("name").unwrap_or_else(|| format!("Step {}", index)),
+            command,
+            timeout,
+            expected_exit_codes,
+            rollback: string("rollback"),
+        })
+    }
+}
+
+/// Reads exit codes from an [Array] or packed int array.
+fn exit_codes(codes: &Variant) -> Option<Vec<i64>> {
+    if let Ok(codes) = codes.try_to::<PackedInt32Array>() {
+        return Some(codes.as_slice().iter().map(|code| *code as i64).collect());
+    }
+    if let Ok(codes) = codes.try_to::<PackedInt64Array>() {
+        return Some(codes.to_vec());
+    }
+    codes
+        .try_to::<Array<Variant>>()
+        .ok()?
+        .iter_shared()
+        .map(|code| code.try_to::<i64>().ok())
+        .collect()
+}
+
+/// Outcome of a single command of a script.
+pub struct CommandReport {
+    pub command: String,
+    pub success: bool,
+    /// -1 if the server didn't send an exit status.
+    pub exit_status: i64,
+    pub stdout: String,
+    pub stderr: String,
+    /// Set if the command couldn't be run or timed out.
+    pub error: Option<String>,
+    pub duration: f64,
+}
+
+impl CommandReport {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        dict! {
+            "command" => self.command.clone(),
+            "success" => self.success,
+            "exit_status" => self.exit_status,
+            "stdout" => self.stdout.clone(),
+            "stderr" => self.stderr.clone(),
+            "error" => match &self.error {
+                Some(error) => Variant::from(error.clone()),
+                None => Variant::nil(),
+            },
+            "duration" => self.duration,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StepState {
+    Succeeded,
+    Failed,
+    /// A previous step failed.
+    NotRun,
+}
+
+impl StepState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StepState::Succeeded => "succeeded",
+            StepState::Failed => "failed",
+            StepState::NotRun => "not_run",
+        }
+    }
+}
+
+pub struct StepReport {
+    pub name: String,
+    pub state: StepState,
+    pub run: Option<CommandReport>,
+    pub rollback: Option<CommandReport>,
+}
+
+impl StepReport {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        let report = |report: &Option<CommandReport>| match report {
+            Some(report) => Variant::from(report.to_dict()),
+            None => Variant::nil(),
+        };
+        dict! {
+            "name" => self.name.clone(),
+            "state" => self.state.as_str().to_string(),
+            "run" => report(&self.run),
+            "rollback" => report(&self.rollback),
+        }
+    }
+}
+
+pub struct ScriptReport {
+    pub steps: Vec<StepReport>,
+    /// Index of the step which failed.
+    pub failed_step: Option<usize>,
+}
+
+impl ScriptReport {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        let mut steps: Array<Variant> = Array::new();
+        for step in &self.steps {
+            steps.push(&Variant::from(step.to_dict()));
+        }
+        let rollback_failed = self
+            .steps
+            .iter()
+            .filter_map(|step| step.rollback.as_ref())
+            .any(|rollback| !rollback.success);
+        dict! {
+            "success" => self.failed_step.is_none(),
+            "failed_step" => self.failed_step.map_or(-1, |index| index as i64),
+            "rollback_failed" => rollback_failed,
+            "steps" => steps,
+        }
+    }
+}
+
+/// Progress of a running script.
+pub enum ScriptEvent<'a> {
+    StepStarted {
+        index: usize,
+        name: &'a str,
+        rollback: bool,
+    },
+    StepFinished {
+        index: usize,
+        report: &'a StepReport,
+    },
+}
+
+/// Runs `steps` in order and stops at the first failure. The rollbacks of the failed step
+/// and all steps before it are then run in reverse order. A failing rollback doesn't stop the others.
+pub fn run_script(
+    client: &mut impl ClientAccess,
+    steps: &[ScriptStep],
+    mut progress: impl FnMut(ScriptEvent),
+    ip: &String,
+    user: &String,
+    port: u16,
+) -> ScriptReport {
+    let mut reports: Vec<StepReport> = steps
+        .iter()
+        .map(|step| StepReport {
+            name: step.name.clone(),
+            state: StepState::NotRun,
+            run: None,
+            rollback: None,
+        })
+        .collect();
+
+    let mut failed_step = None;
+    for (index, step) in steps.iter().enumerate() {
+        progress(ScriptEvent::StepStarted {
+            index,
+            name: &step.name,
+            rollback: false,
+        });
+        let run = run_script_command(
+            client,
+            &step.command,
+            step.timeout,
+            &step.expected_exit_codes,
+            ip,
+            user,
+            port,
+        );
+        let report = &mut reports[index];
+        report.state = if run.success {
+            StepState::Succeeded
+        } else {
+            StepState::Failed
+        };
+        report.run = Some(run);
+        progress(ScriptEvent::StepFinished {
+            index,
+            report: &reports[index],
+        });
+        if reports[index].state == StepState::Failed {
+            failed_step = Some(index);
+            break;
+        }
+    }
+
+    if let Some(failed_step) = failed_step {
+        let _ = client.with_client(|client| {
+            client.log.warn(LogEvent::Exec, ip, port, || {
+                format!(
+                    "Script failed at step \"{}\", rolling back",
+                    steps[failed_step].name
+                )
+            });
+            Ok(())
+        });
+        for index in (0..=failed_step).rev() {
+            let Some(rollback) = &steps[index].rollback else {
+                continue;
+            };
+            progress(ScriptEvent::StepStarted {
+                index,
+                name: &steps[index].name,
+                rollback: true,
+            });
+            reports[index].rollback = Some(run_script_command(
+                client,
+                rollback,
+                steps[index].timeout,
+                &[0],
+                ip,
+                user,
+                port,
+            ));
+            progress(ScriptEvent::StepFinished {
+                index,
+                report: &reports[index],
+            });
+        }
+    }
+
+    ScriptReport {
+        steps: reports,
+        failed_step,
+    }
+}
+
+fn run_script_command(
+    client: &mut impl ClientAccess,
+    command: &str,
+    timeout: Option<Duration>,
+    expected_exit_codes: &[i64],
+    ip: &String,
+    user: &String,
+    port: u16,
+) -> CommandReport {
+    let start = Instant::now();
+    let limits = CaptureLimits {
+        max_stdout: Some(OUTPUT_LIMIT),
+        max_stderr: Some(OUTPUT_LIMIT),
+    };
+    let result = client.exec_timed(command.to_string(), limits, timeout, ip, user, port);
+    let duration = start.elapsed().as_secs_f64();
+    match result {
+        Ok(output) => CommandReport {
+            command: command.to_string(),
+            success: output.exit_signal.is_none()
+                && expected_exit_codes.contains(&output.exit_status),
+            exit_status: output.exit_status,
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            error: output
+                .exit_signal
+                .map(|signal| format!("Terminated by signal {}", signal)),
+            duration,
+        },
+        Err(e) => CommandReport {
+            command: command.to_string(),
+            success: false,
+            exit_status: -1,
+            stdout: String::new(),
+            stderr: String::new(),
+            error: Some(e.to_string()),
+            duration,
+        },
+    }
+}
+
+/// Reads the steps of a script, see `SSHClient::run_script`.
+pub fn parse_steps(steps: &Array<Variant>) -> anyhow::Result<Vec<ScriptStep>> {
+    if steps.is_empty() {
+        anyhow::bail!("Script has no steps");
+    }
+    steps
+        .iter_shared()
+        .enumerate()
+        .map(
+            |(index, step)| match step.try_to::<Dictionary<GString, Variant>>() {
+                Ok(step) => ScriptStep::from_dict(index, &step),
+                Err(_) => anyhow::bail!("Step {} isn't a Dictionary", index),
+            },
+        )
+        .collect()
+}
@@ -0,0 +1,447 @@
+use crate::internal_ssh_client::{ExecOutput, InternalSSHClient};
+use crate::logger::LogEvent;
+use crate::shell::posix_quote;
+use chrono::DateTime;
+use godot::prelude::*;
+use serde_json::Value;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ServiceError {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    PermissionDenied(String),
+    /// systemd or Docker isn't installed or running on the server.
+    #[error("{0}")]
+    Unavailable(String),
+    #[error("Command failed (exit status {exit_status}): {message}")]
+    Failed { exit_status: i64, message: String },
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Connection(#[from] anyhow::Error),
+}
+
+impl ServiceError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServiceError::NotFound(_) => "not_found",
+            ServiceError::PermissionDenied(_) => "permission_denied",
+            ServiceError::Unavailable(_) => "unavailable",
+            ServiceError::Failed { .. } => "failed",
+            ServiceError::Invalid(_) => "invalid",
+            ServiceError::Connection(_) => "connection",
+        }
+    }
+
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        dict! {
+            "kind" => self.kind().to_string(),
+            "message" => self.to_string(),
+        }
+    }
+
+    /// Tells apart why a `systemctl` or `docker` call failed by its output.
+    fn from_output(output: &ExecOutput) -> Self {
+        let message = match output.stderr.trim() {
+            "" => output.stdout.trim().to_string(),
+            stderr => stderr.to_string(),
+        };
+        let lower = message.to_lowercase();
+        if output.exit_status == 127
+            || lower.contains("command not found")
+            || lower.contains("cannot connect to the docker daemon")
+            || lower.contains("system has not been booted with systemd")
+            || lower.contains("failed to connect to bus")
+        {
+            ServiceError::Unavailable(message)
+        } else if lower.contains("permission denied")
+            || lower.contains("access denied")
+            || lower.contains("interactive authentication required")
+        {
+            ServiceError::PermissionDenied(message)
+        } else if lower.contains("not found")
+            || lower.contains("does not exist")
+            || lower.contains("no such container")
+            || lower.contains("not loaded")
+        {
+            ServiceError::NotFound(message)
+        } else {
+            ServiceError::Failed {
+                exit_status: output.exit_status,
+                message,
+            }
+        }
+    }
+}
+
+/// Actions that can be performed on a systemd unit or Docker container.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ServiceAction {
+    Start,
+    Stop,
+    Restart,
+    Reload,
+    /// Start on boot, for containers sets the restart policy to `unless-stopped`.
+    Enable,
+    /// Don't start on boot, for containers sets the restart policy to `no`.
+    Disable,
+}
+
+impl ServiceAction {
+    pub fn parse(action: &str) -> Result<Self, ServiceError> {
+        match action {
+            "start" => Ok(ServiceAction::Start),
+            "stop" => Ok(ServiceAction::Stop),
+            "restart" => Ok(ServiceAction::Restart),
+            "reload" => Ok(ServiceAction::Reload),
+            "enable" => Ok(ServiceAction::Enable),
+            "disable" => Ok(ServiceAction::Disable),
+            _ => Err(ServiceError::Invalid(format!(
+                "Unknown service action: {}",
+                action
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceAction::Start => "start",
+            ServiceAction::Stop => "stop",
+            ServiceAction::Restart => "restart",
+            ServiceAction::Reload => "reload",
+            ServiceAction::Enable => "enable",
+            ServiceAction::Disable => "disable",
+        }
+    }
+}
+
+impl InternalSSHClient {
+    /// Lists all loaded service units with their state.
+    ///
+    /// * `user_units` - List units of the user's service manager instead of the system's.
+    pub fn list_units(
+        &mut self,
+        user_units: bool,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> Result<Vec<Dictionary<GString, Variant>>, ServiceError> {
+        let cmd = format!(
+            "systemctl{} list-units --type=service --all --no-legend --no-pager --plain",
+            scope_flag(user_units)
+        );
+        let output = self.run_service_cmd(cmd, ip, user, port)?;
+        Ok(output
+            .stdout
+            .lines()
+            .filter_map(|line| {
+                let mut columns = line.split_whitespace();
+                let unit = columns.next()?.to_string();
+                let load_state = columns.next()?.to_string();
+                let active_state = columns.next()?.to_string();
+                let sub_state = columns.next()?.to_string();
+                let description = columns.collect::<Vec<&str>>().join(" ");
+                Some(dict! {
+                    "name" => unit,
+                    "load_state" => load_state,
+                    "active_state" => active_state,
+                    "sub_state" => sub_state,
+                    "description" => description,
+                })
+            })
+            .collect())
+    }
+
+    /// Returns the state of a unit. Units that don't exist are reported with `load_state` "not-found".
+    pub fn unit_status(
+        &mut self,
+        unit: &str,
+        user_units: bool,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> Result<Dictionary<GString, Variant>, ServiceError> {
+        // The uptime is calculated on the server, so it doesn't depend on the local clock
+        let cmd = format!(
+            "systemctl{} show --no-pager -p Id,Description,LoadState,ActiveState,SubState,UnitFileState,\
+             MainPID,ExecMainStatus,Result,NRestarts,ActiveEnterTimestampMonotonic -- {} \
+             && echo \"Uptime=$(cut -d ' ' -f 1 /proc/uptime)\"",
+            scope_flag(user_units),
+            posix_quote(unit)
+        );
+        let output = self.run_service_cmd(cmd, ip, user, port)?;
+        let properties: Vec<(&str, &str)> = output
+            .stdout
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .collect();
+        let property = |key: &str| {
+            properties
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.trim())
+                .unwrap_or_default()
+        };
+
+        let active_state = property("ActiveState").to_string();
+        let active_since = property("ActiveEnterTimestampMonotonic")
+            .parse::<f64>()
+            .unwrap_or_default()
+            / 1_000_000.0;
+        let uptime = match property("Uptime").parse::<f64>() {
+            Ok(system_uptime) if active_state == "active" && active_since > 0.0 => {
+                Variant::from((system_uptime - active_since).max(0.0))
+            }
+            _ => Variant::nil(),
+        };
+        Ok(dict! {
+            "name" => property("Id").to_string(),
+            "description" => property("Description").to_string(),
+            "load_state" => property("LoadState").to_string(),
+            "active_state" => active_state,
+            "sub_state" => property("SubState").to_string(),
+            "unit_file_state" => property("UnitFileState").to_string(),
+            "main_pid" => property("MainPID").parse::<i64>().unwrap_or_default(),
+            "exit_code" => property("ExecMainStatus").parse::<i64>().unwrap_or_default(),
+            "result" => property("Result").to_string(),
+            "restarts" => property("NRestarts").parse::<i64>().unwrap_or_default(),
+            "uptime" => uptime,
+        })
+    }
+
+    pub fn unit_action(
+        &mut self,
+        unit: &str,
+        action: ServiceAction,
+        user_units: bool,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> Result<(), ServiceError> {
+        let cmd = format!(
+            "systemctl{} {} -- {}",
+            scope_flag(user_units),
+            action.as_str(),
+            posix_quote(unit)
+        );
+        self.run_service_cmd(cmd, ip, user, port)?;
+        self.log.info(LogEvent::Exec, ip, port, || {
+            format!("Unit {}: {}", unit, action.as_str())
+        });
+        Ok(())
+    }
+
+    /// Lists all containers, including stopped ones.
+    pub fn list_containers(
+        &mut self,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> Result<Vec<Dictionary<GString, Variant>>, ServiceError> {
+        let cmd = "docker ps --all --no-trunc --format '{{json .}}'".to_string();
+        let output = self.run_service_cmd(cmd, ip, user, port)?;
+        Ok(output
+            .stdout
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .map(|container| {
+                let field = |key: &str| container[key].as_str().unwrap_or_default().to_string();
+                dict! {
+                    "id" => field("ID"),
+                    "name" => field("Names"),
+                    "image" => field("Image"),
+                    "state" => field("State"),
+                    "status" => field("Status"),
+                    "created_at" => field("CreatedAt"),
+                }
+            })
+            .collect())
+    }
+
+    pub fn container_status(
+        &mut self,
+        container: &str,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> Result<Dictionary<GString, Variant>, ServiceError> {
+        // The uptime is calculated with the server's time, so it doesn't depend on the local clock
+        let cmd = format!(
+            "docker inspect --type container -- {} && date -u +%s",
+            posix_quote(container)
+        );
+        let output = self.run_service_cmd(cmd, ip, user, port)?;
+        let stdout = output.stdout.trim_end();
+        let (json, now) = stdout.rsplit_once('\n').unwrap_or((stdout, ""));
+        let inspect = match serde_json::from_str::<Value>(json) {
+            Ok(inspect) => inspect,
+            Err(e) => {
+                return Err(ServiceError::Failed {
+                    exit_status: output.exit_status,
+                    message: format!("Failed to parse docker inspect output: {}", e),
+                })
+            }
+        };
+        let inspect = &inspect[0];
+        let state = &inspect["State"];
+        let running = state["Running"].as_bool().unwrap_or_default();
+        let started_at = state["StartedAt"].as_str().unwrap_or_default();
+        let uptime = match (
+            DateTime::parse_from_rfc3339(started_at),
+            now.trim().parse::<i64>(),
+        ) {
+            (Ok(started_at), Ok(now)) if running => {
+                Variant::from((now - started_at.timestamp()).max(0))
+            }
+            _ => Variant::nil(),
+        };
+        Ok(dict! {
+            "id" => inspect["Id"].as_str().unwrap_or_default().to_string(),
+            "name" => inspect["Name"].as_str().unwrap_or_default().trim_start_matches('/').to_string(),
+            "image" => inspect["Config"]["Image"].as_str().unwrap_or_default().to_string(),
+            "state" => state["Status"].as_str().unwrap_or_default().to_string(),
+            "running" => running,
+            "exit_code" => state["ExitCode"].as_i64().unwrap_or_default(),
+            "oom_killed" => state["OOMKilled"].as_bool().unwrap_or_default(),
+            "health" => state["Health"]["Status"].as_str().unwrap_or_default().to_string(),
+            "started_at" => started_at.to_string(),
+            "finished_at" => state["FinishedAt"].as_str().unwrap_or_default().to_string(),
+            "restart_count" => inspect["RestartCount"].as_i64().unwrap_or_default(),
+            "restart_policy" => inspect["HostConfig"]["RestartPolicy"]["Name"].as_str().unwrap_or_default().to_string(),
+            "uptime" => uptime,
+        })
+    }
+
+    pub fn container_action(
+        &mut self,
+        container: &str,
+        action: ServiceAction,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> Result<(), ServiceError> {
+        let container_arg = posix_quote(container);
+        let cmd = match action {
+            ServiceAction::Enable => {
+                format!(
+                    "docker update --restart unless-stopped -- {}",
+                    container_arg
+                )
+            }
+            ServiceAction::Disable => format!("docker update --restart no -- {}", container_arg),
+            // Docker has no reload, sending SIGHUP is the common equivalent
+            ServiceAction::Reload => format!("docker kill --signal HUP -- {}", container_arg),
+            action => format!("docker {} -- {}", action.as_str(), container_arg),
+        };
+        self.run_service_cmd(cmd, ip, user, port)?;
+        self.log.info(LogEvent::Exec, ip, port, || {
+            format!("Container {}: {}", container, action.as_str())
+        });
+        Ok(())
+    }
+
+    fn run_service_cmd(
+        &mut self,
+        cmd: String,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> Result<ExecOutput, ServiceError> {
+        let output = self.exec_ssh_output(cmd, ip, user, port)?;
+        if output.exit_status != 0 {
+            return Err(ServiceError::from_output(&output));
+        }
+        Ok(output)
+    }
+}
+
+fn scope_flag(user_units: bool) -> &'static str {
+    if user_units {
+        " --user"
+    } else {
+        ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(exit_status: i64, stdout: &str, stderr: &str) -> ServiceError {
+        ServiceError::from_output(&ExecOutput {
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+            exit_status,
+        })
+    }
+
+    #[test]
+    fn missing_service_managers_are_unavailable() {
+        for (exit_status, stderr) in [
+            (127, "sh: 1: docker: not found"),
+            (1, "bash: systemctl: command not found"),
+            (
+                1,
+                "Cannot connect to the Docker daemon at unix:///var/run/docker.sock.",
+            ),
+            (
+                1,
+                "System has not been booted with systemd as init system (PID 1). Can't operate.",
+            ),
+            (1, "Failed to connect to bus: No such file or directory"),
+        ] {
+            assert_eq!(
+                error(exit_status, "", stderr).kind(),
+                "unavailable",
+                "{}",
+                stderr
+            );
+        }
+    }
+
+    #[test]
+    fn denied_actions_are_permission_errors() {
+        for stderr in [
+            "Failed to start nginx.service: Access denied",
+            "permission denied while trying to connect to the Docker daemon socket",
+            "Failed to restart nginx.service: Interactive authentication required.",
+        ] {
+            assert_eq!(
+                error(1, "", stderr).kind(),
+                "permission_denied",
+                "{}",
+                stderr
+            );
+        }
+    }
+
+    #[test]
+    fn missing_units_and_containers_are_not_found() {
+        for stderr in [
+            "Failed to start nope.service: Unit nope.service not found.",
+            "Error response from daemon: No such container: nope",
+            "Unit nope.service could not be found.\nUnit nope.service not loaded.",
+        ] {
+            assert_eq!(error(1, "", stderr).kind(), "not_found", "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn other_failures_keep_exit_status_and_message() {
+        let error = error(3, "  stdout is used without stderr\n", " \n");
+        assert_eq!(error.kind(), "failed");
+        assert_eq!(
+            error.to_string(),
+            "Command failed (exit status 3): stdout is used without stderr"
+        );
+    }
+
+    #[test]
+    fn actions_round_trip() {
+        for action in ["start", "stop", "restart", "reload", "enable", "disable"] {
+            assert_eq!(ServiceAction::parse(action).unwrap().as_str(), action);
+        }
+        assert_eq!(ServiceAction::parse("kill").unwrap_err().kind(), "invalid");
+    }
+}
@@ -0,0 +1,178 @@
+use godot::prelude::*;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Traffic and channel statistics of a client, kept across reconnects.
+/// Shared with the connection, which counts the bytes while the session runs.
+#[derive(Default)]
+pub struct SessionStats {
+    /// Bytes written to and read from the connection, including the SSH protocol overhead.
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    channels_opened: AtomicU64,
+    channel_failures: AtomicU64,
+    commands_run: AtomicU64,
+    /// Commands that were waited for and how long they took in total, for the average latency.
+    commands_timed: AtomicU64,
+    command_time_micros: AtomicU64,
+    sessions_opened: AtomicU64,
+    connected_since: Mutex<Option<Instant>>,
+    keepalive_rtt: Mutex<Option<Duration>>,
+}
+
+impl SessionStats {
+    pub fn session_opened(&self) {
+        self.sessions_opened.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut connected_since) = self.connected_since.lock() {
+            *connected_since = Some(Instant::now());
+        }
+    }
+
+    pub fn session_closed(&self) {
+        if let Ok(mut connected_since) = self.connected_since.lock() {
+            *connected_since = None;
+        }
+    }
+
+    pub fn channel_opened(&self) {
+        self.channels_opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn channel_failed(&self) {
+        self.channel_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn command_run(&self) {
+        self.commands_run.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records how long a command that was waited for took until it exited.
+    pub fn command_finished(&self, latency: Duration) {
+        self.commands_timed.fetch_add(1, Ordering::Relaxed);
+        self.command_time_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn keepalive_measured(&self, rtt: Duration) {
+        if let Ok(mut keepalive_rtt) = self.keepalive_rtt.lock() {
+            *keepalive_rtt = Some(rtt);
+        }
+    }
+
+    /// Whether a session was opened and not closed since. Unlike
+    /// `InternalSSHClient::is_session_active` it doesn't notice the server closing the session.
+    pub fn is_connected(&self) -> bool {
+        self.connected_since
+            .lock()
+            .is_ok_and(|connected_since| connected_since.is_some())
+    }
+
+    /// `active` is whether the session is currently open, only then it has an uptime.
+    pub fn to_dict(&self, active: bool) -> Dictionary<GString, Variant> {
+        let commands_timed = self.commands_timed.load(Ordering::Relaxed);
+        let average_latency = match commands_timed {
+            0 => Variant::nil(),
+            timed => Variant::from(
+                self.command_time_micros.load(Ordering::Relaxed) as f64 / timed as f64 / 1e6,
+            ),
+        };
+        let uptime = match self.connected_since.lock() {
+            Ok(connected_since) if active => connected_since.map_or(Variant::nil(), |since| {
+                Variant::from(since.elapsed().as_secs_f64())
+            }),
+            _ => Variant::nil(),
+        };
+        let keepalive_rtt = match self.keepalive_rtt.lock() {
+            Ok(rtt) => rtt.map_or(Variant::nil(), |rtt| Variant::from(rtt.as_secs_f64())),
+            Err(_) => Variant::nil(),
+        };
+        dict! {
+            "active" => active,
+            "bytes_sent" => self.bytes_sent.load(Ordering::Relaxed) as i64,
+            "bytes_received" => self.bytes_received.load(Ordering::Relaxed) as i64,
+            "channels_opened" => self.channels_opened.load(Ordering::Relaxed) as i64,
+            "channel_failures" => self.channel_failures.load(Ordering::Relaxed) as i64,
+            "commands_run" => self.commands_run.load(Ordering::Relaxed) as i64,
+            "average_command_latency" => average_latency,
+            "uptime" => uptime,
+            "reconnects" => self.sessions_opened.load(Ordering::Relaxed).saturating_sub(1) as i64,
+            "keepalive_rtt" => keepalive_rtt,
+        }
+    }
+}
+
+/// A connection which counts the bytes read and written in [SessionStats].
+pub struct CountingStream<S> {
+    inner: S,
+    stats: Arc<SessionStats>,
+}
+
+impl<S> CountingStream<S> {
+    pub fn new(inner: S, stats: Arc<SessionStats>) -> Self {
+        Self { inner, stats }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            self.stats
+                .bytes_received
+                .fetch_add((buf.filled().len() - filled) as u64, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            self.stats
+                .bytes_sent
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = result {
+            self.stats
+                .bytes_sent
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,99 @@
+use crate::remote_info::{RemoteInfo, RemoteShell};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// The shell language a command is written in, see [wrap_command].
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ShellDialect {
+    /// Run the command as is in the default shell of the server.
+    Default,
+    /// POSIX sh, only available on unix servers.
+    Sh,
+    Bash,
+    PowerShell,
+    /// Windows cmd, only available on Windows servers.
+    Cmd,
+}
+
+impl ShellDialect {
+    pub fn parse(dialect: &str) -> anyhow::Result<Self> {
+        match dialect {
+            "" | "default" => Ok(ShellDialect::Default),
+            "sh" => Ok(ShellDialect::Sh),
+            "bash" => Ok(ShellDialect::Bash),
+            "powershell" => Ok(ShellDialect::PowerShell),
+            "cmd" => Ok(ShellDialect::Cmd),
+            _ => anyhow::bail!("Unknown shell: {}", dialect),
+        }
+    }
+}
+
+/// Wraps `cmd`, which is written for `dialect`, so it runs correctly in the default shell of the server.
+///
+/// E.g. a PowerShell command gets passed via `-EncodedCommand`, which avoids any quoting issues
+/// no matter if the server's default shell is cmd or PowerShell.
+pub fn wrap_command(cmd: &str, dialect: ShellDialect, info: &RemoteInfo) -> anyhow::Result<String> {
+    match dialect {
+        ShellDialect::Default => Ok(cmd.to_string()),
+        ShellDialect::Sh | ShellDialect::Bash => {
+            let shell = if dialect == ShellDialect::Sh {
+                "sh"
+            } else {
+                "bash"
+            };
+            if !info.os.is_unix() {
+                anyhow::bail!("{} is not available on {} servers", shell, info.os_name);
+            }
+            Ok(format!("{} -c {}", shell, posix_quote(cmd)))
+        }
+        ShellDialect::PowerShell => {
+            if info.os.is_unix() {
+                Ok(encoded_command("pwsh", cmd))
+            } else {
+                Ok(powershell_encoded_command(cmd))
+            }
+        }
+        ShellDialect::Cmd => {
+            if info.os.is_unix() {
+                anyhow::bail!("cmd is not available on {} servers", info.os_name);
+            }
+            match info.shell {
+                RemoteShell::Cmd => Ok(cmd.to_string()),
+                _ => Ok(powershell_encoded_command(&format!(
+                    "cmd.exe /d /c {}; exit $LASTEXITCODE",
+                    powershell_quote(cmd)
+                ))),
+            }
+        }
+    }
+}
+
+/// Quotes `arg` for POSIX shells, so it is always passed as a single argument.
+pub fn posix_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Quotes `arg` as a PowerShell single quoted string literal.
+pub fn powershell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "''"))
+}
+
+/// Wraps a PowerShell script in a `powershell -EncodedCommand` call.
+/// This way the script doesn't need any further quoting and works
+/// no matter if the default shell is cmd or PowerShell.
+pub fn powershell_encoded_command(script: &str) -> String {
+    encoded_command("powershell", script)
+}
+
+/// Wraps a PowerShell script in a `-EncodedCommand` call of `executable`.
+fn encoded_command(executable: &str, script: &str) -> String {
+    let utf16: Vec<u8> = script
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    format!(
+        "{} -NoProfile -NonInteractive -EncodedCommand {}",
+        executable,
+        STANDARD.encode(utf16)
+    )
+}
@@ -0,0 +1,147 @@
+use crate::audit_log::{AuditFilter, AuditLog, SharedAuditLog};
+use crate::ssh_vault::globalize_path;
+use godot::prelude::*;
+use std::sync::{Arc, Mutex};
+
+/// A persistent log of all commands executed by the clients it is set on.
+///
+/// Every exec is written as one JSON line containing timestamp, client, host, port, user, command,
+/// exit status, duration and the byte counts of stdout and stderr. Optionally the output itself
+/// is captured as well. The log is rotated once it reaches its maximum size.
+///
+/// # Example usage
+///
+/// ```
+/// var audit_log: SSHAuditLog = SSHAuditLog.new()
+/// var err: Variant = audit_log.open("user://ssh_audit.log", 1024 * 1024, 5)
+/// if err:
+///     push_error(err)
+///     return
+/// client.set_audit_log(audit_log, client_uuid)
+/// client.exec_blocking("echo Hello from SSH")
+/// print(audit_log.query(10, {"failed_only": true}))
+/// ```
+#[derive(GodotClass)]
+#[class(base = RefCounted)]
+pub struct SSHAuditLog {
+    log: Option<SharedAuditLog>,
+}
+
+#[godot_api]
+pub impl IRefCounted for SSHAuditLog {
+    fn init(_base: Base<RefCounted>) -> Self {
+        Self { log: None }
+    }
+}
+
+#[godot_api]
+pub impl SSHAuditLog {
+    /// Opens the log at `path`, entries are appended if it already exists.
+    /// Will return null on success, otherwise a string with the error will be returned.
+    ///
+    /// * `path` - Path of the log file. Godot paths like `user://` are supported.
+    /// * `max_file_size` - Size in bytes at which the log is rotated.
+    /// * `max_files` - Number of rotated files that are kept (`<path>.1` to `<path>.<max_files>`).
+    #[func]
+    fn open(&mut self, path: String, max_file_size: i64, max_files: i64) -> Variant {
+        let (Ok(max_file_size), Ok(max_files)) =
+            (u64::try_from(max_file_size), u32::try_from(max_files))
+        else {
+            return Variant::from("Max file size and max files must not be negative".to_string());
+        };
+        match AuditLog::new(&globalize_path(&path), max_file_size, max_files) {
+            Ok(log) => {
+                self.log = Some(Arc::new(Mutex::new(log)));
+                Variant::nil()
+            }
+            Err(e) => Variant::from(e.to_string()),
+        }
+    }
+
+    /// Returns whether the log was opened successfully.
+    #[func]
+    fn is_open(&self) -> bool {
+        self.log.is_some()
+    }
+
+    /// Enables capturing the output of commands.
+    ///
+    /// **Note:** Output can contain sensitive data, which is stored in clear.
+    ///
+    /// * `max_bytes` - Maximum bytes of stdout and stderr each stored per entry, 0 disables capturing.
+    #[func]
+    fn set_output_capture(&mut self, max_bytes: i64) -> bool {
+        let Some(log) = &self.log else {
+            godot_error!("No audit log opened");
+            return false;
+        };
+        match log.lock() {
+            Ok(mut log) => {
+                log.set_capture_limit(max_bytes.max(0) as usize);
+                true
+            }
+            Err(_) => {
+                godot_error!("Audit log is poisoned");
+                false
+            }
+        }
+    }
+
+    /// Returns up to `limit` entries, newest first. Each entry is a [Dictionary] with the keys
+    /// `timestamp`, `client`, `host`, `port`, `user`, `command`, `exit_status` (null if unknown),
+    /// `duration_ms`, `stdout_bytes`, `stderr_bytes`, `error`, `stdout` and `stderr`
+    /// (null if not captured).
+    ///
+    /// * `filter` - Optional keys: `client`, `host`, `user` (exact match), `command` (substring),
+    ///   `since` (unix timestamp in seconds) and `failed_only` (bool).
+    #[func]
+    fn query(&self, limit: i64, filter: Dictionary<GString, Variant>) -> Array<Variant> {
+        let mut entries: Array<Variant> = Array::new();
+        let Some(log) = &self.log else {
+            godot_error!("No audit log opened");
+            return entries;
+        };
+        let filter = AuditFilter {
+            client: filter_value(&filter, "client"),
+            host: filter_value(&filter, "host"),
+            user: filter_value(&filter, "user"),
+            command: filter_value(&filter, "command"),
+            since: filter
+                .get("since")
+                .and_then(|since| since.try_to::<i64>().ok()),
+            failed_only: filter
+                .get("failed_only")
+                .is_some_and(|failed_only| failed_only.booleanize()),
+        };
+        let result = match log.lock() {
+            Ok(log) => log.query(&filter, limit.max(0) as usize),
+            Err(_) => Err(anyhow::anyhow!("Audit log is poisoned")),
+        };
+        match result {
+            Ok(found) => {
+                for entry in found {
+                    entries.push(&Variant::from(entry.to_dict()));
+                }
+            }
+            Err(e) => godot_error!("{}", e),
+        }
+        entries
+    }
+}
+
+impl SSHAuditLog {
+    /// Access to the log for other classes of this extension.
+    pub fn log(&self) -> anyhow::Result<SharedAuditLog> {
+        match &self.log {
+            Some(log) => Ok(log.clone()),
+            None => anyhow::bail!("No audit log opened"),
+        }
+    }
+}
+
+fn filter_value(filter: &Dictionary<GString, Variant>, key: &str) -> Option<String> {
+    filter
+        .get(key)
+        .and_then(|value| value.try_to::<String>().ok())
+        .filter(|value| !value.is_empty())
+}
@@ -1,9 +1,39 @@
+use crate::agent::{AgentBackend, AgentSignRequest, CONFIRMATIONS};
+use crate::audit_log::{AuditTarget, ExecRecord};
+use crate::capture::{BinaryExecOutput, CaptureLimits};
+use crate::certificate::{certificate_path, HostAuthority};
+use crate::command_watcher::MAX_DELAY;
 use crate::internal_ssh_client::{
-    generate_private_key, AuthMethod, InternalSSHClient, ServerCheckMethod,
+    AuthMethod, ClientAccess, InternalSSHClient, SecretSource, ServerCheckMethod,
 };
+use crate::key_deployment::KeyChange;
+use crate::key_utils;
+use crate::logger::{LogEvent, LogLevel};
+use crate::power::{self, Knock, PowerAction};
+use crate::script::{self, ScriptEvent, ScriptStep};
+use crate::services::{ServiceAction, ServiceError};
+use crate::shell::{wrap_command, ShellDialect};
+use crate::ssh_audit_log::SSHAuditLog;
+use crate::ssh_config;
+use crate::ssh_vault::{globalize_path, SSHVault};
+use crate::stats::{self, RawStats};
+use crate::sync::{self, SyncOptions};
 use async_std::task::block_on;
 use godot::prelude::*;
+use russh::client::Msg;
+use russh::Channel;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+/// How long a forwarded agent sign request waits for `SSHClient::confirm_agent_request`.
+const AGENT_CONFIRM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the session stats thread checks whether it was stopped.
+const STATS_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 /// A simple SSH client.
 ///
@@ -31,6 +61,8 @@ use std::path::PathBuf;
 ///     return
 /// client.exec("echo Hello from SSH")
 /// ```
+///
+/// Diagnostics are emitted as [signal log_record], filtered by [method set_log_level].
 #[derive(GodotClass)]
 #[class(base = RefCounted)]
 pub struct SSHClient {
@@ -44,36 +76,150 @@ pub struct SSHClient {
     #[var]
     port: u16,
     _internal_ssh_client: InternalSSHClient,
+    /// Stops the thread emitting [signal session_stats_updated], see [method start_session_stats].
+    stats_stop: Option<Arc<AtomicBool>>,
+    base: Base<RefCounted>,
 }
 
 #[godot_api]
 pub impl IRefCounted for SSHClient {
-    fn init(_base: Base<RefCounted>) -> Self {
+    fn init(base: Base<RefCounted>) -> Self {
+        let internal_ssh_client = InternalSSHClient::default();
+        internal_ssh_client
+            .log
+            .set_emitter(base.to_init_gd().instance_id());
         Self {
             user: Variant::nil(),
             ip: Variant::nil(),
             port: 22,
-            _internal_ssh_client: InternalSSHClient::default(),
+            _internal_ssh_client: internal_ssh_client,
+            stats_stop: None,
+            base,
         }
     }
 }
 
 #[godot_api]
 pub impl SSHClient {
-    /// Set debug state of the client. In debug state it will verbosely print status updates
-    /// and executed command outputs.
+    /// Emitted for every log record at or above the log level.
+    /// Records created on other threads are emitted deferred on the main thread.
+    ///
+    /// * `record` - [Dictionary] with the keys `timestamp`, `level`, `event` ("connect", "auth",
+    ///   "channel", "exec", "stdout", "stderr", "exit_status", "key" or "remote_info"),
+    ///   `host`, `port`, `channel` (null if not channel related) and `message`.
+    #[signal]
+    fn log_record(record: Dictionary<GString, Variant>);
+
+    /// Emitted when the server wants to sign with a forwarded key while confirmation is enabled,
+    /// see [method set_agent_confirmation]. Answer with [method confirm_agent_request].
+    /// Emitted deferred on the main thread.
+    ///
+    /// * `fingerprint` - SHA256 fingerprint of the key.
+    /// * `comment` - Comment of the key, empty when forwarding the local agent.
+    #[signal]
+    fn agent_sign_requested(request_id: i64, fingerprint: GString, comment: GString);
+
+    /// Emitted periodically after [method start_session_stats]. Emitted deferred on the main thread.
+    ///
+    /// * `stats` - See [method get_session_stats].
+    #[signal]
+    fn session_stats_updated(stats: Dictionary<GString, Variant>);
+
+    /// Emitted when a step of a script started, see [method run_script].
+    ///
+    /// * `rollback` - Whether the rollback of the step started.
+    #[signal]
+    fn script_step_started(script_id: GString, index: i64, name: GString, rollback: bool);
+
+    /// Emitted when a step of a script or its rollback finished.
+    ///
+    /// * `step` - See `steps` of the report of [method run_script].
+    #[signal]
+    fn script_step_finished(script_id: GString, index: i64, step: Dictionary<GString, Variant>);
+
+    /// Emitted when a script finished, including its rollbacks.
+    ///
+    /// * `report` - See [method run_script].
+    #[signal]
+    fn script_finished(script_id: GString, report: Dictionary<GString, Variant>);
+
+    /// Emitted after every file a sync transferred or deleted, see [method sync_directory].
+    ///
+    /// * `file` - See `files` of the report of [method sync_directory_blocking].
+    /// * `index` - Index of the file, counting up to `total`.
+    #[signal]
+    fn sync_progress(sync_id: GString, file: Dictionary<GString, Variant>, index: i64, total: i64);
+
+    /// Emitted when a sync finished.
+    ///
+    /// * `report` - See [method sync_directory_blocking].
+    #[signal]
+    fn sync_finished(sync_id: GString, report: Dictionary<GString, Variant>);
+
+    /// Emitted when connecting needs credentials that were wiped after the last authentication,
+    /// e.g. when reconnecting after the session dropped. Set the auth method again and reconnect.
+    /// Emitted deferred on the main thread.
+    #[signal]
+    fn credentials_required();
+
+    /// Sets the minimum level of emitted log records, defaults to "warn".
+    /// Also applies to an already open session.
+    ///
+    /// * `level` - "trace" (includes command output), "debug", "info", "warn", "error" or "off".
+    #[func]
+    fn set_log_level(&mut self, level: String) -> bool {
+        match LogLevel::parse(&level) {
+            Ok(level) => {
+                self._internal_ssh_client.log.set_level(level);
+                true
+            }
+            Err(e) => {
+                godot_error!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Get the current log level.
+    #[func]
+    fn get_log_level(&self) -> String {
+        self._internal_ssh_client.log.level().as_str().to_string()
+    }
+
+    /// Additionally appends log records as JSON lines to the file at `path`.
+    /// An empty path stops writing to a file.
+    /// Will return null on success, otherwise a string with the error will be returned.
     ///
-    /// **Note:** If a session is already open, the debug state won't apply to until closed
-    /// and reopened.
+    /// * `path` - Path of the log file. Godot paths like `user://` are supported.
     #[func]
-    fn set_debug(&mut self, debug: bool) {
-        self._internal_ssh_client.debug = debug;
+    fn set_log_file(&mut self, path: String) -> Variant {
+        let path = optional_string(path).map(|path| globalize_path(&path));
+        match self._internal_ssh_client.log.set_file(path.as_deref()) {
+            Ok(_) => Variant::nil(),
+            Err(e) => Variant::from(e.to_string()),
+        }
     }
 
-    /// Get the current debug state.
+    /// Records every command executed by this client in `audit_log`. Pass null to stop recording.
+    ///
+    /// * `audit_log` - An opened [SSHAuditLog], can be shared between clients.
+    /// * `client_id` - Identity of this client in the log, e.g. its uuid.
     #[func]
-    fn get_debug(&self) -> bool {
-        self._internal_ssh_client.debug
+    fn set_audit_log(&mut self, audit_log: Option<Gd<SSHAuditLog>>, client_id: String) -> bool {
+        let Some(audit_log) = audit_log else {
+            self._internal_ssh_client.audit = None;
+            return true;
+        };
+        match audit_log.bind().log() {
+            Ok(log) => {
+                self._internal_ssh_client.audit = Some(AuditTarget { log, client_id });
+                true
+            }
+            Err(e) => {
+                godot_error!("{}", e);
+                false
+            }
+        }
     }
 
     /// Execute a command asynchronously on the client. Client needs to be configured to work.
@@ -125,147 +271,1701 @@ pub impl SSHClient {
         }
     }
 
-    /// Try to open a session for the client. If a session is already active it will be closed.
-    /// Will return null on success, otherwise a string with the error will be returned.
+    /// Execute a command in a blocking fashion on the client with the agent forwarded, see [method forward_local_agent].
+    /// Returns null on failure, otherwise a [Dictionary] with the keys `stdout`, `stderr` and `exit_status`.
+    ///
+    /// * `cmd` - Command to execute, e.g. "cd /srv/app && git pull".
     #[func]
-    fn open_session(&mut self) -> Variant {
+    fn exec_blocking_forwarding_agent(&mut self, cmd: String) -> Variant {
         if let Err(e) = self.check_configured() {
-            return Variant::from(e.to_string());
+            godot_error!("{}", e);
+            return Variant::nil();
         }
-        if let Err(e) = block_on(self._internal_ssh_client.open_session(
+        match self._internal_ssh_client.exec_ssh_forwarding_agent(
+            cmd,
             &self.ip.to_string(),
             &self.user.to_string(),
             self.port,
-        )) {
-            return Variant::from(e.to_string());
+        ) {
+            Ok(output) => Variant::from(output.to_dict()),
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
+            }
         }
-        Variant::nil()
     }
 
-    /// Disconnect the current session if one is active.
+    /// Execute a command in a blocking fashion on the client, returning its output as raw bytes.
+    /// Unlike [method exec_blocking] this doesn't corrupt binary output and limits how much output is kept.
+    /// Returns null on failure, otherwise a [Dictionary] with the keys
+    /// `stdout` and `stderr` ([PackedByteArray]), `stdout_size` and `stderr_size` (total bytes received),
+    /// `stdout_truncated`, `stderr_truncated`, `exit_status` (-1 if none was sent),
+    /// `exit_signal` (e.g. "TERM", null if not terminated by a signal), `core_dumped` and `signal_message`.
+    ///
+    /// * `cmd` - Command to execute.
+    /// * `max_stdout` - Maximum bytes of stdout to keep. Longer output keeps its first and last half
+    ///   with a truncation marker in between. Negative keeps everything, 0 discards the output.
+    /// * `max_stderr` - Same as `max_stdout` for stderr.
     #[func]
-    fn disconnect_session(&mut self) {
-        if let Err(error) = block_on(self._internal_ssh_client.disconnect_session()) {
-            godot_error!("Failed to disconnect ssh session: {}", error);
+    fn exec_blocking_bytes(&mut self, cmd: String, max_stdout: i64, max_stderr: i64) -> Variant {
+        let limits = CaptureLimits {
+            max_stdout: usize::try_from(max_stdout).ok(),
+            max_stderr: usize::try_from(max_stderr).ok(),
+        };
+        match self.exec_capture(cmd, limits) {
+            Ok(output) => Variant::from(output.to_dict()),
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
+            }
         }
     }
 
-    /// Returns the current session status.
+    /// Execute a command written for `shell` asynchronously on the client.
+    /// The command is wrapped so it runs correctly regardless of the server's default shell,
+    /// see [method get_remote_info]. Otherwise this behaves like [method exec].
+    ///
+    /// * `cmd` - Command to execute.
+    /// * `shell` - Shell the command is written for: "default" (no wrapping), "sh", "bash", "powershell" or "cmd".
     #[func]
-    fn is_session_active(&self) -> bool {
-        if let Some(session) = &self._internal_ssh_client.session {
-            return !session.is_closed();
+    fn exec_in_shell(&mut self, cmd: String, shell: String) -> bool {
+        match self.wrap_for_shell(&cmd, &shell) {
+            Ok(cmd) => self.exec(cmd),
+            Err(e) => {
+                godot_error!("{}", e);
+                false
+            }
         }
-        false
     }
 
-    /// If the current auth method is a private key method, this function can add the private key
-    /// to the current server's authorized keys. It doesn't check if the private key is already authorized, so
-    /// it is recommended to only call this method on auth failure.
+    /// Execute a command written for `shell` in a blocking fashion on the client.
+    /// The command is wrapped so it runs correctly regardless of the server's default shell,
+    /// see [method get_remote_info]. Otherwise this behaves like [method exec_blocking].
     ///
-    /// **Note:** This will close any currently active sessions.
+    /// * `cmd` - Command to execute.
+    /// * `shell` - Shell the command is written for: "default" (no wrapping), "sh", "bash", "powershell" or "cmd".
+    #[func]
+    fn exec_blocking_in_shell(&mut self, cmd: String, shell: String) -> Variant {
+        match self.wrap_for_shell(&cmd, &shell) {
+            Ok(cmd) => self.exec_blocking(cmd),
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
+            }
+        }
+    }
+
+    /// Execute a command as root with `sudo` in a blocking fashion on the client. Only supported on unix servers.
+    /// If sudo asks for a password, it's answered with the password set by [method set_sudo_password],
+    /// which is never part of the command line or logs. Commands are run with a PTY if sudo requires one,
+    /// then stderr is merged into stdout.
+    /// Returns null on failure, otherwise a [Dictionary] like [method exec_blocking] with the additional key
+    /// `sudo_error`: null if the command ran, otherwise why sudo refused to run it:
+    /// "password_required" (no sudo password set), "password_rejected", "not_in_sudoers" or "unavailable".
     ///
-    /// * `password` - Password to temporarily connect to the server.
+    /// * `cmd` - Command to execute.
+    /// * `shell` - Shell the command is written for: "default" or "sh" runs it with `sh`, "bash" with `bash`.
     #[func]
-    fn add_key_to_server(&mut self, password: String) -> bool {
+    fn exec_blocking_elevated(&mut self, cmd: String, shell: String) -> Variant {
         if let Err(e) = self.check_configured() {
             godot_error!("{}", e);
-            return false;
+            return Variant::nil();
         }
-        if let Err(e) = self._internal_ssh_client.add_key_to_server(
-            password,
+        let shell = match shell.as_str() {
+            "default" | "sh" => "sh",
+            "bash" => "bash",
+            _ => {
+                godot_error!("Unsupported shell for elevated commands: {}", shell);
+                return Variant::nil();
+            }
+        };
+        match self._internal_ssh_client.exec_ssh_elevated(
+            &cmd,
+            shell,
             &self.ip.to_string(),
             &self.user.to_string(),
             self.port,
         ) {
-            godot_error!("{}", e);
-            return false;
+            Ok(output) => Variant::from(output.to_dict()),
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
+            }
         }
+    }
+
+    /// Runs a script of steps in order on the client's session, without blocking. Stops at the first failing step,
+    /// then runs the rollbacks of the failed step and all steps before it in reverse order.
+    /// Progress is emitted as [signal script_step_started] and [signal script_step_finished],
+    /// the report as [signal script_finished]. Returns false if the steps are invalid.
+    ///
+    /// * `script_id` - Id the signals of this run are emitted with.
+    /// * `steps` - [Array] of [Dictionary] with the keys `command`, `name` (optional), `timeout` (optional, seconds),
+    ///   `expected_exit_codes` (optional, defaults to [0]) and `rollback` (optional command undoing the step).
+    ///   Rollbacks use the timeout of their step and succeed with exit code 0.
+    #[func]
+    fn run_script(&mut self, script_id: String, steps: Array<Variant>) -> bool {
+        let steps = match self
+            .check_configured()
+            .and_then(|_| script::parse_steps(&steps))
+        {
+            Ok(steps) => steps,
+            Err(e) => {
+                godot_error!("{}", e);
+                return false;
+            }
+        };
+        let client_id = self.base().instance_id();
+        thread::spawn(move || match ClientLease::new(client_id) {
+            Ok(mut client) => {
+                let (ip, user) = (client.ip.clone(), client.user.clone());
+                let port = client.port;
+                execute_script(&mut client, client_id, &script_id, &steps, &ip, &user, port);
+            }
+            Err(e) => godot_error!("{}", e),
+        });
         true
     }
 
-    /// Sets auth method to type private key file.
+    /// Runs a script in a blocking fashion, see [method run_script]. Signals are still emitted, but deferred.
+    /// Returns null if the steps are invalid, otherwise a [Dictionary] with the keys `success`,
+    /// `failed_step` (index, -1 if none failed), `rollback_failed` and `steps`: an [Array] with a [Dictionary]
+    /// per step with the keys `name`, `state` ("succeeded", "failed" or "not_run"), `run` and `rollback`.
+    /// `run` and `rollback` are null if not run, otherwise a [Dictionary] with the keys `command`, `success`,
+    /// `exit_status` (-1 if none was sent), `stdout`, `stderr` (the first and last 32 KiB each),
+    /// `error` (null unless it couldn't be run, timed out or was terminated by a signal) and `duration` (seconds).
     ///
-    /// * `key_path` - Path to private key.
-    /// * `password` - Optional password to decrypt private key.
+    /// * `script_id` - Id the signals of this run are emitted with.
+    /// * `steps` - See [method run_script].
     #[func]
-    fn set_auth_key_file(&mut self, key_path: String, password: String) {
-        self._internal_ssh_client.auth_method = AuthMethod::PrivateKeyFile {
-            key_file_path: PathBuf::from(key_path),
-            key_pass: if password.as_str() != "" {
-                Some(password)
-            } else {
-                None
-            },
+    fn run_script_blocking(&mut self, script_id: String, steps: Array<Variant>) -> Variant {
+        match self
+            .check_configured()
+            .and_then(|_| script::parse_steps(&steps))
+        {
+            Ok(steps) => {
+                let emitter = self.base().instance_id();
+                Variant::from(execute_script(
+                    &mut self._internal_ssh_client,
+                    emitter,
+                    &script_id,
+                    &steps,
+                    &self.ip.to_string(),
+                    &self.user.to_string(),
+                    self.port,
+                ))
+            }
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
+            }
         }
     }
 
-    /// Sets auth method to type private key.
+    /// Pushes a local directory to the server without blocking, transferring only new or changed files.
+    /// Only supported on unix servers. Files are streamed over the client's session and keep their
+    /// permissions and modification time. Symlinks and empty directories are skipped.
+    /// Progress is emitted as [signal sync_progress], the report as [signal sync_finished].
+    /// Returns false if the options are invalid.
     ///
-    /// * `key_data` - Base64 encoded key data of the private key.
-    /// * `password` - Optional password to decrypt private key.
+    /// * `sync_id` - Id the signals of this sync are emitted with.
+    /// * `local_dir` - Local directory, Godot paths like `user://` are supported.
+    /// * `remote_dir` - Remote directory, created if it doesn't exist. May start with `~/`.
+    /// * `options` - [Dictionary] with the optional keys `compare` ("size_mtime", the default,
+    ///   or "hash" to compare MD5 of the content), `delete` (delete remote files which don't exist locally),
+    ///   `exclude` ([Array] of gitignore like patterns, e.g. ".git/" or "*.tmp", also protecting remote files
+    ///   from deletion) and `dry_run` (only report what would be done).
     #[func]
-    fn set_auth_key(&mut self, key_data: String, password: String) {
-        self._internal_ssh_client.auth_method = AuthMethod::PrivateKey {
-            key_data,
-            key_pass: if password.as_str() != "" {
-                Some(password)
-            } else {
-                None
-            },
+    fn sync_directory(
+        &mut self,
+        sync_id: String,
+        local_dir: String,
+        remote_dir: String,
+        options: Dictionary<GString, Variant>,
+    ) -> bool {
+        let options = match self
+            .check_configured()
+            .and_then(|_| SyncOptions::from_dict(globalize_path(&local_dir), remote_dir, &options))
+        {
+            Ok(options) => options,
+            Err(e) => {
+                godot_error!("{}", e);
+                return false;
+            }
+        };
+        let client_id = self.base().instance_id();
+        thread::spawn(move || match ClientLease::new(client_id) {
+            Ok(mut client) => {
+                let (ip, user) = (client.ip.clone(), client.user.clone());
+                let port = client.port;
+                execute_sync(&mut client, client_id, &sync_id, &options, &ip, &user, port);
+            }
+            Err(e) => godot_error!("{}", e),
+        });
+        true
+    }
+
+    /// Syncs a directory in a blocking fashion, see [method sync_directory]. Signals are still emitted, but deferred.
+    /// Returns null if the options are invalid, otherwise a [Dictionary] with the keys `success`, `error`
+    /// (null unless the sync couldn't be run), `dry_run`, `created`, `updated`, `deleted`, `unchanged`, `failed`
+    /// (amounts of files), `bytes` (transferred) and `files`: an [Array] with a [Dictionary] per transferred
+    /// or deleted file with the keys `path` (relative), `action` ("create", "update" or "delete"), `size`
+    /// and `error` (null on success).
+    #[func]
+    fn sync_directory_blocking(
+        &mut self,
+        sync_id: String,
+        local_dir: String,
+        remote_dir: String,
+        options: Dictionary<GString, Variant>,
+    ) -> Variant {
+        match self
+            .check_configured()
+            .and_then(|_| SyncOptions::from_dict(globalize_path(&local_dir), remote_dir, &options))
+        {
+            Ok(options) => {
+                let emitter = self.base().instance_id();
+                Variant::from(execute_sync(
+                    &mut self._internal_ssh_client,
+                    emitter,
+                    &sync_id,
+                    &options,
+                    &self.ip.to_string(),
+                    &self.user.to_string(),
+                    self.port,
+                ))
+            }
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
+            }
         }
     }
 
-    /// Sets auth method to type password.
+    /// Starts `cmd` as a detached job, which keeps running when the session drops or the client is freed.
+    /// Only supported on unix servers. Runs as transient systemd user unit if the user has lingering enabled,
+    /// otherwise with `nohup` and `setsid`. The command is run by `sh` in the home directory and its
+    /// output is written to `~/.dreamdeck/jobs/<job id>` on the server.
+    /// Returns null on failure, otherwise the job id.
     ///
-    /// * `password` - Password for server.
+    /// * `cmd` - Command to execute.
     #[func]
-    fn set_auth_password(&mut self, password: String) {
-        self._internal_ssh_client.auth_method = AuthMethod::Password(password);
+    fn start_job(&mut self, cmd: String) -> Variant {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return Variant::nil();
+        }
+        match self._internal_ssh_client.start_job(
+            &cmd,
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            Ok(job_id) => Variant::from(job_id),
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
+            }
+        }
     }
 
-    /// Sets the method by which to check the server against.
+    /// Returns null on failure, otherwise a [Dictionary] with the keys `state` ("running", "exited",
+    /// "killed", "lost" if it stopped without an exit status, e.g. after a reboot, or "missing"),
+    /// `exit_status` (null unless exited), `pid`, `mode` ("systemd" or "nohup"), `started` (unix time),
+    /// `stdout_size` and `stderr_size`.
     ///
-    /// * `method` - Currently supported: "known_hosts_file" or "no_check".
+    /// * `job_id` - Id returned by [method start_job].
     #[func]
-    fn set_server_check_method(&mut self, method: String) {
-        match method.as_str() {
-            "known_hosts_file" => {
-                self._internal_ssh_client.server_check = ServerCheckMethod::DefaultKnownHostsFile
+    fn get_job_status(&mut self, job_id: String) -> Variant {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return Variant::nil();
+        }
+        match self._internal_ssh_client.job_status(
+            &job_id,
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            Ok(status) => Variant::from(status.to_dict()),
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
             }
-            "no_check" => self._internal_ssh_client.server_check = ServerCheckMethod::NoCheck,
-            _ => {
-                self._internal_ssh_client.server_check = ServerCheckMethod::NoCheck;
+        }
+    }
+
+    /// Reads a job's output incrementally. Returns null on failure, otherwise a [Dictionary] with the keys
+    /// `data` ([PackedByteArray], empty if there is no new output) and `next_offset` to pass to the next call.
+    ///
+    /// * `job_id` - Id returned by [method start_job].
+    /// * `offset` - Byte offset to read from, 0 for the beginning.
+    /// * `max_bytes` - Maximum bytes to read.
+    /// * `stderr` - Read stderr instead of stdout.
+    #[func]
+    fn read_job_output(
+        &mut self,
+        job_id: String,
+        offset: u64,
+        max_bytes: u32,
+        stderr: bool,
+    ) -> Variant {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return Variant::nil();
+        }
+        match self._internal_ssh_client.read_job_output(
+            &job_id,
+            stderr,
+            offset..offset + max_bytes as u64,
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            Ok(output) => Variant::from(output.to_dict()),
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
             }
         }
     }
 
-    // TODO add an optional password to encrypt key
-    /// Generates a private key in the openssh format. This can be used as the `key_data` for `set_auth_key`.
-    /// Returns empty string on failure.
+    /// Terminates a job and all processes it started. Returns false on failure or if it wasn't running.
     ///
-    /// * `key_type` - Currently supported: "ED25519" or "RSA".
-    /// * `key_size` - Size of the key if it's RSA. Recommended value is 4096. (Will be ignored for ED25519)
-    /// * `comment` - Comment of the key. This is e.g. user@hostname by default for openssh.
+    /// * `job_id` - Id returned by [method start_job].
+    #[func]
+    fn kill_job(&mut self, job_id: String) -> bool {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return false;
+        }
+        match self._internal_ssh_client.kill_job(
+            &job_id,
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            Ok(killed) => killed,
+            Err(e) => {
+                godot_error!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Returns the ids of all jobs on the server, oldest first, including finished ones.
     #[func]
-    fn generate_private_key(key_type: String, key_size: i64, comment: String) -> String {
-        match generate_private_key(key_type, key_size, comment) {
-            Ok(base64_key) => base64_key,
+    fn list_jobs(&mut self) -> PackedStringArray {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return PackedStringArray::new();
+        }
+        match self._internal_ssh_client.list_jobs(
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            Ok(job_ids) => job_ids
+                .iter()
+                .map(|job_id| GString::from(job_id.as_str()))
+                .collect(),
             Err(e) => {
                 godot_error!("{}", e);
-                "".to_string()
+                PackedStringArray::new()
             }
         }
     }
 
-    /// Checks that the client is configured.
-    fn check_configured(&self) -> anyhow::Result<()> {
-        if self.user.is_nil() || self.user.get_type() != VariantType::STRING {
-            anyhow::bail!("Invalid user \"{}\"", self.user);
+    /// Deletes a job including its output from the server. Running jobs need to be killed first.
+    ///
+    /// * `job_id` - Id returned by [method start_job].
+    #[func]
+    fn remove_job(&mut self, job_id: String) -> bool {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return false;
         }
-        if self.ip.is_nil() || self.ip.get_type() != VariantType::STRING {
-            anyhow::bail!("Invalid ip \"{}\"", self.ip);
+        if let Err(e) = self._internal_ssh_client.remove_job(
+            &job_id,
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            godot_error!("{}", e);
+            return false;
+        }
+        true
+    }
+
+    /// Lists all loaded systemd service units. Returns null on failure, otherwise an [Array] with a
+    /// [Dictionary] per unit with the keys `name`, `load_state`, `active_state`, `sub_state` and `description`.
+    ///
+    /// * `user_units` - List units of the user's service manager instead of the system's.
+    #[func]
+    fn list_services(&mut self, user_units: bool) -> Variant {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return Variant::nil();
+        }
+        match self._internal_ssh_client.list_units(
+            user_units,
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            Ok(units) => Variant::from(dict_array(units)),
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
+            }
         }
-        Ok(())
     }
+
+    /// Returns null on failure, otherwise a [Dictionary] with the keys `name`, `description`, `load_state`
+    /// ("not-found" if the unit doesn't exist), `active_state`, `sub_state`, `unit_file_state`, `main_pid`,
+    /// `exit_code` (of the last run), `result`, `restarts` and `uptime` (seconds, null if not active).
+    ///
+    /// * `unit` - Name of the unit, e.g. "nginx.service".
+    /// * `user_units` - Use the user's service manager instead of the system's.
+    #[func]
+    fn get_service_status(&mut self, unit: String, user_units: bool) -> Variant {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return Variant::nil();
+        }
+        match self._internal_ssh_client.unit_status(
+            &unit,
+            user_units,
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            Ok(status) => Variant::from(status),
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
+            }
+        }
+    }
+
+    /// Performs `action` on a systemd unit. System units usually require root.
+    /// Returns null on success, otherwise a [Dictionary] with the keys `kind` ("not_found", "permission_denied",
+    /// "unavailable" if systemd isn't available, "failed", "invalid" or "connection") and `message`.
+    ///
+    /// * `unit` - Name of the unit, e.g. "nginx.service".
+    /// * `action` - "start", "stop", "restart", "reload", "enable" or "disable".
+    /// * `user_units` - Use the user's service manager instead of the system's.
+    #[func]
+    fn control_service(&mut self, unit: String, action: String, user_units: bool) -> Variant {
+        if let Err(e) = self.check_configured() {
+            return Variant::from(ServiceError::Invalid(e.to_string()).to_dict());
+        }
+        match ServiceAction::parse(&action).and_then(|action| {
+            self._internal_ssh_client.unit_action(
+                &unit,
+                action,
+                user_units,
+                &self.ip.to_string(),
+                &self.user.to_string(),
+                self.port,
+            )
+        }) {
+            Ok(_) => Variant::nil(),
+            Err(e) => Variant::from(e.to_dict()),
+        }
+    }
+
+    /// Shuts down, reboots or suspends the server with the command of its OS, see [method get_remote_info].
+    /// Suspending isn't supported on every OS. Usually requires root.
+    ///
+    /// * `action` - "shutdown", "reboot" or "suspend".
+    /// * `elevated` - Run the command with sudo, see [method exec_blocking_elevated].
+    #[func]
+    fn power(&mut self, action: String, elevated: bool) -> bool {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return false;
+        }
+        if let Err(e) = PowerAction::parse(&action).and_then(|action| {
+            self._internal_ssh_client.power_action(
+                action,
+                elevated,
+                &self.ip.to_string(),
+                &self.user.to_string(),
+                self.port,
+            )
+        }) {
+            godot_error!("{}", e);
+            return false;
+        }
+        true
+    }
+
+    /// Lists all Docker containers, including stopped ones. Returns null on failure, otherwise an [Array]
+    /// with a [Dictionary] per container with the keys `id`, `name`, `image`, `state` (e.g. "running" or "exited"),
+    /// `status` (e.g. "Up 2 hours") and `created_at`.
+    #[func]
+    fn list_containers(&mut self) -> Variant {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return Variant::nil();
+        }
+        match self._internal_ssh_client.list_containers(
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            Ok(containers) => Variant::from(dict_array(containers)),
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
+            }
+        }
+    }
+
+    /// Returns null on failure, otherwise a [Dictionary] with the keys `id`, `name`, `image`, `state`, `running`,
+    /// `exit_code`, `oom_killed`, `health` (empty without health check), `started_at`, `finished_at`,
+    /// `restart_count`, `restart_policy` and `uptime` (seconds, null if not running).
+    ///
+    /// * `container` - Name or id of the container.
+    #[func]
+    fn get_container_status(&mut self, container: String) -> Variant {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return Variant::nil();
+        }
+        match self._internal_ssh_client.container_status(
+            &container,
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            Ok(status) => Variant::from(status),
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
+            }
+        }
+    }
+
+    /// Performs `action` on a Docker container. Returns null on success, otherwise a [Dictionary]
+    /// like [method control_service].
+    ///
+    /// * `container` - Name or id of the container.
+    /// * `action` - "start", "stop", "restart", "reload" (sends SIGHUP), "enable" (restart policy
+    ///   "unless-stopped") or "disable" (restart policy "no").
+    #[func]
+    fn control_container(&mut self, container: String, action: String) -> Variant {
+        if let Err(e) = self.check_configured() {
+            return Variant::from(ServiceError::Invalid(e.to_string()).to_dict());
+        }
+        match ServiceAction::parse(&action).and_then(|action| {
+            self._internal_ssh_client.container_action(
+                &container,
+                action,
+                &self.ip.to_string(),
+                &self.user.to_string(),
+                self.port,
+            )
+        }) {
+            Ok(_) => Variant::nil(),
+            Err(e) => Variant::from(e.to_dict()),
+        }
+    }
+
+    /// Detects the server's OS, default shell, architecture and hostname.
+    /// The result is cached for the current session.
+    /// Returns null on failure, otherwise a [Dictionary] with the keys
+    /// `os` ("linux", "macos", "bsd", "windows" or "other"), `os_name`,
+    /// `shell` ("sh", "bash", "zsh", "fish", "cmd", "powershell" or "other"), `shell_path`, `arch` and `hostname`.
+    ///
+    /// * `refresh` - Probe the server again even if a cached result exists.
+    #[func]
+    fn get_remote_info(&mut self, refresh: bool) -> Variant {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return Variant::nil();
+        }
+        if refresh {
+            self._internal_ssh_client.remote_info = None;
+        }
+        match self._internal_ssh_client.remote_info(
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            Ok(remote_info) => Variant::from(remote_info.to_dict()),
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
+            }
+        }
+    }
+
+    /// Runs every stage of connecting to the server separately, to find out where a setup breaks.
+    /// An already open session isn't affected.
+    ///
+    /// Returns an [Array] with a [Dictionary] per step with the keys `step`, `status` ("ok", "failed" or
+    /// "skipped" if a previous step failed), `duration_ms`, `message` and `details` (a [Dictionary] of strings).
+    /// The steps are "dns", "tcp" (connect latency), "banner" (server version), "algorithms" (negotiated
+    /// algorithms), "host_key", "auth_methods" (offered by the server), "auth" and "exec" (channel open and test command).
+    #[func]
+    fn diagnose(&mut self) -> Array<Variant> {
+        let mut report: Array<Variant> = Array::new();
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return report;
+        }
+        for step in self._internal_ssh_client.diagnose(
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            report.push(&Variant::from(step.to_dict()));
+        }
+        report
+    }
+
+    /// Try to open a session for the client. If a session is already active it will be closed.
+    /// Will return null on success, otherwise a string with the error will be returned.
+    #[func]
+    fn open_session(&mut self) -> Variant {
+        if let Err(e) = self.check_configured() {
+            return Variant::from(e.to_string());
+        }
+        if let Err(e) = block_on(self._internal_ssh_client.open_session(
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        )) {
+            return Variant::from(e.to_string());
+        }
+        Variant::nil()
+    }
+
+    /// Disconnect the current session if one is active.
+    #[func]
+    fn disconnect_session(&mut self) {
+        if let Err(error) = block_on(self._internal_ssh_client.disconnect_session()) {
+            godot_error!("Failed to disconnect ssh session: {}", error);
+        }
+    }
+
+    /// Returns the current session status.
+    #[func]
+    fn is_session_active(&self) -> bool {
+        self._internal_ssh_client.is_session_active()
+    }
+
+    /// Returns the traffic and channel statistics of this client, counted across all its sessions.
+    /// A [Dictionary] with the keys `active` (whether a session is open), `bytes_sent`, `bytes_received`
+    /// (including the SSH protocol overhead), `channels_opened`, `channel_failures` (channels which
+    /// couldn't be opened), `commands_run`, `average_command_latency` (seconds commands took until they
+    /// exited, null if none ran, streamed commands and terminals aren't included), `uptime` (seconds of the current session, null if none is open),
+    /// `reconnects` (sessions opened after the first one) and `keepalive_rtt` (seconds of the last
+    /// [method measure_keepalive], null if none was measured).
+    #[func]
+    fn get_session_stats(&self) -> Dictionary<GString, Variant> {
+        self._internal_ssh_client.session_stats()
+    }
+
+    /// Sends a keepalive over the current session and waits up to 5 seconds for the reply.
+    /// Doesn't open a session. Returns the round-trip in seconds or null on failure.
+    #[func]
+    fn measure_keepalive(&mut self) -> Variant {
+        match block_on(self._internal_ssh_client.measure_keepalive()) {
+            Ok(rtt) => Variant::from(rtt.as_secs_f64()),
+            Err(e) => {
+                self._internal_ssh_client.log.warn(
+                    LogEvent::Connect,
+                    &self.ip.to_string(),
+                    self.port,
+                    || e.to_string(),
+                );
+                Variant::nil()
+            }
+        }
+    }
+
+    /// Emits [signal session_stats_updated] every `interval` seconds, e.g. for a status panel.
+    /// The updates read the statistics shared with the connection, so they never wait for a busy client.
+    /// `keepalive_rtt` is the round-trip of the last [method measure_keepalive].
+    /// Restarts the updates if they already run.
+    #[func]
+    fn start_session_stats(&mut self, interval: f64) {
+        let interval = match Duration::try_from_secs_f64(interval.max(0.1)) {
+            // Capped, so the updates can't overflow
+            Ok(interval) => interval.min(MAX_DELAY),
+            Err(e) => {
+                godot_error!("Invalid session stats interval {}: {}", interval, e);
+                return;
+            }
+        };
+        self.stop_session_stats();
+        let stop = Arc::new(AtomicBool::new(false));
+        self.stats_stop = Some(stop.clone());
+        let stats = self._internal_ssh_client.stats.clone();
+        let client_id = self.base().instance_id();
+        thread::spawn(move || {
+            let mut next_update = Instant::now() + interval;
+            while !stop.load(Ordering::Relaxed) {
+                if Instant::now() < next_update {
+                    thread::sleep(STATS_POLL_INTERVAL.min(interval));
+                    continue;
+                }
+                next_update += interval;
+                if Gd::<Object>::try_from_instance_id(client_id).is_err() {
+                    break;
+                }
+                let stats = stats.to_dict(stats.is_connected());
+                emit_deferred(client_id, "session_stats_updated", &[stats.to_variant()]);
+            }
+        });
+    }
+
+    #[func]
+    fn stop_session_stats(&mut self) {
+        if let Some(stop) = self.stats_stop.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// If the current auth method is a private key method, this function can add the private key
+    /// to the current server's authorized keys, like `ssh-copy-id`. Works for unix servers as well as
+    /// Windows OpenSSH servers. If the key is already authorized nothing is changed.
+    ///
+    /// **Note:** This will close any currently active sessions.
+    ///
+    /// * `password` - Password to temporarily connect to the server.
+    /// * `options` - Optional key options, e.g. `from="10.0.0.0/8",command="uptime"`.
+    #[func]
+    fn add_key_to_server(&mut self, password: String, options: String) -> bool {
+        let public_key = match self._internal_ssh_client.auth_public_key() {
+            Ok(public_key) => public_key,
+            Err(e) => {
+                godot_error!("{}", e);
+                return false;
+            }
+        };
+        self.add_public_key_to_server(password, public_key, options)
+    }
+
+    /// Adds `public_key` to the current server's authorized keys, like `ssh-copy-id`.
+    /// This can e.g. be used to authorize a new key while rotating keys.
+    /// If the key is already authorized nothing is changed.
+    ///
+    /// **Note:** If `password` is set, this will close any currently active sessions.
+    ///
+    /// * `password` - Password to temporarily connect to the server.
+    ///   If empty the currently set auth method is used.
+    /// * `public_key` - The public key line, e.g. from [method get_public_key].
+    /// * `options` - Optional key options, e.g. `from="10.0.0.0/8",command="uptime"`.
+    #[func]
+    fn add_public_key_to_server(
+        &mut self,
+        password: String,
+        public_key: String,
+        options: String,
+    ) -> bool {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return false;
+        }
+        match self._internal_ssh_client.deploy_public_key(
+            optional_string(password).map(Zeroizing::new),
+            &public_key,
+            &options,
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            Ok(KeyChange::Changed) => true,
+            Ok(KeyChange::Unchanged) => {
+                self._internal_ssh_client.log.info(
+                    LogEvent::Key,
+                    &self.ip.to_string(),
+                    self.port,
+                    || "Public key is already authorized".to_string(),
+                );
+                true
+            }
+            Err(e) => {
+                godot_error!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Removes `public_key` from the current server's authorized keys. Returns true if the key
+    /// is not authorized anymore, also if it wasn't authorized in the first place.
+    ///
+    /// **Note:** If `password` is set, this will close any currently active sessions.
+    ///
+    /// * `password` - Password to temporarily connect to the server.
+    ///   If empty the currently set auth method is used.
+    /// * `public_key` - The public key line to remove. If empty the key of the current auth method is removed.
+    #[func]
+    fn remove_key_from_server(&mut self, password: String, public_key: String) -> bool {
+        if let Err(e) = self.check_configured() {
+            godot_error!("{}", e);
+            return false;
+        }
+        let public_key = if public_key.is_empty() {
+            match self._internal_ssh_client.auth_public_key() {
+                Ok(public_key) => public_key,
+                Err(e) => {
+                    godot_error!("{}", e);
+                    return false;
+                }
+            }
+        } else {
+            public_key
+        };
+        match self._internal_ssh_client.revoke_public_key(
+            optional_string(password).map(Zeroizing::new),
+            &public_key,
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        ) {
+            Ok(KeyChange::Changed) => true,
+            Ok(KeyChange::Unchanged) => {
+                self._internal_ssh_client.log.info(
+                    LogEvent::Key,
+                    &self.ip.to_string(),
+                    self.port,
+                    || "Public key was not authorized".to_string(),
+                );
+                true
+            }
+            Err(e) => {
+                godot_error!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Sets auth method to type private key file.
+    ///
+    /// * `key_path` - Path to private key.
+    /// * `password` - Optional password to decrypt private key. It's wiped after authentication,
+    ///   see [signal credentials_required].
+    #[func]
+    fn set_auth_key_file(&mut self, key_path: String, password: String) {
+        self._internal_ssh_client.auth_method = AuthMethod::PrivateKeyFile {
+            key_file_path: PathBuf::from(key_path),
+            key_pass: secret_value(password),
+        }
+    }
+
+    /// Sets auth method to type certificate: a private key file with an OpenSSH user certificate signed by a CA.
+    /// Before authenticating the certificate is checked to belong to the key, to be currently valid and
+    /// to list the user as principal, so e.g. an expired certificate is reported as such.
+    ///
+    /// * `key_path` - Path to private key.
+    /// * `cert_path` - Path to the certificate. If empty `<key_path>-cert.pub` is used, like ssh does.
+    /// * `password` - Optional password to decrypt private key. It's wiped after authentication,
+    ///   see [signal credentials_required].
+    #[func]
+    fn set_auth_certificate(&mut self, key_path: String, cert_path: String, password: String) {
+        let key_file_path = PathBuf::from(key_path);
+        let cert_file_path = match optional_string(cert_path) {
+            Some(cert_path) => PathBuf::from(cert_path),
+            None => certificate_path(&key_file_path),
+        };
+        self._internal_ssh_client.auth_method = AuthMethod::Certificate {
+            key_file_path,
+            cert_file_path,
+            key_pass: secret_value(password),
+        }
+    }
+
+    /// Sets auth method to type private key. The key is wiped after authentication,
+    /// see [signal credentials_required].
+    ///
+    /// * `key_data` - Base64 encoded key data of the private key.
+    /// * `password` - Optional password to decrypt private key.
+    #[func]
+    fn set_auth_key(&mut self, key_data: String, password: String) {
+        self._internal_ssh_client.auth_method = AuthMethod::PrivateKey {
+            key_data: SecretSource::Value(Zeroizing::new(key_data)),
+            key_pass: secret_value(password),
+        }
+    }
+
+    /// Sets auth method to type password. The password is wiped after authentication,
+    /// see [signal credentials_required].
+    ///
+    /// * `password` - Password for server.
+    #[func]
+    fn set_auth_password(&mut self, password: String) {
+        self._internal_ssh_client.auth_method =
+            AuthMethod::Password(SecretSource::Value(Zeroizing::new(password)));
+    }
+
+    /// Sets auth method to type password, taking the password from an unlocked `vault`.
+    /// The password is read from the vault whenever the client authenticates, so the vault
+    /// has to stay unlocked to reconnect.
+    ///
+    /// * `vault` - The vault containing the password.
+    /// * `password_id` - Id of the password in the vault.
+    #[func]
+    fn set_auth_password_from_vault(&mut self, vault: Gd<SSHVault>, password_id: String) -> bool {
+        let password = SecretSource::Vault {
+            vault,
+            id: password_id,
+        };
+        if let Err(e) = password.get() {
+            godot_error!("{}", e);
+            return false;
+        }
+        self._internal_ssh_client.auth_method = AuthMethod::Password(password);
+        true
+    }
+
+    /// Sets the password sudo is answered with by [method exec_blocking_elevated]. An empty password clears it.
+    ///
+    /// * `password` - The sudo password of the user.
+    #[func]
+    fn set_sudo_password(&mut self, password: String) {
+        self._internal_ssh_client.sudo_password = if password.is_empty() {
+            None
+        } else {
+            Some(Zeroizing::new(password))
+        };
+    }
+
+    /// Sets the password sudo is answered with, taking it from an unlocked `vault`.
+    ///
+    /// * `vault` - The vault containing the password.
+    /// * `password_id` - Id of the password in the vault.
+    #[func]
+    fn set_sudo_password_from_vault(&mut self, vault: Gd<SSHVault>, password_id: String) -> bool {
+        let password = match vault
+            .bind()
+            .vault()
+            .and_then(|vault| vault.get_secret_string(&password_id))
+        {
+            Ok(password) => password,
+            Err(e) => {
+                godot_error!("{}", e);
+                return false;
+            }
+        };
+        self._internal_ssh_client.sudo_password = Some(password);
+        true
+    }
+
+    /// Sets auth method to type private key, taking the key from an unlocked `vault`.
+    /// The key is read from the vault whenever the client authenticates, so the vault
+    /// has to stay unlocked to reconnect.
+    ///
+    /// * `vault` - The vault containing the key.
+    /// * `key_id` - Id of the private key in the vault.
+    /// * `passphrase_id` - Optional id of the passphrase to decrypt the private key in the vault.
+    #[func]
+    fn set_auth_key_from_vault(
+        &mut self,
+        vault: Gd<SSHVault>,
+        key_id: String,
+        passphrase_id: String,
+    ) -> bool {
+        let key_pass = optional_string(passphrase_id).map(|id| SecretSource::Vault {
+            vault: vault.clone(),
+            id,
+        });
+        let key_data = SecretSource::Vault { vault, id: key_id };
+        for secret in std::iter::once(&key_data).chain(&key_pass) {
+            if let Err(e) = secret.get() {
+                godot_error!("{}", e);
+                return false;
+            }
+        }
+        self._internal_ssh_client.auth_method = AuthMethod::PrivateKey { key_data, key_pass };
+        true
+    }
+
+    /// Sets the method by which to check the server against.
+    ///
+    /// * `method` - Currently supported: "known_hosts_file" or "no_check".
+    #[func]
+    fn set_server_check_method(&mut self, method: String) {
+        match method.as_str() {
+            "known_hosts_file" => {
+                self._internal_ssh_client.server_check = ServerCheckMethod::DefaultKnownHostsFile
+            }
+            "no_check" => self._internal_ssh_client.server_check = ServerCheckMethod::NoCheck,
+            _ => {
+                self._internal_ssh_client.server_check = ServerCheckMethod::NoCheck;
+            }
+        }
+    }
+
+    /// Trusts a CA to sign host certificates, in addition to the `@cert-authority` lines of the known hosts file.
+    /// When connecting, a host key the server check doesn't accept is accepted if the server's host certificate
+    /// certifies it and is signed by a trusted CA. Returns false if `public_key` is invalid.
+    ///
+    /// * `public_key` - Public key line of the CA, e.g. "ssh-ed25519 AAAA... ca@example.com".
+    /// * `hosts` - Comma separated known_hosts patterns the CA is trusted for, e.g. "*.example.com,!bastion.example.com".
+    ///   If empty the CA is trusted for all hosts.
+    #[func]
+    fn add_host_certificate_authority(&mut self, public_key: String, hosts: String) -> bool {
+        match HostAuthority::parse(&public_key, &hosts) {
+            Ok(authority) => {
+                self._internal_ssh_client.host_authorities.push(authority);
+                true
+            }
+            Err(e) => {
+                godot_error!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Removes all CAs added with [method add_host_certificate_authority] and forgets all certified host keys.
+    #[func]
+    fn clear_host_certificate_authorities(&mut self) {
+        self._internal_ssh_client.host_authorities.clear();
+        self._internal_ssh_client.attested_host_keys.clear();
+    }
+
+    /// Checks an OpenSSH host certificate of the server and, if it is valid, accepts the host key it certifies
+    /// from now on, even if it isn't in the known hosts. Connecting already checks the certificate the server
+    /// presents, this is for certificates obtained otherwise, e.g. distributed with the inventory.
+    /// As its CA signature is what makes it trustworthy, where it came from doesn't matter.
+    ///
+    /// The certificate has to certify the host key the server presented at the last connection attempt,
+    /// be a currently valid host certificate listing the configured ip as principal and be signed by a CA
+    /// trusted for the host, see [method add_host_certificate_authority].
+    /// Returns false and reports why, e.g. that the certificate expired, otherwise.
+    ///
+    /// * `certificate` - The certificate line, e.g. the content of `ssh_host_ed25519_key-cert.pub`.
+    #[func]
+    fn verify_host_certificate(&mut self, certificate: String) -> bool {
+        let (ip, port) = self.address();
+        match self
+            ._internal_ssh_client
+            .attest_host_key(&certificate, &ip, port)
+        {
+            Ok(()) => true,
+            Err(e) => {
+                godot_error!("Host certificate rejected: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Forwards the local agent at `$SSH_AUTH_SOCK` to the server, e.g. so a `git pull` there can use your keys.
+    /// The server can only list the keys and sign with them, not add, remove or lock keys.
+    /// Applies to channels opened from now on.
+    ///
+    /// * `always` - Forward for every command and shell. Otherwise only for [method exec_blocking_forwarding_agent].
+    #[func]
+    fn forward_local_agent(&mut self, always: bool) {
+        self._internal_ssh_client.agent.backend = Some(AgentBackend::Local);
+        self._internal_ssh_client.agent.always = always;
+    }
+
+    /// Forwards an agent holding only `keys` to the server, e.g. keys of DreamDeck's key store.
+    /// RSA keys only sign with rsa-sha2-512. Applies to channels opened from now on.
+    /// Returns false if a key couldn't be decoded.
+    ///
+    /// * `keys` - Unencrypted private keys in the openssh or PEM format.
+    /// * `always` - Forward for every command and shell. Otherwise only for [method exec_blocking_forwarding_agent].
+    #[func]
+    fn forward_agent_keys(&mut self, keys: PackedStringArray, always: bool) -> bool {
+        let keys = match keys
+            .as_slice()
+            .iter()
+            .map(|key| key_utils::decode_private_key(&Zeroizing::new(key.to_string()), None))
+            .collect::<anyhow::Result<Vec<_>>>()
+        {
+            Ok(keys) => keys,
+            Err(e) => {
+                godot_error!("{}", e);
+                return false;
+            }
+        };
+        self._internal_ssh_client.agent.backend = Some(AgentBackend::Keys(Arc::new(keys)));
+        self._internal_ssh_client.agent.always = always;
+        true
+    }
+
+    /// Forwards an agent holding only the key `key_id` of an unlocked `vault`, see [method forward_agent_keys].
+    ///
+    /// * `vault` - The vault containing the unencrypted private key.
+    /// * `key_id` - Id of the private key in the vault.
+    /// * `always` - Forward for every command and shell. Otherwise only for [method exec_blocking_forwarding_agent].
+    #[func]
+    fn forward_agent_key_from_vault(
+        &mut self,
+        vault: Gd<SSHVault>,
+        key_id: String,
+        always: bool,
+    ) -> bool {
+        let key = match vault
+            .bind()
+            .vault()
+            .and_then(|vault| vault.get_secret_string(&key_id))
+            .and_then(|key_data| key_utils::decode_private_key(&key_data, None))
+        {
+            Ok(key) => key,
+            Err(e) => {
+                godot_error!("{}", e);
+                return false;
+            }
+        };
+        self._internal_ssh_client.agent.backend = Some(AgentBackend::Keys(Arc::new(vec![key])));
+        self._internal_ssh_client.agent.always = always;
+        true
+    }
+
+    /// Stops forwarding an agent for channels opened from now on.
+    #[func]
+    fn disable_agent_forwarding(&mut self) {
+        self._internal_ssh_client.agent.backend = None;
+    }
+
+    /// Asks before every signature of a forwarded agent: [signal agent_sign_requested] is emitted and
+    /// the request waits up to 60 seconds for [method confirm_agent_request], otherwise it is denied.
+    #[func]
+    fn set_agent_confirmation(&mut self, enabled: bool) {
+        if !enabled {
+            self._internal_ssh_client.agent.confirm = None;
+            return;
+        }
+        let emitter = self.base().instance_id();
+        self._internal_ssh_client.agent.confirm =
+            Some(Arc::new(move |request: &AgentSignRequest| {
+                CONFIRMATIONS.confirm(
+                    |request_id| {
+                        emit_deferred(
+                            emitter,
+                            "agent_sign_requested",
+                            &[
+                                (request_id as i64).to_variant(),
+                                request.fingerprint.to_variant(),
+                                request.comment.to_variant(),
+                            ],
+                        )
+                    },
+                    AGENT_CONFIRM_TIMEOUT,
+                )
+            }));
+    }
+
+    /// Allows or denies a sign request of [signal agent_sign_requested] of any client.
+    /// Static, as the client is busy running the command the request comes from.
+    /// Returns false if the request doesn't exist anymore, e.g. because it timed out.
+    #[func]
+    fn confirm_agent_request(request_id: i64, allowed: bool) -> bool {
+        CONFIRMATIONS.answer(request_id as u64, allowed)
+    }
+
+    /// Sends a Wake-on-LAN magic packet before every connection attempt, then waits for the server
+    /// to answer, see [method set_pre_connect_timeout]. An empty `mac` disables waking.
+    ///
+    /// * `mac` - MAC address of the server, e.g. "00:11:22:33:44:55".
+    /// * `broadcast` - Address the packet is sent to, e.g. "192.168.1.255" or "192.168.1.255:7".
+    ///   Defaults to "255.255.255.255:9" if empty.
+    #[func]
+    fn set_wake_on_lan(&mut self, mac: String, broadcast: String) -> bool {
+        if mac.is_empty() {
+            self._internal_ssh_client.pre_connect.wake = None;
+            return true;
+        }
+        let broadcast = if broadcast.is_empty() {
+            "255.255.255.255".to_string()
+        } else {
+            broadcast
+        };
+        match power::parse_mac(&mac).and_then(|mac| Ok((mac, power::parse_broadcast(&broadcast)?)))
+        {
+            Ok(wake) => {
+                self._internal_ssh_client.pre_connect.wake = Some(wake);
+                true
+            }
+            Err(e) => {
+                godot_error!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Knocks a sequence of ports before every connection attempt, then waits for the server
+    /// to answer, see [method set_pre_connect_timeout]. An empty `sequence` disables knocking.
+    ///
+    /// * `sequence` - Ports in order, e.g. ["7000", "8000/udp", "9000/tcp"]. Defaults to TCP.
+    #[func]
+    fn set_port_knock(&mut self, sequence: PackedStringArray) -> bool {
+        let knock: anyhow::Result<Vec<Knock>> = sequence
+            .as_slice()
+            .iter()
+            .map(|knock| Knock::parse(&knock.to_string()))
+            .collect();
+        match knock {
+            Ok(knock) => {
+                self._internal_ssh_client.pre_connect.knock = knock;
+                true
+            }
+            Err(e) => {
+                godot_error!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Sets how long to wait for the server to answer after waking it or knocking, defaults to 60 seconds.
+    ///
+    /// * `timeout` - Timeout in seconds.
+    #[func]
+    fn set_pre_connect_timeout(&mut self, timeout: f64) {
+        match Duration::try_from_secs_f64(timeout.max(0.0)) {
+            Ok(timeout) => self._internal_ssh_client.pre_connect.wait_timeout = timeout,
+            Err(e) => godot_error!("Invalid pre-connect timeout {}: {}", timeout, e),
+        }
+    }
+
+    /// Generates a private key in the openssh format. This can be used as the `key_data` for `set_auth_key`.
+    /// Returns empty string on failure.
+    ///
+    /// * `key_type` - Currently supported: "ED25519", "ECDSA" or "RSA".
+    /// * `key_size` - Size of the key if it's RSA. Recommended value is 4096.
+    ///   For ECDSA this selects the curve and has to be 256, 384 or 521. (Will be ignored for ED25519)
+    /// * `comment` - Comment of the key. This is e.g. user@hostname by default for openssh.
+    /// * `passphrase` - Optional passphrase to encrypt the key with. Leave empty for an unencrypted key.
+    #[func]
+    fn generate_private_key(
+        key_type: String,
+        key_size: i64,
+        comment: String,
+        passphrase: String,
+    ) -> String {
+        match key_utils::generate_private_key(&key_type, key_size, comment, &passphrase) {
+            Ok(key) => key,
+            Err(e) => {
+                godot_error!("{}", e);
+                "".to_string()
+            }
+        }
+    }
+
+    /// Returns the public key line of a private key in the format used by `authorized_keys`.
+    /// Returns empty string on failure.
+    ///
+    /// * `key_data` - The private key in the openssh, PKCS#1, PKCS#8 or SEC1 format.
+    /// * `passphrase` - Optional passphrase to decrypt the private key.
+    ///   Only needed for encrypted keys that aren't in the openssh format.
+    #[func]
+    fn get_public_key(key_data: String, passphrase: String) -> String {
+        match key_utils::public_key_line(&key_data, optional_string(passphrase).as_deref()) {
+            Ok(public_key) => public_key,
+            Err(e) => {
+                godot_error!("{}", e);
+                "".to_string()
+            }
+        }
+    }
+
+    /// Returns the fingerprint of a private key, e.g. `SHA256:...` or `MD5:aa:bb:...`.
+    /// Returns empty string on failure.
+    ///
+    /// * `key_data` - The private key in the openssh, PKCS#1, PKCS#8 or SEC1 format.
+    /// * `passphrase` - Optional passphrase to decrypt the private key.
+    ///   Only needed for encrypted keys that aren't in the openssh format.
+    /// * `hash_alg` - Currently supported: "SHA256" or "MD5".
+    #[func]
+    fn get_key_fingerprint(key_data: String, passphrase: String, hash_alg: String) -> String {
+        match key_utils::fingerprint(&key_data, optional_string(passphrase).as_deref(), &hash_alg) {
+            Ok(fingerprint) => fingerprint,
+            Err(e) => {
+                godot_error!("{}", e);
+                "".to_string()
+            }
+        }
+    }
+
+    /// Converts a PEM encoded private key (PKCS#1, PKCS#8 or SEC1) to the openssh format.
+    /// Keys already in the openssh format are also accepted, e.g. to change their passphrase.
+    /// Returns empty string on failure.
+    ///
+    /// * `key_data` - The private key to convert.
+    /// * `passphrase` - Optional passphrase to decrypt the private key.
+    /// * `new_passphrase` - Optional passphrase to encrypt the converted key with.
+    #[func]
+    fn convert_private_key(key_data: String, passphrase: String, new_passphrase: String) -> String {
+        match key_utils::convert_to_openssh(
+            &key_data,
+            optional_string(passphrase).as_deref(),
+            &new_passphrase,
+        ) {
+            Ok(key) => key,
+            Err(e) => {
+                godot_error!("{}", e);
+                "".to_string()
+            }
+        }
+    }
+
+    /// Resolves `host` from an OpenSSH client config the same way `ssh` would.
+    /// Returns null on failure, otherwise a [Dictionary] with the keys
+    /// `host`, `hostname`, `port`, `user`, `identity_files`, `certificate_files`, `proxy_jump`,
+    /// `user_known_hosts_file`, `strict_host_key_checking` and `options`,
+    /// which contains all resolved keywords in lowercase.
+    ///
+    /// * `config_path` - Path to the config. If empty `~/.ssh/config` is used.
+    /// * `host` - The host alias to resolve.
+    #[func]
+    fn resolve_ssh_config_host(config_path: String, host: String) -> Variant {
+        match ssh_config::resolve_host(&config_path_or_default(config_path), &host) {
+            Ok(resolved) => Variant::from(resolved.to_dict()),
+            Err(e) => {
+                godot_error!("{}", e);
+                Variant::nil()
+            }
+        }
+    }
+
+    /// Returns all host aliases of an OpenSSH client config that can be connected to,
+    /// so all aliases that aren't wildcard patterns.
+    ///
+    /// * `config_path` - Path to the config. If empty `~/.ssh/config` is used.
+    #[func]
+    fn list_ssh_config_hosts(config_path: String) -> PackedStringArray {
+        match ssh_config::list_hosts(&config_path_or_default(config_path)) {
+            Ok(hosts) => {
+                PackedStringArray::from_iter(hosts.iter().map(|host| GString::from(host.as_str())))
+            }
+            Err(e) => {
+                godot_error!("{}", e);
+                PackedStringArray::new()
+            }
+        }
+    }
+
+    /// Configures ip, user, port, auth method and server check method of this client
+    /// from `host` in an OpenSSH client config.
+    /// The first existing `IdentityFile` is used as auth method, if none exist the auth method is left as is.
+    /// Will return null on success, otherwise a string with the error will be returned.
+    ///
+    /// **Note:** `ProxyJump` is not supported, a warning is printed if the host uses it.
+    ///
+    /// * `config_path` - Path to the config. If empty `~/.ssh/config` is used.
+    /// * `host` - The host alias to resolve.
+    #[func]
+    fn configure_from_ssh_config(&mut self, config_path: String, host: String) -> Variant {
+        let resolved = match ssh_config::resolve_host(&config_path_or_default(config_path), &host) {
+            Ok(resolved) => resolved,
+            Err(e) => return Variant::from(e.to_string()),
+        };
+        let port = match resolved.port() {
+            Ok(port) => port,
+            Err(e) => return Variant::from(e.to_string()),
+        };
+        if let Some(proxy_jump) = resolved.proxy_jump() {
+            godot_warn!(
+                "Host {} uses ProxyJump {}, which is not supported",
+                host,
+                proxy_jump
+            );
+        }
+
+        self.ip = Variant::from(resolved.hostname());
+        self.user = Variant::from(resolved.user());
+        self.port = port;
+
+        if let Some(key_file_path) = resolved
+            .identity_files()
+            .into_iter()
+            .find(|path| path.is_file())
+        {
+            // Like ssh, a certificate next to the key is used with it
+            let cert_file_path = certificate_path(&key_file_path);
+            self._internal_ssh_client.auth_method = if cert_file_path.is_file() {
+                AuthMethod::Certificate {
+                    key_file_path,
+                    cert_file_path,
+                    key_pass: None,
+                }
+            } else {
+                AuthMethod::PrivateKeyFile {
+                    key_file_path,
+                    key_pass: None,
+                }
+            };
+        }
+
+        self._internal_ssh_client.server_check = if resolved.host_key_checking_disabled() {
+            ServerCheckMethod::NoCheck
+        } else if let Some(known_hosts_file) = resolved.user_known_hosts_file() {
+            ServerCheckMethod::KnownHostsFile(known_hosts_file.to_string_lossy().to_string())
+        } else {
+            ServerCheckMethod::DefaultKnownHostsFile
+        };
+
+        Variant::nil()
+    }
+
+    /// Wraps `cmd` written for `shell` for the server's default shell.
+    fn wrap_for_shell(&mut self, cmd: &str, shell: &str) -> anyhow::Result<String> {
+        let dialect = ShellDialect::parse(shell)?;
+        if dialect == ShellDialect::Default {
+            return Ok(cmd.to_string());
+        }
+        self.check_configured()?;
+        let remote_info = self._internal_ssh_client.remote_info(
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        )?;
+        wrap_command(cmd, dialect, &remote_info)
+    }
+
+    /// Execute `cmd` in a blocking fashion, keeping its output within `limits`.
+    pub fn exec_capture(
+        &mut self,
+        cmd: String,
+        limits: CaptureLimits,
+    ) -> anyhow::Result<BinaryExecOutput> {
+        self.check_configured()?;
+        self._internal_ssh_client.exec_ssh_bytes(
+            cmd,
+            limits,
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        )
+    }
+
+    /// Execute `cmd` and return its channel to stream the output from.
+    /// See [InternalSSHClient::exec_ssh_channel].
+    pub fn exec_channel(
+        &mut self,
+        cmd: String,
+    ) -> anyhow::Result<(Channel<Msg>, Option<ExecRecord>)> {
+        self.check_configured()?;
+        self._internal_ssh_client.exec_ssh_channel(
+            cmd,
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        )
+    }
+
+    /// Open an interactive shell and return its channel. See [InternalSSHClient::exec_ssh_shell].
+    pub fn shell_channel(
+        &mut self,
+        term: &str,
+        cols: u32,
+        rows: u32,
+    ) -> anyhow::Result<(Channel<Msg>, Option<ExecRecord>)> {
+        self.check_configured()?;
+        self._internal_ssh_client.exec_ssh_shell(
+            term,
+            cols,
+            rows,
+            &self.ip.to_string(),
+            &self.user.to_string(),
+            self.port,
+        )
+    }
+
+    /// Returns the configured ip, empty if none is set, and port.
+    pub fn address(&self) -> (String, u16) {
+        if self.ip.is_nil() || self.ip.get_type() != VariantType::STRING {
+            return (String::new(), self.port);
+        }
+        (self.ip.to::<String>(), self.port)
+    }
+
+    /// Checks that the client is configured.
+    fn check_configured(&self) -> anyhow::Result<()> {
+        if self.user.is_nil() || self.user.get_type() != VariantType::STRING {
+            anyhow::bail!("Invalid user \"{}\"", self.user);
+        }
+        if self.ip.is_nil() || self.ip.get_type() != VariantType::STRING {
+            anyhow::bail!("Invalid ip \"{}\"", self.ip);
+        }
+        Ok(())
+    }
+}
+
+/// Address of a client for background threads. The client is only bound while a command is started,
+/// so it stays usable while the thread waits for output.
+pub struct ClientLease {
+    client_id: InstanceId,
+    ip: String,
+    user: String,
+    port: u16,
+}
+
+impl ClientLease {
+    /// Reads the address of the client with `client_id`. Fails if it was freed or isn't configured.
+    pub fn new(client_id: InstanceId) -> anyhow::Result<Self> {
+        let Ok(client) = Gd::<SSHClient>::try_from_instance_id(client_id) else {
+            anyhow::bail!("Client was freed");
+        };
+        let client = client.bind();
+        client.check_configured()?;
+        Ok(Self {
+            client_id,
+            ip: client.ip.to_string(),
+            user: client.user.to_string(),
+            port: client.port,
+        })
+    }
+
+    /// Execute `cmd` in a blocking fashion, keeping its output within `limits`.
+    pub fn exec_capture(
+        &mut self,
+        cmd: String,
+        limits: CaptureLimits,
+    ) -> anyhow::Result<BinaryExecOutput> {
+        let (ip, user) = (self.ip.clone(), self.user.clone());
+        self.exec_timed(cmd, limits, None, &ip, &user, self.port)
+    }
+
+    /// Reads the current resource statistics of the server. See [stats::collect_stats].
+    pub fn collect_stats(&mut self) -> anyhow::Result<RawStats> {
+        let (ip, user) = (self.ip.clone(), self.user.clone());
+        stats::collect_stats(self, &ip, &user, self.port)
+    }
+}
+
+impl ClientAccess for ClientLease {
+    fn with_client<R>(
+        &mut self,
+        f: impl FnOnce(&mut InternalSSHClient) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let Ok(mut client) = Gd::<SSHClient>::try_from_instance_id(self.client_id) else {
+            anyhow::bail!("Client was freed");
+        };
+        let mut client = client.bind_mut();
+        f(&mut client._internal_ssh_client)
+    }
+}
+
+/// Runs a script, emitting its progress deferred, so it can be called from any thread.
+#[allow(clippy::too_many_arguments)]
+fn execute_script(
+    client: &mut impl ClientAccess,
+    emitter: InstanceId,
+    script_id: &str,
+    steps: &[ScriptStep],
+    ip: &String,
+    user: &String,
+    port: u16,
+) -> Dictionary<GString, Variant> {
+    let emit = |signal: &str, args: &[Variant]| {
+        let mut args = args.to_vec();
+        args.insert(0, script_id.to_variant());
+        emit_deferred(emitter, signal, &args);
+    };
+    let report = script::run_script(
+        client,
+        steps,
+        |event| match event {
+            ScriptEvent::StepStarted {
+                index,
+                name,
+                rollback,
+            } => emit(
+                "script_step_started",
+                &[
+                    (index as i64).to_variant(),
+                    name.to_variant(),
+                    rollback.to_variant(),
+                ],
+            ),
+            ScriptEvent::StepFinished { index, report } => emit(
+                "script_step_finished",
+                &[(index as i64).to_variant(), report.to_dict().to_variant()],
+            ),
+        },
+        ip,
+        user,
+        port,
+    );
+    let report = report.to_dict();
+    emit("script_finished", &[report.to_variant()]);
+    report
+}
+
+/// Syncs a directory, emitting its progress deferred, so it can be called from any thread.
+#[allow(clippy::too_many_arguments)]
+fn execute_sync(
+    client: &mut impl ClientAccess,
+    emitter: InstanceId,
+    sync_id: &str,
+    options: &SyncOptions,
+    ip: &String,
+    user: &String,
+    port: u16,
+) -> Dictionary<GString, Variant> {
+    let report = sync::sync_directory(
+        client,
+        options,
+        |file, index, total| {
+            emit_deferred(
+                emitter,
+                "sync_progress",
+                &[
+                    sync_id.to_variant(),
+                    file.to_dict().to_variant(),
+                    (index as i64).to_variant(),
+                    (total as i64).to_variant(),
+                ],
+            )
+        },
+        ip,
+        user,
+        port,
+    );
+    let report = report.to_dict();
+    emit_deferred(
+        emitter,
+        "sync_finished",
+        &[sync_id.to_variant(), report.to_variant()],
+    );
+    report
+}
+
+/// Emits `signal` of the object with `emitter` deferred on the main thread.
+pub fn emit_deferred(emitter: InstanceId, signal: &str, args: &[Variant]) {
+    if let Ok(mut object) = Gd::<Object>::try_from_instance_id(emitter) {
+        let mut call_args = vec![signal.to_variant()];
+        call_args.extend_from_slice(args);
+        object.call_deferred("emit_signal", &call_args);
+    }
+}
+
+fn dict_array(dicts: Vec<Dictionary<GString, Variant>>) -> Array<Variant> {
+    let mut array: Array<Variant> = Array::new();
+    for dict in dicts {
+        array.push(&Variant::from(dict));
+    }
+    array
+}
+
+/// Returns `config_path` as path or the default `~/.ssh/config` if it is empty.
+fn config_path_or_default(config_path: String) -> PathBuf {
+    if config_path.is_empty() {
+        ssh_config::default_config_path()
+    } else {
+        PathBuf::from(ssh_config::expand_tilde(&config_path))
+    }
+}
+
+/// Converts an empty string to None, as Godot has no optional strings.
+fn optional_string(string: String) -> Option<String> {
+    if string.is_empty() {
+        None
+    } else {
+        Some(string)
+    }
+}
+
+/// Wraps a secret set directly, empty strings meaning no secret.
+fn secret_value(secret: String) -> Option<SecretSource> {
+    optional_string(secret).map(|secret| SecretSource::Value(Zeroizing::new(secret)))
 }
@@ -0,0 +1,259 @@
+use crate::capture::CaptureLimits;
+use crate::command_watcher::{backoff_delay, due_after, json_to_variant, Extractor, WatchMode};
+use crate::ssh_client::{ClientLease, SSHClient};
+use godot::classes::{INode, Node};
+use godot::prelude::*;
+use serde_json::Value;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Output of the watched command is only kept up to this size.
+const MAX_OUTPUT: usize = 64 * 1024;
+const DEFAULT_INTERVAL: f64 = 5.0;
+const DEFAULT_MAX_BACKOFF: f64 = 300.0;
+
+/// Runs a command on a client at an interval and emits [signal value_changed] when its result changes,
+/// e.g. to mirror whether a service is active on a toggle button.
+///
+/// The watched value is the exit status, the output or both, see [member mode]. The output can be narrowed
+/// down by [member regex] or [member json_path]. Commands run on a background thread, so polling never blocks
+/// the main thread. If a command can't be executed, the delay until the next poll doubles up to [member max_backoff].
+///
+/// **Note:** The watcher needs to be in the scene tree, as it polls while processing.
+/// Changed properties apply when [method start] is called again.
+///
+/// # Example usage
+///
+/// ```
+/// var watcher: SSHCommandWatcher = SSHCommandWatcher.new()
+/// add_child(watcher)
+/// watcher.set_client(client)
+/// watcher.command = "systemctl is-active nginx"
+/// watcher.value_changed.connect(func(old, new): button.button_pressed = new == "active")
+/// watcher.start()
+/// ```
+#[derive(GodotClass)]
+#[class(base = Node)]
+pub struct SSHCommandWatcher {
+    /// Command to execute.
+    #[export]
+    command: GString,
+    /// Seconds between two polls.
+    #[export]
+    interval: f64,
+    /// Maximum seconds between two polls while the command fails.
+    #[export]
+    max_backoff: f64,
+    /// What is watched: "exit_status", "output" (stdout without surrounding whitespace)
+    /// or "both" (a [Dictionary] with the keys `exit_status` and `output`).
+    #[export]
+    mode: GString,
+    /// If set, only the first group of the regex, or the whole match if it has none, is watched of the output.
+    #[export]
+    regex: GString,
+    /// If set, the output is parsed as JSON and only the value at this path is watched, e.g. `$.services[0].state`.
+    /// Ignored if [member regex] is set.
+    #[export]
+    json_path: GString,
+    client: Option<Gd<SSHClient>>,
+    extractor: Option<Extractor>,
+    /// None until the first successful poll.
+    value: Option<Value>,
+    failures: u32,
+    next_poll: Option<Instant>,
+    /// Incremented on every start and stop, so results of a previous start are discarded.
+    generation: u64,
+    poll: Option<(u64, JoinHandle<Result<Value, String>>)>,
+    base: Base<Node>,
+}
+
+#[godot_api]
+pub impl INode for SSHCommandWatcher {
+    fn init(base: Base<Node>) -> Self {
+        Self {
+            command: GString::new(),
+            interval: DEFAULT_INTERVAL,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            mode: GString::from("output"),
+            regex: GString::new(),
+            json_path: GString::new(),
+            client: None,
+            extractor: None,
+            value: None,
+            failures: 0,
+            next_poll: None,
+            generation: 0,
+            poll: None,
+            base,
+        }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        if let Some((generation, poll)) = self.poll.take_if(|(_, poll)| poll.is_finished()) {
+            let result = match poll.join() {
+                Ok(result) => result,
+                Err(_) => Err("Polling thread panicked".to_string()),
+            };
+            if generation == self.generation {
+                self.handle_result(result);
+            }
+        }
+        if self.extractor.is_none() || self.poll.is_some() {
+            return;
+        }
+        if self
+            .next_poll
+            .is_none_or(|next_poll| Instant::now() >= next_poll)
+        {
+            self.start_poll();
+        }
+    }
+
+    fn exit_tree(&mut self) {
+        self.stop();
+    }
+}
+
+#[godot_api]
+pub impl SSHCommandWatcher {
+    /// Emitted when the watched value changes. The first successful poll always emits with `old` being null.
+    /// Output that doesn't match [member regex] or [member json_path] results in null.
+    #[signal]
+    fn value_changed(old: Variant, new: Variant);
+
+    /// Emitted when the command couldn't be executed.
+    ///
+    /// * `retry_in` - Seconds until the next attempt.
+    #[signal]
+    fn poll_failed(error: GString, retry_in: f64);
+
+    /// Sets the client the command is executed on.
+    #[func]
+    fn set_client(&mut self, client: Option<Gd<SSHClient>>) {
+        self.client = client;
+    }
+
+    /// Starts polling, the first poll happens immediately.
+    /// Returns false if no client or command is set or the properties are invalid.
+    #[func]
+    fn start(&mut self) -> bool {
+        if self.client.is_none() {
+            godot_error!("No client set");
+            return false;
+        }
+        if self.command.is_empty() {
+            godot_error!("No command set");
+            return false;
+        }
+        let extractor = match WatchMode::parse(&self.mode.to_string()).and_then(|mode| {
+            Extractor::new(mode, &self.regex.to_string(), &self.json_path.to_string())
+        }) {
+            Ok(extractor) => extractor,
+            Err(e) => {
+                godot_error!("{}", e);
+                return false;
+            }
+        };
+        self.stop();
+        self.extractor = Some(extractor);
+        true
+    }
+
+    /// Stops polling. The last value is kept.
+    #[func]
+    fn stop(&mut self) {
+        self.generation += 1;
+        self.extractor = None;
+        self.failures = 0;
+        self.next_poll = None;
+    }
+
+    #[func]
+    fn is_running(&self) -> bool {
+        self.extractor.is_some()
+    }
+
+    /// Polls as soon as possible instead of waiting for the interval, e.g. after toggling the remote state.
+    #[func]
+    fn poll_now(&mut self) {
+        self.next_poll = None;
+    }
+
+    /// Returns the last watched value, null if there was no successful poll yet.
+    #[func]
+    fn get_value(&self) -> Variant {
+        match &self.value {
+            Some(value) => json_to_variant(value),
+            None => Variant::nil(),
+        }
+    }
+}
+
+impl SSHCommandWatcher {
+    fn start_poll(&mut self) {
+        let (Some(client), Some(extractor)) = (&self.client, self.extractor.clone()) else {
+            return;
+        };
+        let client_id = client.instance_id();
+        let command = self.command.to_string();
+        self.next_poll = None;
+        self.poll = Some((
+            self.generation,
+            thread::spawn(move || {
+                let limits = CaptureLimits {
+                    max_stdout: Some(MAX_OUTPUT),
+                    max_stderr: Some(0),
+                };
+                let result = ClientLease::new(client_id)
+                    .and_then(|mut client| client.exec_capture(command, limits));
+                match result {
+                    Ok(output) => Ok(extractor.extract(&output)),
+                    Err(e) => Err(e.to_string()),
+                }
+            }),
+        ));
+    }
+
+    fn handle_result(&mut self, result: Result<Value, String>) {
+        let interval = match Duration::try_from_secs_f64(self.interval.max(0.1)) {
+            Ok(interval) => interval,
+            Err(e) => {
+                godot_error!("Invalid interval {}: {}", self.interval, e);
+                self.interval = DEFAULT_INTERVAL;
+                Duration::from_secs_f64(DEFAULT_INTERVAL)
+            }
+        };
+        match result {
+            Ok(value) => {
+                self.failures = 0;
+                self.next_poll = Some(due_after(interval));
+                if self.value.as_ref() == Some(&value) {
+                    return;
+                }
+                let old = match self.value.replace(value.clone()) {
+                    Some(old) => json_to_variant(&old),
+                    None => Variant::nil(),
+                };
+                self.base_mut()
+                    .emit_signal("value_changed", &[old, json_to_variant(&value)]);
+            }
+            Err(e) => {
+                self.failures += 1;
+                let max_backoff = match Duration::try_from_secs_f64(self.max_backoff.max(0.0)) {
+                    Ok(max_backoff) => max_backoff,
+                    Err(e) => {
+                        godot_error!("Invalid max backoff {}: {}", self.max_backoff, e);
+                        self.max_backoff = DEFAULT_MAX_BACKOFF;
+                        Duration::from_secs_f64(DEFAULT_MAX_BACKOFF)
+                    }
+                };
+                let delay = backoff_delay(interval, max_backoff, self.failures);
+                self.next_poll = Some(due_after(delay));
+                self.base_mut().emit_signal(
+                    "poll_failed",
+                    &[e.to_variant(), delay.as_secs_f64().to_variant()],
+                );
+            }
+        }
+    }
+}
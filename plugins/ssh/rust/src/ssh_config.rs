@@ -0,0 +1,651 @@
+use anyhow::anyhow;
+use godot::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Maximum nesting depth of `Include` directives, same limit as OpenSSH.
+const MAX_INCLUDE_DEPTH: u32 = 16;
+
+/// Keywords which accumulate all their values instead of using the first obtained one.
+const MULTI_VALUE_KEYWORDS: &[&str] = &[
+    "identityfile",
+    "certificatefile",
+    "localforward",
+    "remoteforward",
+    "dynamicforward",
+    "sendenv",
+    "setenv",
+];
+
+/// A single parsed line of a config file.
+enum ConfigLine {
+    /// A keyword, already lowercased, with its arguments.
+    Keyword { keyword: String, args: Vec<String> },
+    /// The lines of the files an `Include` resolved to.
+    Include(Vec<ConfigLine>),
+}
+
+/// A host resolved from an OpenSSH client config.
+#[derive(Debug, Default)]
+pub struct ResolvedHost {
+    /// The alias that was resolved.
+    pub host: String,
+    /// All first obtained values by lowercase keyword.
+    /// Multi value keywords like `IdentityFile` contain all values in order.
+    pub options: HashMap<String, Vec<String>>,
+}
+
+impl ResolvedHost {
+    /// Returns the first value of `keyword` (lowercase).
+    pub fn get(&self, keyword: &str) -> Option<&str> {
+        self.options
+            .get(keyword)
+            .and_then(|values| values.first())
+            .map(|value| value.as_str())
+    }
+
+    /// Real hostname to connect to. Falls back to the alias like OpenSSH does.
+    pub fn hostname(&self) -> String {
+        match self.get("hostname") {
+            Some(hostname) => hostname.replace("%h", &self.host).replace("%%", "%"),
+            None => self.host.clone(),
+        }
+    }
+
+    pub fn port(&self) -> anyhow::Result<u16> {
+        match self.get("port") {
+            Some(port) => port
+                .parse()
+                .map_err(|_| anyhow!("Invalid port \"{}\" for host {}", port, self.host)),
+            None => Ok(22),
+        }
+    }
+
+    /// The configured user or the local user if none is set.
+    pub fn user(&self) -> String {
+        match self.get("user") {
+            Some(user) => user.to_string(),
+            None => local_user(),
+        }
+    }
+
+    /// All identity files with `~` and the `%` tokens expanded.
+    pub fn identity_files(&self) -> Vec<PathBuf> {
+        self.expanded_paths("identityfile")
+    }
+
+    /// All certificate files with `~` and the `%` tokens expanded.
+    pub fn certificate_files(&self) -> Vec<PathBuf> {
+        self.expanded_paths("certificatefile")
+    }
+
+    /// Returns the `ProxyJump` value, `none` is treated as unset.
+    pub fn proxy_jump(&self) -> Option<&str> {
+        self.get("proxyjump")
+            .filter(|jump| !jump.eq_ignore_ascii_case("none"))
+    }
+
+    /// Returns the first `UserKnownHostsFile` with `~` and the `%` tokens expanded.
+    pub fn user_known_hosts_file(&self) -> Option<PathBuf> {
+        self.get("userknownhostsfile")
+            .filter(|file| !file.eq_ignore_ascii_case("none"))
+            .map(|file| PathBuf::from(self.expand_tokens(file)))
+    }
+
+    /// Whether `StrictHostKeyChecking` disables host key checking.
+    pub fn host_key_checking_disabled(&self) -> bool {
+        matches!(
+            self.get("stricthostkeychecking")
+                .map(|value| value.to_ascii_lowercase())
+                .as_deref(),
+            Some("no") | Some("off")
+        )
+    }
+
+    fn expanded_paths(&self, keyword: &str) -> Vec<PathBuf> {
+        self.options
+            .get(keyword)
+            .map(|values| {
+                values
+                    .iter()
+                    .filter(|value| !value.eq_ignore_ascii_case("none"))
+                    .map(|value| PathBuf::from(self.expand_tokens(value)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Expands `~` and the `%d`, `%h`, `%n`, `%p`, `%r`, `%u` and `%%` tokens.
+    fn expand_tokens(&self, value: &str) -> String {
+        let value = expand_tilde(value);
+        let mut expanded = String::with_capacity(value.len());
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                expanded.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('%') => expanded.push('%'),
+                Some('d') => expanded.push_str(&home_dir().to_string_lossy()),
+                Some('h') => expanded.push_str(&self.hostname()),
+                Some('n') => expanded.push_str(&self.host),
+                Some('p') => expanded.push_str(&self.port().unwrap_or(22).to_string()),
+                Some('r') => expanded.push_str(&self.user()),
+                Some('u') => expanded.push_str(&local_user()),
+                Some(other) => {
+                    expanded.push('%');
+                    expanded.push(other);
+                }
+                None => expanded.push('%'),
+            }
+        }
+        expanded
+    }
+
+    /// Converts the host to a [Dictionary] for usage in Godot.
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        let path_array = |paths: Vec<PathBuf>| {
+            PackedStringArray::from_iter(
+                paths
+                    .iter()
+                    .map(|path| GString::from(path.to_string_lossy().as_ref())),
+            )
+        };
+
+        let mut options: Dictionary<GString, Variant> = Dictionary::new();
+        for (keyword, values) in &self.options {
+            let value = if MULTI_VALUE_KEYWORDS.contains(&keyword.as_str()) {
+                PackedStringArray::from_iter(
+                    values.iter().map(|value| GString::from(value.as_str())),
+                )
+                .to_variant()
+            } else {
+                values.join(" ").to_variant()
+            };
+            options.set(keyword.as_str(), &value);
+        }
+
+        let user_known_hosts_file = match self.user_known_hosts_file() {
+            Some(file) => file.to_string_lossy().to_string().to_variant(),
+            None => Variant::nil(),
+        };
+        let proxy_jump = match self.proxy_jump() {
+            Some(jump) => jump.to_string().to_variant(),
+            None => Variant::nil(),
+        };
+        let port = match self.port() {
+            Ok(port) => port.to_variant(),
+            Err(_) => Variant::nil(),
+        };
+
+        dict! {
+            "host" => self.host.clone(),
+            "hostname" => self.hostname(),
+            "port" => port,
+            "user" => self.user(),
+            "identity_files" => path_array(self.identity_files()),
+            "certificate_files" => path_array(self.certificate_files()),
+            "proxy_jump" => proxy_jump,
+            "user_known_hosts_file" => user_known_hosts_file,
+            "strict_host_key_checking" => !self.host_key_checking_disabled(),
+            "options" => options,
+        }
+    }
+}
+
+/// Parses the config at `path` and resolves `host` the same way `ssh -G` would.
+///
+/// Like OpenSSH the first obtained value for each keyword is used, so more specific
+/// `Host` blocks need to come before generic ones.
+pub fn resolve_host(path: &Path, host: &str) -> anyhow::Result<ResolvedHost> {
+    let lines = read_config(path, 0)?;
+    let mut resolved = ResolvedHost {
+        host: host.to_string(),
+        options: HashMap::new(),
+    };
+    // Lines before the first Host or Match block apply to every host
+    apply_lines(&lines, host, true, &mut resolved)?;
+    Ok(resolved)
+}
+
+/// Applies the `lines` of a single file to `resolved`, starting in the block of its `Include` line.
+/// Like OpenSSH, a `Host` or `Match` block only lasts until the end of its file and blocks of a file
+/// included from an inactive block never match.
+fn apply_lines(
+    lines: &[ConfigLine],
+    host: &str,
+    included_active: bool,
+    resolved: &mut ResolvedHost,
+) -> anyhow::Result<()> {
+    let mut active = included_active;
+    for line in lines {
+        let (keyword, args) = match line {
+            ConfigLine::Include(lines) => {
+                apply_lines(lines, host, active, resolved)?;
+                continue;
+            }
+            ConfigLine::Keyword { keyword, args } => (keyword.as_str(), args),
+        };
+        match keyword {
+            "host" => active = included_active && hostname_patterns_match(args, host),
+            "match" => active = included_active && match_criteria_match(args, resolved)?,
+            keyword if active => {
+                let multi_value = MULTI_VALUE_KEYWORDS.contains(&keyword);
+                match resolved.options.get_mut(keyword) {
+                    Some(values) if multi_value => values.push(args.join(" ")),
+                    Some(_) => (),
+                    None => {
+                        let values = if multi_value {
+                            vec![args.join(" ")]
+                        } else {
+                            args.clone()
+                        };
+                        resolved.options.insert(keyword.to_string(), values);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Returns all concrete host aliases of the config at `path`.
+/// Patterns containing wildcards or negations are skipped as they can't be connected to.
+pub fn list_hosts(path: &Path) -> anyhow::Result<Vec<String>> {
+    let mut hosts: Vec<String> = Vec::new();
+    collect_hosts(&read_config(path, 0)?, &mut hosts);
+    Ok(hosts)
+}
+
+fn collect_hosts(lines: &[ConfigLine], hosts: &mut Vec<String>) {
+    for line in lines {
+        match line {
+            ConfigLine::Include(lines) => collect_hosts(lines, hosts),
+            ConfigLine::Keyword { keyword, args } if keyword == "host" => {
+                for pattern in args {
+                    if pattern.contains(['*', '?', '!']) || hosts.contains(pattern) {
+                        continue;
+                    }
+                    hosts.push(pattern.clone());
+                }
+            }
+            ConfigLine::Keyword { .. } => (),
+        }
+    }
+}
+
+/// The default user config path `~/.ssh/config`.
+pub fn default_config_path() -> PathBuf {
+    home_dir().join(".ssh").join("config")
+}
+
+/// Simple wildcard matching supporting `*` and `?` like OpenSSH patterns.
+/// Case sensitive, hostnames need to be lowercased first.
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Matches a comma separated pattern list (used by `Match`) or a list of `Host` patterns.
+/// A matching negated pattern always results in no match.
+fn host_patterns_match<S: AsRef<str>>(patterns: &[S], host: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        if let Some(negated) = pattern.strip_prefix('!') {
+            if wildcard_match(negated, host) {
+                return false;
+            }
+        } else if wildcard_match(pattern, host) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+/// Like [host_patterns_match], but ignores case like OpenSSH does for hostnames.
+fn hostname_patterns_match<S: AsRef<str>>(patterns: &[S], host: &str) -> bool {
+    let patterns: Vec<String> = patterns
+        .iter()
+        .map(|pattern| pattern.as_ref().to_lowercase())
+        .collect();
+    host_patterns_match(&patterns, &host.to_lowercase())
+}
+
+/// Evaluates the criteria of a `Match` line.
+/// Only `all`, `host`, `originalhost`, `user` and `localuser` are supported,
+/// every other criteria (e.g. `exec`) is treated as not matching.
+fn match_criteria_match(args: &[String], resolved: &ResolvedHost) -> anyhow::Result<bool> {
+    let mut args = args.iter();
+    while let Some(criteria) = args.next() {
+        let lowercase = criteria.to_ascii_lowercase();
+        let (negate, criteria) = match lowercase.strip_prefix('!') {
+            Some(criteria) => (true, criteria),
+            None => (false, lowercase.as_str()),
+        };
+        let matched = match criteria {
+            "all" => true,
+            "canonical" | "final" => false,
+            _ => {
+                let Some(value) = args.next() else {
+                    anyhow::bail!("Match criteria \"{}\" is missing an argument", criteria);
+                };
+                let patterns: Vec<&str> = value.split(',').collect();
+                match criteria {
+                    "host" => hostname_patterns_match(&patterns, &resolved.hostname()),
+                    "originalhost" => hostname_patterns_match(&patterns, &resolved.host),
+                    "user" => host_patterns_match(&patterns, &resolved.user()),
+                    "localuser" => host_patterns_match(&patterns, &local_user()),
+                    _ => false,
+                }
+            }
+        };
+        if matched == negate {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Reads and tokenizes a config file, resolving `Include` directives to the lines of the included files.
+fn read_config(path: &Path, depth: u32) -> anyhow::Result<Vec<ConfigLine>> {
+    if depth > MAX_INCLUDE_DEPTH {
+        anyhow::bail!("Too many nested includes at {}", path.display());
+    }
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => anyhow::bail!("Failed to read ssh config {}: {}", path.display(), e),
+    };
+
+    let mut lines: Vec<ConfigLine> = Vec::new();
+    for (line_number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // Keyword and arguments may be separated by whitespace or a single "="
+        let split_at = line
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(line.len());
+        let keyword = line[..split_at].to_ascii_lowercase();
+        let rest = line[split_at..].trim_start();
+        let rest = rest.strip_prefix('=').unwrap_or(rest);
+        let args = match split_args(rest) {
+            Ok(args) => args,
+            Err(e) => anyhow::bail!("{}:{}: {}", path.display(), line_number + 1, e),
+        };
+        if args.is_empty() {
+            anyhow::bail!(
+                "{}:{}: Missing argument for \"{}\"",
+                path.display(),
+                line_number + 1,
+                keyword
+            );
+        }
+
+        if keyword == "include" {
+            for pattern in &args {
+                for include_path in resolve_include(pattern) {
+                    lines.push(ConfigLine::Include(read_config(&include_path, depth + 1)?));
+                }
+            }
+        } else {
+            lines.push(ConfigLine::Keyword { keyword, args });
+        }
+    }
+    Ok(lines)
+}
+
+/// Splits arguments on whitespace while respecting double quotes.
+fn split_args(text: &str) -> anyhow::Result<Vec<String>> {
+    let mut args: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_arg = false;
+
+    for c in text.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_arg = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_arg {
+                    args.push(std::mem::take(&mut current));
+                    has_arg = false;
+                }
+            }
+            // Trailing comments
+            '#' if !in_quotes && !has_arg => break,
+            c => {
+                current.push(c);
+                has_arg = true;
+            }
+        }
+    }
+    if in_quotes {
+        anyhow::bail!("Unterminated quote");
+    }
+    if has_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+/// Resolves an `Include` argument to a sorted list of files.
+/// Relative paths are relative to `~/.ssh` and the last path component may contain wildcards.
+fn resolve_include(pattern: &str) -> Vec<PathBuf> {
+    let mut path = PathBuf::from(expand_tilde(pattern));
+    if path.is_relative() {
+        path = home_dir().join(".ssh").join(path);
+    }
+
+    let file_pattern = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if !file_pattern.contains(['*', '?']) {
+        // Missing includes are silently ignored by OpenSSH
+        return if path.is_file() { vec![path] } else { vec![] };
+    }
+
+    let Some(dir) = path.parent() else {
+        return vec![];
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| wildcard_match(&file_pattern, &name.to_string_lossy()))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+pub fn home_dir() -> PathBuf {
+    PathBuf::from(std::env::var("HOME").unwrap_or_default())
+}
+
+pub fn local_user() -> String {
+    std::env::var("USER").unwrap_or_default()
+}
+
+pub fn expand_tilde(path: &str) -> String {
+    match path.strip_prefix("~/") {
+        Some(rest) => home_dir().join(rest).to_string_lossy().to_string(),
+        None if path == "~" => home_dir().to_string_lossy().to_string(),
+        None => path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `files` (name, content) into a new temporary directory and returns it.
+    fn write_configs(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "dreamdeck-ssh-config-{}-{}",
+            std::process::id(),
+            test
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, content) in files {
+            let content = content.replace("$DIR", &dir.to_string_lossy());
+            fs::write(dir.join(name), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn first_value_wins_and_identity_files_accumulate() {
+        let dir = write_configs(
+            "first_value",
+            &[(
+                "config",
+                "Host web\n  Port 2222\n  IdentityFile /keys/web\nHost *\n  Port 22\n  IdentityFile /keys/default\n  User admin\n",
+            )],
+        );
+        let resolved = resolve_host(&dir.join("config"), "web").unwrap();
+        assert_eq!(resolved.port().unwrap(), 2222);
+        assert_eq!(resolved.user(), "admin");
+        assert_eq!(
+            resolved.identity_files(),
+            vec![PathBuf::from("/keys/web"), PathBuf::from("/keys/default")]
+        );
+    }
+
+    #[test]
+    fn include_blocks_end_with_their_file() {
+        let dir = write_configs(
+            "include_scope",
+            &[
+                (
+                    "config",
+                    "Host web\n  Include $DIR/included\n  User web-user\nHost db\n  User db-user\n",
+                ),
+                (
+                    "included",
+                    "Port 2222\nHost other\n  HostName other.example.com\n",
+                ),
+            ],
+        );
+        let resolved = resolve_host(&dir.join("config"), "web").unwrap();
+        assert_eq!(resolved.port().unwrap(), 2222);
+        // The Host block of the included file doesn't swallow the lines after the Include
+        assert_eq!(resolved.user(), "web-user");
+        assert_eq!(resolved.hostname(), "web");
+    }
+
+    #[test]
+    fn include_from_inactive_block_never_matches() {
+        let dir = write_configs(
+            "include_inactive",
+            &[
+                ("config", "Host db\n  Include $DIR/included\n"),
+                ("included", "Host web\n  Port 2222\n"),
+            ],
+        );
+        let resolved = resolve_host(&dir.join("config"), "web").unwrap();
+        assert_eq!(resolved.get("port"), None);
+    }
+
+    #[test]
+    fn list_hosts_skips_patterns_and_follows_includes() {
+        let dir = write_configs(
+            "list_hosts",
+            &[
+                (
+                    "config",
+                    "Host web *.example.com !bastion\nInclude $DIR/hosts-*\n",
+                ),
+                ("hosts-a", "Host db web\n"),
+            ],
+        );
+        assert_eq!(list_hosts(&dir.join("config")).unwrap(), vec!["web", "db"]);
+    }
+
+    #[test]
+    fn match_criteria() {
+        let dir = write_configs(
+            "match",
+            &[(
+                "config",
+                "Host *.lan\n  HostName nas.example.com\n  User bob\nMatch host *.example.com user bob\n  Port 2222\nMatch !originalhost *.lan\n  Port 22\nMatch all\n  Compression yes\n",
+            )],
+        );
+        let resolved = resolve_host(&dir.join("config"), "nas.lan").unwrap();
+        assert_eq!(resolved.port().unwrap(), 2222);
+        assert_eq!(resolved.get("compression"), Some("yes"));
+
+        let resolved = resolve_host(&dir.join("config"), "other").unwrap();
+        assert_eq!(resolved.port().unwrap(), 22);
+    }
+
+    #[test]
+    fn hosts_match_case_insensitive() {
+        let dir = write_configs(
+            "host_case",
+            &[(
+                "config",
+                "Host Web.Example.com\n  Port 2222\nMatch originalhost WEB.*\n  User bob\n",
+            )],
+        );
+        let resolved = resolve_host(&dir.join("config"), "web.EXAMPLE.com").unwrap();
+        assert_eq!(resolved.port().unwrap(), 2222);
+        assert_eq!(resolved.user(), "bob");
+        assert_eq!(resolved.host, "web.EXAMPLE.com");
+    }
+
+    #[test]
+    fn match_without_argument_fails() {
+        let dir = write_configs("match_missing", &[("config", "Match host\n")]);
+        assert!(resolve_host(&dir.join("config"), "web").is_err());
+    }
+
+    #[test]
+    fn split_args_respects_quotes_and_comments() {
+        assert_eq!(
+            split_args("\"/path/with space\" second # comment").unwrap(),
+            vec!["/path/with space", "second"]
+        );
+        assert!(split_args("\"unterminated").is_err());
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("*.example.com", "web.example.com"));
+        assert!(wildcard_match("web?", "web1"));
+        assert!(!wildcard_match("web?", "web"));
+        assert!(host_patterns_match(&["*", "!bastion"], "web"));
+        assert!(!host_patterns_match(&["*", "!bastion"], "bastion"));
+    }
+}
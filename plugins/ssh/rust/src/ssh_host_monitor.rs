@@ -0,0 +1,168 @@
+use crate::host_monitor::{HostMonitor, MonitorConfig};
+use crate::ssh_client::SSHClient;
+use godot::classes::{INode, Node};
+use godot::prelude::*;
+use std::time::Duration;
+
+/// Monitors whether hosts are up, e.g. to show the state of a machine on a deck button.
+///
+/// Every interval each host is probed by connecting to it and waiting for its SSH banner,
+/// which doesn't require authentication. Probes run in the background, so they never block
+/// the main thread. Signals are only emitted when a host changes between up and down.
+///
+/// **Note:** The monitor needs to be in the scene tree, as signals are emitted while processing.
+///
+/// # Example usage
+///
+/// ```
+/// var monitor: SSHHostMonitor = SSHHostMonitor.new()
+/// add_child(monitor)
+/// monitor.add_host("nas", "192.168.1.10", 22)
+/// monitor.host_state_changed.connect(func(host_id, up, status): print(host_id, " up: ", up))
+/// monitor.start()
+/// ```
+#[derive(GodotClass)]
+#[class(base = Node)]
+pub struct SSHHostMonitor {
+    /// Seconds between two probes of a host.
+    #[export]
+    interval: f64,
+    /// Seconds after which a probe fails.
+    #[export]
+    timeout: f64,
+    /// Consecutive failed probes after which a host is considered down.
+    #[export]
+    failure_threshold: u32,
+    monitor: HostMonitor,
+    base: Base<Node>,
+}
+
+#[godot_api]
+pub impl INode for SSHHostMonitor {
+    fn init(base: Base<Node>) -> Self {
+        let config = MonitorConfig::default();
+        Self {
+            interval: config.interval.as_secs_f64(),
+            timeout: config.timeout.as_secs_f64(),
+            failure_threshold: config.failure_threshold,
+            monitor: HostMonitor::default(),
+            base,
+        }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        for change in self.monitor.take_changes() {
+            let up = change.status.up.unwrap_or_default();
+            self.base_mut().emit_signal(
+                "host_state_changed",
+                &[
+                    change.host_id.to_variant(),
+                    up.to_variant(),
+                    change.status.to_dict().to_variant(),
+                ],
+            );
+        }
+    }
+
+    fn exit_tree(&mut self) {
+        self.monitor.stop();
+    }
+}
+
+#[godot_api]
+pub impl SSHHostMonitor {
+    /// Emitted when a host goes up or down.
+    ///
+    /// * `status` - See [method get_status].
+    #[signal]
+    fn host_state_changed(host_id: GString, up: bool, status: Dictionary<GString, Variant>);
+
+    /// Adds a host to monitor, or updates its address if `host_id` is already monitored.
+    #[func]
+    fn add_host(&mut self, host_id: String, ip: String, port: u16) {
+        self.monitor.set_host(&host_id, &ip, port);
+    }
+
+    /// Adds the server of `client` to monitor. Later changes of the client's address aren't applied.
+    #[func]
+    fn add_client(&mut self, host_id: String, client: Gd<SSHClient>) -> bool {
+        let (ip, port) = client.bind().address();
+        if ip.is_empty() {
+            godot_error!("Client {} has no ip set", host_id);
+            return false;
+        }
+        self.monitor.set_host(&host_id, &ip, port);
+        true
+    }
+
+    /// Stops monitoring a host. Returns false if it wasn't monitored.
+    #[func]
+    fn remove_host(&mut self, host_id: String) -> bool {
+        self.monitor.remove_host(&host_id)
+    }
+
+    /// Starts probing all hosts. Calling it while running applies changed properties from the next round.
+    #[func]
+    fn start(&mut self) {
+        self.monitor.start(self.config());
+    }
+
+    #[func]
+    fn stop(&mut self) {
+        self.monitor.stop();
+    }
+
+    #[func]
+    fn is_running(&self) -> bool {
+        self.monitor.is_running()
+    }
+
+    /// Returns null if `host_id` isn't monitored, otherwise a [Dictionary] with the keys
+    /// `up` (null until known), `connect_ms`, `rtt_ms` (time until the SSH banner was received,
+    /// null if the last probe failed), `consecutive_failures`, `banner`, `last_error` and `last_probe`.
+    #[func]
+    fn get_status(&self, host_id: String) -> Variant {
+        match self.monitor.status(&host_id) {
+            Some(status) => Variant::from(status.to_dict()),
+            None => Variant::nil(),
+        }
+    }
+
+    /// Returns a [Dictionary] with the status of every monitored host by its id, see [method get_status].
+    #[func]
+    fn get_statuses(&self) -> Dictionary<GString, Variant> {
+        let mut statuses: Dictionary<GString, Variant> = Dictionary::new();
+        for (host_id, status) in self.monitor.statuses() {
+            statuses.set(host_id.as_str(), &Variant::from(status.to_dict()));
+        }
+        statuses
+    }
+}
+
+impl SSHHostMonitor {
+    /// Invalid intervals or timeouts, e.g. infinite ones, are reported and reset to the default.
+    fn config(&mut self) -> MonitorConfig {
+        let default = MonitorConfig::default();
+        let interval = match Duration::try_from_secs_f64(self.interval.max(0.1)) {
+            Ok(interval) => interval,
+            Err(e) => {
+                godot_error!("Invalid interval {}: {}", self.interval, e);
+                self.interval = default.interval.as_secs_f64();
+                default.interval
+            }
+        };
+        let timeout = match Duration::try_from_secs_f64(self.timeout.max(0.1)) {
+            Ok(timeout) => timeout,
+            Err(e) => {
+                godot_error!("Invalid timeout {}: {}", self.timeout, e);
+                self.timeout = default.timeout.as_secs_f64();
+                default.timeout
+            }
+        };
+        MonitorConfig {
+            interval,
+            timeout,
+            failure_threshold: self.failure_threshold.max(1),
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use crate::command_watcher::{backoff_delay, due_after};
+use crate::log_tail::{run_stream, LineBuffer, SharedStream};
+use crate::shell::posix_quote;
+use crate::ssh_client::SSHClient;
+use godot::classes::{INode, Node};
+use godot::prelude::*;
+use regex::Regex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const DEFAULT_RESTART_DELAY: f64 = 2.0;
+const DEFAULT_MAX_RESTART_DELAY: f64 = 60.0;
+
+/// Streams the output of a long running command like `tail -F` or `journalctl -f` from a client
+/// line by line and emits [signal line_matched] for lines matching registered patterns,
+/// e.g. to turn a button red once "ERROR" appears in a service log.
+///
+/// The most recent lines are kept, see [method get_lines]. The stream runs on a background thread over
+/// its own channel, so the client can still be used for other commands. If the stream ends while running,
+/// e.g. because the session was reconnected, it is restarted after [member restart_delay], which doubles
+/// up to [member max_restart_delay] while the stream keeps failing.
+///
+/// **Note:** The tail needs to be in the scene tree, as signals are emitted while processing.
+///
+/// # Example usage
+///
+/// ```
+/// var tail: SSHLogTail = SSHLogTail.new()
+/// add_child(tail)
+/// tail.set_client(client)
+/// tail.follow_journal("nginx")
+/// tail.add_pattern("error", "(?i)error")
+/// tail.line_matched.connect(func(pattern_id, line, captures): button.modulate = Color.RED)
+/// tail.start()
+/// ```
+#[derive(GodotClass)]
+#[class(base = Node)]
+pub struct SSHLogTail {
+    /// Command whose output is streamed, see [method follow_file] and [method follow_journal].
+    #[export]
+    command: GString,
+    /// Amount of recent lines kept.
+    #[export]
+    buffer_size: u32,
+    /// Seconds until an ended stream is restarted.
+    #[export]
+    restart_delay: f64,
+    /// Maximum seconds until an ended stream is restarted while it keeps failing.
+    #[export]
+    max_restart_delay: f64,
+    client: Option<Gd<SSHClient>>,
+    patterns: Vec<(String, Regex)>,
+    buffer: LineBuffer,
+    running: bool,
+    /// Consecutive streams which ended without receiving a line.
+    failures: u32,
+    received: bool,
+    streaming: bool,
+    next_start: Option<Instant>,
+    stream: Option<(SharedStream, JoinHandle<()>)>,
+    base: Base<Node>,
+}
+
+#[godot_api]
+pub impl INode for SSHLogTail {
+    fn init(base: Base<Node>) -> Self {
+        Self {
+            command: GString::new(),
+            buffer_size: 500,
+            restart_delay: DEFAULT_RESTART_DELAY,
+            max_restart_delay: DEFAULT_MAX_RESTART_DELAY,
+            client: None,
+            patterns: Vec::new(),
+            buffer: LineBuffer::new(500),
+            running: false,
+            failures: 0,
+            received: false,
+            streaming: false,
+            next_start: None,
+            stream: None,
+            base,
+        }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        self.drain_stream();
+        if !self.running || self.stream.is_some() {
+            return;
+        }
+        if self
+            .next_start
+            .is_none_or(|next_start| Instant::now() >= next_start)
+        {
+            self.start_stream();
+        }
+    }
+
+    fn exit_tree(&mut self) {
+        self.stop();
+    }
+}
+
+#[godot_api]
+pub impl SSHLogTail {
+    /// Emitted for every received line of stdout and stderr.
+    #[signal]
+    fn line_received(line: GString);
+
+    /// Emitted for every pattern a received line matches.
+    ///
+    /// * `captures` - The whole match followed by all groups, empty for groups that didn't participate.
+    #[signal]
+    fn line_matched(pattern_id: GString, line: GString, captures: PackedStringArray);
+
+    /// Emitted when the command was started, also after every restart.
+    #[signal]
+    fn stream_started();
+
+    /// Emitted when the stream ended.
+    ///
+    /// * `reason` - Why the stream ended, e.g. "Channel closed" after the session was closed.
+    #[signal]
+    fn stream_stopped(reason: GString);
+
+    /// Sets the client the command is executed on. Applies from the next (re)start of the stream.
+    #[func]
+    fn set_client(&mut self, client: Option<Gd<SSHClient>>) {
+        self.client = client;
+    }
+
+    /// Sets the command to follow a file with `tail -F`, which also keeps following it when it is rotated.
+    #[func]
+    fn follow_file(&mut self, path: String) {
+        self.command = format!("tail -n 0 -F {}", posix_quote(&path)).into();
+    }
+
+    /// Sets the command to follow the journal of a systemd unit, or the whole journal if `unit` is empty.
+    #[func]
+    fn follow_journal(&mut self, unit: String) {
+        self.command = if unit.is_empty() {
+            "journalctl -f -n 0 -o cat".into()
+        } else {
+            format!("journalctl -f -n 0 -o cat -u {}", posix_quote(&unit)).into()
+        };
+    }
+
+    /// Adds a pattern received lines are matched against, or replaces the one with the same `pattern_id`.
+    /// Returns false if `regex` is invalid.
+    #[func]
+    fn add_pattern(&mut self, pattern_id: String, regex: String) -> bool {
+        let regex = match Regex::new(&regex) {
+            Ok(regex) => regex,
+            Err(e) => {
+                godot_error!("Invalid regex \"{}\": {}", regex, e);
+                return false;
+            }
+        };
+        match self.patterns.iter_mut().find(|(id, _)| *id == pattern_id) {
+            Some(pattern) => pattern.1 = regex,
+            None => self.patterns.push((pattern_id, regex)),
+        }
+        true
+    }
+
+    /// Returns false if no pattern with `pattern_id` exists.
+    #[func]
+    fn remove_pattern(&mut self, pattern_id: String) -> bool {
+        let count = self.patterns.len();
+        self.patterns.retain(|(id, _)| *id != pattern_id);
+        self.patterns.len() != count
+    }
+
+    /// Starts streaming. Returns false if no client or command is set.
+    #[func]
+    fn start(&mut self) -> bool {
+        if self.client.is_none() {
+            godot_error!("No client set");
+            return false;
+        }
+        if self.command.is_empty() {
+            godot_error!("No command set");
+            return false;
+        }
+        self.running = true;
+        self.failures = 0;
+        self.next_start = None;
+        true
+    }
+
+    /// Stops streaming. [signal stream_stopped] is emitted once the command was closed.
+    #[func]
+    fn stop(&mut self) {
+        self.running = false;
+        if let Some((shared, _)) = &self.stream {
+            if let Ok(mut shared) = shared.lock() {
+                shared.stop = true;
+            }
+        }
+    }
+
+    /// Returns whether the tail is started, even while waiting for a restart.
+    #[func]
+    fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Returns whether the command is currently streaming.
+    #[func]
+    fn is_streaming(&self) -> bool {
+        self.streaming
+    }
+
+    /// Returns up to `count` of the most recent lines, oldest first.
+    #[func]
+    fn get_lines(&self, count: u32) -> PackedStringArray {
+        self.buffer
+            .recent(count as usize)
+            .map(|line| GString::from(line.as_str()))
+            .collect()
+    }
+
+    /// Clears the kept lines.
+    #[func]
+    fn clear_lines(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+impl SSHLogTail {
+    fn start_stream(&mut self) {
+        let Some(client) = &self.client else {
+            return;
+        };
+        let client_id = client.instance_id();
+        let command = self.command.to_string();
+        let shared = SharedStream::default();
+        let thread_shared = shared.clone();
+        self.received = false;
+        self.next_start = None;
+        self.stream = Some((
+            shared,
+            thread::spawn(move || run_stream(client_id, command, thread_shared)),
+        ));
+    }
+
+    /// Handles everything received from the stream since the last frame.
+    fn drain_stream(&mut self) {
+        let Some((shared, thread)) = &self.stream else {
+            return;
+        };
+        // Checked first, as the thread always sets the reason before it finishes
+        let finished = thread.is_finished();
+        let (lines, started, ended) = match shared.lock() {
+            Ok(mut shared) => (
+                std::mem::take(&mut shared.lines),
+                std::mem::take(&mut shared.started),
+                shared.ended.take(),
+            ),
+            Err(_) => (
+                Vec::new(),
+                false,
+                Some("Stream state is poisoned".to_string()),
+            ),
+        };
+        let ended = match ended {
+            Some(reason) => Some(reason),
+            None if finished => Some("Streaming thread panicked".to_string()),
+            None => None,
+        };
+
+        if started {
+            self.streaming = true;
+            self.base_mut().emit_signal("stream_started", &[]);
+        }
+        self.buffer.set_capacity(self.buffer_size as usize);
+        for line in lines {
+            self.handle_line(line);
+        }
+        if let Some(reason) = ended {
+            self.stream = None;
+            self.streaming = false;
+            self.base_mut()
+                .emit_signal("stream_stopped", &[reason.to_variant()]);
+            if self.received {
+                self.failures = 0;
+            } else {
+                self.failures += 1;
+            }
+            let restart_delay = match Duration::try_from_secs_f64(self.restart_delay.max(0.1)) {
+                Ok(restart_delay) => restart_delay,
+                Err(e) => {
+                    godot_error!("Invalid restart delay {}: {}", self.restart_delay, e);
+                    self.restart_delay = DEFAULT_RESTART_DELAY;
+                    Duration::from_secs_f64(DEFAULT_RESTART_DELAY)
+                }
+            };
+            let max_restart_delay =
+                match Duration::try_from_secs_f64(self.max_restart_delay.max(0.0)) {
+                    Ok(max_restart_delay) => max_restart_delay,
+                    Err(e) => {
+                        godot_error!(
+                            "Invalid max restart delay {}: {}",
+                            self.max_restart_delay,
+                            e
+                        );
+                        self.max_restart_delay = DEFAULT_MAX_RESTART_DELAY;
+                        Duration::from_secs_f64(DEFAULT_MAX_RESTART_DELAY)
+                    }
+                };
+            let delay = backoff_delay(
+                restart_delay,
+                max_restart_delay,
+                self.failures.saturating_sub(1),
+            );
+            self.next_start = Some(due_after(delay));
+        }
+    }
+
+    fn handle_line(&mut self, line: String) {
+        self.received = true;
+        let matches: Vec<(String, PackedStringArray)> = self
+            .patterns
+            .iter()
+            .filter_map(|(pattern_id, regex)| {
+                let captures = regex.captures(&line)?;
+                let captures = captures
+                    .iter()
+                    .map(|capture| GString::from(capture.map_or("", |capture| capture.as_str())))
+                    .collect();
+                Some((pattern_id.clone(), captures))
+            })
+            .collect();
+
+        self.base_mut()
+            .emit_signal("line_received", &[line.to_variant()]);
+        for (pattern_id, captures) in matches {
+            self.base_mut().emit_signal(
+                "line_matched",
+                &[
+                    pattern_id.to_variant(),
+                    line.to_variant(),
+                    captures.to_variant(),
+                ],
+            );
+        }
+        self.buffer.push(line);
+    }
+}
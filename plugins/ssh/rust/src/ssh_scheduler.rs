@@ -0,0 +1,384 @@
+use crate::capture::{BinaryExecOutput, CaptureLimits};
+use crate::scheduler::{self, JobRecord, MissedPolicy, Schedule};
+use crate::ssh_client::{ClientLease, SSHClient};
+use crate::ssh_vault::globalize_path;
+use chrono::{DateTime, Local};
+use godot::classes::{INode, Node};
+use godot::prelude::*;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+/// Maximum bytes of stdout and stderr each kept of a run.
+const OUTPUT_LIMIT: usize = 64 * 1024;
+
+struct ScheduledJob {
+    client: Gd<SSHClient>,
+    command: String,
+    schedule: Schedule,
+    /// The schedule as it was passed in.
+    schedule_text: String,
+    missed_policy: MissedPolicy,
+    next_due: Option<DateTime<Local>>,
+    run: Option<JoinHandle<Result<BinaryExecOutput, String>>>,
+    started: (DateTime<Local>, Instant),
+}
+
+enum JobEvent {
+    Started,
+    Finished(Dictionary<GString, Variant>),
+    Skipped(&'static str, DateTime<Local>),
+}
+
+/// Runs commands on clients on a schedule, e.g. nightly housekeeping.
+///
+/// Jobs are scheduled by a cron expression like "30 2 * * *", a macro like "@daily", or an interval like "@every 30m".
+/// Runs which were missed, e.g. because the application wasn't running, are either skipped or run once.
+/// A run is skipped while the previous run of the same job is still running.
+/// The last run of every job can be persisted, so missed runs are detected across restarts, see [method set_state_file].
+/// Commands run on background threads, so they never block the main thread.
+///
+/// **Note:** The scheduler needs to be in the scene tree, as jobs are started while processing.
+///
+/// # Example usage
+///
+/// ```
+/// var scheduler: SSHScheduler = SSHScheduler.new()
+/// add_child(scheduler)
+/// scheduler.set_state_file("user://ssh_scheduler.json")
+/// scheduler.add_job("cleanup", client, "docker system prune -f", "0 3 * * *", "run_once")
+/// scheduler.job_finished.connect(func(job_id, result): print(job_id, ": ", result.exit_status))
+/// scheduler.start()
+/// ```
+#[derive(GodotClass)]
+#[class(base = Node)]
+pub struct SSHScheduler {
+    jobs: HashMap<String, ScheduledJob>,
+    records: HashMap<String, JobRecord>,
+    state_path: Option<PathBuf>,
+    running: bool,
+    base: Base<Node>,
+}
+
+#[godot_api]
+pub impl INode for SSHScheduler {
+    fn init(base: Base<Node>) -> Self {
+        Self {
+            jobs: HashMap::new(),
+            records: HashMap::new(),
+            state_path: None,
+            running: false,
+            base,
+        }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        let now = Local::now();
+        let mut events = Vec::new();
+        for (job_id, job) in self.jobs.iter_mut() {
+            let record = self.records.entry(job_id.clone()).or_default();
+            if let Some(run) = job.run.take_if(|run| run.is_finished()) {
+                let result = match run.join() {
+                    Ok(result) => result,
+                    Err(_) => Err("Job thread panicked".to_string()),
+                };
+                let result = finish_run(record, job.started, result);
+                events.push((job_id.clone(), JobEvent::Finished(result)));
+            }
+            if !self.running {
+                continue;
+            }
+
+            let Some(due) = job
+                .next_due
+                .or_else(|| scheduler::first_due(&job.schedule, record.last_due, now))
+            else {
+                continue;
+            };
+            job.next_due = Some(due);
+            if now < due {
+                continue;
+            }
+
+            let (missed, next_due) = scheduler::catch_up(&job.schedule, due, now);
+            record.last_due = Some(due.timestamp());
+            job.next_due = next_due;
+            let event = if job.run.is_some() {
+                JobEvent::Skipped("overlap", due)
+            } else if missed && job.missed_policy == MissedPolicy::Skip {
+                JobEvent::Skipped("missed", due)
+            } else {
+                start_run(job);
+                JobEvent::Started
+            };
+            events.push((job_id.clone(), event));
+        }
+
+        if !events.is_empty() {
+            self.save_state();
+        }
+        for (job_id, event) in events {
+            match event {
+                JobEvent::Started => self
+                    .base_mut()
+                    .emit_signal("job_started", &[job_id.to_variant()]),
+                JobEvent::Finished(result) => self
+                    .base_mut()
+                    .emit_signal("job_finished", &[job_id.to_variant(), result.to_variant()]),
+                JobEvent::Skipped(reason, due) => self.base_mut().emit_signal(
+                    "job_skipped",
+                    &[
+                        job_id.to_variant(),
+                        reason.to_variant(),
+                        due.timestamp().to_variant(),
+                    ],
+                ),
+            };
+        }
+    }
+
+    fn exit_tree(&mut self) {
+        self.running = false;
+    }
+}
+
+#[godot_api]
+pub impl SSHScheduler {
+    /// Emitted when a run of a job starts.
+    #[signal]
+    fn job_started(job_id: GString);
+
+    /// Emitted when a run of a job finished.
+    ///
+    /// * `result` - [Dictionary] with the keys `started` (unix time), `duration` (seconds), `exit_status`
+    ///   (-1 if none was sent), `stdout`, `stderr` (the first and last 32 KiB each) and `error`
+    ///   (null unless the command couldn't be run).
+    #[signal]
+    fn job_finished(job_id: GString, result: Dictionary<GString, Variant>);
+
+    /// Emitted when a run of a job is skipped.
+    ///
+    /// * `reason` - "overlap" if the previous run was still running, "missed" if the run was missed
+    ///   and the job's missed run policy is "skip".
+    /// * `due` - Unix time the run was due.
+    #[signal]
+    fn job_skipped(job_id: GString, reason: GString, due: i64);
+
+    /// Adds a job running `command` on `client`, or replaces the job with the same id.
+    /// A running run of a replaced job isn't stopped.
+    ///
+    /// * `schedule` - Cron expression ("minute hour day month weekday", e.g. "*/15 8-18 * * mon-fri"),
+    ///   "@yearly", "@monthly", "@weekly", "@daily", "@hourly" or an interval like "@every 1h30m".
+    ///   Interval jobs which never ran before run right away.
+    /// * `missed_policy` - "skip" to skip missed runs or "run_once" to run once for all missed runs.
+    #[func]
+    fn add_job(
+        &mut self,
+        job_id: String,
+        client: Gd<SSHClient>,
+        command: String,
+        schedule: String,
+        missed_policy: String,
+    ) -> bool {
+        let parsed = Schedule::parse(&schedule)
+            .and_then(|parsed| Ok((parsed, MissedPolicy::parse(&missed_policy)?)));
+        let (parsed, missed_policy) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                godot_error!("Invalid job {}: {}", job_id, e);
+                return false;
+            }
+        };
+        let (run, started) = match self.jobs.remove(&job_id) {
+            Some(job) => (job.run, job.started),
+            None => (None, (Local::now(), Instant::now())),
+        };
+        self.jobs.insert(
+            job_id,
+            ScheduledJob {
+                client,
+                command,
+                schedule: parsed,
+                schedule_text: schedule,
+                missed_policy,
+                next_due: None,
+                run,
+                started,
+            },
+        );
+        true
+    }
+
+    /// Removes a job and its persisted record. A running run isn't stopped. Returns false if there is no such job.
+    #[func]
+    fn remove_job(&mut self, job_id: String) -> bool {
+        if self.jobs.remove(&job_id).is_none() {
+            return false;
+        }
+        self.records.remove(&job_id);
+        self.save_state();
+        true
+    }
+
+    /// Persists the last run of every job in `path` and loads the records already in it.
+    /// Should be set before adding jobs and starting, so runs missed while the application
+    /// wasn't running are detected.
+    ///
+    /// * `path` - Path of the state file. Godot paths like `user://` are supported.
+    #[func]
+    fn set_state_file(&mut self, path: String) -> bool {
+        let path = globalize_path(&path);
+        match scheduler::load_records(&path) {
+            Ok(records) => {
+                self.records = records;
+                self.state_path = Some(path);
+                for job in self.jobs.values_mut() {
+                    job.next_due = None;
+                }
+                true
+            }
+            Err(e) => {
+                godot_error!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Starts running jobs when they are due. Missed runs are handled on the first process.
+    #[func]
+    fn start(&mut self) {
+        self.running = true;
+    }
+
+    /// Stops starting new runs. Already running runs finish.
+    #[func]
+    fn stop(&mut self) {
+        self.running = false;
+        for job in self.jobs.values_mut() {
+            job.next_due = None;
+        }
+    }
+
+    #[func]
+    fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Runs a job right away, regardless of its schedule. Returns false if there is no such job
+    /// or it's already running.
+    #[func]
+    fn run_now(&mut self, job_id: String) -> bool {
+        let Some(job) = self.jobs.get_mut(&job_id) else {
+            godot_error!("No job with id {}", job_id);
+            return false;
+        };
+        if job.run.is_some() {
+            return false;
+        }
+        start_run(job);
+        self.base_mut()
+            .emit_signal("job_started", &[job_id.to_variant()]);
+        true
+    }
+
+    /// Returns null if there is no job with `job_id`, otherwise a [Dictionary] with the keys `id`, `command`,
+    /// `schedule`, `missed_policy`, `running`, `next_run` (unix time, null if not scheduled), `last_run`
+    /// (unix time), `last_exit_status`, `last_error` and `last_duration` (null if it never ran).
+    #[func]
+    fn get_job(&self, job_id: String) -> Variant {
+        match self.jobs.get(&job_id) {
+            Some(job) => Variant::from(self.job_dict(&job_id, job)),
+            None => Variant::nil(),
+        }
+    }
+
+    /// Returns all jobs, see [method get_job] for their layout.
+    #[func]
+    fn get_jobs(&self) -> Array<Variant> {
+        let mut jobs: Array<Variant> = Array::new();
+        for (job_id, job) in &self.jobs {
+            jobs.push(&Variant::from(self.job_dict(job_id, job)));
+        }
+        jobs
+    }
+}
+
+impl SSHScheduler {
+    fn job_dict(&self, job_id: &str, job: &ScheduledJob) -> Dictionary<GString, Variant> {
+        let mut dict = self
+            .records
+            .get(job_id)
+            .cloned()
+            .unwrap_or_default()
+            .to_dict();
+        dict.set("id", &job_id.to_variant());
+        dict.set("command", &job.command.to_variant());
+        dict.set("schedule", &job.schedule_text.to_variant());
+        dict.set("missed_policy", &job.missed_policy.as_str().to_variant());
+        dict.set("running", &job.run.is_some().to_variant());
+        dict.set(
+            "next_run",
+            &match job.next_due {
+                Some(next_due) if self.running => next_due.timestamp().to_variant(),
+                _ => Variant::nil(),
+            },
+        );
+        dict
+    }
+
+    fn save_state(&self) {
+        if let Some(path) = &self.state_path {
+            if let Err(e) = scheduler::save_records(path, &self.records) {
+                godot_error!("{}", e);
+            }
+        }
+    }
+}
+
+fn start_run(job: &mut ScheduledJob) {
+    job.started = (Local::now(), Instant::now());
+    let client_id = job.client.instance_id();
+    let command = job.command.clone();
+    job.run = Some(thread::spawn(move || {
+        let limits = CaptureLimits {
+            max_stdout: Some(OUTPUT_LIMIT),
+            max_stderr: Some(OUTPUT_LIMIT),
+        };
+        let result =
+            ClientLease::new(client_id).and_then(|mut client| client.exec_capture(command, limits));
+        result.map_err(|e| e.to_string())
+    }));
+}
+
+/// Records a finished run and returns its result for [signal SSHScheduler.job_finished].
+fn finish_run(
+    record: &mut JobRecord,
+    (started, start): (DateTime<Local>, Instant),
+    result: Result<BinaryExecOutput, String>,
+) -> Dictionary<GString, Variant> {
+    let duration = start.elapsed().as_secs_f64();
+    record.last_run = Some(started.timestamp());
+    record.duration = Some(duration);
+    let (stdout, stderr, exit_status) = match &result {
+        Ok(output) => (
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+            output.exit_status,
+        ),
+        Err(_) => (String::new(), String::new(), -1),
+    };
+    record.exit_status = result.is_ok().then_some(exit_status);
+    record.error = result.err();
+    dict! {
+        "started" => started.timestamp(),
+        "duration" => duration,
+        "exit_status" => exit_status,
+        "stdout" => stdout,
+        "stderr" => stderr,
+        "error" => match &record.error {
+            Some(error) => Variant::from(error.clone()),
+            None => Variant::nil(),
+        },
+    }
+}
@@ -0,0 +1,230 @@
+use crate::command_watcher::{backoff_delay, due_after};
+use crate::ssh_client::{ClientLease, SSHClient};
+use crate::stats::{RawStats, StatsSample};
+use godot::classes::{INode, Node};
+use godot::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Longest delay between two collections of a host which keeps failing.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+const DEFAULT_INTERVAL: f64 = 2.0;
+
+struct CollectedHost {
+    client: Gd<SSHClient>,
+    /// Last reading, to calculate rates from counters.
+    previous: Option<(RawStats, Instant)>,
+    history: VecDeque<StatsSample>,
+    failures: u32,
+    next_collect: Option<Instant>,
+    collect: Option<JoinHandle<Result<RawStats, String>>>,
+}
+
+/// Collects CPU, memory, disk, load, network and temperature statistics of hosts at an interval,
+/// e.g. for dashboard panels.
+///
+/// Statistics are read with a single lightweight command over the client's session, depending on the server's OS:
+/// `/proc` on Linux, `vm_stat` and `sysctl` on macOS and CIM on Windows. A short history of samples is kept per host.
+/// Collection runs on background threads, so it never blocks the main thread.
+///
+/// **Note:** The collector needs to be in the scene tree, as it collects while processing.
+///
+/// # Example usage
+///
+/// ```
+/// var collector: SSHStatsCollector = SSHStatsCollector.new()
+/// add_child(collector)
+/// collector.add_client("nas", client)
+/// collector.sample_collected.connect(func(host_id, sample): cpu_bar.value = sample.cpu_percent if sample.cpu_percent != null else 0)
+/// collector.start()
+/// ```
+#[derive(GodotClass)]
+#[class(base = Node)]
+pub struct SSHStatsCollector {
+    /// Seconds between two samples of a host.
+    #[export]
+    interval: f64,
+    /// Amount of samples kept per host.
+    #[export]
+    history_size: u32,
+    hosts: HashMap<String, CollectedHost>,
+    running: bool,
+    base: Base<Node>,
+}
+
+#[godot_api]
+pub impl INode for SSHStatsCollector {
+    fn init(base: Base<Node>) -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            history_size: 60,
+            hosts: HashMap::new(),
+            running: false,
+            base,
+        }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        let interval = match Duration::try_from_secs_f64(self.interval.max(0.1)) {
+            Ok(interval) => interval,
+            Err(e) => {
+                godot_error!(
+                    "Invalid interval {}: {}, using {}s",
+                    self.interval,
+                    e,
+                    DEFAULT_INTERVAL
+                );
+                self.interval = DEFAULT_INTERVAL;
+                Duration::from_secs_f64(DEFAULT_INTERVAL)
+            }
+        };
+        let history_size = self.history_size as usize;
+        let mut results = Vec::new();
+        for (host_id, host) in self.hosts.iter_mut() {
+            if let Some(collect) = host.collect.take_if(|collect| collect.is_finished()) {
+                let result = match collect.join() {
+                    Ok(result) => result,
+                    Err(_) => Err("Collecting thread panicked".to_string()),
+                };
+                let result = match result {
+                    Ok(raw) => {
+                        let sample = StatsSample::new(raw.clone(), host.previous.as_ref());
+                        host.previous = Some((raw, Instant::now()));
+                        host.history.push_back(sample.clone());
+                        let excess = host.history.len().saturating_sub(history_size);
+                        host.history.drain(..excess);
+                        host.failures = 0;
+                        host.next_collect = Some(due_after(interval));
+                        Ok(sample)
+                    }
+                    Err(e) => {
+                        host.failures += 1;
+                        host.next_collect = Some(due_after(backoff_delay(
+                            interval,
+                            MAX_BACKOFF,
+                            host.failures,
+                        )));
+                        Err(e)
+                    }
+                };
+                results.push((host_id.clone(), result));
+            }
+            if self.running
+                && host.collect.is_none()
+                && host
+                    .next_collect
+                    .is_none_or(|next_collect| Instant::now() >= next_collect)
+            {
+                host.collect = Some(spawn_collect(&host.client));
+            }
+        }
+
+        for (host_id, result) in results {
+            match result {
+                Ok(sample) => self.base_mut().emit_signal(
+                    "sample_collected",
+                    &[host_id.to_variant(), sample.to_dict().to_variant()],
+                ),
+                Err(e) => self
+                    .base_mut()
+                    .emit_signal("collect_failed", &[host_id.to_variant(), e.to_variant()]),
+            };
+        }
+    }
+
+    fn exit_tree(&mut self) {
+        self.running = false;
+    }
+}
+
+#[godot_api]
+pub impl SSHStatsCollector {
+    /// Emitted for every collected sample.
+    ///
+    /// * `sample` - See [method get_latest].
+    #[signal]
+    fn sample_collected(host_id: GString, sample: Dictionary<GString, Variant>);
+
+    /// Emitted when the statistics of a host couldn't be collected.
+    /// The delay until the next attempt doubles while it keeps failing.
+    #[signal]
+    fn collect_failed(host_id: GString, error: GString);
+
+    /// Adds the server of `client` to collect statistics of, or replaces the client of `host_id`.
+    #[func]
+    fn add_client(&mut self, host_id: String, client: Gd<SSHClient>) {
+        self.hosts.insert(
+            host_id,
+            CollectedHost {
+                client,
+                previous: None,
+                history: VecDeque::new(),
+                failures: 0,
+                next_collect: None,
+                collect: None,
+            },
+        );
+    }
+
+    /// Stops collecting statistics of a host and drops its history. Returns false if it wasn't added.
+    #[func]
+    fn remove_host(&mut self, host_id: String) -> bool {
+        self.hosts.remove(&host_id).is_some()
+    }
+
+    /// Starts collecting, the first sample of every host is collected immediately.
+    #[func]
+    fn start(&mut self) {
+        self.running = true;
+    }
+
+    #[func]
+    fn stop(&mut self) {
+        self.running = false;
+    }
+
+    #[func]
+    fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Returns null if there is no sample of `host_id` yet, otherwise a [Dictionary] with the keys
+    /// `timestamp` (unix time), `cpu_percent` (null for the first sample on Linux), `cpu_count`,
+    /// `load` ([PackedFloat64Array] of the 1, 5 and 15 minute load, null on Windows), `memory_total`,
+    /// `memory_used`, `memory_available`, `swap_total`, `swap_used` (all bytes), `uptime` (seconds),
+    /// `net_rx_rate` and `net_tx_rate` (bytes per second of all interfaces, null for the first sample),
+    /// `disks` ([Array] of [Dictionary] with the keys `mount`, `total` and `used`)
+    /// and `temperatures` ([Dictionary] of °C by sensor).
+    #[func]
+    fn get_latest(&self, host_id: String) -> Variant {
+        match self
+            .hosts
+            .get(&host_id)
+            .and_then(|host| host.history.back())
+        {
+            Some(sample) => Variant::from(sample.to_dict()),
+            None => Variant::nil(),
+        }
+    }
+
+    /// Returns the kept samples of `host_id`, oldest first. See [method get_latest] for their layout.
+    #[func]
+    fn get_history(&self, host_id: String) -> Array<Variant> {
+        let mut history: Array<Variant> = Array::new();
+        if let Some(host) = self.hosts.get(&host_id) {
+            for sample in &host.history {
+                history.push(&Variant::from(sample.to_dict()));
+            }
+        }
+        history
+    }
+}
+
+fn spawn_collect(client: &Gd<SSHClient>) -> JoinHandle<Result<RawStats, String>> {
+    let client_id = client.instance_id();
+    thread::spawn(move || {
+        let result = ClientLease::new(client_id).and_then(|mut client| client.collect_stats());
+        result.map_err(|e| e.to_string())
+    })
+}
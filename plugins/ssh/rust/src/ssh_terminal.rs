@@ -0,0 +1,319 @@
+use crate::asciicast::AsciicastWriter;
+use crate::ssh_client::SSHClient;
+use crate::ssh_vault::globalize_path;
+use crate::terminal::{run_terminal, SharedTerminal};
+use godot::classes::{INode, Node};
+use godot::prelude::*;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+/// An interactive shell with a PTY on a client, e.g. to show a terminal in a panel.
+/// Output is emitted as raw bytes including escape sequences, which a terminal emulator needs to render.
+///
+/// The session can be recorded as an [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/) file,
+/// which can be played back with [SSHTerminalPlayer] or asciinema. The shell runs on a background thread
+/// over its own channel, so the client can still be used for other commands.
+///
+/// **Note:** The terminal needs to be in the scene tree, as signals are emitted while processing.
+///
+/// # Example usage
+///
+/// ```
+/// var terminal: SSHTerminal = SSHTerminal.new()
+/// add_child(terminal)
+/// terminal.set_client(client)
+/// terminal.output_received.connect(func(data): panel.write(data))
+/// terminal.open()
+/// terminal.start_recording("user://recordings/session.cast", false, "Deploy")
+/// terminal.send_text("uptime\n")
+/// ```
+#[derive(GodotClass)]
+#[class(base = Node)]
+pub struct SSHTerminal {
+    /// Terminal type reported to the server. Applies from the next [method open].
+    #[export]
+    term: GString,
+    /// Columns of the terminal, see [method resize].
+    #[export]
+    cols: u32,
+    /// Rows of the terminal, see [method resize].
+    #[export]
+    rows: u32,
+    client: Option<Gd<SSHClient>>,
+    opened: bool,
+    terminal: Option<(SharedTerminal, JoinHandle<()>)>,
+    recording: Option<Recording>,
+    base: Base<Node>,
+}
+
+struct Recording {
+    path: String,
+    writer: AsciicastWriter,
+    record_input: bool,
+}
+
+#[godot_api]
+pub impl INode for SSHTerminal {
+    fn init(base: Base<Node>) -> Self {
+        Self {
+            term: "xterm-256color".into(),
+            cols: 80,
+            rows: 24,
+            client: None,
+            opened: false,
+            terminal: None,
+            recording: None,
+            base,
+        }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        self.drain_terminal();
+    }
+
+    fn exit_tree(&mut self) {
+        self.close();
+        self.stop_recording();
+    }
+}
+
+#[godot_api]
+pub impl SSHTerminal {
+    /// Emitted for all output of the shell, which may split UTF-8 characters and escape sequences.
+    #[signal]
+    fn output_received(data: PackedByteArray);
+
+    /// Emitted when the size of the terminal changed, see [method resize].
+    #[signal]
+    fn terminal_resized(cols: i64, rows: i64);
+
+    /// Emitted when the shell was started.
+    #[signal]
+    fn terminal_opened();
+
+    /// Emitted when the shell ended.
+    ///
+    /// * `reason` - Why it ended, e.g. "Shell exited with status 0" or "Closed" after [method close].
+    #[signal]
+    fn terminal_closed(reason: GString);
+
+    /// Sets the client the shell is opened on. Applies from the next [method open].
+    #[func]
+    fn set_client(&mut self, client: Option<Gd<SSHClient>>) {
+        self.client = client;
+    }
+
+    /// Opens the shell. Returns false if no client is set or the terminal is already open.
+    /// [signal terminal_opened] or [signal terminal_closed] is emitted once the shell started or failed to.
+    #[func]
+    fn open(&mut self) -> bool {
+        let Some(client) = &self.client else {
+            godot_error!("No client set");
+            return false;
+        };
+        if self.terminal.is_some() {
+            godot_error!("Terminal is already open");
+            return false;
+        }
+        let client_id = client.instance_id();
+        let term = self.term.to_string();
+        let (cols, rows) = (self.cols.max(1), self.rows.max(1));
+        let shared = SharedTerminal::default();
+        let thread_shared = shared.clone();
+        self.terminal = Some((
+            shared,
+            thread::spawn(move || run_terminal(client_id, term, cols, rows, thread_shared)),
+        ));
+        true
+    }
+
+    /// Closes the shell. [signal terminal_closed] is emitted once the channel was closed.
+    #[func]
+    fn close(&mut self) {
+        if let Some((shared, _)) = &self.terminal {
+            if let Ok(mut shared) = shared.lock() {
+                shared.close = true;
+            }
+        }
+    }
+
+    /// Returns whether the shell is running.
+    #[func]
+    fn is_open(&self) -> bool {
+        self.opened
+    }
+
+    /// Sends raw input like key presses or escape sequences to the shell.
+    /// Input sent before the shell started is sent once it did.
+    #[func]
+    fn send_input(&mut self, data: PackedByteArray) -> bool {
+        let Some((shared, _)) = &self.terminal else {
+            godot_error!("Terminal isn't open");
+            return false;
+        };
+        match shared.lock() {
+            Ok(mut shared) => shared.input.extend_from_slice(data.as_slice()),
+            Err(_) => return false,
+        }
+        if let Some(recording) = self.recording.as_mut() {
+            if recording.record_input {
+                let result = recording.writer.input(Instant::now(), data.as_slice());
+                self.check_recording(result);
+            }
+        }
+        true
+    }
+
+    /// Sends `text` as UTF-8 to the shell, see [method send_input].
+    #[func]
+    fn send_text(&mut self, text: String) -> bool {
+        self.send_input(PackedByteArray::from(text.as_bytes()))
+    }
+
+    /// Changes the size of the terminal, also while it is open.
+    #[func]
+    fn resize(&mut self, cols: u32, rows: u32) {
+        let (cols, rows) = (cols.max(1), rows.max(1));
+        if (cols, rows) == (self.cols, self.rows) {
+            return;
+        }
+        self.cols = cols;
+        self.rows = rows;
+        if let Some((shared, _)) = &self.terminal {
+            if let Ok(mut shared) = shared.lock() {
+                shared.resize = Some((cols, rows));
+            }
+        }
+        if let Some(recording) = self.recording.as_mut() {
+            let result = recording.writer.resize(Instant::now(), cols, rows);
+            self.check_recording(result);
+        }
+        self.base_mut().emit_signal(
+            "terminal_resized",
+            &[(cols as i64).to_variant(), (rows as i64).to_variant()],
+        );
+    }
+
+    /// Starts recording the terminal to an asciicast v2 file at `path`, replacing an existing file.
+    /// The recording stops with [method stop_recording] or when the shell ends. Returns false if the
+    /// file couldn't be created or a recording is already running.
+    ///
+    /// * `record_input` - Also record the input, which includes everything typed, e.g. passwords.
+    /// * `title` - Optional title stored in the recording.
+    #[func]
+    fn start_recording(&mut self, path: String, record_input: bool, title: String) -> bool {
+        if self.recording.is_some() {
+            godot_error!("Terminal is already being recorded");
+            return false;
+        }
+        let title = (!title.is_empty()).then_some(title);
+        match AsciicastWriter::create(
+            &globalize_path(&path),
+            self.cols,
+            self.rows,
+            &self.term.to_string(),
+            title,
+        ) {
+            Ok(writer) => {
+                self.recording = Some(Recording {
+                    path,
+                    writer,
+                    record_input,
+                });
+                true
+            }
+            Err(e) => {
+                godot_error!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Stops recording and completes the file. Returns false if no recording was running.
+    #[func]
+    fn stop_recording(&mut self) -> bool {
+        let Some(recording) = self.recording.take() else {
+            return false;
+        };
+        if let Err(e) = recording.writer.finish() {
+            godot_error!("Failed to write recording {}: {}", recording.path, e);
+        }
+        true
+    }
+
+    /// Returns whether the terminal is being recorded.
+    #[func]
+    fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Adds a marker to the recording, e.g. before an important step. Returns false if no recording is running.
+    #[func]
+    fn add_marker(&mut self, label: String) -> bool {
+        let Some(recording) = self.recording.as_mut() else {
+            return false;
+        };
+        let result = recording.writer.marker(Instant::now(), &label);
+        self.check_recording(result);
+        true
+    }
+}
+
+impl SSHTerminal {
+    /// Handles everything received from the shell since the last frame.
+    fn drain_terminal(&mut self) {
+        let Some((shared, thread)) = &self.terminal else {
+            return;
+        };
+        // Checked first, as the thread always sets the reason before it finishes
+        let finished = thread.is_finished();
+        let (output, started, ended) = match shared.lock() {
+            Ok(mut shared) => (
+                std::mem::take(&mut shared.output),
+                std::mem::take(&mut shared.started),
+                shared.ended.take(),
+            ),
+            Err(_) => (
+                Vec::new(),
+                false,
+                Some("Terminal state is poisoned".to_string()),
+            ),
+        };
+        let ended = match ended {
+            Some(reason) => Some(reason),
+            None if finished => Some("Terminal thread panicked".to_string()),
+            None => None,
+        };
+
+        if started {
+            self.opened = true;
+            self.base_mut().emit_signal("terminal_opened", &[]);
+        }
+        for (at, data) in output {
+            if let Some(recording) = self.recording.as_mut() {
+                let result = recording.writer.output(at, &data);
+                self.check_recording(result);
+            }
+            self.base_mut().emit_signal(
+                "output_received",
+                &[PackedByteArray::from(data.as_slice()).to_variant()],
+            );
+        }
+        if let Some(reason) = ended {
+            self.terminal = None;
+            self.opened = false;
+            self.stop_recording();
+            self.base_mut()
+                .emit_signal("terminal_closed", &[reason.to_variant()]);
+        }
+    }
+
+    /// Stops the recording if writing to it failed, so a full disk doesn't log an error per frame.
+    fn check_recording(&mut self, result: anyhow::Result<()>) {
+        if let Err(e) = result {
+            if let Some(recording) = self.recording.take() {
+                godot_error!("Failed to write recording {}: {}", recording.path, e);
+            }
+        }
+    }
+}
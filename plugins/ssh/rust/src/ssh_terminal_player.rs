@@ -0,0 +1,267 @@
+use crate::asciicast::{EventKind, Recording};
+use crate::ssh_vault::globalize_path;
+use godot::classes::{INode, Node};
+use godot::prelude::*;
+
+/// Plays back an asciicast v2 recording, e.g. made by [SSHTerminal], through the same
+/// [signal output_received] and [signal terminal_resized] signals as [SSHTerminal],
+/// so a panel showing a terminal can show a recording too.
+///
+/// **Note:** The player needs to be in the scene tree, as it plays while processing.
+///
+/// # Example usage
+///
+/// ```
+/// var player: SSHTerminalPlayer = SSHTerminalPlayer.new()
+/// add_child(player)
+/// player.output_received.connect(func(data): panel.write(data))
+/// player.playback_reset.connect(func(): panel.clear())
+/// player.load("user://recordings/session.cast")
+/// player.play()
+/// ```
+#[derive(GodotClass)]
+#[class(base = Node)]
+pub struct SSHTerminalPlayer {
+    /// Playback speed, 2.0 plays twice as fast.
+    #[export]
+    speed: f64,
+    /// Pauses longer than this many seconds are shortened to it. 0 keeps them, unless the recording
+    /// sets its own limit. Applies from the next [method load].
+    #[export]
+    max_idle: f64,
+    recording: Option<Recording>,
+    position: f64,
+    /// Index of the next event to play.
+    next_event: usize,
+    playing: bool,
+    base: Base<Node>,
+}
+
+#[godot_api]
+pub impl INode for SSHTerminalPlayer {
+    fn init(base: Base<Node>) -> Self {
+        Self {
+            speed: 1.0,
+            max_idle: 0.0,
+            recording: None,
+            position: 0.0,
+            next_event: 0,
+            playing: false,
+            base,
+        }
+    }
+
+    fn process(&mut self, delta: f64) {
+        if !self.playing {
+            return;
+        }
+        let Some(recording) = &self.recording else {
+            return;
+        };
+        self.position = (self.position + delta * self.speed.max(0.0)).min(recording.duration());
+        while let Some(event) = self
+            .recording
+            .as_ref()
+            .and_then(|recording| recording.events.get(self.next_event))
+            .filter(|event| event.time <= self.position)
+            .map(|event| event.kind.clone())
+        {
+            self.next_event += 1;
+            self.emit_event(event);
+        }
+        if self
+            .recording
+            .as_ref()
+            .is_some_and(|recording| self.next_event >= recording.events.len())
+        {
+            self.playing = false;
+            self.base_mut().emit_signal("playback_finished", &[]);
+        }
+    }
+}
+
+#[godot_api]
+pub impl SSHTerminalPlayer {
+    /// Emitted for the recorded output, see [signal SSHTerminal.output_received].
+    #[signal]
+    fn output_received(data: PackedByteArray);
+
+    /// Emitted when the recorded terminal changed its size, and with the initial size after loading or seeking.
+    #[signal]
+    fn terminal_resized(cols: i64, rows: i64);
+
+    /// Emitted when a marker of the recording is reached while playing.
+    #[signal]
+    fn marker_reached(label: GString);
+
+    /// Emitted before the output up to the new position is replayed after loading, seeking or stopping,
+    /// so the terminal showing it should be cleared.
+    #[signal]
+    fn playback_reset();
+
+    /// Emitted when the end of the recording was reached.
+    #[signal]
+    fn playback_finished();
+
+    /// Loads the recording at `path` and seeks to its start. Returns false if it couldn't be loaded.
+    #[func]
+    fn load(&mut self, path: String) -> bool {
+        let mut recording = match Recording::load(&globalize_path(&path)) {
+            Ok(recording) => recording,
+            Err(e) => {
+                godot_error!("{}", e);
+                return false;
+            }
+        };
+        if self.max_idle > 0.0 {
+            recording.limit_idle(self.max_idle);
+        }
+        self.recording = Some(recording);
+        self.playing = false;
+        self.seek(0.0);
+        true
+    }
+
+    /// Starts or resumes playing, from the start if the end was reached. Returns false if nothing is loaded.
+    #[func]
+    fn play(&mut self) -> bool {
+        let Some(recording) = &self.recording else {
+            godot_error!("No recording loaded");
+            return false;
+        };
+        if self.next_event >= recording.events.len() {
+            self.seek(0.0);
+        }
+        self.playing = true;
+        true
+    }
+
+    #[func]
+    fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    /// Stops playing and seeks to the start.
+    #[func]
+    fn stop(&mut self) {
+        self.playing = false;
+        if self.recording.is_some() {
+            self.seek(0.0);
+        }
+    }
+
+    /// Jumps to `position` seconds. Emits [signal playback_reset] followed by all output up to `position` at once.
+    #[func]
+    fn seek(&mut self, position: f64) {
+        let Some(recording) = &self.recording else {
+            return;
+        };
+        let position = position.clamp(0.0, recording.duration());
+        let (cols, rows) = (recording.width, recording.height);
+        let mut events = Vec::new();
+        let mut next_event = 0;
+        // Output is combined, so the terminal doesn't get a signal per recorded chunk
+        let mut output = String::new();
+        for event in recording
+            .events
+            .iter()
+            .take_while(|event| event.time <= position)
+        {
+            next_event += 1;
+            match &event.kind {
+                EventKind::Output(data) => output.push_str(data),
+                EventKind::Resize(..) => {
+                    events.push(EventKind::Output(std::mem::take(&mut output)));
+                    events.push(event.kind.clone());
+                }
+                EventKind::Input(_) | EventKind::Marker(_) => {}
+            }
+        }
+        events.push(EventKind::Output(output));
+
+        self.position = position;
+        self.next_event = next_event;
+        self.base_mut().emit_signal("playback_reset", &[]);
+        self.emit_event(EventKind::Resize(cols, rows));
+        for event in events {
+            self.emit_event(event);
+        }
+    }
+
+    #[func]
+    fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Returns the current position in seconds.
+    #[func]
+    fn get_position(&self) -> f64 {
+        self.position
+    }
+
+    /// Returns the length of the loaded recording in seconds, 0 if nothing is loaded.
+    #[func]
+    fn get_duration(&self) -> f64 {
+        self.recording
+            .as_ref()
+            .map_or(0.0, |recording| recording.duration())
+    }
+
+    /// Returns the initial size of the recorded terminal as columns and rows.
+    #[func]
+    fn get_size(&self) -> Vector2i {
+        self.recording.as_ref().map_or(Vector2i::ZERO, |recording| {
+            Vector2i::new(recording.width as i32, recording.height as i32)
+        })
+    }
+
+    /// Returns the title of the loaded recording, empty if it has none.
+    #[func]
+    fn get_title(&self) -> GString {
+        self.recording
+            .as_ref()
+            .and_then(|recording| recording.title.as_deref())
+            .unwrap_or_default()
+            .into()
+    }
+
+    /// Returns an [Array] with a [Dictionary] per marker with the keys `time` and `label`,
+    /// e.g. to [method seek] to them.
+    #[func]
+    fn get_markers(&self) -> Array<Variant> {
+        let mut markers: Array<Variant> = Array::new();
+        let Some(recording) = &self.recording else {
+            return markers;
+        };
+        for event in &recording.events {
+            if let EventKind::Marker(label) = &event.kind {
+                let marker: Dictionary<GString, Variant> = dict! {
+                    "time" => event.time,
+                    "label" => label.clone(),
+                };
+                markers.push(&Variant::from(marker));
+            }
+        }
+        markers
+    }
+}
+
+impl SSHTerminalPlayer {
+    fn emit_event(&mut self, event: EventKind) {
+        match event {
+            EventKind::Output(data) if data.is_empty() => {}
+            EventKind::Output(data) => self.base_mut().emit_signal(
+                "output_received",
+                &[PackedByteArray::from(data.as_bytes()).to_variant()],
+            ),
+            EventKind::Resize(cols, rows) => self.base_mut().emit_signal(
+                "terminal_resized",
+                &[(cols as i64).to_variant(), (rows as i64).to_variant()],
+            ),
+            EventKind::Marker(label) => self
+                .base_mut()
+                .emit_signal("marker_reached", &[label.to_variant()]),
+            EventKind::Input(_) => {}
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use crate::vault::{Vault, VaultError};
+use godot::prelude::*;
+use std::path::PathBuf;
+use zeroize::Zeroizing;
+
+/// An encrypted store for SSH keys and passwords.
+///
+/// Secrets are encrypted at rest with a key derived from a master passphrase (Argon2id)
+/// and XChaCha20-Poly1305. After unlocking once, clients can take their credentials directly
+/// from the vault via [method SSHClient.set_auth_password_from_vault] and
+/// [method SSHClient.set_auth_key_from_vault], so secrets never have to pass through GDScript
+/// or be written to disk in clear.
+///
+/// # Example usage
+///
+/// ```
+/// var vault: SSHVault = SSHVault.new()
+/// var err: Variant = vault.open("user://vault.json")
+/// if err:
+///     err = vault.create("user://vault.json", master_passphrase)
+/// else:
+///     var unlock_err: Variant = vault.unlock(master_passphrase)
+///     if unlock_err:
+///         err = unlock_err.message
+/// if err:
+///     push_error(err)
+///     return
+/// vault.set_secret("server_pw", "secure_pw")
+/// client.set_auth_password_from_vault(vault, "server_pw")
+/// ```
+#[derive(GodotClass)]
+#[class(base = RefCounted)]
+pub struct SSHVault {
+    vault: Option<Vault>,
+}
+
+#[godot_api]
+pub impl IRefCounted for SSHVault {
+    fn init(_base: Base<RefCounted>) -> Self {
+        Self { vault: None }
+    }
+}
+
+#[godot_api]
+pub impl SSHVault {
+    /// Creates a new vault at `path` protected by `passphrase`. The vault is unlocked afterwards.
+    /// Will return null on success, otherwise a string with the error will be returned.
+    ///
+    /// * `path` - Path of the vault file. Godot paths like `user://` are supported.
+    /// * `passphrase` - The master passphrase.
+    #[func]
+    fn create(&mut self, path: String, passphrase: String) -> Variant {
+        let passphrase = Zeroizing::new(passphrase);
+        match Vault::create(&globalize_path(&path), &passphrase) {
+            Ok(vault) => {
+                self.vault = Some(vault);
+                Variant::nil()
+            }
+            Err(e) => Variant::from(e.to_string()),
+        }
+    }
+
+    /// Loads the vault at `path`. The vault is locked afterwards.
+    /// Will return null on success, otherwise a string with the error will be returned.
+    ///
+    /// * `path` - Path of the vault file. Godot paths like `user://` are supported.
+    #[func]
+    fn open(&mut self, path: String) -> Variant {
+        match Vault::open(&globalize_path(&path)) {
+            Ok(vault) => {
+                self.vault = Some(vault);
+                Variant::nil()
+            }
+            Err(e) => Variant::from(e.to_string()),
+        }
+    }
+
+    /// Unlocks the vault for the rest of the session.
+    /// Will return null on success, otherwise a dictionary with the error's `kind` and `message`.
+    /// `kind` is "wrong_passphrase" if `passphrase` is wrong, otherwise "error".
+    #[func]
+    fn unlock(&mut self, passphrase: String) -> Variant {
+        let passphrase = Zeroizing::new(passphrase);
+        let result = match self.vault_mut() {
+            Ok(vault) => vault.unlock(&passphrase),
+            Err(e) => Err(VaultError::from(e)),
+        };
+        match result {
+            Ok(_) => Variant::nil(),
+            Err(e) => Variant::from(dict! {
+                "kind" => e.kind(),
+                "message" => e.to_string(),
+            }),
+        }
+    }
+
+    /// Locks the vault, so no secrets can be read until it is unlocked again.
+    #[func]
+    fn lock(&mut self) {
+        if let Some(vault) = &mut self.vault {
+            vault.lock();
+        }
+    }
+
+    /// Returns whether the vault is open and unlocked.
+    #[func]
+    fn is_unlocked(&self) -> bool {
+        self.vault.as_ref().is_some_and(|vault| vault.is_unlocked())
+    }
+
+    /// Re-encrypts all secrets with a new passphrase.
+    /// Will return null on success, otherwise a string with the error will be returned.
+    #[func]
+    fn change_passphrase(&mut self, old_passphrase: String, new_passphrase: String) -> Variant {
+        let old_passphrase = Zeroizing::new(old_passphrase);
+        let new_passphrase = Zeroizing::new(new_passphrase);
+        match self
+            .vault_mut()
+            .and_then(|vault| vault.change_passphrase(&old_passphrase, &new_passphrase))
+        {
+            Ok(_) => Variant::nil(),
+            Err(e) => Variant::from(e.to_string()),
+        }
+    }
+
+    /// Encrypts and stores `secret` under `id`. The vault needs to be unlocked.
+    ///
+    /// * `id` - Id of the secret, e.g. a key uuid. Ids are stored in clear.
+    /// * `secret` - A password or the contents of a private key.
+    #[func]
+    fn set_secret(&mut self, id: String, secret: String) -> bool {
+        let secret = Zeroizing::new(secret);
+        if let Err(e) = self
+            .vault_mut()
+            .and_then(|vault| vault.set_secret(&id, secret.as_bytes()))
+        {
+            godot_error!("{}", e);
+            return false;
+        }
+        true
+    }
+
+    /// Imports the private key file at `key_path` into the vault under `id`.
+    /// This way the key never passes through GDScript. The vault needs to be unlocked.
+    #[func]
+    fn import_key_file(&mut self, id: String, key_path: String) -> bool {
+        let key_data = match std::fs::read(globalize_path(&key_path)) {
+            Ok(key_data) => Zeroizing::new(key_data),
+            Err(e) => {
+                godot_error!("Failed to read key at {}: {}", key_path, e);
+                return false;
+            }
+        };
+        if let Err(e) = self
+            .vault_mut()
+            .and_then(|vault| vault.set_secret(&id, &key_data))
+        {
+            godot_error!("{}", e);
+            return false;
+        }
+        true
+    }
+
+    /// Removes the secret stored under `id`. Returns false if it didn't exist.
+    #[func]
+    fn remove_secret(&mut self, id: String) -> bool {
+        match self.vault_mut().and_then(|vault| vault.remove_secret(&id)) {
+            Ok(removed) => removed,
+            Err(e) => {
+                godot_error!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Returns whether a secret with `id` is stored. Works while locked.
+    #[func]
+    fn has_secret(&self, id: String) -> bool {
+        self.vault
+            .as_ref()
+            .is_some_and(|vault| vault.has_secret(&id))
+    }
+
+    /// Returns the ids of all stored secrets. Works while locked.
+    #[func]
+    fn get_secret_ids(&self) -> PackedStringArray {
+        match &self.vault {
+            Some(vault) => PackedStringArray::from_iter(
+                vault
+                    .secret_ids()
+                    .iter()
+                    .map(|id| GString::from(id.as_str())),
+            ),
+            None => PackedStringArray::new(),
+        }
+    }
+}
+
+impl SSHVault {
+    /// Access to the vault for other classes of this extension.
+    pub fn vault(&self) -> anyhow::Result<&Vault> {
+        match &self.vault {
+            Some(vault) => Ok(vault),
+            None => anyhow::bail!("No vault opened"),
+        }
+    }
+
+    fn vault_mut(&mut self) -> anyhow::Result<&mut Vault> {
+        match &mut self.vault {
+            Some(vault) => Ok(vault),
+            None => anyhow::bail!("No vault opened"),
+        }
+    }
+}
+
+/// Converts Godot paths like `user://` to absolute paths.
+pub fn globalize_path(path: &str) -> PathBuf {
+    PathBuf::from(
+        godot::classes::ProjectSettings::singleton()
+            .globalize_path(path)
+            .to_string(),
+    )
+}
@@ -0,0 +1,529 @@
+use crate::internal_ssh_client::ClientAccess;
+use crate::remote_info::RemoteOs;
+use crate::shell::{posix_quote, powershell_encoded_command};
+use chrono::Utc;
+use godot::prelude::*;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Prints the raw statistics of a Linux server, split into sections starting with `@name`.
+const LINUX_PROBE: &str = r#"echo @cpu; grep '^cpu' /proc/stat
+echo @mem; cat /proc/meminfo
+echo @load; cat /proc/loadavg
+echo @uptime; cat /proc/uptime
+echo @net; cat /proc/net/dev
+echo @disk; df -Pk 2>/dev/null
+echo @temp
+for z in /sys/class/thermal/thermal_zone*; do
+  [ -r "$z/temp" ] && echo "$(cat "$z/type") $(cat "$z/temp")"
+done
+true"#;
+
+/// Prints the raw statistics of a macOS server, split into sections starting with `@name`.
+const MACOS_PROBE: &str = r#"echo @cpu; ps -A -o %cpu= | awk '{ s += $1 } END { print s }'; sysctl -n hw.ncpu
+echo @mem; sysctl -n hw.memsize; vm_stat; sysctl -n vm.swapusage
+echo @load; sysctl -n vm.loadavg
+echo @uptime; sysctl -n kern.boottime; date +%s
+echo @net; netstat -ibn
+echo @disk; df -Pk 2>/dev/null
+true"#;
+
+/// Prints the raw statistics of a Windows server as `key=value` lines.
+const WINDOWS_PROBE: &str = r#"$ErrorActionPreference = 'SilentlyContinue'
+$os = Get-CimInstance Win32_OperatingSystem
+"cpu=$((Get-CimInstance Win32_Processor | Measure-Object -Property LoadPercentage -Average).Average)"
+"mem_total=$($os.TotalVisibleMemorySize * 1024)"
+"mem_available=$($os.FreePhysicalMemory * 1024)"
+"swap_total=$($os.SizeStoredInPagingFiles * 1024)"
+"swap_free=$($os.FreeSpaceInPagingFiles * 1024)"
+"uptime=$([long]((Get-Date) - $os.LastBootUpTime).TotalSeconds)"
+Get-CimInstance Win32_LogicalDisk -Filter 'DriveType=3' | ForEach-Object { "disk=$($_.Size) $($_.Size - $_.FreeSpace) $($_.DeviceID)" }
+Get-CimInstance Win32_PerfRawData_Tcpip_NetworkInterface | ForEach-Object { "net=$($_.BytesReceivedPersec) $($_.BytesSentPersec)" }
+Get-CimInstance -Namespace root/wmi MSAcpi_ThermalZoneTemperature | ForEach-Object { "temp=$($_.CurrentTemperature / 10 - 273.15) $($_.InstanceName)" }
+"#;
+
+/// Filesystems which don't represent real storage.
+const PSEUDO_FILESYSTEMS: [&str; 9] = [
+    "tmpfs", "devtmpfs", "devfs", "overlay", "squashfs", "udev", "none", "shm", "efivarfs",
+];
+
+/// How the CPU usage was reported.
+#[derive(Clone, Copy, Debug)]
+pub enum CpuReading {
+    /// Jiffy counters since boot, the usage is calculated from two consecutive readings.
+    Counters {
+        total: u64,
+        idle: u64,
+    },
+    Percent(f64),
+    Unknown,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiskUsage {
+    pub mount: String,
+    pub total: u64,
+    pub used: u64,
+}
+
+/// Statistics as reported by the server, counters still need to be turned into rates.
+#[derive(Clone, Debug)]
+pub struct RawStats {
+    pub cpu: CpuReading,
+    pub cpu_count: Option<u32>,
+    pub load: Option<[f64; 3]>,
+    pub memory_total: u64,
+    pub memory_available: u64,
+    pub swap_total: u64,
+    pub swap_free: u64,
+    pub uptime: Option<f64>,
+    /// Bytes received and sent by all interfaces except loopback since boot.
+    pub net_rx: Option<u64>,
+    pub net_tx: Option<u64>,
+    pub disks: Vec<DiskUsage>,
+    /// Temperatures in °C by sensor name.
+    pub temperatures: Vec<(String, f64)>,
+}
+
+impl RawStats {
+    fn new() -> Self {
+        Self {
+            cpu: CpuReading::Unknown,
+            cpu_count: None,
+            load: None,
+            memory_total: 0,
+            memory_available: 0,
+            swap_total: 0,
+            swap_free: 0,
+            uptime: None,
+            net_rx: None,
+            net_tx: None,
+            disks: Vec::new(),
+            temperatures: Vec::new(),
+        }
+    }
+}
+
+/// A single statistics sample of a host.
+#[derive(Clone, Debug)]
+pub struct StatsSample {
+    /// Unix time the sample was collected at.
+    pub timestamp: f64,
+    /// None for the first sample if the usage is calculated from counters.
+    pub cpu_percent: Option<f64>,
+    /// Bytes per second, None for the first sample.
+    pub net_rx_rate: Option<f64>,
+    pub net_tx_rate: Option<f64>,
+    pub raw: RawStats,
+}
+
+impl StatsSample {
+    /// Calculates a sample from `raw` and the previous reading of the same host.
+    pub fn new(raw: RawStats, previous: Option<&(RawStats, Instant)>) -> Self {
+        let elapsed = previous.map(|(_, time)| time.elapsed().as_secs_f64());
+        let cpu_percent = match (raw.cpu, previous.map(|(previous, _)| previous.cpu)) {
+            (CpuReading::Percent(percent), _) => Some(percent),
+            (
+                CpuReading::Counters { total, idle },
+                Some(CpuReading::Counters {
+                    total: previous_total,
+                    idle: previous_idle,
+                }),
+            ) if total > previous_total => {
+                let busy =
+                    (total - previous_total).saturating_sub(idle.saturating_sub(previous_idle));
+                Some(busy as f64 * 100.0 / (total - previous_total) as f64)
+            }
+            _ => None,
+        };
+        let rate = |current: Option<u64>, previous: Option<u64>| match (current, previous, elapsed)
+        {
+            // Counters reset on reboot or when interfaces are removed
+            (Some(current), Some(previous), Some(elapsed))
+                if current >= previous && elapsed > 0.0 =>
+            {
+                Some((current - previous) as f64 / elapsed)
+            }
+            _ => None,
+        };
+        Self {
+            timestamp: Utc::now().timestamp_millis() as f64 / 1000.0,
+            cpu_percent,
+            net_rx_rate: rate(
+                raw.net_rx,
+                previous.and_then(|(previous, _)| previous.net_rx),
+            ),
+            net_tx_rate: rate(
+                raw.net_tx,
+                previous.and_then(|(previous, _)| previous.net_tx),
+            ),
+            raw,
+        }
+    }
+
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        let optional = |value: Option<f64>| match value {
+            Some(value) => Variant::from(value),
+            None => Variant::nil(),
+        };
+        let mut disks: Array<Variant> = Array::new();
+        for disk in &self.raw.disks {
+            disks.push(&Variant::from(dict! {
+                "mount" => disk.mount.clone(),
+                "total" => disk.total as i64,
+                "used" => disk.used as i64,
+            }));
+        }
+        let mut temperatures: Dictionary<GString, Variant> = Dictionary::new();
+        for (sensor, temperature) in &self.raw.temperatures {
+            temperatures.set(sensor.as_str(), &Variant::from(*temperature));
+        }
+        dict! {
+            "timestamp" => self.timestamp,
+            "cpu_percent" => optional(self.cpu_percent),
+            "cpu_count" => match self.raw.cpu_count {
+                Some(cpu_count) => Variant::from(cpu_count as i64),
+                None => Variant::nil(),
+            },
+            "load" => match self.raw.load {
+                Some(load) => Variant::from(PackedFloat64Array::from(load.as_slice())),
+                None => Variant::nil(),
+            },
+            "memory_total" => self.raw.memory_total as i64,
+            "memory_used" => self.raw.memory_total.saturating_sub(self.raw.memory_available) as i64,
+            "memory_available" => self.raw.memory_available as i64,
+            "swap_total" => self.raw.swap_total as i64,
+            "swap_used" => self.raw.swap_total.saturating_sub(self.raw.swap_free) as i64,
+            "uptime" => optional(self.raw.uptime),
+            "net_rx_rate" => optional(self.net_rx_rate),
+            "net_tx_rate" => optional(self.net_tx_rate),
+            "disks" => disks,
+            "temperatures" => temperatures,
+        }
+    }
+}
+
+/// Reads the current resource statistics of the server with a single command.
+pub fn collect_stats(
+    client: &mut impl ClientAccess,
+    ip: &String,
+    user: &String,
+    port: u16,
+) -> anyhow::Result<RawStats> {
+    let os = client
+        .with_client(|client| client.remote_info(ip, user, port))?
+        .os;
+    let cmd = match os {
+        RemoteOs::Linux => format!("sh -c {}", posix_quote(LINUX_PROBE)),
+        RemoteOs::MacOs => format!("sh -c {}", posix_quote(MACOS_PROBE)),
+        RemoteOs::Windows => powershell_encoded_command(WINDOWS_PROBE),
+        os => anyhow::bail!(
+            "Collecting stats isn't supported on {} servers",
+            os.as_str()
+        ),
+    };
+    let output = client.exec_output(cmd, ip, user, port)?;
+    if output.exit_status != 0 {
+        anyhow::bail!(
+            "Failed to collect stats (exit status {}): {}",
+            output.exit_status,
+            output.stderr.trim()
+        );
+    }
+    Ok(match os {
+        RemoteOs::Linux => parse_linux(&output.stdout),
+        RemoteOs::MacOs => parse_macos(&output.stdout),
+        _ => parse_windows(&output.stdout),
+    })
+}
+
+/// Splits probe output into its `@name` sections.
+fn sections(output: &str) -> HashMap<&str, Vec<&str>> {
+    let mut sections: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut current = "";
+    for line in output.lines() {
+        match line.strip_prefix('@') {
+            Some(name) => current = name.trim(),
+            None => sections.entry(current).or_default().push(line),
+        }
+    }
+    sections
+}
+
+fn parse_linux(output: &str) -> RawStats {
+    let sections = sections(output);
+    let section = |name: &str| sections.get(name).map(Vec::as_slice).unwrap_or_default();
+    let mut stats = RawStats::new();
+
+    // cpu user nice system idle iowait irq softirq steal ..., followed by a cpuN line per core
+    let cpu = section("cpu");
+    stats.cpu_count = Some(cpu.len().saturating_sub(1) as u32).filter(|count| *count > 0);
+    if let Some(line) = cpu.first() {
+        let values: Vec<u64> = line
+            .split_whitespace()
+            .skip(1)
+            .filter_map(|value| value.parse().ok())
+            .collect();
+        if values.len() >= 4 {
+            stats.cpu = CpuReading::Counters {
+                total: values.iter().take(8).sum(),
+                idle: values[3] + values.get(4).copied().unwrap_or_default(),
+            };
+        }
+    }
+
+    let meminfo: HashMap<&str, u64> = section("mem")
+        .iter()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            let kb = value.split_whitespace().next()?.parse::<u64>().ok()?;
+            Some((key, kb * 1024))
+        })
+        .collect();
+    let mem = |key: &str| meminfo.get(key).copied().unwrap_or_default();
+    stats.memory_total = mem("MemTotal");
+    stats.memory_available = meminfo
+        .get("MemAvailable")
+        .copied()
+        .unwrap_or_else(|| mem("MemFree") + mem("Buffers") + mem("Cached"));
+    stats.swap_total = mem("SwapTotal");
+    stats.swap_free = mem("SwapFree");
+
+    stats.load = section("load").first().and_then(|line| parse_load(line));
+    stats.uptime = section("uptime")
+        .first()
+        .and_then(|line| line.split_whitespace().next()?.parse().ok());
+
+    // iface: rx_bytes rx_packets ... (8 values) tx_bytes ...
+    let mut net = None;
+    for line in section("net") {
+        let Some((interface, values)) = line.split_once(':') else {
+            continue;
+        };
+        if interface.trim() == "lo" {
+            continue;
+        }
+        let values: Vec<u64> = values
+            .split_whitespace()
+            .filter_map(|value| value.parse().ok())
+            .collect();
+        if values.len() >= 9 {
+            let (rx, tx) = net.unwrap_or((0, 0));
+            net = Some((rx + values[0], tx + values[8]));
+        }
+    }
+    stats.net_rx = net.map(|(rx, _)| rx);
+    stats.net_tx = net.map(|(_, tx)| tx);
+
+    stats.disks = parse_df(section("disk"));
+    stats.temperatures = section("temp")
+        .iter()
+        .filter_map(|line| {
+            let (sensor, millidegrees) = line.rsplit_once(' ')?;
+            Some((
+                sensor.to_string(),
+                millidegrees.parse::<f64>().ok()? / 1000.0,
+            ))
+        })
+        .collect();
+    stats
+}
+
+fn parse_macos(output: &str) -> RawStats {
+    let sections = sections(output);
+    let section = |name: &str| sections.get(name).map(Vec::as_slice).unwrap_or_default();
+    let mut stats = RawStats::new();
+
+    // Sum of the usage of all processes, 100% per core
+    let cpu = section("cpu");
+    let cpu_count = cpu.get(1).and_then(|line| line.trim().parse::<u32>().ok());
+    if let (Some(usage), Some(cpu_count)) = (
+        cpu.first().and_then(|line| line.trim().parse::<f64>().ok()),
+        cpu_count,
+    ) {
+        stats.cpu = CpuReading::Percent((usage / cpu_count.max(1) as f64).min(100.0));
+    }
+    stats.cpu_count = cpu_count;
+
+    let mem = section("mem");
+    stats.memory_total = mem
+        .first()
+        .and_then(|line| line.trim().parse().ok())
+        .unwrap_or_default();
+    let page_size = mem
+        .iter()
+        .find_map(|line| {
+            line.split_once("page size of ")?
+                .1
+                .split_whitespace()
+                .next()?
+                .parse::<u64>()
+                .ok()
+        })
+        .unwrap_or(4096);
+    let pages = |key: &str| {
+        mem.iter()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                (name.trim() == key)
+                    .then(|| value.trim().trim_end_matches('.').parse::<u64>().ok())?
+            })
+            .unwrap_or_default()
+    };
+    stats.memory_available =
+        (pages("Pages free") + pages("Pages inactive") + pages("Pages speculative")) * page_size;
+    // total = 2048.00M  used = 1024.00M  free = 1024.00M  (encrypted)
+    if let Some(line) = mem.iter().find(|line| line.contains("total =")) {
+        let value = |key: &str| {
+            let value = line.split_once(key)?.1.split_whitespace().next()?;
+            let megabytes = value.trim_end_matches('M').parse::<f64>().ok()?;
+            Some((megabytes * 1024.0 * 1024.0) as u64)
+        };
+        stats.swap_total = value("total =").unwrap_or_default();
+        stats.swap_free = value("free =").unwrap_or_default();
+    }
+
+    // { 1.23 1.45 1.67 }
+    stats.load = section("load")
+        .first()
+        .and_then(|line| parse_load(line.trim().trim_matches(['{', '}'])));
+
+    // { sec = 1700000000, usec = 0 } Thu Nov 14 22:13:20 2023
+    let uptime = section("uptime");
+    let boot_time = uptime.first().and_then(|line| {
+        line.split_once("sec = ")?
+            .1
+            .split(',')
+            .next()?
+            .trim()
+            .parse::<i64>()
+            .ok()
+    });
+    let now = uptime
+        .get(1)
+        .and_then(|line| line.trim().parse::<i64>().ok());
+    if let (Some(boot_time), Some(now)) = (boot_time, now) {
+        stats.uptime = Some((now - boot_time) as f64);
+    }
+
+    // Name Mtu Network Address Ipkts Ierrs Ibytes Opkts Oerrs Obytes Coll
+    // The address column is empty for some interfaces, so the byte columns are counted from the end.
+    let mut net = None;
+    for line in section("net") {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        if columns.len() < 7 || !columns[2].starts_with("<Link#") || columns[0].starts_with("lo") {
+            continue;
+        }
+        let rx = columns[columns.len() - 5]
+            .parse::<u64>()
+            .unwrap_or_default();
+        let tx = columns[columns.len() - 2]
+            .parse::<u64>()
+            .unwrap_or_default();
+        let (total_rx, total_tx) = net.unwrap_or((0, 0));
+        net = Some((total_rx + rx, total_tx + tx));
+    }
+    stats.net_rx = net.map(|(rx, _)| rx);
+    stats.net_tx = net.map(|(_, tx)| tx);
+
+    stats.disks = parse_df(section("disk"))
+        .into_iter()
+        .filter(|disk| {
+            !disk.mount.starts_with("/System/Volumes/") || disk.mount == "/System/Volumes/Data"
+        })
+        .collect();
+    stats
+}
+
+fn parse_windows(output: &str) -> RawStats {
+    let mut stats = RawStats::new();
+    let mut net = None;
+    for line in output.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let number = || value.trim().parse::<f64>().ok();
+        match key {
+            "cpu" => {
+                if let Some(percent) = number() {
+                    stats.cpu = CpuReading::Percent(percent);
+                }
+            }
+            "mem_total" => stats.memory_total = number().unwrap_or_default() as u64,
+            "mem_available" => stats.memory_available = number().unwrap_or_default() as u64,
+            "swap_total" => stats.swap_total = number().unwrap_or_default() as u64,
+            "swap_free" => stats.swap_free = number().unwrap_or_default() as u64,
+            "uptime" => stats.uptime = number(),
+            "disk" => {
+                let mut columns = value.splitn(3, ' ');
+                if let (Some(Ok(total)), Some(Ok(used)), Some(mount)) = (
+                    columns.next().map(str::parse::<u64>),
+                    columns.next().map(str::parse::<u64>),
+                    columns.next(),
+                ) {
+                    stats.disks.push(DiskUsage {
+                        mount: mount.to_string(),
+                        total,
+                        used,
+                    });
+                }
+            }
+            "net" => {
+                let mut columns = value.split_whitespace();
+                if let (Some(Ok(rx)), Some(Ok(tx))) = (
+                    columns.next().map(str::parse::<u64>),
+                    columns.next().map(str::parse::<u64>),
+                ) {
+                    let (total_rx, total_tx) = net.unwrap_or((0, 0));
+                    net = Some((total_rx + rx, total_tx + tx));
+                }
+            }
+            "temp" => {
+                if let Some((celsius, sensor)) = value.split_once(' ') {
+                    if let Ok(celsius) = celsius.parse::<f64>() {
+                        stats.temperatures.push((sensor.to_string(), celsius));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    stats.net_rx = net.map(|(rx, _)| rx);
+    stats.net_tx = net.map(|(_, tx)| tx);
+    stats
+}
+
+fn parse_load(line: &str) -> Option<[f64; 3]> {
+    let mut values = line.split_whitespace().map(|value| value.parse::<f64>());
+    Some([
+        values.next()?.ok()?,
+        values.next()?.ok()?,
+        values.next()?.ok()?,
+    ])
+}
+
+/// Parses `df -Pk` output, skipping pseudo filesystems.
+fn parse_df(lines: &[&str]) -> Vec<DiskUsage> {
+    lines
+        .iter()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() < 6 || PSEUDO_FILESYSTEMS.contains(&columns[0]) {
+                return None;
+            }
+            let mount = columns[5..].join(" ");
+            if ["/proc", "/sys", "/dev", "/snap"]
+                .iter()
+                .any(|prefix| mount.starts_with(prefix))
+            {
+                return None;
+            }
+            Some(DiskUsage {
+                mount,
+                total: columns[1].parse::<u64>().ok()? * 1024,
+                used: columns[2].parse::<u64>().ok()? * 1024,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,416 @@
+use crate::audit_log::ExecRecord;
+use crate::capture::CaptureBuffer;
+use crate::internal_ssh_client::{ExecOutput, InternalSSHClient};
+use crate::logger::{LogEvent, LogLevel};
+use crate::shell::posix_quote;
+use async_std::task::block_on;
+use chrono::Utc;
+use godot::prelude::*;
+use russh::ChannelMsg;
+use zeroize::Zeroizing;
+
+/// Why a command couldn't be run elevated.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SudoError {
+    /// sudo asked for a password, but none is set.
+    PasswordRequired,
+    PasswordRejected,
+    /// The user isn't allowed to run the command with sudo.
+    NotInSudoers,
+    /// sudo isn't installed.
+    Unavailable,
+    /// sudo only runs in a terminal, which is retried with a PTY.
+    TtyRequired,
+}
+
+impl SudoError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SudoError::PasswordRequired => "password_required",
+            SudoError::PasswordRejected => "password_rejected",
+            SudoError::NotInSudoers => "not_in_sudoers",
+            SudoError::Unavailable => "unavailable",
+            SudoError::TtyRequired => "tty_required",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            SudoError::PasswordRequired => "sudo password required, but none is set",
+            SudoError::PasswordRejected => "sudo password rejected",
+            SudoError::NotInSudoers => "User is not in sudoers",
+            SudoError::Unavailable => "sudo is not available on the server",
+            SudoError::TtyRequired => "sudo requires a terminal",
+        }
+    }
+}
+
+/// Output of a command run with sudo.
+pub struct ElevatedOutput {
+    pub output: ExecOutput,
+    /// Set if sudo didn't run the command.
+    pub sudo_error: Option<SudoError>,
+}
+
+impl ElevatedOutput {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        let mut dict = self.output.to_dict();
+        dict.set(
+            "sudo_error",
+            &match self.sudo_error {
+                Some(sudo_error) => Variant::from(sudo_error.as_str()),
+                None => Variant::nil(),
+            },
+        );
+        dict
+    }
+}
+
+/// Markers to tell apart sudo's prompt and the start of the command in the output.
+struct Markers {
+    id: i64,
+    prompt: String,
+    ready: String,
+}
+
+impl Markers {
+    fn new(id: i64) -> Self {
+        Self {
+            id,
+            prompt: format!("[dreamdeck-sudo-prompt-{}]", id),
+            ready: format!("dreamdeck-sudo-ready-{}", id),
+        }
+    }
+
+    /// Command printing the ready marker to stderr. The marker is put together when it runs,
+    /// as sudo repeats the command line in some errors, which must not match it.
+    fn announce_ready(&self) -> String {
+        format!("printf '%s-%s\\n' dreamdeck-sudo-ready {} >&2", self.id)
+    }
+}
+
+impl InternalSSHClient {
+    /// Runs `cmd` as root with `sudo -S`. If sudo asks for a password, the sudo password is sent on stdin,
+    /// so it never shows up in the command line, logs or audit log. If sudo requires a terminal,
+    /// the command is run again with a PTY, which merges stderr into stdout.
+    ///
+    /// * `shell` - Shell running `cmd`, e.g. "sh" or "bash".
+    pub fn exec_ssh_elevated(
+        &mut self,
+        cmd: &str,
+        shell: &str,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<ElevatedOutput> {
+        let id = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        let markers = Markers::new(id);
+        // The elevated shell announces itself, so everything before belongs to sudo
+        let script = format!("{}\n{}", markers.announce_ready(), cmd);
+        let wrapped = format!(
+            "sudo -S -p {} -- {} -c {}",
+            posix_quote(&markers.prompt),
+            shell,
+            posix_quote(&script)
+        );
+
+        let mut record = self
+            .audit
+            .as_ref()
+            .map(|target| ExecRecord::start(target, ip, port, user, &format!("sudo {}", cmd)));
+        self.log.debug(LogEvent::Exec, ip, port, || {
+            format!("Executing command with sudo: \"{}\"", cmd)
+        });
+        let mut result = self.run_elevated(&wrapped, &markers, false, &mut record, ip, user, port);
+        if matches!(&result, Ok(output) if output.sudo_error == Some(SudoError::TtyRequired)) {
+            self.log.debug(LogEvent::Exec, ip, port, || {
+                "sudo requires a terminal, retrying with a PTY".to_string()
+            });
+            result = self.run_elevated(&wrapped, &markers, true, &mut record, ip, user, port);
+        }
+
+        if let Ok(ElevatedOutput {
+            sudo_error: Some(sudo_error),
+            ..
+        }) = &result
+        {
+            self.log.warn(LogEvent::Auth, ip, port, || {
+                sudo_error.message().to_string()
+            });
+        }
+        if let Some(record) = record {
+            match &result {
+                Ok(output) => record.finish(
+                    (output.output.exit_status != -1).then_some(output.output.exit_status),
+                    output
+                        .sudo_error
+                        .map(|sudo_error| sudo_error.message().to_string()),
+                ),
+                Err(e) => record.finish(None, Some(e.to_string())),
+            }
+        }
+        result
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn run_elevated(
+        &mut self,
+        wrapped: &str,
+        markers: &Markers,
+        pty: bool,
+        record: &mut Option<ExecRecord>,
+        ip: &String,
+        user: &String,
+        port: u16,
+    ) -> anyhow::Result<ElevatedOutput> {
+        let password = self.sudo_password.clone();
+        let mut channel = block_on(self.open_channel(ip, user, port))?;
+        if pty {
+            if let Err(e) = block_on(channel.request_pty(false, "xterm", 200, 24, 0, 0, &[])) {
+                anyhow::bail!("Couldn't request PTY: {}", e);
+            }
+        }
+        if let Err(e) = block_on(channel.exec(false, wrapped)) {
+            anyhow::bail!("Couldn't execute command on {:?}: {}", channel.id(), e);
+        }
+        self.log.log(
+            LogLevel::Trace,
+            LogEvent::Exec,
+            ip,
+            port,
+            Some(channel.id()),
+            || format!("Running sudo{}", if pty { " with PTY" } else { "" }),
+        );
+
+        let mut stdout = CaptureBuffer::new(None);
+        let mut stderr = CaptureBuffer::new(None);
+        // Output of sudo itself, until the elevated shell announced itself
+        let mut preamble: Vec<u8> = Vec::new();
+        let mut authenticated = false;
+        let mut password_sent = false;
+        let mut exit_status = -1;
+        let mut sudo_error = None;
+        loop {
+            let data = match block_on(channel.wait()) {
+                None => break,
+                Some(ChannelMsg::ExitStatus {
+                    exit_status: new_exit_status,
+                }) => {
+                    exit_status = new_exit_status as i64;
+                    continue;
+                }
+                // With a PTY everything arrives on stdout
+                Some(ChannelMsg::Data { data }) if pty && !authenticated => data,
+                Some(ChannelMsg::ExtendedData { ext: 1, data }) if !pty && !authenticated => data,
+                Some(ChannelMsg::Data { data }) => {
+                    if let Some(record) = record.as_mut() {
+                        record.stdout(&data);
+                    }
+                    stdout.push(&data);
+                    continue;
+                }
+                Some(ChannelMsg::ExtendedData { ext: 1, data }) => {
+                    if let Some(record) = record.as_mut() {
+                        record.stderr(&data);
+                    }
+                    stderr.push(&data);
+                    continue;
+                }
+                Some(_) => continue,
+            };
+            preamble.extend_from_slice(&data);
+
+            let error = match scan_preamble(&preamble, markers, password_sent) {
+                Preamble::Ready(position) => {
+                    authenticated = true;
+                    // The command gets an empty stdin, just like with a regular exec
+                    if pty {
+                        let _ = block_on(channel.data(&b"\x04"[..]));
+                    }
+                    let _ = block_on(channel.eof());
+                    let rest = &preamble[position..];
+                    let rest = rest.strip_prefix(b"\r").unwrap_or(rest);
+                    let rest = rest.strip_prefix(b"\n").unwrap_or(rest);
+                    if pty {
+                        stdout.push(rest);
+                    } else {
+                        stderr.push(rest);
+                    }
+                    preamble.clear();
+                    continue;
+                }
+                Preamble::PasswordPrompt => match &password {
+                    Some(password) => {
+                        let line = Zeroizing::new(format!("{}\n", password.as_str()));
+                        if let Err(e) = block_on(channel.data(line.as_bytes())) {
+                            anyhow::bail!("Failed to send sudo password: {}", e);
+                        }
+                        password_sent = true;
+                        None
+                    }
+                    None => Some(SudoError::PasswordRequired),
+                },
+                Preamble::Failed(error) => Some(error),
+                Preamble::Pending => None,
+            };
+            if error.is_some() {
+                sudo_error = error;
+                let _ = block_on(channel.close());
+            }
+        }
+
+        if !authenticated && sudo_error.is_none() {
+            let text = String::from_utf8_lossy(&preamble).to_lowercase();
+            if exit_status == 127
+                || text.contains("sudo: not found")
+                || text.contains("sudo: command not found")
+            {
+                sudo_error = Some(SudoError::Unavailable);
+            } else {
+                // sudo failed for another reason, its message is the only output
+                stderr.push(&preamble);
+            }
+        }
+        Ok(ElevatedOutput {
+            output: ExecOutput {
+                stdout: String::from_utf8_lossy(&stdout.into_bytes()).into_owned(),
+                stderr: String::from_utf8_lossy(&stderr.into_bytes()).into_owned(),
+                exit_status,
+            },
+            sudo_error,
+        })
+    }
+}
+
+/// What sudo printed so far before running the command.
+#[derive(PartialEq, Debug)]
+enum Preamble {
+    /// The elevated shell announced itself, the command's output starts at the position.
+    Ready(usize),
+    /// sudo asks for the password.
+    PasswordPrompt,
+    Failed(SudoError),
+    /// Nothing conclusive yet.
+    Pending,
+}
+
+fn scan_preamble(preamble: &[u8], markers: &Markers, password_sent: bool) -> Preamble {
+    if let Some(position) = find(preamble, markers.ready.as_bytes()) {
+        return Preamble::Ready(position + markers.ready.len());
+    }
+
+    let prompts = count(preamble, markers.prompt.as_bytes());
+    let text = String::from_utf8_lossy(preamble).to_lowercase();
+    if prompts > 1 || (prompts == 1 && text.contains("sorry, try again")) {
+        Preamble::Failed(SudoError::PasswordRejected)
+    } else if prompts == 1 && !password_sent {
+        Preamble::PasswordPrompt
+    } else if text.contains("not in the sudoers")
+        || text.contains("is not allowed to")
+        || text.contains("may not run sudo")
+    {
+        Preamble::Failed(SudoError::NotInSudoers)
+    } else if text.contains("must have a tty") || text.contains("terminal is required") {
+        Preamble::Failed(SudoError::TtyRequired)
+    } else {
+        Preamble::Pending
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn count(haystack: &[u8], needle: &[u8]) -> usize {
+    haystack
+        .windows(needle.len())
+        .filter(|window| *window == needle)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn markers() -> Markers {
+        Markers::new(42)
+    }
+
+    #[test]
+    fn announce_ready_prints_the_marker() {
+        let output = std::process::Command::new("sh")
+            .args(["-c", &markers().announce_ready()])
+            .output()
+            .unwrap();
+        assert_eq!(output.stderr, b"dreamdeck-sudo-ready-42\n");
+    }
+
+    #[test]
+    fn command_line_in_errors_is_not_ready() {
+        let markers = markers();
+        let error = format!(
+            "Sorry, user alice is not allowed to execute '/bin/sh -c {}\nid' as root on host.\n",
+            markers.announce_ready()
+        );
+        assert_eq!(
+            scan_preamble(error.as_bytes(), &markers, false),
+            Preamble::Failed(SudoError::NotInSudoers)
+        );
+    }
+
+    #[test]
+    fn ready_after_password() {
+        let markers = markers();
+        let preamble = b"[dreamdeck-sudo-prompt-42]dreamdeck-sudo-ready-42\noutput";
+        assert_eq!(
+            scan_preamble(preamble, &markers, true),
+            Preamble::Ready(preamble.len() - b"\noutput".len())
+        );
+    }
+
+    #[test]
+    fn password_prompt() {
+        let markers = markers();
+        let preamble = b"[dreamdeck-sudo-prompt-42]";
+        assert_eq!(
+            scan_preamble(preamble, &markers, false),
+            Preamble::PasswordPrompt
+        );
+        assert_eq!(scan_preamble(preamble, &markers, true), Preamble::Pending);
+    }
+
+    #[test]
+    fn password_rejected() {
+        let markers = markers();
+        assert_eq!(
+            scan_preamble(
+                b"[dreamdeck-sudo-prompt-42]\nSorry, try again.\n",
+                &markers,
+                true
+            ),
+            Preamble::Failed(SudoError::PasswordRejected)
+        );
+        assert_eq!(
+            scan_preamble(
+                b"[dreamdeck-sudo-prompt-42]\n[dreamdeck-sudo-prompt-42]",
+                &markers,
+                true
+            ),
+            Preamble::Failed(SudoError::PasswordRejected)
+        );
+    }
+
+    #[test]
+    fn tty_required() {
+        assert_eq!(
+            scan_preamble(
+                b"sudo: sorry, you must have a tty to run sudo\n",
+                &markers(),
+                false
+            ),
+            Preamble::Failed(SudoError::TtyRequired)
+        );
+    }
+}
@@ -0,0 +1,708 @@
+use crate::audit_log::ExecRecord;
+use crate::internal_ssh_client::ClientAccess;
+use crate::logger::LogEvent;
+use crate::shell::posix_quote;
+use async_std::task::block_on;
+use chrono::DateTime;
+use godot::prelude::*;
+use regex::Regex;
+use russh::ChannelMsg;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Bytes read from a local file at once while uploading.
+const CHUNK_SIZE: usize = 32 * 1024;
+
+/// Lists everything below the current directory as `type|size|mtime|./path`,
+/// with GNU `stat` on Linux and BSD `stat` on macOS and BSD.
+const LIST_SCRIPT: &str = r#"if stat -c %s . >/dev/null 2>&1; then
+  find . ! -name . -exec stat -c '%F|%s|%Y|%n' {} +
+else
+  find . ! -name . -exec stat -f '%HT|%z|%m|%N' {} +
+fi"#;
+
+/// Prints `md5 ./path` of every file below the current directory.
+const HASH_SCRIPT: &str =
+    "find . -type f -exec md5sum {} + 2>/dev/null || find . -type f -exec md5 -r {} +";
+
+/// How files are compared to decide whether they changed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CompareMode {
+    /// Size and modification time, which are kept when uploading.
+    SizeMtime,
+    /// MD5 of the content, which reads every file on both sides.
+    Hash,
+}
+
+impl CompareMode {
+    pub fn parse(mode: &str) -> anyhow::Result<Self> {
+        match mode {
+            "size_mtime" => Ok(CompareMode::SizeMtime),
+            "hash" => Ok(CompareMode::Hash),
+            _ => anyhow::bail!("Unknown compare mode: {}", mode),
+        }
+    }
+}
+
+/// A gitignore like exclude pattern. Patterns without a slash match a file or directory name anywhere,
+/// patterns with a slash match the path relative to the synced directory.
+/// `*` matches within a name, `**` across directories and a trailing slash only matches directories.
+pub struct ExcludePattern {
+    regex: Regex,
+    dir_only: bool,
+}
+
+impl ExcludePattern {
+    pub fn parse(pattern: &str) -> anyhow::Result<Self> {
+        let dir_only = pattern.ends_with('/');
+        let trimmed = pattern.trim_end_matches('/');
+        let anchored = trimmed.contains('/');
+        let trimmed = trimmed.trim_start_matches('/');
+        if trimmed.is_empty() {
+            anyhow::bail!("Invalid exclude pattern: \"{}\"", pattern);
+        }
+
+        let mut regex = String::new();
+        let mut chars = trimmed.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        regex.push_str("(?:.*/)?");
+                    } else {
+                        regex.push_str(".*");
+                    }
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        let regex = if anchored {
+            format!("^{}$", regex)
+        } else {
+            format!("^(?:.*/)?{}$", regex)
+        };
+        match Regex::new(&regex) {
+            Ok(regex) => Ok(Self { regex, dir_only }),
+            Err(e) => anyhow::bail!("Invalid exclude pattern \"{}\": {}", pattern, e),
+        }
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        (is_dir || !self.dir_only) && self.regex.is_match(path)
+    }
+}
+
+pub struct SyncOptions {
+    pub local_dir: PathBuf,
+    /// Remote directory, may start with `~/`.
+    pub remote_dir: String,
+    pub compare: CompareMode,
+    /// Delete remote files which don't exist locally.
+    pub delete: bool,
+    /// Only report what would be done.
+    pub dry_run: bool,
+    pub exclude: Vec<ExcludePattern>,
+}
+
+impl SyncOptions {
+    /// Reads the options as described in `SSHClient::sync_directory`.
+    pub fn from_dict(
+        local_dir: PathBuf,
+        remote_dir: String,
+        dict: &Dictionary<GString, Variant>,
+    ) -> anyhow::Result<Self> {
+        if remote_dir.is_empty() {
+            anyhow::bail!("No remote directory set");
+        }
+        let compare = match dict.get("compare") {
+            Some(compare) => CompareMode::parse(&compare.to_string())?,
+            None => CompareMode::SizeMtime,
+        };
+        let patterns: Vec<String> = match dict.get("exclude") {
+            None => Vec::new(),
+            Some(exclude) => match exclude.try_to::<PackedStringArray>() {
+                Ok(exclude) => exclude.as_slice().iter().map(|p| p.to_string()).collect(),
+                Err(_) => match exclude.try_to::<Array<Variant>>() {
+                    Ok(exclude) => exclude.iter_shared().map(|p| p.to_string()).collect(),
+                    Err(_) => anyhow::bail!("Exclude patterns need to be an Array of strings"),
+                },
+            },
+        };
+        Ok(Self {
+            local_dir,
+            remote_dir,
+            compare,
+            delete: dict.get("delete").is_some_and(|delete| delete.booleanize()),
+            dry_run: dict
+                .get("dry_run")
+                .is_some_and(|dry_run| dry_run.booleanize()),
+            exclude: patterns
+                .iter()
+                .map(|pattern| ExcludePattern::parse(pattern))
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+
+    /// Whether `path` or one of its parent directories is excluded.
+    fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+        let mut parent = path;
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            if self
+                .exclude
+                .iter()
+                .any(|pattern| pattern.matches(dir, true))
+            {
+                return true;
+            }
+            parent = dir;
+        }
+        self.exclude
+            .iter()
+            .any(|pattern| pattern.matches(path, is_dir))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SyncAction {
+    Create,
+    Update,
+    Delete,
+}
+
+impl SyncAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncAction::Create => "create",
+            SyncAction::Update => "update",
+            SyncAction::Delete => "delete",
+        }
+    }
+}
+
+/// A file which is, or would be with a dry run, transferred or deleted.
+pub struct SyncFile {
+    /// Path relative to the synced directory.
+    pub path: String,
+    pub action: SyncAction,
+    pub size: u64,
+    pub error: Option<String>,
+}
+
+impl SyncFile {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        dict! {
+            "path" => self.path.clone(),
+            "action" => self.action.as_str().to_string(),
+            "size" => self.size as i64,
+            "error" => match &self.error {
+                Some(error) => Variant::from(error.clone()),
+                None => Variant::nil(),
+            },
+        }
+    }
+}
+
+pub struct SyncReport {
+    pub dry_run: bool,
+    pub files: Vec<SyncFile>,
+    pub unchanged: usize,
+    /// Set if the sync couldn't be run at all.
+    pub error: Option<String>,
+}
+
+impl SyncReport {
+    pub fn to_dict(&self) -> Dictionary<GString, Variant> {
+        let mut files: Array<Variant> = Array::new();
+        for file in &self.files {
+            files.push(&Variant::from(file.to_dict()));
+        }
+        let count = |action: SyncAction| {
+            self.files
+                .iter()
+                .filter(|file| file.action == action && file.error.is_none())
+                .count() as i64
+        };
+        let failed = self
+            .files
+            .iter()
+            .filter(|file| file.error.is_some())
+            .count() as i64;
+        let bytes: u64 = self
+            .files
+            .iter()
+            .filter(|file| file.action != SyncAction::Delete && file.error.is_none())
+            .map(|file| file.size)
+            .sum();
+        dict! {
+            "success" => self.error.is_none() && failed == 0,
+            "error" => match &self.error {
+                Some(error) => Variant::from(error.clone()),
+                None => Variant::nil(),
+            },
+            "dry_run" => self.dry_run,
+            "created" => count(SyncAction::Create),
+            "updated" => count(SyncAction::Update),
+            "deleted" => count(SyncAction::Delete),
+            "unchanged" => self.unchanged as i64,
+            "failed" => failed,
+            "bytes" => bytes as i64,
+            "files" => files,
+        }
+    }
+}
+
+struct LocalFile {
+    path: PathBuf,
+    size: u64,
+    mtime: i64,
+    mode: u32,
+}
+
+struct RemoteEntry {
+    is_dir: bool,
+    size: u64,
+    mtime: i64,
+}
+
+/// Pushes the local directory to the remote directory, transferring only new or changed files.
+/// Only supported on unix servers. Symlinks are skipped.
+///
+/// * `progress` - Called after every processed file with its index and the total amount of files.
+pub fn sync_directory(
+    client: &mut impl ClientAccess,
+    options: &SyncOptions,
+    mut progress: impl FnMut(&SyncFile, usize, usize),
+    ip: &String,
+    user: &String,
+    port: u16,
+) -> SyncReport {
+    let mut report = SyncReport {
+        dry_run: options.dry_run,
+        files: Vec::new(),
+        unchanged: 0,
+        error: None,
+    };
+    let planned = match plan_sync(client, options, ip, user, port) {
+        Ok(planned) => planned,
+        Err(e) => {
+            report.error = Some(e.to_string());
+            return report;
+        }
+    };
+    let (transfers, deletions, unchanged) = planned;
+    report.unchanged = unchanged;
+    let total = transfers.len() + deletions.len();
+    let _ = client.with_client(|client| {
+        client.log.info(LogEvent::Exec, ip, port, || {
+            format!(
+                "Syncing {} to {}: {} to transfer, {} to delete{}",
+                options.local_dir.display(),
+                options.remote_dir,
+                transfers.len(),
+                deletions.len(),
+                if options.dry_run { " (dry run)" } else { "" }
+            )
+        });
+        Ok(())
+    });
+
+    for (path, action, local, conflict) in transfers {
+        let error = if conflict.is_some() || options.dry_run {
+            conflict
+        } else {
+            upload_file(client, &options.remote_dir, &path, &local, ip, user, port)
+                .err()
+                .map(|e| e.to_string())
+        };
+        report.files.push(SyncFile {
+            path,
+            action,
+            size: local.size,
+            error,
+        });
+        progress(report.files.last().unwrap(), report.files.len() - 1, total);
+    }
+
+    if !deletions.is_empty() && !options.dry_run {
+        let error = delete_remote(client, &options.remote_dir, &deletions, ip, user, port)
+            .err()
+            .map(|e| e.to_string());
+        for (path, _) in &deletions {
+            report.files.push(SyncFile {
+                path: path.clone(),
+                action: SyncAction::Delete,
+                size: 0,
+                error: error.clone(),
+            });
+            progress(report.files.last().unwrap(), report.files.len() - 1, total);
+        }
+    } else {
+        for (path, _) in deletions {
+            report.files.push(SyncFile {
+                path,
+                action: SyncAction::Delete,
+                size: 0,
+                error: None,
+            });
+            progress(report.files.last().unwrap(), report.files.len() - 1, total);
+        }
+    }
+    report
+}
+
+/// Compares both sides and returns the files to transfer (with why they can't be, if so),
+/// the remote paths to delete (with whether they are directories) and the amount of unchanged files.
+#[allow(clippy::type_complexity)]
+fn plan_sync(
+    client: &mut impl ClientAccess,
+    options: &SyncOptions,
+    ip: &String,
+    user: &String,
+    port: u16,
+) -> anyhow::Result<(
+    Vec<(String, SyncAction, LocalFile, Option<String>)>,
+    Vec<(String, bool)>,
+    usize,
+)> {
+    if !client
+        .with_client(|client| client.remote_info(ip, user, port))?
+        .os
+        .is_unix()
+    {
+        anyhow::bail!("Syncing is only supported on unix servers");
+    }
+    let mut local = BTreeMap::new();
+    let mut local_dirs = Vec::new();
+    scan_local(options, &options.local_dir, "", &mut local, &mut local_dirs)?;
+    let remote = list_remote(client, &options.remote_dir, ip, user, port)?;
+    let hashes = match options.compare {
+        CompareMode::Hash if !remote.is_empty() => {
+            Some(hash_remote(client, &options.remote_dir, ip, user, port)?)
+        }
+        _ => None,
+    };
+
+    let mut deletions = Vec::new();
+    if options.delete {
+        // A directory sorts before its contents, so in reverse contents are deleted first
+        for (path, entry) in remote.iter().rev() {
+            let exists = if entry.is_dir {
+                local_dirs.contains(path)
+            } else {
+                local.contains_key(path)
+            };
+            if !exists && !options.is_excluded(path, entry.is_dir) {
+                deletions.push((path.clone(), entry.is_dir));
+            }
+        }
+    }
+
+    let mut transfers = Vec::new();
+    let mut unchanged = 0;
+    for (path, file) in local {
+        let action = match remote.get(&path) {
+            None => SyncAction::Create,
+            Some(entry) if entry.is_dir => {
+                // Uploading would move the file into the directory
+                let conflict = "Remote path is a directory, but the local one is a file";
+                transfers.push((path, SyncAction::Update, file, Some(conflict.to_string())));
+                continue;
+            }
+            Some(entry) if entry.size != file.size => SyncAction::Update,
+            Some(entry) => {
+                let changed = match &hashes {
+                    Some(hashes) => hashes.get(&path) != Some(&hash_file(&file.path)?),
+                    None => entry.mtime != file.mtime,
+                };
+                if !changed {
+                    unchanged += 1;
+                    continue;
+                }
+                SyncAction::Update
+            }
+        };
+        transfers.push((path, action, file, None));
+    }
+    Ok((transfers, deletions, unchanged))
+}
+
+/// Lists the remote directory, empty if it doesn't exist yet.
+fn list_remote(
+    client: &mut impl ClientAccess,
+    remote_dir: &str,
+    ip: &String,
+    user: &String,
+    port: u16,
+) -> anyhow::Result<BTreeMap<String, RemoteEntry>> {
+    let script = format!(
+        "cd -- {} 2>/dev/null || exit 0\n{}",
+        remote_dir_arg(remote_dir),
+        LIST_SCRIPT
+    );
+    let output = client.exec_output(format!("sh -c {}", posix_quote(&script)), ip, user, port)?;
+    if output.exit_status != 0 {
+        anyhow::bail!("Failed to list {}: {}", remote_dir, output.stderr.trim());
+    }
+    let mut entries = BTreeMap::new();
+    for line in output.stdout.lines() {
+        let mut fields = line.splitn(4, '|');
+        let (Some(kind), Some(size), Some(mtime), Some(path)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let kind = kind.to_lowercase();
+        let is_dir = kind == "directory";
+        // Symlinks and special files are left alone
+        if !is_dir && !kind.starts_with("regular") {
+            continue;
+        }
+        entries.insert(
+            path.strip_prefix("./").unwrap_or(path).to_string(),
+            RemoteEntry {
+                is_dir,
+                size: size.parse().unwrap_or_default(),
+                mtime: mtime.parse().unwrap_or_default(),
+            },
+        );
+    }
+    Ok(entries)
+}
+
+fn hash_remote(
+    client: &mut impl ClientAccess,
+    remote_dir: &str,
+    ip: &String,
+    user: &String,
+    port: u16,
+) -> anyhow::Result<HashMap<String, String>> {
+    let script = format!(
+        "cd -- {} && {{ {}; }}",
+        remote_dir_arg(remote_dir),
+        HASH_SCRIPT
+    );
+    let output = client.exec_output(format!("sh -c {}", posix_quote(&script)), ip, user, port)?;
+    if output.exit_status != 0 {
+        anyhow::bail!("Failed to hash {}: {}", remote_dir, output.stderr.trim());
+    }
+    Ok(output
+        .stdout
+        .lines()
+        .filter_map(|line| {
+            let (hash, path) = line.split_once(' ')?;
+            let path = path.trim_start();
+            Some((
+                path.strip_prefix("./").unwrap_or(path).to_string(),
+                hash.to_lowercase(),
+            ))
+        })
+        .collect())
+}
+
+/// Streams a local file to `path` in the remote directory through the command's stdin.
+/// The file is written to a temporary file first, so an interrupted upload doesn't leave a partial file.
+fn upload_file(
+    client: &mut impl ClientAccess,
+    remote_dir: &str,
+    path: &str,
+    file: &LocalFile,
+    ip: &String,
+    user: &String,
+    port: u16,
+) -> anyhow::Result<()> {
+    let (parent, name) = path.rsplit_once('/').unwrap_or((".", path));
+    let tmp = posix_quote(&format!("{}/.{}.dreamdeck-sync", parent, name));
+    let target = posix_quote(path);
+    let stamp = match DateTime::from_timestamp(file.mtime, 0) {
+        Some(mtime) => mtime.format("%Y%m%d%H%M.%S").to_string(),
+        None => anyhow::bail!("Invalid modification time of {}", path),
+    };
+    let script = format!(
+        "cd -- {dir} && mkdir -p -- {parent} && {{ cat > {tmp} && mv -f -- {tmp} {target} || {{ rm -f -- {tmp}; exit 1; }}; }} \
+         && chmod {mode:o} -- {target} && TZ=UTC0 touch -t {stamp} -- {target}",
+        dir = remote_dir_arg(remote_dir),
+        parent = posix_quote(parent),
+        mode = file.mode,
+    );
+    let cmd = format!("sh -c {}", posix_quote(&script));
+
+    let mut record = client.with_client(|client| {
+        Ok(client
+            .audit
+            .as_ref()
+            .map(|target| ExecRecord::start(target, ip, port, user, &cmd)))
+    })?;
+    let result = stream_upload(client, &cmd, file, ip, user, port);
+    if let Some(record) = record.take() {
+        match &result {
+            Ok(_) => record.finish(Some(0), None),
+            Err(e) => record.finish(None, Some(e.to_string())),
+        }
+    }
+    result
+}
+
+fn stream_upload(
+    client: &mut impl ClientAccess,
+    cmd: &str,
+    file: &LocalFile,
+    ip: &String,
+    user: &String,
+    port: u16,
+) -> anyhow::Result<()> {
+    let mut local = match fs::File::open(&file.path) {
+        Ok(local) => local,
+        Err(e) => anyhow::bail!("Failed to open {}: {}", file.path.display(), e),
+    };
+    let mut channel = client.with_client(|client| block_on(client.open_channel(ip, user, port)))?;
+    if let Err(e) = block_on(channel.exec(false, cmd)) {
+        anyhow::bail!("Couldn't execute upload on {:?}: {}", channel.id(), e);
+    }
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = match local.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) => {
+                let _ = block_on(channel.close());
+                anyhow::bail!("Failed to read {}: {}", file.path.display(), e);
+            }
+        };
+        if let Err(e) = block_on(channel.data(&buffer[..read])) {
+            anyhow::bail!("Failed to send {}: {}", file.path.display(), e);
+        }
+    }
+    let _ = block_on(channel.eof());
+
+    let mut exit_status = -1;
+    let mut stderr = Vec::new();
+    while let Some(msg) = block_on(channel.wait()) {
+        match msg {
+            ChannelMsg::ExtendedData { ext: 1, data } => stderr.extend_from_slice(&data),
+            ChannelMsg::ExitStatus {
+                exit_status: new_exit_status,
+            } => exit_status = new_exit_status as i64,
+            _ => (),
+        }
+    }
+    if exit_status != 0 {
+        anyhow::bail!(
+            "Upload failed (exit status {}): {}",
+            exit_status,
+            String::from_utf8_lossy(&stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Deletes the remote files and then the directories, which are only removed if they are empty,
+/// e.g. because they contain excluded files.
+fn delete_remote(
+    client: &mut impl ClientAccess,
+    remote_dir: &str,
+    paths: &[(String, bool)],
+    ip: &String,
+    user: &String,
+    port: u16,
+) -> anyhow::Result<()> {
+    let quoted = |dirs: bool| {
+        paths
+            .iter()
+            .filter(|(_, is_dir)| *is_dir == dirs)
+            .map(|(path, _)| posix_quote(path))
+            .collect::<Vec<String>>()
+    };
+    let mut script = format!("cd -- {} || exit 1\n", remote_dir_arg(remote_dir));
+    let files = quoted(false);
+    if !files.is_empty() {
+        script.push_str(&format!("rm -f -- {} || exit 1\n", files.join(" ")));
+    }
+    for dir in quoted(true) {
+        script.push_str(&format!("rmdir -- {} 2>/dev/null\n", dir));
+    }
+    script.push_str("exit 0");
+    let output = client.exec_output(format!("sh -c {}", posix_quote(&script)), ip, user, port)?;
+    if output.exit_status != 0 {
+        anyhow::bail!("Failed to delete files: {}", output.stderr.trim());
+    }
+    Ok(())
+}
+
+/// Quotes the remote directory for `sh`, expanding a leading `~` to the home directory.
+fn remote_dir_arg(remote_dir: &str) -> String {
+    match remote_dir.strip_prefix('~') {
+        Some("") => "\"$HOME\"".to_string(),
+        Some(rest) if rest.starts_with('/') => format!("\"$HOME\"{}", posix_quote(rest)),
+        _ => posix_quote(remote_dir),
+    }
+}
+
+/// Collects all files below `dir`, skipping symlinks and excluded paths.
+fn scan_local(
+    options: &SyncOptions,
+    dir: &Path,
+    prefix: &str,
+    files: &mut BTreeMap<String, LocalFile>,
+    dirs: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => anyhow::bail!("Failed to read {}: {}", dir.display(), e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = match prefix {
+            "" => name,
+            prefix => format!("{}/{}", prefix, name),
+        };
+        let metadata = entry.metadata()?;
+        if metadata.file_type().is_symlink() || options.is_excluded(&path, metadata.is_dir()) {
+            continue;
+        }
+        if metadata.is_dir() {
+            scan_local(options, &entry.path(), &path, files, dirs)?;
+            dirs.push(path);
+        } else if metadata.is_file() {
+            let mtime = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|mtime| mtime.as_secs() as i64)
+                .unwrap_or_default();
+            files.insert(
+                path,
+                LocalFile {
+                    path: entry.path(),
+                    size: metadata.len(),
+                    mtime,
+                    mode: metadata.permissions().mode() & 0o777,
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+fn hash_file(path: &Path) -> anyhow::Result<String> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => anyhow::bail!("Failed to open {}: {}", path.display(), e),
+    };
+    let mut context = md5::Context::new();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => break,
+            read => context.consume(&buffer[..read]),
+        }
+    }
+    Ok(format!("{:x}", context.compute()))
+}
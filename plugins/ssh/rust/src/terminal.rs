@@ -0,0 +1,126 @@
+use crate::ssh_client::SSHClient;
+use async_std::future;
+use async_std::task::block_on;
+use godot::prelude::*;
+use russh::ChannelMsg;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long the terminal thread waits for output before checking for input again.
+/// Kept short, as every keystroke waits for it.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// State shared between a terminal's thread and its owner.
+#[derive(Default)]
+pub struct TerminalState {
+    /// Output received since it was last taken, with the time it arrived.
+    pub output: Vec<(Instant, Vec<u8>)>,
+    /// Input to send to the shell.
+    pub input: Vec<u8>,
+    /// New size of the terminal to send to the server.
+    pub resize: Option<(u32, u32)>,
+    /// Set once the shell runs.
+    pub started: bool,
+    /// Reason the terminal ended, set once it did.
+    pub ended: Option<String>,
+    /// Requests the terminal to close.
+    pub close: bool,
+}
+
+pub type SharedTerminal = Arc<Mutex<TerminalState>>;
+
+/// Runs an interactive shell with a PTY on the client until it exits, the session closes or a close is
+/// requested, passing input and output through `shared`. Blocks, so call it on a separate thread.
+///
+/// The whole shell is audited as a single entry, its output is captured as stdout.
+pub fn run_terminal(
+    client_id: InstanceId,
+    term: String,
+    cols: u32,
+    rows: u32,
+    shared: SharedTerminal,
+) {
+    let reason = match terminal(client_id, &term, cols, rows, &shared) {
+        Ok(reason) => reason,
+        Err(e) => e.to_string(),
+    };
+    if let Ok(mut shared) = shared.lock() {
+        shared.ended = Some(reason);
+    }
+}
+
+fn terminal(
+    client_id: InstanceId,
+    term: &str,
+    cols: u32,
+    rows: u32,
+    shared: &SharedTerminal,
+) -> anyhow::Result<String> {
+    // The client is only bound while opening the shell, so it can be used while the terminal runs
+    let (mut channel, mut record) = {
+        let Ok(mut client) = Gd::<SSHClient>::try_from_instance_id(client_id) else {
+            anyhow::bail!("Client was freed");
+        };
+        let result = client.bind_mut().shell_channel(term, cols, rows);
+        result?
+    };
+    match shared.lock() {
+        Ok(mut shared) => shared.started = true,
+        Err(_) => anyhow::bail!("Terminal state is poisoned"),
+    }
+
+    let mut exit_status = None;
+    let reason = loop {
+        let (input, resize, close) = match shared.lock() {
+            Ok(mut shared) => (
+                std::mem::take(&mut shared.input),
+                shared.resize.take(),
+                shared.close,
+            ),
+            Err(_) => (Vec::new(), None, true),
+        };
+        if close {
+            let _ = block_on(channel.close());
+            break "Closed".to_string();
+        }
+        if let Some((cols, rows)) = resize {
+            if let Err(e) = block_on(channel.window_change(cols, rows, 0, 0)) {
+                break format!("Failed to resize terminal: {}", e);
+            }
+        }
+        if !input.is_empty() {
+            if let Err(e) = block_on(channel.data(&input[..])) {
+                break format!("Failed to send input: {}", e);
+            }
+        }
+
+        let data = match block_on(future::timeout(POLL_INTERVAL, channel.wait())) {
+            Err(_) => continue,
+            Ok(None) => match exit_status {
+                Some(exit_status) => break format!("Shell exited with status {}", exit_status),
+                None => break "Channel closed".to_string(),
+            },
+            // With a PTY stderr is usually merged into stdout, but not every server does so
+            Ok(Some(ChannelMsg::Data { data }))
+            | Ok(Some(ChannelMsg::ExtendedData { data, .. })) => data,
+            Ok(Some(ChannelMsg::ExitStatus {
+                exit_status: new_exit_status,
+            })) => {
+                exit_status = Some(new_exit_status as i64);
+                continue;
+            }
+            Ok(Some(_)) => continue,
+        };
+        if let Some(record) = record.as_mut() {
+            record.stdout(&data);
+        }
+        if let Ok(mut shared) = shared.lock() {
+            shared.output.push((Instant::now(), data.to_vec()));
+        }
+    };
+
+    if let Some(record) = record {
+        record.finish(exit_status, None);
+    }
+    Ok(reason)
+}
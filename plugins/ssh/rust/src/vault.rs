@@ -0,0 +1,406 @@
+use anyhow::anyhow;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+const VAULT_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+/// Known plaintext used to check if a passphrase is correct.
+const CHECK_PLAINTEXT: &[u8] = b"dreamdeck_ssh vault";
+/// Associated data of the check entry, can't collide with secret ids which are prefixed.
+const CHECK_AAD: &[u8] = b"check";
+
+/// Argon2id parameters, stored in the vault so they can be raised in the future
+/// without breaking existing vaults.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct KdfParams {
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+impl Default for KdfParams {
+    /// OWASP recommended minimum for Argon2id.
+    fn default() -> Self {
+        Self {
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct EncryptedEntry {
+    /// base64 encoded 24 byte XChaCha20 nonce
+    nonce: String,
+    /// base64 encoded ciphertext including the Poly1305 tag
+    ciphertext: String,
+}
+
+/// On disk format of the vault.
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    version: u32,
+    kdf: KdfParams,
+    /// base64 encoded salt
+    salt: String,
+    check: EncryptedEntry,
+    entries: BTreeMap<String, EncryptedEntry>,
+}
+
+/// Why the vault couldn't be unlocked.
+#[derive(thiserror::Error, Debug)]
+pub enum VaultError {
+    #[error("Wrong vault passphrase")]
+    WrongPassphrase,
+    #[error("{0}")]
+    Other(#[from] anyhow::Error),
+}
+
+impl VaultError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            VaultError::WrongPassphrase => "wrong_passphrase",
+            VaultError::Other(_) => "error",
+        }
+    }
+}
+
+/// Encrypted store for SSH secrets.
+///
+/// Secrets are encrypted with XChaCha20-Poly1305 using a key derived from the
+/// master passphrase with Argon2id. The secret id is used as associated data,
+/// so entries can't be swapped around in the file.
+/// Decrypted secrets are only ever held in memory.
+pub struct Vault {
+    path: PathBuf,
+    file: VaultFile,
+    key: Option<Zeroizing<[u8; KEY_LEN]>>,
+}
+
+impl Vault {
+    /// Creates a new empty vault at `path` protected by `passphrase`.
+    /// The vault is unlocked afterwards.
+    pub fn create(path: &Path, passphrase: &str) -> anyhow::Result<Self> {
+        if path.exists() {
+            anyhow::bail!("Vault {} already exists", path.display());
+        }
+        if passphrase.is_empty() {
+            anyhow::bail!("Passphrase must not be empty");
+        }
+        let (file, key) = new_vault_file(passphrase)?;
+        let vault = Self {
+            path: path.to_path_buf(),
+            file,
+            key: Some(key),
+        };
+        vault.save()?;
+        Ok(vault)
+    }
+
+    /// Loads the vault at `path`. The vault is locked afterwards.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => anyhow::bail!("Failed to read vault {}: {}", path.display(), e),
+        };
+        let file: VaultFile = match serde_json::from_str(&content) {
+            Ok(file) => file,
+            Err(e) => anyhow::bail!("Failed to parse vault {}: {}", path.display(), e),
+        };
+        if file.version != VAULT_VERSION {
+            anyhow::bail!("Unsupported vault version {}", file.version);
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            key: None,
+        })
+    }
+
+    /// Unlocks the vault. Fails with [VaultError::WrongPassphrase] if `passphrase` is wrong.
+    pub fn unlock(&mut self, passphrase: &str) -> Result<(), VaultError> {
+        let salt = decode_base64(&self.file.salt)?;
+        let key = derive_key(passphrase, &salt, self.file.kdf)?;
+        match decrypt(&key, &self.file.check, CHECK_AAD) {
+            Ok(check) if check.as_slice() == CHECK_PLAINTEXT => {}
+            _ => return Err(VaultError::WrongPassphrase),
+        }
+        self.key = Some(key);
+        Ok(())
+    }
+
+    /// Forgets the derived key, so secrets can't be accessed until unlocked again.
+    pub fn lock(&mut self) {
+        self.key = None;
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Re-encrypts all secrets with a key derived from `new_passphrase` and a fresh salt.
+    pub fn change_passphrase(
+        &mut self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> anyhow::Result<()> {
+        if new_passphrase.is_empty() {
+            anyhow::bail!("Passphrase must not be empty");
+        }
+        self.unlock(old_passphrase)?;
+
+        let mut secrets: BTreeMap<String, Zeroizing<Vec<u8>>> = BTreeMap::new();
+        for id in self.file.entries.keys() {
+            secrets.insert(id.clone(), self.get_secret(id)?);
+        }
+        let (file, key) = new_vault_file(new_passphrase)?;
+        let mut entries: BTreeMap<String, EncryptedEntry> = BTreeMap::new();
+        for (id, secret) in &secrets {
+            entries.insert(id.clone(), encrypt(&key, secret, &entry_aad(id))?);
+        }
+
+        self.file = VaultFile { entries, ..file };
+        self.key = Some(key);
+        self.save()
+    }
+
+    /// Encrypts and stores `secret` under `id`, overwriting any existing secret.
+    pub fn set_secret(&mut self, id: &str, secret: &[u8]) -> anyhow::Result<()> {
+        let entry = encrypt(self.key()?, secret, &entry_aad(id))?;
+        self.file.entries.insert(id.to_string(), entry);
+        self.save()
+    }
+
+    /// Decrypts the secret stored under `id`.
+    pub fn get_secret(&self, id: &str) -> anyhow::Result<Zeroizing<Vec<u8>>> {
+        let Some(entry) = self.file.entries.get(id) else {
+            anyhow::bail!("No secret with id \"{}\" in vault", id);
+        };
+        decrypt(self.key()?, entry, &entry_aad(id))
+    }
+
+    /// Decrypts the secret stored under `id` as UTF-8 string.
+    pub fn get_secret_string(&self, id: &str) -> anyhow::Result<Zeroizing<String>> {
+        let secret = self.get_secret(id)?;
+        match std::str::from_utf8(&secret) {
+            Ok(secret) => Ok(Zeroizing::new(secret.to_string())),
+            Err(_) => anyhow::bail!("Secret \"{}\" is not valid UTF-8", id),
+        }
+    }
+
+    pub fn remove_secret(&mut self, id: &str) -> anyhow::Result<bool> {
+        if self.file.entries.remove(id).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn has_secret(&self, id: &str) -> bool {
+        self.file.entries.contains_key(id)
+    }
+
+    /// Ids of all stored secrets. These are stored in clear.
+    pub fn secret_ids(&self) -> Vec<String> {
+        self.file.entries.keys().cloned().collect()
+    }
+
+    fn key(&self) -> anyhow::Result<&[u8; KEY_LEN]> {
+        match &self.key {
+            Some(key) => Ok(&**key),
+            None => anyhow::bail!("Vault is locked"),
+        }
+    }
+
+    /// Writes the vault atomically and only readable by the current user.
+    fn save(&self) -> anyhow::Result<()> {
+        let content = serde_json::to_string_pretty(&self.file)?;
+        let tmp_path = self.path.with_extension("tmp");
+        let mut tmp_file = match fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)
+        {
+            Ok(tmp_file) => tmp_file,
+            Err(e) => anyhow::bail!("Failed to write vault {}: {}", tmp_path.display(), e),
+        };
+        tmp_file.write_all(content.as_bytes())?;
+        tmp_file.sync_all()?;
+        if let Err(e) = fs::rename(&tmp_path, &self.path) {
+            anyhow::bail!("Failed to write vault {}: {}", self.path.display(), e);
+        }
+        Ok(())
+    }
+}
+
+/// Creates an empty vault file with a fresh salt and returns it with the derived key.
+fn new_vault_file(passphrase: &str) -> anyhow::Result<(VaultFile, Zeroizing<[u8; KEY_LEN]>)> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let kdf = KdfParams::default();
+    let key = derive_key(passphrase, &salt, kdf)?;
+    let check = encrypt(&key, CHECK_PLAINTEXT, CHECK_AAD)?;
+    Ok((
+        VaultFile {
+            version: VAULT_VERSION,
+            kdf,
+            salt: STANDARD.encode(salt),
+            check,
+            entries: BTreeMap::new(),
+        },
+        key,
+    ))
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    kdf: KdfParams,
+) -> anyhow::Result<Zeroizing<[u8; KEY_LEN]>> {
+    let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(KEY_LEN))
+        .map_err(|e| anyhow!("Invalid vault kdf parameters: {}", e))?;
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key[..])
+        .map_err(|e| anyhow!("Failed to derive vault key: {}", e))?;
+    Ok(key)
+}
+
+fn entry_aad(id: &str) -> Vec<u8> {
+    format!("secret:{}", id).into_bytes()
+}
+
+fn encrypt(key: &[u8; KEY_LEN], plaintext: &[u8], aad: &[u8]) -> anyhow::Result<EncryptedEntry> {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow!("Failed to encrypt secret"))?;
+    Ok(EncryptedEntry {
+        nonce: STANDARD.encode(nonce),
+        ciphertext: STANDARD.encode(ciphertext),
+    })
+}
+
+fn decrypt(
+    key: &[u8; KEY_LEN],
+    entry: &EncryptedEntry,
+    aad: &[u8],
+) -> anyhow::Result<Zeroizing<Vec<u8>>> {
+    let nonce = decode_base64(&entry.nonce)?;
+    if nonce.len() != 24 {
+        anyhow::bail!("Invalid nonce in vault");
+    }
+    let ciphertext = decode_base64(&entry.ciphertext)?;
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    match cipher.decrypt(
+        XNonce::from_slice(&nonce),
+        Payload {
+            msg: &ciphertext,
+            aad,
+        },
+    ) {
+        Ok(plaintext) => Ok(Zeroizing::new(plaintext)),
+        // Either the passphrase is wrong or the vault was tampered with
+        Err(_) => anyhow::bail!("Failed to decrypt vault entry"),
+    }
+}
+
+fn decode_base64(data: &str) -> anyhow::Result<Vec<u8>> {
+    match STANDARD.decode(data) {
+        Ok(data) => Ok(data),
+        Err(e) => anyhow::bail!("Invalid base64 in vault: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault_path(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "dreamdeck_ssh_vault_{}_{}",
+            test,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("vault.json")
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = vault_path("round_trip");
+        let mut vault = Vault::create(&path, "passphrase").unwrap();
+        vault.set_secret("key", b"secret").unwrap();
+
+        let mut vault = Vault::open(&path).unwrap();
+        assert!(!vault.is_unlocked());
+        assert!(vault.has_secret("key"));
+        assert!(vault.get_secret("key").is_err());
+        vault.unlock("passphrase").unwrap();
+        assert_eq!(vault.get_secret("key").unwrap().as_slice(), b"secret");
+        assert!(!fs::read_to_string(&path).unwrap().contains("secret\""));
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let path = vault_path("wrong_passphrase");
+        Vault::create(&path, "passphrase").unwrap();
+
+        let mut vault = Vault::open(&path).unwrap();
+        let error = vault.unlock("wrong").unwrap_err();
+        assert!(matches!(error, VaultError::WrongPassphrase));
+        assert_eq!(error.kind(), "wrong_passphrase");
+        assert!(!vault.is_unlocked());
+    }
+
+    #[test]
+    fn change_passphrase() {
+        let path = vault_path("change_passphrase");
+        let mut vault = Vault::create(&path, "old").unwrap();
+        vault.set_secret("password", b"hunter2").unwrap();
+        assert!(vault.change_passphrase("wrong", "new").is_err());
+        vault.change_passphrase("old", "new").unwrap();
+
+        let mut vault = Vault::open(&path).unwrap();
+        assert!(matches!(
+            vault.unlock("old"),
+            Err(VaultError::WrongPassphrase)
+        ));
+        vault.unlock("new").unwrap();
+        assert_eq!(vault.get_secret("password").unwrap().as_slice(), b"hunter2");
+    }
+
+    #[test]
+    fn entries_cant_be_swapped() {
+        let path = vault_path("entries_cant_be_swapped");
+        let mut vault = Vault::create(&path, "passphrase").unwrap();
+        vault.set_secret("a", b"first").unwrap();
+        vault.set_secret("b", b"second").unwrap();
+        let a = vault.file.entries["a"].clone();
+        vault.file.entries.insert("b".to_string(), a);
+        assert!(vault.get_secret("b").is_err());
+    }
+}
@@ -6,6 +6,10 @@ class_name SSHClientWrapper
 ## Emitted when the client config was updated.
 ## Note: not emitted on [method deserialize].
 signal client_updated
+## Emitted for every log record of the client, see [signal SSHClient.log_record].
+signal log_record(record: Dictionary)
+## Emitted when the forwarded agent is asked to sign, see [signal SSHClient.agent_sign_requested].
+signal agent_sign_requested(request_id: int, fingerprint: String, comment: String)
 
 ## All available methods to check the server against.
 ## [code]KNOWN_HOSTS[/code] uses the default known hosts file.
@@ -14,6 +18,14 @@ enum ServerCheckMethod {
 	KNOWN_HOSTS,
 }
 
+## Which agent is forwarded to the server, see [method SSHClient.forward_local_agent].
+## [code]CLIENT_KEY[/code] forwards only the client's own key instead of all keys of the local agent.
+enum AgentForwarding {
+	OFF,
+	LOCAL_AGENT,
+	CLIENT_KEY,
+}
+
 ## User facing name of the client.
 var name: String:
 	set(value):
@@ -46,24 +58,19 @@ var port: int:
 	set(value):
 		_client.port = value
 		_config.get_object("port").set_value(value)
-## Debug state.
-var debug: bool:
+## Minimum level of log records, see [method SSHClient.set_log_level].
+var log_level: String:
 	get:
-		return _client.get_debug()
+		return _client.get_log_level()
 	set(value):
-		_client.set_debug(value)
-		_config.get_object("debug").set_value(value)
+		_client.set_log_level(value)
+		_config.get_object("log_level").set_value(value)
 ## The [SSHKey]'s uuid.
 var key_uuid: String:
 	get:
 		return _key.uuid
 	set(value):
-		var key: SSHKey = (
-			PluginCoordinator
-			. get_plugin_loader("SSH")
-			. get_controller("SSHController")
-			. get_key(value)
-		)
+		var key: SSHKey = _get_controller().get_key(value)
 		if key:
 			_key = key
 			_config.get_object("key_uuid").set_value(value)
@@ -78,6 +85,56 @@ var server_check_method: ServerCheckMethod:
 				_client.set_server_check_method("no_check")
 			ServerCheckMethod.KNOWN_HOSTS:
 				_client.set_server_check_method("known_hosts_file")
+## Public key of a CA whose host certificates are trusted for this client, empty to only trust the
+## [code]@cert-authority[/code] lines of the known hosts. See [method SSHClient.add_host_certificate_authority].
+var host_ca_key: String:
+	set(value):
+		host_ca_key = value
+		_config.get_object("host_ca_key").set_value(value)
+		_client.clear_host_certificate_authorities()
+		if value and not _client.add_host_certificate_authority(value, ""):
+			push_error("Invalid host CA key of client %s" % name)
+
+## MAC address to send a Wake-on-LAN packet to before connecting, empty to not wake the server.
+var wake_mac: String:
+	set(value):
+		wake_mac = value
+		_config.get_object("wake_mac").set_value(value)
+		_client.set_wake_on_lan(wake_mac, wake_broadcast)
+## Broadcast address the Wake-on-LAN packet is sent to, see [method SSHClient.set_wake_on_lan].
+var wake_broadcast: String:
+	set(value):
+		wake_broadcast = value
+		_config.get_object("wake_broadcast").set_value(value)
+		_client.set_wake_on_lan(wake_mac, wake_broadcast)
+## Comma separated ports to knock before connecting, see [method SSHClient.set_port_knock].
+var port_knock: String:
+	set(value):
+		port_knock = value
+		_config.get_object("port_knock").set_value(value)
+		var sequence: PackedStringArray = []
+		for knock in value.split(",", false):
+			sequence.append(knock.strip_edges())
+		_client.set_port_knock(sequence)
+
+## Agent forwarded to the server when running commands with it, see [method SSHController.exec_with_agent].
+var agent_forwarding: AgentForwarding:
+	set(value):
+		agent_forwarding = value
+		_config.get_object("agent_forwarding").set_value(value)
+		if _key:
+			apply_key_to_client()
+## Whether the sudo password is kept in the SSH vault once entered, see [method get_sudo_password_id].
+var remember_sudo_password: bool:
+	set(value):
+		remember_sudo_password = value
+		_config.get_object("remember_sudo_password").set_value(value)
+## Whether every signature of the forwarded agent has to be confirmed, see [method SSHClient.set_agent_confirmation].
+var confirm_agent_signatures: bool:
+	set(value):
+		confirm_agent_signatures = value
+		_config.get_object("confirm_agent_signatures").set_value(value)
+		_client.set_agent_confirmation(value)
 
 # Internal [SSHKey], use [member key_uuid] to set this.
 var _key: SSHKey:
@@ -94,6 +151,13 @@ var _client: SSHClient = SSHClient.new()
 var _config: Config = _generate_default_client_config()
 
 
+func _init() -> void:
+	_client.log_record.connect(log_record.emit)
+	_client.agent_sign_requested.connect(agent_sign_requested.emit)
+	# Keys set directly are wiped after authentication, so they're set again for reconnects.
+	_client.credentials_required.connect(apply_key_to_client)
+
+
 ## Only use this to call functions on the client.
 ## To configure the client, use the properties of this wrapper.
 func get_client() -> SSHClient:
@@ -127,11 +191,22 @@ func deserialize(dict: Dictionary) -> void:
 	user = dict.user
 	ip = dict.ip
 	port = dict.port
-	debug = dict.debug
+	if dict.has("log_level"):
+		log_level = dict.log_level
+	else:
+		# Configs from before log levels only had a debug toggle
+		log_level = "debug" if dict.get("debug", false) else "warn"
 	uuid = dict.uuid
 	if dict.has("key_uuid") and dict.key_uuid:
 		key_uuid = dict.key_uuid
 	server_check_method = dict.server_check_method as ServerCheckMethod
+	host_ca_key = dict.get("host_ca_key", "")
+	wake_broadcast = dict.get("wake_broadcast", "")
+	wake_mac = dict.get("wake_mac", "")
+	port_knock = dict.get("port_knock", "")
+	agent_forwarding = dict.get("agent_forwarding", AgentForwarding.OFF) as AgentForwarding
+	confirm_agent_signatures = dict.get("confirm_agent_signatures", false)
+	remember_sudo_password = dict.get("remember_sudo_password", false)
 
 
 ## Generate a new uuid for this object.
@@ -145,13 +220,69 @@ func update_keys(keys_dict: Dictionary) -> void:
 	_config.get_object("key_uuid").set_dict(keys_dict)
 
 
+## Records all commands of this client in [param audit_log], identified by [member uuid].
+## Pass null to stop recording.
+func set_audit_log(audit_log: SSHAuditLog) -> void:
+	_client.set_audit_log(audit_log, uuid)
+
+
+## Id of the client's sudo password in the SSH vault, see [member remember_sudo_password].
+func get_sudo_password_id() -> String:
+	return "sudo_password:%s" % uuid
+
+
+## Applies the secrets kept in the SSH vault to the internal ssh client.
+## Does nothing while the vault is locked, so this is called again once it's unlocked.
+func apply_vault_secrets() -> void:
+	var vault: SSHVault = _get_controller().get_vault()
+	if not vault.is_unlocked():
+		return
+
+	if _key:
+		apply_key_to_client()
+	if remember_sudo_password and vault.has_secret(get_sudo_password_id()):
+		_client.set_sudo_password_from_vault(vault, get_sudo_password_id())
+
+
 ## Applies the currently set key to the internal ssh client.
+## Keys in the SSH vault are only applied while it's unlocked.
 func apply_key_to_client() -> void:
+	var vault: SSHVault = _get_controller().get_vault()
 	match _key.type:
 		SSHKey.KeyTypes.NEW_KEY:
-			_client.set_auth_key(Marshalls.base64_to_utf8(_key.key_data), "")
+			if not _key.in_vault:
+				_client.set_auth_key(Marshalls.base64_to_utf8(_key.key_data), "")
+			elif vault.is_unlocked():
+				_client.set_auth_key_from_vault(vault, _key.get_vault_id(), "")
 		SSHKey.KeyTypes.EXISTING_KEY:
-			_client.set_auth_key_file(_key.key_path, "")
+			if _key.cert_path or FileAccess.file_exists(_key.key_path + "-cert.pub"):
+				_client.set_auth_certificate(_key.key_path, _key.cert_path, "")
+			else:
+				_client.set_auth_key_file(_key.key_path, "")
+
+	match agent_forwarding:
+		AgentForwarding.OFF:
+			_client.disable_agent_forwarding()
+		AgentForwarding.LOCAL_AGENT:
+			_client.forward_local_agent(false)
+		AgentForwarding.CLIENT_KEY:
+			if _key.type == SSHKey.KeyTypes.NEW_KEY and _key.in_vault:
+				if (
+					vault.is_unlocked()
+					and not _client.forward_agent_key_from_vault(vault, _key.get_vault_id(), false)
+				):
+					push_error("Couldn't forward key %s of client %s" % [_key.name, name])
+			elif _key.type == SSHKey.KeyTypes.NEW_KEY:
+				_client.forward_agent_keys([Marshalls.base64_to_utf8(_key.key_data)], false)
+			else:
+				var key_data: String = FileAccess.get_file_as_string(_key.key_path)
+				if not key_data or not _client.forward_agent_keys([key_data], false):
+					push_error("Couldn't forward key %s of client %s" % [_key.name, name])
+
+
+# Returns the [SSHController] owning the keys and the vault.
+func _get_controller() -> SSHController:
+	return PluginCoordinator.get_plugin_loader("SSH").get_controller("SSHController")
 
 
 # Generates a [Config] with all default objects configured.
@@ -170,7 +301,56 @@ func _generate_default_client_config() -> Config:
 		ServerCheckMethod,
 		"Whether the server should be checked against the known hosts"
 	)
-	client_config.add_bool("Debug", "debug", false)
+	client_config.add_string(
+		"Host CA key",
+		"host_ca_key",
+		"",
+		"CA (ssh-ed25519 AAAA...) trusted to sign host certificates, besides @cert-authority lines of known hosts"
+	)
+	client_config.add_string_array(
+		"Log level",
+		"log_level",
+		"warn",
+		["trace", "debug", "info", "warn", "error", "off"],
+		"Minimum level of diagnostics the client reports. Trace includes command output."
+	)
+	client_config.add_string(
+		"Wake-on-LAN MAC",
+		"wake_mac",
+		"",
+		"Wakes the server before connecting, e.g. 00:11:22:33:44:55. Leave empty to not wake it."
+	)
+	client_config.add_string(
+		"Wake-on-LAN broadcast",
+		"wake_broadcast",
+		"",
+		"Address the wake packet is sent to, e.g. 192.168.1.255. Defaults to 255.255.255.255."
+	)
+	client_config.add_string(
+		"Port knock sequence",
+		"port_knock",
+		"",
+		"Ports knocked before connecting, e.g. 7000, 8000/udp, 9000. Leave empty to not knock."
+	)
+	client_config.add_dict(
+		"Agent forwarding",
+		"agent_forwarding",
+		AgentForwarding.OFF,
+		AgentForwarding,
+		"Agent forwarded to the server for commands that need it, e.g. a git pull with your keys"
+	)
+	client_config.add_bool(
+		"Confirm agent signatures",
+		"confirm_agent_signatures",
+		false,
+		"Asks before the server may use the forwarded agent to sign"
+	)
+	client_config.add_bool(
+		"Remember sudo password",
+		"remember_sudo_password",
+		false,
+		"Keeps the sudo password in the SSH vault once entered instead of asking every session"
+	)
 	return client_config
 
 
@@ -181,6 +361,8 @@ class SSHClientsEditor:
 	signal client_added(client: SSHClientWrapper)
 	## Emitted when [param key] is supposed to be deleted.
 	signal client_deleted(client: SSHClientWrapper)
+	## Emitted when the hosts of the OpenSSH client config are supposed to be imported.
+	signal ssh_config_import_requested
 
 	const SSH_THEME = preload("res://plugins/ssh/assets/ssh_theme.tres")
 
@@ -206,6 +388,11 @@ class SSHClientsEditor:
 		add_client_button.pressed.connect(_on_add_client_button_pressed)
 		add_child(add_client_button)
 
+		var import_button: Button = Button.new()
+		import_button.text = "Import from SSH config"
+		import_button.pressed.connect(ssh_config_import_requested.emit)
+		add_child(import_button)
+
 	## Populate clients list with [param clients].
 	func set_clients(clients: Array[SSHClientWrapper]) -> void:
 		for client_entry in _client_entries.get_children():
@@ -304,6 +491,7 @@ class ClientEditor:
 	var _client: SSHClientWrapper
 	var _client_editor: Config.ConfigEditor = null
 	var _add_key_button: Button
+	var _diagnose_button: Button
 
 	func _init(client: SSHClientWrapper) -> void:
 		if not client:
@@ -332,6 +520,11 @@ class ClientEditor:
 		_add_key_button.pressed.connect(_on_add_key_button_pressed)
 		add_child(_add_key_button)
 
+		_diagnose_button = Button.new()
+		_diagnose_button.text = "Diagnose connection"
+		_diagnose_button.pressed.connect(_on_diagnose_button_pressed)
+		add_child(_diagnose_button)
+
 	## Called on confirm button pressed.
 	func confirm() -> bool:
 		var abort: bool = false
@@ -372,11 +565,34 @@ class ClientEditor:
 		if not ret:
 			return
 
-		if not _client.get_client().add_key_to_server(pw_string):
+		if not _client.get_client().add_key_to_server(pw_string, ""):
 			_add_key_button.modulate = Color.RED
 		else:
 			_add_key_button.modulate = Color.WHITE
 
+	func _on_diagnose_button_pressed() -> void:
+		if not confirm():
+			return
+
+		var lines: PackedStringArray = []
+		for step in _client.get_client().diagnose():
+			match step.status:
+				"ok":
+					lines.append("[OK] %s: %s" % [step.step, step.message])
+				"failed":
+					lines.append("[FAILED] %s: %s" % [step.step, step.message])
+				_:
+					lines.append("[SKIPPED] %s" % step.step)
+
+		var report_dialog: AcceptDialog = AcceptDialog.new()
+		report_dialog.title = "Connection diagnosis"
+		report_dialog.dialog_text = "\n".join(lines)
+		report_dialog.confirmed.connect(report_dialog.queue_free)
+		report_dialog.canceled.connect(report_dialog.queue_free)
+		add_child(report_dialog)
+		report_dialog.initial_position = Window.WINDOW_INITIAL_POSITION_CENTER_PRIMARY_SCREEN
+		report_dialog.show()
+
 	func _on_pw_edit_text_submitted(_text: String) -> void:
 		confirm_dialog_closed.emit(true)
 
@@ -1,13 +1,35 @@
 class_name SSHController
 extends PluginControllerBase
 
+## Emitted for every log record of any client, see [signal SSHClient.log_record].
+signal client_log_record(client_uuid: String, record: Dictionary)
+## Emitted when a monitored client's server goes up or down, see [method monitor_client].
+signal client_state_changed(client_uuid: String, up: bool, status: Dictionary)
+## Emitted when a scheduled command finished, see [method schedule_command].
+signal scheduled_job_finished(job_id: String, result: Dictionary)
+## Emitted when a run of a scheduled command was skipped, see [signal SSHScheduler.job_skipped].
+signal scheduled_job_skipped(job_id: String, reason: String, due: int)
+## Emitted when a command of [method exec_with_agent] finished, [param output] is null on failure.
+signal agent_exec_finished(client_uuid: String, cmd: String, output: Variant)
+## Emitted when the SSH vault was unlocked and its secrets were applied, see [method unlock_vault].
+signal vault_unlocked
+
 const PLUGIN_NAME = "SSH"
 
 var _thread_pool: Array[Thread] = []
 var _clients: Array[SSHClientWrapper] = []
 var _keys: Array[SSHKey] = []
+var _audit_log: SSHAuditLog = null
+var _host_monitor: SSHHostMonitor = SSHHostMonitor.new()
+var _scheduler: SSHScheduler = SSHScheduler.new()
+var _vault: SSHVault = SSHVault.new()
+# Scheduled commands by job id, see [method schedule_command]
+var _scheduled_commands: Dictionary = {}
 @onready var _keys_conf_path: String = conf_dir.path_join("keys.json")
 @onready var _clients_conf_path: String = conf_dir.path_join("clients.json")
+@onready var _scheduler_state_path: String = conf_dir.path_join("scheduler.json")
+@onready var _scheduled_commands_conf_path: String = conf_dir.path_join("scheduled_commands.json")
+@onready var _vault_path: String = conf_dir.path_join("vault.json")
 
 
 func _init() -> void:
@@ -15,8 +37,22 @@ func _init() -> void:
 
 
 func _ready() -> void:
+	if FileAccess.file_exists(_vault_path):
+		var err: Variant = _vault.open(_vault_path)
+		if err:
+			push_error("Couldn't open the SSH vault: %s" % err)
 	load_keys()
 	load_clients()
+	if _is_vault_used():
+		prompt_vault_unlock()
+	_host_monitor.host_state_changed.connect(_on_host_state_changed)
+	add_child(_host_monitor)
+	_scheduler.set_state_file(_scheduler_state_path)
+	_scheduler.job_finished.connect(scheduled_job_finished.emit)
+	_scheduler.job_skipped.connect(scheduled_job_skipped.emit)
+	add_child(_scheduler)
+	load_scheduled_commands()
+	_scheduler.start()
 
 
 func _process(_delta) -> void:
@@ -37,6 +73,55 @@ func get_clients() -> Dictionary:
 	return ret
 
 
+## Returns the session statistics of all clients, e.g. for a status panel.[br]
+## Layout: [code]{client.uuid: stats}[/code], see [method SSHClient.get_session_stats].
+func get_clients_session_stats() -> Dictionary:
+	var ret: Dictionary = {}
+	for client in _clients:
+		ret[client.uuid] = client.get_client().get_session_stats()
+
+	return ret
+
+
+## Returns the vault keys and sudo passwords are stored in, it may be locked.
+func get_vault() -> SSHVault:
+	return _vault
+
+
+## Unlocks the SSH vault with [param passphrase], it's created on first use.
+## Afterwards the secrets stored in it are applied to all clients.
+## Returns null on success, otherwise a dictionary with the error's [code]kind[/code] and
+## [code]message[/code], see [method SSHVault.unlock].
+func unlock_vault(passphrase: String) -> Variant:
+	if FileAccess.file_exists(_vault_path):
+		var unlock_err: Variant = _vault.unlock(passphrase)
+		if unlock_err:
+			return unlock_err
+	else:
+		var create_err: Variant = _vault.create(_vault_path, passphrase)
+		if create_err:
+			return {"kind": "error", "message": create_err}
+
+	for client in _clients:
+		client.apply_vault_secrets()
+	vault_unlocked.emit()
+	return null
+
+
+## Asks the user for the passphrase of the SSH vault and unlocks it, see [method unlock_vault].
+## If [param wrong_passphrase] the user is told the last one was wrong.
+func prompt_vault_unlock(wrong_passphrase: bool = false) -> void:
+	if _vault.is_unlocked():
+		return
+
+	var text: String = "Enter the passphrase of the SSH vault"
+	if wrong_passphrase:
+		text = "Wrong passphrase, try again"
+	elif not FileAccess.file_exists(_vault_path):
+		text = "Choose a passphrase for the new SSH vault"
+	_prompt_password("SSH vault", text, _on_vault_passphrase_entered)
+
+
 ## Adds a key to the keys list and also saves to disk.[br]
 ## Also updates the keys editor if it is being used
 func add_key(new_key: SSHKey) -> void:
@@ -49,6 +134,8 @@ func add_key(new_key: SSHKey) -> void:
 ## Removes a key from the keys list and saves to disk.
 func remove_key(key: SSHKey) -> void:
 	_keys.erase(key)
+	if key.in_vault and _vault.is_unlocked():
+		_vault.remove_secret(key.get_vault_id())
 	save_keys()
 
 
@@ -119,6 +206,9 @@ func load_clients() -> void:
 		new_client.update_keys(get_keys_dict())
 		new_client.client_updated.connect(save_clients)
 		new_client.deserialize(client_dict)
+		new_client.set_audit_log(_audit_log)
+		new_client.log_record.connect(_on_client_log_record.bind(new_client))
+		new_client.agent_sign_requested.connect(_on_agent_sign_requested.bind(new_client))
 		_clients.append(new_client)
 
 	update_loader_clients()
@@ -134,12 +224,268 @@ func save_clients() -> void:
 
 ## Adds a new client with the [param client_config].
 func add_client(client: SSHClientWrapper) -> void:
+	client.set_audit_log(_audit_log)
+	client.log_record.connect(_on_client_log_record.bind(client))
+	client.agent_sign_requested.connect(_on_agent_sign_requested.bind(client))
 	_clients.append(client)
 	client.client_updated.connect(save_clients)
 	update_loader_clients()
 	save_clients()
 
 
+## Imports all hosts of the OpenSSH client config at [param config_path] as new clients.
+## If [param config_path] is empty [code]~/.ssh/config[/code] is used.[br]
+## Hosts for which a client with the same name already exists are skipped.
+## The first identity file of a host is added as an existing key, unless a key with
+## the same path already exists. Returns the amount of imported clients.
+func import_ssh_config(config_path: String = "") -> int:
+	var imported: int = 0
+	for host in SSHClient.list_ssh_config_hosts(config_path):
+		if get_client(host):
+			continue
+
+		var host_config: Variant = SSHClient.resolve_ssh_config_host(config_path, host)
+		if not host_config:
+			continue
+		if host_config.port == null:
+			push_error("Skipping ssh config host %s: Invalid port" % host)
+			continue
+		if host_config.proxy_jump:
+			push_warning("Host %s uses ProxyJump, which is not supported" % host)
+
+		var new_client: SSHClientWrapper = SSHClientWrapper.new()
+		new_client.gen_uuid()
+		new_client.update_keys(get_keys_dict())
+		new_client.name = host
+		new_client.ip = host_config.hostname
+		new_client.user = host_config.user
+		new_client.port = host_config.port
+		new_client.server_check_method = (
+			SSHClientWrapper.ServerCheckMethod.KNOWN_HOSTS
+			if host_config.strict_host_key_checking
+			else SSHClientWrapper.ServerCheckMethod.NO_CHECK
+		)
+		for identity_file in host_config.identity_files:
+			if not FileAccess.file_exists(identity_file):
+				continue
+			new_client.key_uuid = _get_or_add_key_for_path(identity_file).uuid
+			break
+
+		add_client(new_client)
+		imported += 1
+
+	return imported
+
+
+## Records all commands executed by any client in [code]audit.log[/code] in the config dir.[br]
+## The log is rotated once it reaches [param max_file_size] bytes, keeping [param max_files] old logs.
+## If [param capture_output_bytes] is greater than 0, up to that many bytes of each command's
+## stdout and stderr are stored as well.
+func enable_audit_log(
+	max_file_size: int = 1024 * 1024, max_files: int = 5, capture_output_bytes: int = 0
+) -> bool:
+	var audit_log: SSHAuditLog = SSHAuditLog.new()
+	var err: Variant = audit_log.open(
+		conf_dir.path_join("audit.log"), max_file_size, max_files
+	)
+	if err:
+		push_error("Couldn't open audit log: %s" % err)
+		return false
+
+	audit_log.set_output_capture(capture_output_bytes)
+	_audit_log = audit_log
+	for client in _clients:
+		client.set_audit_log(_audit_log)
+
+	return true
+
+
+## Stops recording commands in the audit log.
+func disable_audit_log() -> void:
+	_audit_log = null
+	for client in _clients:
+		client.set_audit_log(null)
+
+
+## Returns up to [param limit] recorded commands, newest first.
+## See [method SSHAuditLog.query] for the layout of the entries and the [param filter] keys.
+func get_audit_history(limit: int = 50, filter: Dictionary = {}) -> Array:
+	if not _audit_log:
+		return []
+
+	return _audit_log.query(limit, filter)
+
+
+## Periodically checks whether the server of the client identified by [param client_uuid] is up.
+## Changes are emitted as [signal client_state_changed], the current state is returned by
+## [method get_client_status]. Probing starts with the first monitored client.
+func monitor_client(client_uuid: String) -> bool:
+	var ssh_client: SSHClientWrapper = get_client(client_uuid)
+	if not ssh_client:
+		push_error("Couldn't monitor SSHClient %s: not found" % client_uuid)
+		return false
+
+	if not _host_monitor.add_client(ssh_client.uuid, ssh_client.get_client()):
+		return false
+
+	if not _host_monitor.is_running():
+		_host_monitor.start()
+
+	return true
+
+
+## Stops monitoring the client identified by [param client_uuid].
+func unmonitor_client(client_uuid: String) -> void:
+	var ssh_client: SSHClientWrapper = get_client(client_uuid)
+	if ssh_client:
+		client_uuid = ssh_client.uuid
+	_host_monitor.remove_host(client_uuid)
+
+
+## Returns the health of a monitored client, see [method SSHHostMonitor.get_status].
+## Returns null if the client isn't monitored.
+func get_client_status(client_uuid: String) -> Variant:
+	var ssh_client: SSHClientWrapper = get_client(client_uuid)
+	if not ssh_client:
+		return null
+
+	return _host_monitor.get_status(ssh_client.uuid)
+
+
+## Creates a started [SSHCommandWatcher] running [param cmd] on the client identified by
+## [param client_uuid] every [param interval] seconds. Connect to its
+## [signal SSHCommandWatcher.value_changed] signal and free it once it isn't needed anymore.[br]
+## [param mode], [param regex] and [param json_path] set the respective watcher properties.
+func watch_command(
+	client_uuid: String,
+	cmd: String,
+	interval: float = 5.0,
+	mode: String = "output",
+	regex: String = "",
+	json_path: String = ""
+) -> SSHCommandWatcher:
+	var ssh_client: SSHClientWrapper = get_client(client_uuid)
+	if not ssh_client:
+		push_error("Couldn't watch %s: SSHClient %s not found" % [cmd, client_uuid])
+		return null
+
+	var watcher: SSHCommandWatcher = SSHCommandWatcher.new()
+	watcher.set_client(ssh_client.get_client())
+	watcher.command = cmd
+	watcher.interval = interval
+	watcher.mode = mode
+	watcher.regex = regex
+	watcher.json_path = json_path
+	if not watcher.start():
+		watcher.free()
+		return null
+
+	add_child(watcher)
+	return watcher
+
+
+## Creates a started [SSHLogTail] streaming the output of [param cmd] on the client identified by
+## [param client_uuid], e.g. [code]tail -F /var/log/syslog[/code]. Register patterns with
+## [method SSHLogTail.add_pattern] and free it once it isn't needed anymore.
+func tail_command(client_uuid: String, cmd: String) -> SSHLogTail:
+	var ssh_client: SSHClientWrapper = get_client(client_uuid)
+	if not ssh_client:
+		push_error("Couldn't tail %s: SSHClient %s not found" % [cmd, client_uuid])
+		return null
+
+	var tail: SSHLogTail = SSHLogTail.new()
+	tail.set_client(ssh_client.get_client())
+	tail.command = cmd
+	if not tail.start():
+		tail.free()
+		return null
+
+	add_child(tail)
+	return tail
+
+
+## Creates an opened [SSHTerminal] with an interactive shell on the client identified by
+## [param client_uuid]. If [param recording_path] isn't empty the session is recorded there,
+## see [method SSHTerminal.start_recording]. Free it once it isn't needed anymore.
+func open_terminal(
+	client_uuid: String, cols: int = 80, rows: int = 24, recording_path: String = ""
+) -> SSHTerminal:
+	var ssh_client: SSHClientWrapper = get_client(client_uuid)
+	if not ssh_client:
+		push_error("Couldn't open terminal: SSHClient %s not found" % client_uuid)
+		return null
+
+	var terminal: SSHTerminal = SSHTerminal.new()
+	terminal.set_client(ssh_client.get_client())
+	terminal.cols = cols
+	terminal.rows = rows
+	if (
+		not recording_path.is_empty()
+		and not terminal.start_recording(recording_path, false, ssh_client.name)
+	):
+		terminal.free()
+		return null
+
+	if not terminal.open():
+		terminal.free()
+		return null
+
+	add_child(terminal)
+	return terminal
+
+
+## Runs [param cmd] on the client identified by [param client_uuid] on a [param schedule],
+## replacing the scheduled command with the same [param job_id]. Results are emitted as
+## [signal scheduled_job_finished]. See [method SSHScheduler.add_job] for the schedule format
+## and [param missed_policy]. Scheduled commands and their last run are saved to disk,
+## so they keep running after a restart.
+func schedule_command(
+	job_id: String,
+	client_uuid: String,
+	cmd: String,
+	schedule: String,
+	missed_policy: String = "skip"
+) -> bool:
+	if not _add_scheduled_command(job_id, client_uuid, cmd, schedule, missed_policy):
+		return false
+
+	save_scheduled_commands()
+	return true
+
+
+## Removes the scheduled command with [param job_id]. A running run isn't stopped.
+func unschedule_command(job_id: String) -> bool:
+	_scheduled_commands.erase(job_id)
+	save_scheduled_commands()
+	return _scheduler.remove_job(job_id)
+
+
+## Returns all scheduled commands, see [method SSHScheduler.get_job].
+func get_scheduled_commands() -> Array:
+	return _scheduler.get_jobs()
+
+
+## Loads the scheduled commands from disk and adds them to the scheduler.
+func load_scheduled_commands() -> void:
+	var loaded_commands_config: Variant = ConfLib.load_config(_scheduled_commands_conf_path)
+	if loaded_commands_config is not Array:
+		return
+
+	for command in loaded_commands_config:
+		_add_scheduled_command(
+			command.get("id", ""),
+			command.get("client_uuid", ""),
+			command.get("command", ""),
+			command.get("schedule", ""),
+			command.get("missed_policy", "skip")
+		)
+
+
+## Saves the scheduled commands to disk.
+func save_scheduled_commands() -> void:
+	ConfLib.save_config(_scheduled_commands_conf_path, _scheduled_commands.values())
+
+
 ## Updates the action in the loader so it always shows all available clients.
 func update_loader_clients() -> void:
 	var clients: Dictionary = {}
@@ -182,19 +528,42 @@ func get_client(client_uuid: String) -> SSHClientWrapper:
 ## Removes a SSH client identified by [param client_uuid].
 func remove_client(client: SSHClientWrapper) -> void:
 	_clients.erase(client)
+	if _vault.is_unlocked() and _vault.has_secret(client.get_sudo_password_id()):
+		_vault.remove_secret(client.get_sudo_password_id())
+	_host_monitor.remove_host(client.uuid)
+	for job_id in _scheduled_commands.keys():
+		if _scheduled_commands[job_id].client_uuid == client.uuid:
+			unschedule_command(job_id)
 	save_clients()
 
 
 ## Executes the [param cmd] string on client, which is identified by [param client_uuid].
+## [param shell] is the shell the command is written for, see [method SSHClient.exec_in_shell].
+## If [param elevated] the command is run with sudo, see [method SSHClient.exec_blocking_elevated].
 ## This operation is done asynchronously to not block the main thread.
-func exec_on_client(blocking: bool, client_uuid: String, cmd: String) -> bool:
+func exec_on_client(
+	blocking: bool,
+	client_uuid: String,
+	cmd: String,
+	shell: String = "default",
+	elevated: bool = false
+) -> bool:
 	var ssh_client: SSHClientWrapper = get_client(client_uuid)
 	if not ssh_client:
 		push_error("Couldn't execute %s: SSHClient %s not found" % [cmd, client_uuid])
 		return false
 
+	if elevated:
+		if blocking:
+			return _exec_elevated(ssh_client, cmd, shell)
+
+		var elevated_thread: Thread = Thread.new()
+		elevated_thread.start(_exec_elevated.bind(ssh_client, cmd, shell))
+		_thread_pool.append(elevated_thread)
+		return true
+
 	if blocking:
-		var output: Variant = ssh_client.get_client().exec_blocking(cmd)
+		var output: Variant = ssh_client.get_client().exec_blocking_in_shell(cmd, shell)
 		if not output:
 			return false
 
@@ -204,17 +573,209 @@ func exec_on_client(blocking: bool, client_uuid: String, cmd: String) -> bool:
 	# it isn't actually non blocking, it just doesn't block when the execution has started
 	# until then it does block, so to avoid any delay it is still moved to a different thread.
 	var thread: Thread = Thread.new()
-	thread.start(ssh_client.get_client().exec.bind(cmd))
+	thread.start(ssh_client.get_client().exec_in_shell.bind(cmd, shell))
+	_thread_pool.append(thread)
+
+	return true
+
+
+## Shuts down, reboots or suspends the server of the client identified by [param client_uuid].
+## [param action] is "shutdown", "reboot" or "suspend", see [method SSHClient.power].
+## If [param elevated] the command is run with sudo.
+func power_client(
+	blocking: bool, client_uuid: String, action: String, elevated: bool = false
+) -> bool:
+	var ssh_client: SSHClientWrapper = get_client(client_uuid)
+	if not ssh_client:
+		push_error("Couldn't %s: SSHClient %s not found" % [action, client_uuid])
+		return false
+
+	if blocking:
+		return ssh_client.get_client().power(action, elevated)
+
+	var thread: Thread = Thread.new()
+	thread.start(ssh_client.get_client().power.bind(action, elevated))
 	_thread_pool.append(thread)
+	return true
+
+
+## Executes [param cmd] on the client identified by [param client_uuid] with the agent configured in
+## [member SSHClientWrapper.agent_forwarding] forwarded, e.g. for a [code]git pull[/code] over SSH.
+## Runs on a thread, so sign requests can be confirmed on the main thread while it runs, see
+## [member SSHClientWrapper.confirm_agent_signatures]. The output is emitted by [signal agent_exec_finished],
+## see [method SSHClient.exec_blocking_forwarding_agent]. Returns false if it couldn't be started.
+func exec_with_agent(client_uuid: String, cmd: String) -> bool:
+	var ssh_client: SSHClientWrapper = get_client(client_uuid)
+	if not ssh_client:
+		push_error("Couldn't execute %s: SSHClient %s not found" % [cmd, client_uuid])
+		return false
+
+	if ssh_client.agent_forwarding == SSHClientWrapper.AgentForwarding.OFF:
+		push_error("Couldn't execute %s: agent forwarding is off for %s" % [cmd, ssh_client.name])
+		return false
+
+	var thread: Thread = Thread.new()
+	thread.start(_exec_with_agent.bind(ssh_client, cmd))
+	_thread_pool.append(thread)
+	return true
+
+
+## Runs a script of [param steps] on the client identified by [param client_uuid] without blocking,
+## see [method SSHClient.run_script]. Progress and the report are emitted by the client's
+## [signal SSHClient.script_step_finished] and [signal SSHClient.script_finished] with [param script_id].
+func run_script_on_client(client_uuid: String, script_id: String, steps: Array) -> bool:
+	var ssh_client: SSHClientWrapper = get_client(client_uuid)
+	if not ssh_client:
+		push_error("Couldn't run script %s: SSHClient %s not found" % [script_id, client_uuid])
+		return false
+
+	return ssh_client.get_client().run_script(script_id, steps)
+
+
+## Syncs [param local_dir] to [param remote_dir] on every client of [param client_uuids] without blocking,
+## see [method SSHClient.sync_directory]. Progress and the reports are emitted by the clients'
+## [signal SSHClient.sync_progress] and [signal SSHClient.sync_finished] with [param sync_id].
+## Returns false if any sync couldn't be started.
+func sync_to_clients(
+	sync_id: String,
+	client_uuids: Array,
+	local_dir: String,
+	remote_dir: String,
+	options: Dictionary = {}
+) -> bool:
+	var success: bool = true
+	for client_uuid in client_uuids:
+		var ssh_client: SSHClientWrapper = get_client(client_uuid)
+		if not ssh_client:
+			push_error("Couldn't sync %s: SSHClient %s not found" % [sync_id, client_uuid])
+			success = false
+			continue
+
+		if not ssh_client.get_client().sync_directory(sync_id, local_dir, remote_dir, options):
+			success = false
+	return success
+
+
+# Executes [param cmd] with sudo, reporting why if sudo refused to run it.
+# If sudo needs a password, the user is asked for it and [param cmd] runs again in the background.
+func _exec_elevated(ssh_client: SSHClientWrapper, cmd: String, shell: String) -> bool:
+	var output: Variant = ssh_client.get_client().exec_blocking_elevated(cmd, shell)
+	if not output:
+		return false
+
+	match output.sudo_error:
+		null:
+			return output.exit_status != -1
+		"password_required":
+			_prompt_sudo_password.call_deferred(ssh_client, cmd, shell, false)
+		"password_rejected":
+			ssh_client.get_client().set_sudo_password("")
+			if _vault.is_unlocked():
+				_vault.remove_secret(ssh_client.get_sudo_password_id())
+			_prompt_sudo_password.call_deferred(ssh_client, cmd, shell, true)
+		"not_in_sudoers":
+			push_error("Couldn't execute %s with sudo: user is not in sudoers" % cmd)
+		_:
+			push_error("Couldn't execute %s with sudo: sudo is not available" % cmd)
+	return false
+
+
+# Executes [param cmd] with the agent forwarded, meant to run on a thread.
+func _exec_with_agent(ssh_client: SSHClientWrapper, cmd: String) -> void:
+	var output: Variant = ssh_client.get_client().exec_blocking_forwarding_agent(cmd)
+	agent_exec_finished.emit.call_deferred(ssh_client.uuid, cmd, output)
+
+
+# Asks for the sudo password of [param ssh_client] and executes [param cmd] again with it.
+# The client keeps the password until it's rejected.
+func _prompt_sudo_password(
+	ssh_client: SSHClientWrapper, cmd: String, shell: String, rejected: bool
+) -> void:
+	_prompt_password(
+		"sudo password of %s" % ssh_client.name,
+		"Password rejected, try again" if rejected else "Enter the sudo password",
+		_on_sudo_password_entered.bind(ssh_client, cmd, shell)
+	)
+
+
+# Shows a dialog asking for a password, [param on_closed] is called with it,
+# or with an empty string if the dialog was canceled.
+func _prompt_password(title: String, text: String, on_closed: Callable) -> void:
+	var password_edit: LineEdit = LineEdit.new()
+	password_edit.secret = true
+	password_edit.placeholder_text = "Password"
+	var password_dialog: ConfirmationDialog = ConfirmationDialog.new()
+	password_dialog.title = title
+	password_dialog.dialog_text = text
+	password_dialog.add_child(password_edit)
+	password_dialog.register_text_enter(password_edit)
+	add_child(password_dialog)
+	password_dialog.initial_position = Window.WINDOW_INITIAL_POSITION_CENTER_PRIMARY_SCREEN
+	password_dialog.show()
+	password_edit.grab_focus()
+	password_dialog.confirmed.connect(
+		_on_password_dialog_closed.bind(password_dialog, password_edit, on_closed, true)
+	)
+	password_dialog.canceled.connect(
+		_on_password_dialog_closed.bind(password_dialog, password_edit, on_closed, false)
+	)
+
+
+# Adds a job to the scheduler and remembers its definition, see [method schedule_command].
+func _add_scheduled_command(
+	job_id: String, client_uuid: String, cmd: String, schedule: String, missed_policy: String
+) -> bool:
+	var ssh_client: SSHClientWrapper = get_client(client_uuid)
+	if not ssh_client:
+		push_error("Couldn't schedule %s: SSHClient %s not found" % [cmd, client_uuid])
+		return false
+	if not _scheduler.add_job(job_id, ssh_client.get_client(), cmd, schedule, missed_policy):
+		return false
 
+	_scheduled_commands[job_id] = {
+		"id": job_id,
+		"client_uuid": ssh_client.uuid,
+		"command": cmd,
+		"schedule": schedule,
+		"missed_policy": missed_policy,
+	}
 	return true
 
 
+# Whether any key or client has secrets in the vault.
+func _is_vault_used() -> bool:
+	for key in _keys:
+		if key.in_vault:
+			return true
+	for client in _clients:
+		if client.remember_sudo_password and _vault.has_secret(client.get_sudo_password_id()):
+			return true
+	return false
+
+
+# Returns the existing key with [param key_path], if none exists a new one is added.
+func _get_or_add_key_for_path(key_path: String) -> SSHKey:
+	for key in _keys:
+		if key.type == SSHKey.KeyTypes.EXISTING_KEY and key.key_path == key_path:
+			return key
+
+	var new_key: SSHKey = SSHKey.new()
+	new_key.gen_uuid()
+	new_key.name = key_path.get_file()
+	new_key.type = SSHKey.KeyTypes.EXISTING_KEY
+	new_key.key_path = key_path
+	add_key(new_key)
+	return new_key
+
+
 func _on_settings_button_pressed() -> void:
 	var clients_editor: SSHClientWrapper.SSHClientsEditor = SSHClientWrapper.SSHClientsEditor.new()
 	clients_editor.set_clients(_clients)
 	clients_editor.client_added.connect(add_client)
 	clients_editor.client_deleted.connect(remove_client)
+	clients_editor.ssh_config_import_requested.connect(
+		_on_ssh_config_import_requested.bind(clients_editor)
+	)
 
 	var keys_editor: SSHKey.KeysEditor = SSHKey.KeysEditor.new()
 	keys_editor.set_keys(_keys)
@@ -222,3 +783,81 @@ func _on_settings_button_pressed() -> void:
 	keys_editor.key_deleted.connect(remove_key)
 
 	PopupManager.push_stack_item([clients_editor, keys_editor])
+
+
+func _on_client_log_record(record: Dictionary, client: SSHClientWrapper) -> void:
+	client_log_record.emit(client.uuid, record)
+
+
+func _on_agent_sign_requested(
+	request_id: int, fingerprint: String, comment: String, client: SSHClientWrapper
+) -> void:
+	var confirm_dialog: ConfirmationDialog = ConfirmationDialog.new()
+	confirm_dialog.dialog_text = (
+		"Allow %s to sign with key %s %s?" % [client.name, fingerprint, comment]
+	)
+	confirm_dialog.ok_button_text = "Allow"
+	confirm_dialog.cancel_button_text = "Deny"
+	add_child(confirm_dialog)
+	confirm_dialog.initial_position = Window.WINDOW_INITIAL_POSITION_CENTER_PRIMARY_SCREEN
+	confirm_dialog.show()
+	confirm_dialog.confirmed.connect(
+		_on_agent_sign_dialog_closed.bind(confirm_dialog, request_id, true)
+	)
+	confirm_dialog.canceled.connect(
+		_on_agent_sign_dialog_closed.bind(confirm_dialog, request_id, false)
+	)
+
+
+func _on_agent_sign_dialog_closed(
+	confirm_dialog: ConfirmationDialog, request_id: int, allowed: bool
+) -> void:
+	confirm_dialog.queue_free()
+	if not SSHClient.confirm_agent_request(request_id, allowed):
+		push_warning("Agent sign request %d already timed out" % request_id)
+
+
+func _on_password_dialog_closed(
+	password_dialog: ConfirmationDialog,
+	password_edit: LineEdit,
+	on_closed: Callable,
+	confirmed: bool
+) -> void:
+	var password: String = password_edit.text if confirmed else ""
+	password_dialog.queue_free()
+	on_closed.call(password)
+
+
+func _on_sudo_password_entered(
+	password: String, ssh_client: SSHClientWrapper, cmd: String, shell: String
+) -> void:
+	if not password:
+		push_error("Couldn't execute %s with sudo: no sudo password set" % cmd)
+		return
+
+	ssh_client.get_client().set_sudo_password(password)
+	if ssh_client.remember_sudo_password and _vault.is_unlocked():
+		_vault.set_secret(ssh_client.get_sudo_password_id(), password)
+	exec_on_client(false, ssh_client.uuid, cmd, shell, true)
+
+
+func _on_vault_passphrase_entered(passphrase: String) -> void:
+	if not passphrase:
+		return
+
+	var err: Variant = unlock_vault(passphrase)
+	if not err:
+		return
+	if err.kind == "wrong_passphrase":
+		prompt_vault_unlock(true)
+	else:
+		push_error("Couldn't unlock the SSH vault: %s" % err.message)
+
+
+func _on_host_state_changed(client_uuid: String, up: bool, status: Dictionary) -> void:
+	client_state_changed.emit(client_uuid, up, status)
+
+
+func _on_ssh_config_import_requested(clients_editor: SSHClientWrapper.SSHClientsEditor) -> void:
+	import_ssh_config()
+	clients_editor.set_clients(_clients)
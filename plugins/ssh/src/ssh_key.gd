@@ -9,6 +9,7 @@ signal key_updated
 enum CryptoTypes {
 	ED25519,
 	RSA,
+	ECDSA,
 }
 
 ## The types a key can have.
@@ -34,15 +35,28 @@ var type: KeyTypes:
 		_config.get_object("type").set_value(value)
 
 ## Data of the private key when [member type] is [code]NEW_KEY[/code].
+## Empty if the key is stored in the SSH vault, see [member in_vault].
 var key_data: String:
 	set(value):
 		key_data = value
 		_config.get_object("key_data").set_value(value)
+## Whether the private key is stored encrypted in the SSH vault instead of [member key_data],
+## see [method get_vault_id].
+var in_vault: bool:
+	set(value):
+		in_vault = value
+		_config.get_object("in_vault").set_value(value)
 ## Path to the private key when [member type] is [code]EXISTING_KEY[/code].
 var key_path: String:
 	set(value):
 		key_path = value
 		_config.get_object("key_path").set_value(value)
+## Path to an OpenSSH user certificate of the key when [member type] is [code]EXISTING_KEY[/code].
+## If empty [code]<key_path>-cert.pub[/code] is used if it exists.
+var cert_path: String:
+	set(value):
+		cert_path = value
+		_config.get_object("cert_path").set_value(value)
 
 # Internal config.
 var _config: Config = _generate_default_config()
@@ -55,11 +69,13 @@ func generate_editor() -> Config.ConfigEditor:
 	# These editors are never supposed to be edited by a user on an existing key
 	editor.get_editor("uuid").visible = false
 	editor.get_editor("key_data").visible = false
+	editor.get_editor("in_vault").visible = false
 	editor.get_editor("type").visible = false
 
 	match type:
 		KeyTypes.NEW_KEY:
 			editor.get_editor("key_path").visible = false
+			editor.get_editor("cert_path").visible = false
 
 	return editor
 
@@ -80,9 +96,11 @@ func deserialize(dict: Dictionary) -> void:
 
 	match type:
 		KeyTypes.NEW_KEY:
-			key_data = dict.key_data
+			key_data = dict.get("key_data", "")
+			in_vault = dict.get("in_vault", false)
 		KeyTypes.EXISTING_KEY:
 			key_path = dict.key_path
+			cert_path = dict.get("cert_path", "")
 
 
 ## Create a [Dictionary] with appropriate values.
@@ -95,8 +113,10 @@ func serialize() -> Dictionary:
 	match type:
 		KeyTypes.NEW_KEY:
 			ret_dict.erase("key_path")
+			ret_dict.erase("cert_path")
 		KeyTypes.EXISTING_KEY:
 			ret_dict.erase("key_data")
+			ret_dict.erase("in_vault")
 
 	return ret_dict
 
@@ -107,6 +127,22 @@ func gen_uuid() -> void:
 	uuid = UUID.v4()
 
 
+## Id of the private key in the SSH vault, see [member in_vault].
+func get_vault_id() -> String:
+	return "key:%s" % uuid
+
+
+## Stores [param private_key] encrypted in the unlocked [param vault] instead of [member key_data].
+## Returns false if it couldn't be stored.
+func store_in_vault(vault: SSHVault, private_key: String) -> bool:
+	if not vault.set_secret(get_vault_id(), private_key):
+		return false
+
+	key_data = ""
+	in_vault = true
+	return true
+
+
 # Generates a [Config] with all default objects configured.
 func _generate_default_config() -> Config:
 	var config: Config = Config.new()
@@ -115,7 +151,9 @@ func _generate_default_config() -> Config:
 	config.add_string("Name", "name", "")
 	config.add_dict("Key type", "type", KeyTypes.NEW_KEY, KeyTypes)
 	config.add_string("Key data", "key_data", "")
+	config.add_bool("Stored in vault", "in_vault", false)
 	config.add_file_path("Key path", "key_path", "")
+	config.add_file_path("Certificate path", "cert_path", "")
 
 	return config
 
@@ -304,6 +342,12 @@ class NewSSHKeyEditor:
 		"8192",
 		"16384",
 	]
+	## Possible key sizes for an ECDSA key, which select the curve.
+	const ECDSA_SIZES: Array[String] = [
+		"256",
+		"384",
+		"521",
+	]
 
 	var _key_creator_editor: Config.ConfigEditor = null
 	var _new_key_creator_editor: Config.ConfigEditor = null
@@ -326,6 +370,15 @@ class NewSSHKeyEditor:
 
 		var import_key_config: Config = Config.new()
 		import_key_config.add_file_path("Key path", "key_path", key.key_path)
+		import_key_config.add_bool(
+			"Copy key into DreamDeck",
+			"copy_key_data",
+			false,
+			"Converts the key to the openssh format and stores it in the SSH vault instead of the path"
+		)
+		import_key_config.add_string(
+			"Key passphrase", "key_pass", "", "Only needed when copying an encrypted key"
+		)
 		_import_key_creator_editor = import_key_config.generate_editor()
 		_import_key_creator_editor.visible = false
 		add_child(_import_key_creator_editor)
@@ -333,8 +386,10 @@ class NewSSHKeyEditor:
 		var new_key_config: Config = Config.new()
 		new_key_config.add_dict("Crypto", "crypto", CryptoTypes.ED25519, CryptoTypes)
 		new_key_config.add_string_array("Key size", "rsa_size", RSA_SIZES[1], RSA_SIZES)
+		new_key_config.add_string_array("Key size", "ecdsa_size", ECDSA_SIZES[0], ECDSA_SIZES)
 		_new_key_creator_editor = new_key_config.generate_editor()
 		_new_key_creator_editor.get_editor("rsa_size").visible = false
+		_new_key_creator_editor.get_editor("ecdsa_size").visible = false
 		_new_key_creator_editor.get_editor("crypto").value_selected.connect(
 			_on_crypto_value_selected
 		)
@@ -355,28 +410,60 @@ class NewSSHKeyEditor:
 		match new_key_dict.type:
 			KeyTypes.NEW_KEY:
 				var new_key_settings: Dictionary = _new_key_creator_editor.serialize()
-				var key_size: int = (
-					256
-					if new_key_settings.crypto == CryptoTypes.ED25519
-					else int(new_key_settings.rsa_size)
-				)
+				var key_size: int = 256
+				match new_key_settings.crypto:
+					CryptoTypes.RSA:
+						key_size = int(new_key_settings.rsa_size)
+					CryptoTypes.ECDSA:
+						key_size = int(new_key_settings.ecdsa_size)
+				var vault: SSHVault = _get_unlocked_vault()
+				if not vault:
+					return false
 				var new_key: String = SSHClient.generate_private_key(
 					CryptoTypes.find_key(new_key_settings.crypto),
 					key_size,
-					"%s@dreamdeck" % new_key_dict.name
+					"%s@dreamdeck" % new_key_dict.name,
+					""
 				)
-				if new_key == "":
+				if new_key == "" or not _key.store_in_vault(vault, new_key):
 					push_error("Failed to generate key")
 					return false
-				new_key = Marshalls.utf8_to_base64(new_key)
-				_key.key_data = new_key
 				_key.type = KeyTypes.NEW_KEY
 			KeyTypes.EXISTING_KEY:
-				_key.key_path = import_key_dict.key_path
-				_key.type = KeyTypes.EXISTING_KEY
+				if not import_key_dict.copy_key_data:
+					_key.key_path = import_key_dict.key_path
+					_key.type = KeyTypes.EXISTING_KEY
+					return true
+
+				# The passphrase is removed, so the key is only stored encrypted by the vault
+				var vault: SSHVault = _get_unlocked_vault()
+				if not vault:
+					return false
+				var converted_key: String = SSHClient.convert_private_key(
+					FileAccess.get_file_as_string(import_key_dict.key_path),
+					import_key_dict.key_pass,
+					""
+				)
+				if converted_key == "" or not _key.store_in_vault(vault, converted_key):
+					push_error("Failed to import key from %s" % import_key_dict.key_path)
+					return false
+				_key.type = KeyTypes.NEW_KEY
 
 		return true
 
+	# Returns the SSH vault keys are stored in. If it's locked, the user is asked to unlock it
+	# and null is returned.
+	func _get_unlocked_vault() -> SSHVault:
+		var ssh_controller: SSHController = (
+			PluginCoordinator.get_plugin_loader("SSH").get_controller("SSHController")
+		)
+		if ssh_controller.get_vault().is_unlocked():
+			return ssh_controller.get_vault()
+
+		push_error("The SSH vault needs to be unlocked to store the key")
+		ssh_controller.prompt_vault_unlock()
+		return null
+
 	func _on_key_type_editor_value_selected(value_text: String) -> void:
 		if not _new_key_creator_editor and not is_instance_valid(_new_key_creator_editor):
 			return
@@ -394,3 +481,7 @@ class NewSSHKeyEditor:
 			"rsa_size"
 		)
 		rsa_size_editor.visible = value_text == "RSA"
+		var ecdsa_size_editor: Config.StringArrayEditor = _new_key_creator_editor.get_editor(
+			"ecdsa_size"
+		)
+		ecdsa_size_editor.visible = value_text == "ECDSA"